@@ -0,0 +1,4 @@
+-- Add down migration script here
+DROP TABLE outbox;
+DROP TABLE messages;
+DROP TABLE rooms;
@@ -0,0 +1,31 @@
+-- Add up migration script here
+CREATE TABLE rooms (
+    id TEXT NOT NULL PRIMARY KEY,
+    name TEXT NOT NULL,
+    creator_id TEXT NOT NULL,
+    create_date INT NOT NULL,
+    topic TEXT NOT NULL,
+    archived BOOLEAN NOT NULL
+);
+
+CREATE TABLE messages (
+    id TEXT NOT NULL PRIMARY KEY,
+    content TEXT NOT NULL,
+    sender_id TEXT NOT NULL,
+    room_id TEXT NOT NULL REFERENCES rooms (id) ON DELETE CASCADE,
+    create_date INT NOT NULL,
+    sender_name TEXT NOT NULL,
+    client_id TEXT
+);
+CREATE INDEX messages_room_date ON messages (room_id, create_date);
+
+-- messages written while offline, sent in `seq` order once the server is back
+CREATE TABLE outbox (
+    seq INTEGER PRIMARY KEY AUTOINCREMENT,
+    client_id TEXT NOT NULL UNIQUE,
+    content TEXT NOT NULL,
+    sender_id TEXT NOT NULL,
+    room_id TEXT NOT NULL,
+    create_date INT NOT NULL,
+    sender_name TEXT NOT NULL
+);
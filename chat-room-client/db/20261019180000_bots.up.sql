@@ -0,0 +1,2 @@
+-- Add up migration script here
+ALTER TABLE messages ADD COLUMN is_bot BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,337 @@
+//! Rooms and recent messages kept on disk per server and user, so they render before the
+//! server answers, and the outbox of messages written while offline.
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use qu_chat_models::{Message, Room};
+use sqlx::migrate::Migrator;
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
+use sqlx::{FromRow, SqlitePool};
+
+use crate::data_files;
+
+/// Migrations in `db/`, embedded at build time.
+static MIGRATOR: Migrator = sqlx::migrate!("./db");
+
+/// Messages kept per room, older ones are dropped.
+const MESSAGES_PER_ROOM: i64 = 200;
+
+#[derive(Debug)]
+pub struct Cache {
+    pool: SqlitePool,
+}
+
+#[derive(FromRow)]
+struct RoomRow {
+    id: String,
+    name: String,
+    creator_id: String,
+    create_date: i64,
+    topic: String,
+    archived: bool,
+}
+
+impl From<RoomRow> for Room {
+    fn from(row: RoomRow) -> Self {
+        Room {
+            id: row.id,
+            name: row.name,
+            creator_id: row.creator_id,
+            create_date: row.create_date,
+            topic: row.topic,
+            archived: row.archived,
+        }
+    }
+}
+
+#[derive(FromRow)]
+struct MessageRow {
+    id: String,
+    content: String,
+    sender_id: String,
+    room_id: String,
+    create_date: i64,
+    sender_name: String,
+    client_id: Option<String>,
+    is_bot: bool,
+}
+
+impl From<MessageRow> for Message {
+    fn from(row: MessageRow) -> Self {
+        Message {
+            id: row.id,
+            content: row.content,
+            sender_id: row.sender_id,
+            room_id: row.room_id,
+            create_date: row.create_date,
+            sender_name: row.sender_name,
+            client_id: row.client_id,
+            is_bot: row.is_bot,
+        }
+    }
+}
+
+/// One file per server and user, e.g. `cache/http___127_0_0_1_8000-<user id>.sqlite` in the data directory.
+fn file(dir: &Path, base_url: &str, user_id: &str) -> PathBuf {
+    let server = base_url
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect::<String>();
+    dir.join(format!("{}-{}.sqlite", server, user_id))
+}
+
+impl Cache {
+    pub async fn open(base_url: &str, user_id: &str) -> anyhow::Result<Self> {
+        let dir = data_files::path().join("cache");
+        std::fs::create_dir_all(&dir)?;
+        Self::open_file(&file(&dir, base_url, user_id)).await
+    }
+
+    async fn open_file(path: &Path) -> anyhow::Result<Self> {
+        let options = SqliteConnectOptions::from_str(&format!("sqlite://{}", path.display()))?
+            .create_if_missing(true)
+            .foreign_keys(true);
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect_with(options)
+            .await?;
+        MIGRATOR.run(&pool).await?;
+        Ok(Cache { pool })
+    }
+
+    pub async fn rooms(&self) -> sqlx::Result<Vec<Room>> {
+        let rows = sqlx::query_as::<_, RoomRow>("SELECT * FROM rooms ORDER BY create_date DESC")
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(rows.into_iter().map(Room::from).collect())
+    }
+
+    /// Replaces the cached rooms, messages of rooms that are gone go with them.
+    pub async fn save_rooms(&self, rooms: &[Room]) -> sqlx::Result<()> {
+        let mut tx = self.pool.begin().await?;
+        let ids = rooms
+            .iter()
+            .map(|room| room.id.as_str())
+            .collect::<Vec<_>>();
+        sqlx::query("DELETE FROM rooms WHERE id NOT IN (SELECT value FROM json_each($1))")
+            .bind(serde_json::to_string(&ids).unwrap_or_default())
+            .execute(&mut *tx)
+            .await?;
+        for room in rooms {
+            save_room(&mut *tx, room).await?;
+        }
+        tx.commit().await
+    }
+
+    pub async fn save_room(&self, room: &Room) -> sqlx::Result<()> {
+        save_room(&self.pool, room).await
+    }
+
+    pub async fn remove_room(&self, room_id: &str) -> sqlx::Result<()> {
+        sqlx::query("DELETE FROM rooms WHERE id = $1")
+            .bind(room_id)
+            .execute(&self.pool)
+            .await
+            .map(|_| ())
+    }
+
+    /// The latest messages of the room, oldest first.
+    pub async fn messages(&self, room_id: &str) -> sqlx::Result<Vec<Message>> {
+        let rows = sqlx::query_as::<_, MessageRow>(
+            "SELECT * FROM (
+                SELECT * FROM messages WHERE room_id = $1 ORDER BY create_date DESC LIMIT $2
+            ) ORDER BY create_date",
+        )
+        .bind(room_id)
+        .bind(MESSAGES_PER_ROOM)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows.into_iter().map(Message::from).collect())
+    }
+
+    /// Stores the messages and drops the oldest ones past the limit of their room. Messages of
+    /// rooms that aren't cached are ignored.
+    pub async fn save_messages(&self, room_id: &str, messages: &[Message]) -> sqlx::Result<()> {
+        let mut tx = self.pool.begin().await?;
+        for message in messages {
+            sqlx::query(
+                "INSERT OR REPLACE INTO messages
+                    (id, content, sender_id, room_id, create_date, sender_name, client_id, is_bot)
+                SELECT $1, $2, $3, $4, $5, $6, $7, $8
+                WHERE EXISTS (SELECT 1 FROM rooms WHERE id = $4)",
+            )
+            .bind(&message.id)
+            .bind(&message.content)
+            .bind(&message.sender_id)
+            .bind(&message.room_id)
+            .bind(message.create_date)
+            .bind(&message.sender_name)
+            .bind(&message.client_id)
+            .bind(message.is_bot)
+            .execute(&mut *tx)
+            .await?;
+        }
+        sqlx::query(
+            "DELETE FROM messages WHERE room_id = $1 AND id NOT IN (
+                SELECT id FROM messages WHERE room_id = $1 ORDER BY create_date DESC LIMIT $2
+            )",
+        )
+        .bind(room_id)
+        .bind(MESSAGES_PER_ROOM)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await
+    }
+
+    pub async fn remove_message(&self, message_id: &str) -> sqlx::Result<()> {
+        sqlx::query("DELETE FROM messages WHERE id = $1")
+            .bind(message_id)
+            .execute(&self.pool)
+            .await
+            .map(|_| ())
+    }
+
+    /// Queues the message, queuing it again keeps its place.
+    pub async fn queue(&self, message: &Message) -> sqlx::Result<()> {
+        sqlx::query(
+            "INSERT OR IGNORE INTO outbox
+                (client_id, content, sender_id, room_id, create_date, sender_name)
+            VALUES ($1, $2, $3, $4, $5, $6)",
+        )
+        .bind(&message.client_id)
+        .bind(&message.content)
+        .bind(&message.sender_id)
+        .bind(&message.room_id)
+        .bind(message.create_date)
+        .bind(&message.sender_name)
+        .execute(&self.pool)
+        .await
+        .map(|_| ())
+    }
+
+    /// Queued messages in the order they were written.
+    pub async fn outbox(&self) -> sqlx::Result<Vec<Message>> {
+        let rows = sqlx::query_as::<_, MessageRow>(
+            "SELECT '' AS id, content, sender_id, room_id, create_date, sender_name, client_id,
+                FALSE AS is_bot
+            FROM outbox ORDER BY seq",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows.into_iter().map(Message::from).collect())
+    }
+
+    pub async fn dequeue(&self, client_id: &str) -> sqlx::Result<()> {
+        sqlx::query("DELETE FROM outbox WHERE client_id = $1")
+            .bind(client_id)
+            .execute(&self.pool)
+            .await
+            .map(|_| ())
+    }
+}
+
+async fn save_room<'e, E>(executor: E, room: &Room) -> sqlx::Result<()>
+where
+    E: sqlx::Executor<'e, Database = sqlx::Sqlite>,
+{
+    sqlx::query(
+        "INSERT INTO rooms (id, name, creator_id, create_date, topic, archived)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        ON CONFLICT (id) DO UPDATE SET name = $2, topic = $5, archived = $6",
+    )
+    .bind(&room.id)
+    .bind(&room.name)
+    .bind(&room.creator_id)
+    .bind(room.create_date)
+    .bind(&room.topic)
+    .bind(room.archived)
+    .execute(executor)
+    .await
+    .map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cache::*;
+
+    fn room(id: &str) -> Room {
+        Room {
+            id: id.to_string(),
+            name: id.to_string(),
+            creator_id: "creator".to_string(),
+            create_date: 0,
+            topic: String::new(),
+            archived: false,
+        }
+    }
+
+    fn message(room_id: &str, create_date: i64) -> Message {
+        Message {
+            id: uuid::Uuid::new_v4().to_string(),
+            content: format!("message at {}", create_date),
+            sender_id: "sender".to_string(),
+            room_id: room_id.to_string(),
+            create_date,
+            sender_name: "sender".to_string(),
+            client_id: Some(uuid::Uuid::new_v4().to_string()),
+            is_bot: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rooms_messages_and_outbox() {
+        let dir = std::env::temp_dir();
+        let path = file(
+            &dir,
+            "http://127.0.0.1:8000",
+            &uuid::Uuid::new_v4().to_string(),
+        );
+        let cache = Cache::open_file(&path).await.unwrap();
+
+        cache
+            .save_rooms(&[room("lobby"), room("old")])
+            .await
+            .unwrap();
+        let history = [message("lobby", 2), message("lobby", 1)];
+        cache.save_messages("lobby", &history).await.unwrap();
+        cache
+            .save_messages("missing", &[message("missing", 1)])
+            .await
+            .unwrap();
+
+        cache.save_rooms(&[room("lobby")]).await.unwrap();
+        let rooms = cache.rooms().await.unwrap();
+        assert_eq!(rooms.len(), 1);
+        let dates = cache
+            .messages("lobby")
+            .await
+            .unwrap()
+            .iter()
+            .map(|m| m.create_date)
+            .collect::<Vec<_>>();
+        assert_eq!(dates, vec![1, 2]);
+        assert!(cache.messages("missing").await.unwrap().is_empty());
+
+        let (first, second) = (message("lobby", 5), message("lobby", 3));
+        cache.queue(&first).await.unwrap();
+        cache.queue(&second).await.unwrap();
+        cache.queue(&first).await.unwrap();
+        let queued = cache
+            .outbox()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|m| m.client_id)
+            .collect::<Vec<_>>();
+        assert_eq!(queued, vec![first.client_id.clone(), second.client_id]);
+
+        cache
+            .dequeue(first.client_id.as_deref().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(cache.outbox().await.unwrap().len(), 1);
+
+        drop(cache);
+        let _ = std::fs::remove_file(path);
+    }
+}
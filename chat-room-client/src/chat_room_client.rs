@@ -1,98 +1,53 @@
-use futures::StreamExt;
+//! The server calls of the TUI, made through `qu_chat_client` on the server of the account in
+//! use. Refused tokens are reported on the `unauthorized_sender` of the `Client` to sign out.
+use std::sync::RwLock;
+
+use qu_chat_client::ChatClient;
 use qu_chat_models::{
-    CreateRoomParam, Message, RegisterParams, RegisterResponse, Room, RoomState, SendMessageParams,
-    SignInParams, SignInResponse, UserProfile,
+    CreateRoomParam, Message, RegisterResponse, Room, RoomCommand, RoomEvent, RoomState,
+    SignInResponse, UserProfile,
 };
-use serde::Deserialize;
+use tokio::sync::mpsc;
 
-use crate::data_files;
+pub use qu_chat_client::{Error, EventStream, Result, Socket};
 
 pub async fn register<'r>(
     client: &Client,
     username: &'r str,
     password: &'r str,
 ) -> Result<RegisterResponse> {
-    let body = RegisterParams {
-        username: username.to_string(),
-        password: password.to_string(),
-    };
-    let response = client
-        .inner
-        .post(URLs::register())
-        .json(&body)
-        .send()
-        .await
-        .map_err(|e| Error::from(e))
-        .inspect_err(|e| handle_unauthorized(e, client.unauthtorized_sender.clone()))?
-        .json::<BaseRes<RegisterResponse>>()
-        .await?;
-    handle_result(response)
+    let result = client.sdk(None)?.register(username, password).await;
+    client.check(result)
 }
 
 pub async fn whoami(client: &Client, token: &str) -> Result<UserProfile> {
-    let response = client
-        .inner
-        .get(URLs::whoami())
-        .bearer_auth(token)
-        .send()
-        .await
-        .and_then(|r| r.error_for_status())
-        .map_err(|e| Error::from(e))
-        .inspect_err(|e| handle_unauthorized(e, client.unauthtorized_sender.clone()))?
-        .json::<BaseRes<UserProfile>>()
-        .await?;
-
-    handle_result(response)
+    let result = client.sdk(Some(token))?.whoami().await;
+    client.check(result)
 }
 
 pub async fn sigin(client: &Client, username: &str, password: &str) -> Result<SignInResponse> {
-    let body = SignInParams {
-        username: username.to_string(),
-        password: password.to_string(),
-    };
-    let response = client
-        .inner
-        .post(URLs::signin())
-        .json(&body)
-        .send()
-        .await
-        .map_err(|e| Error::from(e))
-        .inspect_err(|e| handle_unauthorized(e, client.unauthtorized_sender.clone()))?
-        .json::<BaseRes<SignInResponse>>()
-        .await?;
-    handle_result(response)
-    // Ok(response)
+    let result = client.sdk(None)?.sign_in(username, password).await;
+    client.check(result)
 }
 
-pub async fn rooms(client: &Client, token: &str) -> Result<Vec<Room>> {
-    let response = client
-        .inner
-        .get(URLs::rooms())
-        .bearer_auth(token)
-        .send()
-        .await
-        .map_err(|e| Error::from(e))
-        .inspect_err(|e| handle_unauthorized(e, client.unauthtorized_sender.clone()))?
-        .json::<BaseRes<Vec<Room>>>()
-        .await?;
+pub async fn rename(client: &Client, token: &str, name: &str) -> Result<UserProfile> {
+    let result = client.sdk(Some(token))?.rename(name).await;
+    client.check(result)
+}
 
-    handle_result(response)
+pub async fn rooms(client: &Client, token: &str) -> Result<Vec<Room>> {
+    let result = client.sdk(Some(token))?.rooms().await;
+    client.check(result)
 }
 
 pub async fn create_room(client: &Client, params: &CreateRoomParam, token: &str) -> Result<Room> {
-    let response = client
-        .inner
-        .post(URLs::create_room())
-        .json(params)
-        .bearer_auth(token)
-        .send()
-        .await
-        .map_err(|e| Error::from(e))
-        .inspect_err(|e| handle_unauthorized(e, client.unauthtorized_sender.clone()))?
-        .json::<BaseRes<Room>>()
-        .await?;
+    let result = client.sdk(Some(token))?.create_room(params).await;
+    client.check(result)
+}
 
-    handle_result(response)
+pub async fn open_direct_room(client: &Client, token: &str, user_id: &str) -> Result<Room> {
+    let result = client.sdk(Some(token))?.open_direct_room(user_id).await;
+    client.check(result)
 }
 
 pub async fn send_message(
@@ -100,331 +55,300 @@ pub async fn send_message(
     token: &str,
     message: &str,
     room_id: &str,
+    client_id: &str,
 ) -> Result<()> {
-    let body = SendMessageParams {
-        text: message.to_string(),
-        room_id: room_id.to_string(),
-    };
-    let response = client
-        .inner
-        .post(URLs::send_messages())
-        .bearer_auth(token)
-        .json(&body)
-        .send()
-        .await
-        .map_err(|e| Error::from(e))
-        .inspect_err(|e| handle_unauthorized(e, client.unauthtorized_sender.clone()))?;
-    if response.status().is_success() {
-        Ok(())
-    } else {
-        Err(Error::Logical(format!(
-            "Unable to send message for {}",
-            room_id
-        )))
+    let result = client
+        .sdk(Some(token))?
+        .send_message(room_id, message, Some(client_id))
+        .await;
+    client.check(result)
+}
+
+/// Opens the stream of every room's events, see [`Events::forward`].
+pub async fn events(client: &Client, token: &str) -> Result<Events> {
+    let result = client.sdk(Some(token))?.events().await;
+    client.check(result).map(Events)
+}
+
+/// An open `/events` stream.
+pub struct Events(EventStream);
+
+impl Events {
+    /// Hands the events to `sender` until the server ends the stream or the receiver is gone.
+    pub async fn forward(self, sender: mpsc::Sender<RoomEvent>) {
+        forward(self.0, sender).await
     }
 }
 
-pub async fn messages<'r>(
-    client: &Client,
-    token: &str,
-    room_id: &'r str,
-    sender: tokio::sync::mpsc::Sender<Message>,
-) {
-    use std::result::Result;
-    let res = client
-        .inner
-        .get(URLs::messages(room_id))
-        .bearer_auth(token)
-        .send()
-        .await
-        .unwrap();
-    let mut stream = res.bytes_stream();
-    while let Some(chunk) = stream.next().await {
-        if let Ok(chunk) = chunk {
-            match std::str::from_utf8(&chunk) {
-                Ok(s) => {
-                    if !(s.starts_with("data:")) {
-                        continue;
-                    }
-                    //remove the initial data:
-                    let mut string = String::from(s);
-                    let string = string.split_off(5);
-                    let message: Result<Message, serde_json::Error> = serde_json::from_str(&string);
-                    match message {
-                        Ok(msg) => sender.send(msg).await.unwrap(),
-                        Err(_) => println!("Unable to decode {}", string),
-                    }
-                }
-                Err(e) => println!("Error: {}", e),
-            };
+async fn forward(mut events: EventStream, sender: mpsc::Sender<RoomEvent>) {
+    use futures::StreamExt;
+    while let Some(event) = events.next().await {
+        if sender.send(event).await.is_err() {
+            break;
         }
-        // sender.send(chunk);
     }
 }
 
+/// Opens the WebSocket, events of the rooms it subscribes to are handed to `sender`.
+/// Fails when the server can't be reached over WebSocket, callers then fall back to SSE.
+pub async fn connect_socket(
+    client: &Client,
+    token: &str,
+    sender: mpsc::Sender<RoomEvent>,
+) -> Result<Socket> {
+    let (socket, events) = client.sdk(Some(token))?.connect().await?;
+    tokio::spawn(forward(events, sender));
+    Ok(socket)
+}
+
 pub async fn last_messages(client: &Client, room_id: &str, token: &str) -> Result<Vec<Message>> {
+    let result = client.sdk(Some(token))?.messages(room_id).await;
+    client.check(result)
+}
+
+/// Up to `size` messages of the room sent at or after `since`, oldest first.
+pub async fn messages_since(
+    client: &Client,
+    token: &str,
+    room_id: &str,
+    since: i64,
+    size: u32,
+) -> Result<Vec<Message>> {
     let result = client
-        .inner
-        .get(URLs::last_messages(room_id))
-        .bearer_auth(token)
-        .send()
-        .await
-        .map_err(|e| Error::from(e))
-        .inspect_err(|e| handle_unauthorized(e, client.unauthtorized_sender.clone()))?
-        .json::<BaseRes<Vec<Message>>>()
-        .await?;
-    handle_result(result)
+        .sdk(Some(token))?
+        .messages_since(room_id, since, size)
+        .await;
+    client.check(result)
 }
 
-pub async fn signout(client: &Client, token: &str) -> Result<()> {
+/// The last `size` messages of the room, newest first.
+pub async fn latest_messages(
+    client: &Client,
+    token: &str,
+    room_id: &str,
+    size: u32,
+) -> Result<Vec<Message>> {
     let result = client
-        .inner
-        .post(URLs::signout())
-        .bearer_auth(token)
-        .send()
-        .await
-        .map_err(|e| Error::from(e))
-        .inspect_err(|e| handle_unauthorized(e, client.unauthtorized_sender.clone()))?
-        .json::<BaseRes<String>>()
-        .await?;
+        .sdk(Some(token))?
+        .latest_messages(room_id, size)
+        .await;
+    client.check(result)
+}
 
-    handle_result(result).map(|_| ())
+pub async fn room_members(client: &Client, token: &str, room_id: &str) -> Result<Vec<UserProfile>> {
+    let result = client.sdk(Some(token))?.room_members(room_id).await;
+    client.check(result)
 }
 
-pub async fn room_states(client: &Client, token: &str, ids: Vec<&str>) -> Result<Vec<RoomState>> {
+pub async fn set_room_topic(
+    client: &Client,
+    token: &str,
+    room_id: &str,
+    topic: &str,
+) -> Result<()> {
     let result = client
-        .inner
-        .get(URLs::room_states())
-        .query(&[("room_ids", ids.join(","))])
-        .bearer_auth(token)
-        .send()
-        .await
-        .map_err(|e| Error::from(e))
-        .inspect_err(|e| handle_unauthorized(e, client.unauthtorized_sender.clone()))?
-        .json::<BaseRes<Vec<RoomState>>>()
-        .await?;
+        .sdk(Some(token))?
+        .set_room_topic(room_id, topic)
+        .await;
+    client.check(result)
+}
 
-    handle_result(result)
+pub async fn leave_room(client: &Client, token: &str, room_id: &str) -> Result<()> {
+    let result = client.sdk(Some(token))?.leave_room(room_id).await;
+    client.check(result)
 }
 
-pub async fn room_states_events(
+pub async fn search_messages(
     client: &Client,
     token: &str,
-    ids: Vec<&str>,
-    sender: tokio::sync::mpsc::Sender<()>,
-) {
-    let res = client
-        .inner
-        .get(URLs::room_states_events())
-        .query(&[("room_ids", ids.join(","))])
-        .bearer_auth(token)
-        .send()
-        .await
-        .unwrap();
-    let mut stream = res.bytes_stream();
-    while let Some(chunk) = stream.next().await {
-        if let Ok(chunk) = chunk {
-            match std::str::from_utf8(&chunk) {
-                Ok(s) => {
-                    if !(s.starts_with("data:")) {
-                        continue;
-                    }
-                    sender.send(()).await.unwrap();
-                }
-                Err(e) => println!("Error: {}", e),
-            };
-        }
-    }
+    room_id: &str,
+    text: &str,
+) -> Result<Vec<Message>> {
+    let result = client
+        .sdk(Some(token))?
+        .search_messages(room_id, text)
+        .await;
+    client.check(result)
 }
 
-pub async fn update_room_seen(client: &Client, token: &str, room_id: &str) -> Result<()> {
-    let result = client
-        .inner
-        .post(URLs::update_room_state(room_id))
-        .bearer_auth(token)
-        .send()
-        .await
-        .map_err(|e| Error::from(e))
-        .inspect_err(|e| handle_unauthorized(e, client.unauthtorized_sender.clone()))?
-        .json::<BaseRes<String>>()
-        .await?;
-
-    handle_result(result).map(|_| ())
-}
-
-fn handle_result<T>(res: BaseRes<T>) -> Result<T> {
-    match res {
-        BaseRes::Data(data) => Ok(data),
-        BaseRes::Error(base_error) => Err(Error::Logical(base_error)),
-    }
+pub async fn room_commands(
+    client: &Client,
+    token: &str,
+    room_id: &str,
+) -> Result<Vec<RoomCommand>> {
+    let result = client.sdk(Some(token))?.room_commands(room_id).await;
+    client.check(result)
 }
 
-fn handle_unauthorized(error: &Error, sender: tokio::sync::mpsc::UnboundedSender<()>) {
-    match error {
-        Error::Unauthorized => {
-            sender.send(()).unwrap();
-        }
-        _ => (),
-    };
+pub async fn invoke_command(
+    client: &Client,
+    token: &str,
+    room_id: &str,
+    name: &str,
+    args: &str,
+) -> Result<()> {
+    let result = client
+        .sdk(Some(token))?
+        .invoke_command(room_id, name, args)
+        .await;
+    client.check(result)
 }
 
-pub struct User {
-    pub id: String,
-    pub name: String,
-    pub token: String,
+pub async fn kick_user(
+    client: &Client,
+    token: &str,
+    room_id: &str,
+    user_id: &str,
+    reason: Option<String>,
+) -> Result<()> {
+    let result = client
+        .sdk(Some(token))?
+        .kick_user(room_id, user_id, reason)
+        .await;
+    client.check(result)
 }
 
-#[derive(Deserialize)]
-enum BaseRes<T> {
-    #[serde(rename = "data")]
-    Data(T),
-    //TODO: temp
-    #[serde(rename = "msg")]
-    Error(String),
-}
-
-pub type Result<T> = std::result::Result<T, self::Error>;
-
-#[derive(Debug, PartialEq)]
-pub enum Error {
-    Unauthorized,
-    TimedOut,
-    Decoding,
-    Other(String),
-    Logical(String),
-}
-
-impl std::fmt::Display for Error {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let string = match self {
-            Error::Unauthorized => String::from("Unauthorized"),
-            Error::TimedOut => String::from("Timed out"),
-            Error::Other(str) => String::from(format!("Unknown Error {}", str)),
-            Error::Logical(string) => string.to_string(),
-            Error::Decoding => String::from("Unable to decode"),
-        };
-        write!(f, "{}", string)
-    }
+pub async fn ban_user(
+    client: &Client,
+    token: &str,
+    room_id: &str,
+    user_id: &str,
+    reason: Option<String>,
+) -> Result<()> {
+    let result = client
+        .sdk(Some(token))?
+        .ban_user(room_id, user_id, reason)
+        .await;
+    client.check(result)
 }
 
-// Implement Error trait
-impl std::error::Error for Error {}
+pub async fn mute_user(
+    client: &Client,
+    token: &str,
+    room_id: &str,
+    user_id: &str,
+    minutes: u32,
+    reason: Option<String>,
+) -> Result<()> {
+    let result = client
+        .sdk(Some(token))?
+        .mute_user(room_id, user_id, minutes, reason)
+        .await;
+    client.check(result)
+}
 
-impl From<reqwest::Error> for Error {
-    fn from(error: reqwest::Error) -> Self {
-        Self::from(&error)
-    }
+pub async fn remove_message(
+    client: &Client,
+    token: &str,
+    room_id: &str,
+    message_id: &str,
+    reason: String,
+) -> Result<()> {
+    let result = client
+        .sdk(Some(token))?
+        .remove_message(room_id, message_id, reason)
+        .await;
+    client.check(result)
 }
 
-impl From<&reqwest::Error> for Error {
-    fn from(error: &reqwest::Error) -> Self {
-        if error.is_decode() {
-            return Self::Decoding;
-        }
+pub async fn signout(client: &Client, token: &str) -> Result<()> {
+    let result = client.sdk(Some(token))?.sign_out().await;
+    client.check(result)
+}
 
-        if error.status() == Some(reqwest::StatusCode::UNAUTHORIZED) {
-            return Self::Unauthorized;
-        }
+pub async fn room_states(client: &Client, token: &str, ids: Vec<&str>) -> Result<Vec<RoomState>> {
+    let result = client.sdk(Some(token))?.room_states(&ids).await;
+    client.check(result)
+}
 
-        if error.is_timeout() {
-            return Self::TimedOut;
-        }
+pub async fn update_room_seen(client: &Client, token: &str, room_id: &str) -> Result<()> {
+    let result = client.sdk(Some(token))?.mark_room_seen(room_id).await;
+    client.check(result)
+}
 
-        Self::Other(error.to_string())
-    }
+pub struct User {
+    pub id: String,
+    pub name: String,
+    pub token: String,
 }
 
 pub struct Client {
     pub inner: reqwest::Client,
-    pub unauthtorized_sender: tokio::sync::mpsc::UnboundedSender<()>,
+    /// Server of the account in use, set before signing in to it.
+    base_url: RwLock<Option<String>>,
+    unauthorized_sender: mpsc::UnboundedSender<()>,
 }
 
-struct URLs;
-
-impl URLs {
-    fn base() -> String {
-        let url = read_base_url().expect("base url is not configed");
-        String::from(url)
-    }
-
-    fn register() -> String {
-        format!("{}/auth/register", URLs::base())
-    }
-
-    fn signin() -> String {
-        format!("{}/auth/signin", URLs::base())
-    }
-
-    fn rooms() -> String {
-        format!("{}/rooms", URLs::base())
-    }
-
-    fn messages(room_id: &str) -> String {
-        format!("{}/messages/events/{}", URLs::base(), room_id)
-    }
-
-    fn last_messages(room_id: &str) -> String {
-        format!("{}/messages/{}", URLs::base(), room_id)
-    }
-
-    fn send_messages() -> String {
-        format!("{}/messages/send", URLs::base())
-    }
-
-    fn whoami() -> String {
-        format!("{}/users/whoami", URLs::base())
-    }
-
-    fn signout() -> String {
-        format!("{}/auth/signout", URLs::base())
+impl Client {
+    pub fn new(inner: reqwest::Client, unauthorized_sender: mpsc::UnboundedSender<()>) -> Self {
+        Client {
+            inner,
+            base_url: RwLock::new(None),
+            unauthorized_sender,
+        }
     }
 
-    fn create_room() -> String {
-        format!("{}/rooms", URLs::base())
+    pub fn set_base_url(&self, base: &str) {
+        *self.base_url.write().unwrap() = Some(base.to_string());
     }
 
-    fn room_states<'r>() -> String {
-        format!("{}/rooms/states/", URLs::base())
+    pub fn base_url(&self) -> Option<String> {
+        self.base_url.read().unwrap().clone()
     }
 
-    fn room_states_events() -> String {
-        format!("{}/rooms/states/events", URLs::base())
+    /// A client of the server in use, sharing the connection pool.
+    fn sdk(&self, token: Option<&str>) -> Result<ChatClient> {
+        let base = self
+            .base_url()
+            .ok_or(Error::Other("No server is set, sign in first".to_string()))?;
+        Ok(ChatClient::with_http(
+            self.inner.clone(),
+            base,
+            token.map(str::to_string),
+        ))
     }
 
-    fn update_room_state(room_id: &str) -> String {
-        format!("{}/rooms/states/{}", URLs::base(), room_id)
+    fn check<T>(&self, result: Result<T>) -> Result<T> {
+        if let Err(Error::Unauthorized) = result {
+            // nobody listens once the app is closing
+            let _ = self.unauthorized_sender.send(());
+        }
+        result
     }
 }
 
-pub fn wite_base_url(base: &str) {
-    std::fs::write(data_files::path().join("base-url"), base).unwrap();
-}
-
-pub fn read_base_url() -> Option<String> {
-    std::fs::read_to_string(data_files::path().join("base-url")).ok()
-}
-
 #[cfg(test)]
 mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
     use crate::chat_room_client::*;
 
     #[tokio::test]
-
     async fn test_unauthenticate() {
-        let rqwclient_builder = reqwest::Client::builder();
-        let rqwclient = rqwclient_builder.build().unwrap();
-        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel::<()>();
-        let client = Client {
-            inner: rqwclient,
-            unauthtorized_sender: tx,
-        };
+        // a server refusing every token
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = [0; 1024];
+            let _ = stream.read(&mut request).await;
+            let response =
+                "HTTP/1.1 401 Unauthorized\r\ncontent-length: 0\r\nconnection: close\r\n\r\n";
+            stream.write_all(response.as_bytes()).await.unwrap();
+        });
 
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel::<()>();
+        let client = Client::new(reqwest::Client::new(), tx);
+        // no server yet
+        assert!(matches!(
+            whoami(&client, "blah").await,
+            Err(Error::Other(_))
+        ));
+
+        client.set_base_url(&base);
         let res = whoami(&client, "blah").await;
-        print!("{:?}", res);
-        let mut event_recved = false;
-        while let Ok(_) = rx.try_recv() {
-            event_recved = true;
-        }
-        assert!(event_recved);
+        assert!(matches!(res, Err(Error::Unauthorized)));
+        assert!(rx.try_recv().is_ok());
     }
 }
@@ -0,0 +1,208 @@
+//! Commands run without the TUI, for scripts and cron jobs. They act as the account in use.
+use anyhow::{anyhow, bail, Context};
+use clap::{Parser, Subcommand};
+use qu_chat_models::{Message, Room, RoomEvent};
+use tokio::sync::mpsc;
+
+use crate::chat_room_client::{self, Client};
+use crate::profiles::{Profile, Profiles};
+use crate::state_machine;
+use crate::token;
+
+#[derive(Parser, Debug)]
+#[command(version, about = "Terminal client of quchat")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Opens the chat, the default.
+    Tui,
+    /// Signs in and makes the account the one in use.
+    Login {
+        /// Address of the server, e.g. `http://127.0.0.1:8000`.
+        #[arg(long)]
+        server: String,
+        #[arg(long)]
+        user: String,
+    },
+    /// Lists the rooms, one id and name per line.
+    Rooms,
+    /// Sends a message to a room, given by id or name.
+    Send { room: String, text: String },
+    /// Prints the messages of a room as they're sent, until interrupted or the room is deleted.
+    Tail { room: String },
+    /// Prints the messages of a room.
+    History {
+        room: String,
+        /// A date (`2025-01-31`, RFC 3339 or a unix timestamp) or an age (`30m`, `2h`, `7d`).
+        #[arg(long)]
+        since: Option<String>,
+        /// Most messages to print, the latest ones without `--since`.
+        #[arg(long, default_value_t = 100)]
+        size: u32,
+    },
+}
+
+pub async fn run(command: Command, client: &Client) -> anyhow::Result<()> {
+    match command {
+        Command::Tui => unreachable!("the TUI isn't a script command"),
+        Command::Login { server, user } => login(client, server, user).await,
+        Command::Rooms => {
+            let token = session(client)?;
+            for room in chat_room_client::rooms(client, &token).await? {
+                println!("{}\t{}", room.id, room.name);
+            }
+            Ok(())
+        }
+        Command::Send { room, text } => {
+            let token = session(client)?;
+            let room = find_room(client, &token, &room).await?;
+            let client_id = uuid::Uuid::new_v4().to_string();
+            chat_room_client::send_message(client, &token, &text, &room.id, &client_id).await?;
+            Ok(())
+        }
+        Command::Tail { room } => {
+            let token = session(client)?;
+            let room = find_room(client, &token, &room).await?;
+            tail(client, &token, &room).await
+        }
+        Command::History { room, since, size } => {
+            let token = session(client)?;
+            let room = find_room(client, &token, &room).await?;
+            let messages = match since {
+                Some(since) => {
+                    let since = parse_since(&since, chrono::Utc::now().timestamp())?;
+                    chat_room_client::messages_since(client, &token, &room.id, since, size).await?
+                }
+                None => {
+                    let mut latest =
+                        chat_room_client::latest_messages(client, &token, &room.id, size).await?;
+                    latest.reverse();
+                    latest
+                }
+            };
+            for message in messages {
+                print_message(&message);
+            }
+            Ok(())
+        }
+    }
+}
+
+async fn login(client: &Client, server: String, username: String) -> anyhow::Result<()> {
+    let password = rpassword::prompt_password(format!("Password of {}: ", username))?;
+    client.set_base_url(&server);
+    let response = chat_room_client::sigin(client, &username, &password).await?;
+    let profile = Profile { server, username };
+    state_machine::save_account(&profile, &response.token)?;
+    eprintln!("Signed in as {}", profile.name());
+    Ok(())
+}
+
+/// The token of the account in use, pointing the client at its server.
+fn session(client: &Client) -> anyhow::Result<String> {
+    let profiles = Profiles::load();
+    let profile = profiles
+        .active()
+        .ok_or(anyhow!("No account in use, sign in with `login` first"))?;
+    let token = token::read_token(profile).ok_or(anyhow!(
+        "{} is signed out, sign in with `login`",
+        profile.name()
+    ))?;
+    client.set_base_url(&profile.server);
+    Ok(token)
+}
+
+/// The room with that id, or else the only one with that name.
+async fn find_room(client: &Client, token: &str, room: &str) -> anyhow::Result<Room> {
+    let rooms = chat_room_client::rooms(client, token).await?;
+    if let Some(found) = rooms.iter().find(|r| r.id == room) {
+        return Ok(found.clone());
+    }
+    let mut named = rooms.into_iter().filter(|r| r.name == room);
+    match (named.next(), named.next()) {
+        (Some(found), None) => Ok(found),
+        (Some(_), Some(_)) => bail!("Several rooms are named {}, use its id", room),
+        (None, _) => bail!("No room {}", room),
+    }
+}
+
+async fn tail(client: &Client, token: &str, room: &Room) -> anyhow::Result<()> {
+    let events = chat_room_client::events(client, token).await?;
+    let (tx, mut rx) = mpsc::channel::<RoomEvent>(32);
+    let forward = tokio::spawn(events.forward(tx));
+    while let Some(event) = rx.recv().await {
+        match event {
+            RoomEvent::Message(message) if message.room_id == room.id => print_message(&message),
+            RoomEvent::Deleted { room_id } if room_id == room.id => {
+                forward.abort();
+                return Ok(());
+            }
+            _ => (),
+        }
+    }
+    bail!("The connection to the server closed")
+}
+
+/// One line per message, tab separated: date, sender and text.
+fn print_message(message: &Message) {
+    let date = chrono::DateTime::from_timestamp(message.create_date, 0)
+        .map(|date| date.to_rfc3339())
+        .unwrap_or_default();
+    println!(
+        "{}\t{}\t{}",
+        date,
+        message.sender_name,
+        message.content.replace('\n', " ")
+    );
+}
+
+/// Timestamp of `since`, an age being counted back from `now`.
+fn parse_since(since: &str, now: i64) -> anyhow::Result<i64> {
+    if let Ok(timestamp) = since.parse::<i64>() {
+        return Ok(timestamp);
+    }
+    if let Ok(date) = chrono::DateTime::parse_from_rfc3339(since) {
+        return Ok(date.timestamp());
+    }
+    if let Ok(date) = chrono::NaiveDate::parse_from_str(since, "%Y-%m-%d") {
+        return Ok(date.and_time(chrono::NaiveTime::MIN).and_utc().timestamp());
+    }
+    let unit = since.chars().last().unwrap_or_default();
+    let seconds = match unit {
+        's' => 1,
+        'm' => 60,
+        'h' => 60 * 60,
+        'd' => 24 * 60 * 60,
+        'w' => 7 * 24 * 60 * 60,
+        _ => bail!("Can't read the date {}", since),
+    };
+    let amount = since[..since.len() - 1]
+        .parse::<i64>()
+        .with_context(|| format!("Can't read the date {}", since))?;
+    amount
+        .checked_mul(seconds)
+        .and_then(|age| now.checked_sub(age))
+        .with_context(|| format!("Can't read the date {}", since))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cli::*;
+
+    #[test]
+    fn test_parse_since() {
+        let now = 1_000_000;
+        assert_eq!(parse_since("90", now).unwrap(), 90);
+        assert_eq!(parse_since("30m", now).unwrap(), now - 1800);
+        assert_eq!(parse_since("2d", now).unwrap(), now - 172_800);
+        assert_eq!(parse_since("1970-01-02", now).unwrap(), 86_400);
+        assert_eq!(parse_since("1970-01-01T01:00:00+01:00", now).unwrap(), 0);
+        assert!(parse_since("yesterday", now).is_err());
+        assert!(parse_since("h", now).is_err());
+        assert!(parse_since("9999999999999999w", now).is_err());
+    }
+}
@@ -0,0 +1,267 @@
+//! Slash commands typed in the message field, e.g. `/join general`. The built in ones are run by
+//! the client, any other has to be one of the commands bots registered for the room. A message
+//! starting with `/` is sent by typing it as `//`.
+use qu_chat_models::{Room, RoomCommand, UserProfile};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Join(String),
+    Leave,
+    Nick(String),
+    Topic(String),
+    Me(String),
+    Search(String),
+    Dm(String),
+    Help,
+    /// A command a bot answers, see `RoomCommand`.
+    Bot {
+        name: String,
+        args: String,
+    },
+}
+
+/// A command the client runs itself.
+pub struct Builtin {
+    pub name: &'static str,
+    pub usage: &'static str,
+    pub description: &'static str,
+}
+
+pub const BUILTINS: [Builtin; 8] = [
+    Builtin {
+        name: "join",
+        usage: "/join <room>",
+        description: "Open a room",
+    },
+    Builtin {
+        name: "leave",
+        usage: "/leave",
+        description: "Leave the members of the room and close it",
+    },
+    Builtin {
+        name: "nick",
+        usage: "/nick <name>",
+        description: "Change your name, also the one you sign in with",
+    },
+    Builtin {
+        name: "topic",
+        usage: "/topic <text>",
+        description: "Set the topic of the room",
+    },
+    Builtin {
+        name: "me",
+        usage: "/me <action>",
+        description: "Say what you are doing",
+    },
+    Builtin {
+        name: "search",
+        usage: "/search <text>",
+        description: "Search the messages of the room",
+    },
+    Builtin {
+        name: "dm",
+        usage: "/dm <member>",
+        description: "Open a private room with a member of this one",
+    },
+    Builtin {
+        name: "help",
+        usage: "/help",
+        description: "Show the commands",
+    },
+];
+
+#[derive(Debug, PartialEq)]
+pub enum Input {
+    Message(String),
+    Command(Command),
+}
+
+/// Reads what was typed in the message field, failing on unknown commands and missing arguments.
+pub fn parse(text: &str, commands: &[RoomCommand]) -> Result<Input, String> {
+    let Some(typed) = text.strip_prefix('/') else {
+        return Ok(Input::Message(text.to_string()));
+    };
+    if typed.starts_with('/') {
+        return Ok(Input::Message(typed.to_string()));
+    }
+    let (name, args) = match typed.split_once(char::is_whitespace) {
+        Some((name, args)) => (name.to_lowercase(), args.trim()),
+        None => (typed.to_lowercase(), ""),
+    };
+    let arg = || match BUILTINS.iter().find(|b| b.name == name) {
+        Some(builtin) if args.is_empty() => Err(format!("Usage: {}", builtin.usage)),
+        _ => Ok(args.to_string()),
+    };
+
+    let command = match name.as_str() {
+        "join" => Command::Join(arg()?),
+        "leave" => Command::Leave,
+        "nick" => Command::Nick(arg()?),
+        "topic" => Command::Topic(arg()?),
+        "me" => Command::Me(arg()?),
+        "search" => Command::Search(arg()?),
+        "dm" => Command::Dm(arg()?),
+        "help" => Command::Help,
+        _ if commands.iter().any(|c| c.name == name) => Command::Bot {
+            args: args.to_string(),
+            name,
+        },
+        _ => return Err(format!("Unknown command /{}, see /help", name)),
+    };
+    Ok(Input::Command(command))
+}
+
+/// Completes the name of the command being typed, or the room of `/join` and the member of
+/// `/dm`, as far as every candidate agrees. None when nothing matches.
+pub fn complete(
+    text: &str,
+    commands: &[RoomCommand],
+    rooms: &[Room],
+    members: &[UserProfile],
+) -> Option<String> {
+    let typed = text.strip_prefix('/')?;
+    match typed.split_once(' ') {
+        None => {
+            let names = BUILTINS
+                .iter()
+                .map(|b| b.name)
+                .chain(commands.iter().map(|c| c.name.as_str()));
+            let (name, unique) = complete_word(typed, names)?;
+            Some(format!("/{}{}", name, if unique { " " } else { "" }))
+        }
+        Some((command, arg)) => {
+            let (name, _) = match command {
+                "join" => complete_word(arg, rooms.iter().map(|r| r.name.as_str()))?,
+                "dm" => complete_word(arg, members.iter().map(|m| m.name.as_str()))?,
+                _ => return None,
+            };
+            Some(format!("/{} {}", command, name))
+        }
+    }
+}
+
+/// The longest start shared by the candidates starting with `prefix`, ignoring case, and whether
+/// a single one does.
+fn complete_word<'a>(
+    prefix: &str,
+    candidates: impl Iterator<Item = &'a str>,
+) -> Option<(String, bool)> {
+    let prefix_lower = prefix.to_lowercase();
+    let mut matches = candidates.filter(|c| c.to_lowercase().starts_with(&prefix_lower));
+    let first = matches.next()?;
+    let mut common = first;
+    let mut unique = true;
+    for other in matches.filter(|other| *other != first) {
+        unique = false;
+        let len = common
+            .char_indices()
+            .zip(other.chars())
+            .take_while(|((_, a), b)| a == b)
+            .last()
+            .map_or(0, |((i, a), _)| i + a.len_utf8());
+        common = &common[..len];
+    }
+    // candidates only differing in case from what is typed
+    if common.len() < prefix.len() {
+        return Some((prefix.to_string(), unique));
+    }
+    Some((common.to_string(), unique))
+}
+
+/// The room named so, ignoring case, or else the only one whose name starts so.
+pub fn find_room(rooms: &[Room], name: &str) -> Option<usize> {
+    let name = name.to_lowercase();
+    if let Some(index) = rooms.iter().position(|r| r.name.to_lowercase() == name) {
+        return Some(index);
+    }
+    let mut matches = rooms
+        .iter()
+        .enumerate()
+        .filter(|(_, r)| r.name.to_lowercase().starts_with(&name));
+    match (matches.next(), matches.next()) {
+        (Some((index, _)), None) => Some(index),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::commands::*;
+
+    fn room(name: &str) -> Room {
+        Room {
+            id: name.to_string(),
+            name: name.to_string(),
+            creator_id: String::new(),
+            create_date: 0,
+            topic: String::new(),
+            archived: false,
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        let deploy = RoomCommand {
+            room_id: "r".to_string(),
+            name: "deploy".to_string(),
+            description: String::new(),
+            bot_id: "b".to_string(),
+            bot_name: "ci".to_string(),
+        };
+        let commands = [deploy];
+        let command = |text: &str| match parse(text, &commands) {
+            Ok(Input::Command(command)) => Ok(command),
+            Ok(Input::Message(text)) => Err(text),
+            Err(err) => Err(err),
+        };
+
+        assert_eq!(
+            parse("hi /all", &commands),
+            Ok(Input::Message("hi /all".into()))
+        );
+        assert_eq!(
+            parse("//shrug", &commands),
+            Ok(Input::Message("/shrug".into()))
+        );
+        assert_eq!(
+            command("/JOIN  general "),
+            Ok(Command::Join("general".into()))
+        );
+        assert_eq!(command("/leave now"), Ok(Command::Leave));
+        assert_eq!(command("/me waves"), Ok(Command::Me("waves".into())));
+        assert_eq!(command("/topic"), Err("Usage: /topic <text>".into()));
+        assert_eq!(
+            command("/deploy main --force"),
+            Ok(Command::Bot {
+                name: "deploy".into(),
+                args: "main --force".into()
+            })
+        );
+        assert_eq!(
+            command("/nope"),
+            Err("Unknown command /nope, see /help".into())
+        );
+    }
+
+    #[test]
+    fn test_complete() {
+        let rooms = [room("general"), room("games"), room("Random")];
+        let members = [UserProfile {
+            id: "1".to_string(),
+            name: "alice".to_string(),
+        }];
+        let complete = |text: &str| complete(text, &[], &rooms, &members);
+
+        assert_eq!(complete("/jo"), Some("/join ".into()));
+        assert_eq!(complete("/join g"), Some("/join g".into()));
+        assert_eq!(complete("/join ge"), Some("/join general".into()));
+        assert_eq!(complete("/join r"), Some("/join Random".into()));
+        assert_eq!(complete("/dm A"), Some("/dm alice".into()));
+        assert_eq!(complete("/x"), None);
+        assert_eq!(complete("hello"), None);
+
+        assert_eq!(find_room(&rooms, "GENERAL"), Some(0));
+        assert_eq!(find_room(&rooms, "ran"), Some(2));
+        assert_eq!(find_room(&rooms, "g"), None);
+    }
+}
@@ -1,15 +1,74 @@
 use std::path::{Path, PathBuf};
 
+use crate::chat_room_client::{self, Client};
+use crate::profiles::{Profile, Profiles};
+use crate::token;
+
 pub fn create_dir_if_needed() -> Result<(), std::io::Error> {
-    let mut path = std::env::current_dir()?;
-    path.push(".data");
-    if path.exists() {
-        Ok(())
-    } else {
-        std::fs::create_dir_all(&path)
-    }
+    std::fs::create_dir_all(config_path())?;
+    std::fs::create_dir_all(path())
 }
 
+/// Data of the client, `$XDG_DATA_HOME/quchat` or `~/.local/share/quchat`.
 pub fn path() -> PathBuf {
-    Path::new("./.data").to_path_buf()
+    xdg_dir("XDG_DATA_HOME", ".local/share")
+}
+
+/// Settings of the client, `$XDG_CONFIG_HOME/quchat` or `~/.config/quchat`.
+pub fn config_path() -> PathBuf {
+    xdg_dir("XDG_CONFIG_HOME", ".config")
+}
+
+/// Falls back to `./.data` when neither the variable nor `$HOME` is set.
+fn xdg_dir(var: &str, home_relative: &str) -> PathBuf {
+    let base = std::env::var_os(var)
+        .filter(|dir| Path::new(dir).is_absolute())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(home_relative)));
+    match base {
+        Some(base) => base.join("quchat"),
+        None => Path::new("./.data").to_path_buf(),
+    }
+}
+
+/// Moves the token and server kept in `./.data` by older clients, relative to the directory
+/// they ran from, to a profile. The user name comes from the server, so the move waits for the
+/// next start when it can't be reached, expired tokens are dropped.
+pub async fn migrate_legacy_layout(client: &Client) {
+    let legacy = Path::new("./.data");
+    let (Ok(token), Ok(server)) = (
+        std::fs::read_to_string(legacy.join("token")),
+        std::fs::read_to_string(legacy.join("base-url")),
+    ) else {
+        return;
+    };
+
+    client.set_base_url(&server);
+    match chat_room_client::whoami(client, &token).await {
+        Ok(user) => {
+            let profile = Profile {
+                server,
+                username: user.name,
+            };
+            if token::persist_token(&profile, &token).is_err() {
+                return;
+            }
+            let mut profiles = Profiles::load();
+            match profiles.active {
+                Some(_) => profiles.add(profile),
+                None => profiles.activate(profile),
+            }
+            if profiles.save().is_err() {
+                return;
+            }
+        }
+        Err(chat_room_client::Error::Unauthorized) => (),
+        Err(_) => return,
+    }
+
+    for file in ["token", "base-url"] {
+        let _ = std::fs::remove_file(legacy.join(file));
+    }
+    let _ = std::fs::remove_dir_all(legacy.join("cache"));
+    let _ = std::fs::remove_dir(legacy);
 }
@@ -58,6 +58,9 @@ impl<'r> SignedOutState<'r> {
             Event::Key(key) => match key.code {
                 KeyCode::Tab => Some(SignedOutAction::NextFocus),
                 KeyCode::Esc => Some(SignedOutAction::UnFocus),
+                KeyCode::Up => Some(SignedOutAction::SelectPrevAccount),
+                KeyCode::Down => Some(SignedOutAction::SelectNextAccount),
+                KeyCode::Enter => Some(SignedOutAction::OpenAccount),
                 KeyCode::Char('r') if key.modifiers.contains(KeyModifiers::CONTROL) => {
                     Some(SignedOutAction::Register)
                 }
@@ -76,6 +79,18 @@ impl<'r> AuthenticatedState<'r> {
     fn handle_events(&self, event: &Event) -> Option<Action> {
         let action: Option<AuthenticatedAction> = match event {
             Event::Key(key) => match key.code {
+                KeyCode::Up if self.admin_menu_open() => {
+                    Some(AuthenticatedAction::SelectPrevAdminAction)
+                }
+                KeyCode::Down if self.admin_menu_open() => {
+                    Some(AuthenticatedAction::SelectNextAdminAction)
+                }
+                KeyCode::Enter if self.admin_menu_open() => {
+                    Some(AuthenticatedAction::ApplyAdminAction)
+                }
+                KeyCode::Esc if self.admin_menu_open() => Some(AuthenticatedAction::CloseAdminMenu),
+                KeyCode::Esc if self.popup_open() => Some(AuthenticatedAction::ClosePopup),
+                KeyCode::Tab if self.typing_command() => Some(AuthenticatedAction::CompleteCommand),
                 KeyCode::Up if self.current_room.is_none() => {
                     Some(AuthenticatedAction::SelectPrevRoom)
                 }
@@ -109,6 +124,25 @@ impl<'r> AuthenticatedState<'r> {
                 KeyCode::Char('r') if key.modifiers.contains(KeyModifiers::CONTROL) => {
                     Some(AuthenticatedAction::StartCreatingRoom)
                 }
+                KeyCode::Char('p') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    Some(AuthenticatedAction::SwitchAccount)
+                }
+                KeyCode::Char('a')
+                    if key.modifiers.contains(KeyModifiers::CONTROL)
+                        && self.current_room.is_some() =>
+                {
+                    Some(AuthenticatedAction::OpenAdminMenu)
+                }
+                KeyCode::Char('t')
+                    if key.modifiers.contains(KeyModifiers::CONTROL)
+                        && self.current_room.is_some() =>
+                {
+                    Some(AuthenticatedAction::RetryMessage)
+                }
+
+                _ if self.admin_menu_open() => {
+                    try_handle_text_events(event).map(AuthenticatedAction::AdminReason)
+                }
 
                 _ if self.create_room.is_some() => {
                     try_handle_text_events(event).map(|e| AuthenticatedAction::CreateRoomName(e))
@@ -125,6 +159,28 @@ impl<'r> AuthenticatedState<'r> {
     }
 }
 
+impl AuthenticatedState<'_> {
+    fn admin_menu_open(&self) -> bool {
+        self.current_room
+            .as_ref()
+            .is_some_and(|r| r.admin_menu.is_some())
+    }
+
+    fn popup_open(&self) -> bool {
+        self.current_room
+            .as_ref()
+            .is_some_and(|r| r.popup.is_some())
+    }
+
+    fn typing_command(&self) -> bool {
+        self.create_room.is_none()
+            && self
+                .current_room
+                .as_ref()
+                .is_some_and(|r| r.message_field.text.starts_with('/'))
+    }
+}
+
 fn try_handle_text_events(event: &Event) -> Option<TextFieldAction> {
     match event {
         Event::Key(key) => match key.code {
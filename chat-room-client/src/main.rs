@@ -1,10 +1,19 @@
+use clap::Parser;
+use cli::{Cli, Command};
+use profiles::Profiles;
 use state::{Action, App};
 use std::sync::Arc;
 
 pub mod asciiart;
+pub mod cache;
 pub mod chat_room_client;
+pub mod cli;
+pub mod commands;
 pub mod data_files;
 pub mod events;
+pub mod markup;
+pub mod message_list;
+pub mod profiles;
 pub mod render;
 pub mod state;
 pub mod token;
@@ -13,6 +22,7 @@ pub mod state_machine;
 
 #[tokio::main]
 async fn main() {
+    let cli = Cli::parse();
     data_files::create_dir_if_needed().expect("Can't create data files");
     let client_builder = reqwest::ClientBuilder::new();
     let reqwest_client = client_builder
@@ -20,12 +30,31 @@ async fn main() {
         .expect("Unable to create http client");
 
     let (unauthorized_tx, mut unauthorized_rx) = tokio::sync::mpsc::unbounded_channel::<()>();
-    let client = chat_room_client::Client {
-        inner: reqwest_client,
-        unauthtorized_sender: unauthorized_tx,
-    };
+    let client = chat_room_client::Client::new(reqwest_client, unauthorized_tx);
     let client = Arc::new(client);
 
+    if let Err(err) = unlock_credentials() {
+        eprintln!("{}", err);
+        return;
+    }
+    {
+        // a stale token found there must not sign out the account in use
+        let (unauthorized_tx, _unauthorized_rx) = tokio::sync::mpsc::unbounded_channel::<()>();
+        let client = chat_room_client::Client::new(client.inner.clone(), unauthorized_tx);
+        data_files::migrate_legacy_layout(&client).await;
+    }
+
+    match cli.command {
+        None | Some(Command::Tui) => (),
+        Some(command) => {
+            if let Err(err) = cli::run(command, &client).await {
+                eprintln!("{}", err);
+                std::process::exit(1);
+            }
+            return;
+        }
+    }
+
     let (action_tx, mut action_rx) = tokio::sync::mpsc::unbounded_channel::<Action>();
 
     let mut terminal = ratatui::init();
@@ -48,19 +77,57 @@ async fn main() {
     }
 }
 
+/// Asks for the passphrase of encrypted credentials before the TUI or a command starts.
+/// `QUCHAT_ENCRYPT_CREDENTIALS=1` encrypts them with a new passphrase, `=0` decrypts them.
+fn unlock_credentials() -> anyhow::Result<()> {
+    let wanted = std::env::var("QUCHAT_ENCRYPT_CREDENTIALS")
+        .ok()
+        .map(|value| value != "0");
+    if token::is_encrypted() {
+        let mut attempts = 3;
+        loop {
+            let passphrase = rpassword::prompt_password("Passphrase of the saved accounts: ")?;
+            match token::unlock(&passphrase) {
+                Ok(_) => break,
+                Err(err) if attempts > 1 => eprintln!("{}", err),
+                Err(err) => return Err(err),
+            }
+            attempts -= 1;
+        }
+        if wanted == Some(false) {
+            token::decrypt()?;
+        }
+    } else if wanted == Some(true) {
+        let passphrase = rpassword::prompt_password("New passphrase of the saved accounts: ")?;
+        if passphrase.is_empty() {
+            anyhow::bail!("The passphrase can't be empty");
+        }
+        if rpassword::prompt_password("Repeat it: ")? != passphrase {
+            anyhow::bail!("The passphrases don't match");
+        }
+        token::encrypt(&passphrase)?;
+    }
+    Ok(())
+}
+
 fn start_app(
     client: Arc<chat_room_client::Client>,
     sideeffect: &tokio::sync::mpsc::UnboundedSender<Action>,
 ) -> App {
     let mut app = App::initial();
 
-    match token::read_token() {
-        Some(token) => {
+    let profiles = Profiles::load();
+    let active = profiles
+        .active()
+        .and_then(|profile| token::read_token(profile).map(|token| (profile, token)));
+    match active {
+        Some((profile, token)) => {
+            client.set_base_url(&profile.server);
             state_machine::new_authenticate(&mut app, token, client, sideeffect);
             app
         }
         None => {
-            state_machine::new_signout(&mut app);
+            state_machine::new_welcome(&mut app);
             app
         }
     }
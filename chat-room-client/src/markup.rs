@@ -0,0 +1,116 @@
+//! Message markup, see `qu_chat_models::markup`, drawn with ratatui styles. Code blocks naming
+//! their language are syntax highlighted when built with `--features highlight`.
+use qu_chat_models::markup::{Block, Span as MarkupSpan};
+use ratatui::style::{Style, Stylize};
+use ratatui::text::{Line, Span};
+
+/// Drawn before each line of a code block.
+const CODE_MARGIN: &str = "│ ";
+
+pub fn lines(blocks: Vec<Block>) -> Vec<Line<'static>> {
+    blocks
+        .into_iter()
+        .flat_map(|block| match block {
+            Block::Line(spans) => vec![Line::from(
+                spans.into_iter().flat_map(span).collect::<Vec<Span>>(),
+            )],
+            Block::Code { language, code } => code_lines(language.as_deref(), &code),
+        })
+        .collect()
+}
+
+fn span(span: MarkupSpan) -> Vec<Span<'static>> {
+    let mut style = Style::new();
+    if span.style.bold {
+        style = style.bold();
+    }
+    if span.style.italic {
+        style = style.italic();
+    }
+    if span.style.code {
+        style = style.yellow();
+    }
+    match span.link {
+        Some(url) if url == span.text => vec![Span::styled(url, style.blue().underlined())],
+        Some(url) => vec![
+            Span::styled(span.text, style.blue().underlined()),
+            Span::from(format!(" <{}>", url)).dim(),
+        ],
+        None => vec![Span::styled(span.text, style)],
+    }
+}
+
+fn code_lines(language: Option<&str>, code: &str) -> Vec<Line<'static>> {
+    // tabs aren't drawn by the terminal backend
+    let code = code.replace('\t', "    ");
+    let lines = language
+        .and_then(|language| highlight::highlight(language, &code))
+        .unwrap_or_else(|| {
+            code.split('\n')
+                .map(|line| vec![Span::from(line.to_string()).yellow()])
+                .collect()
+        });
+    lines
+        .into_iter()
+        .map(|spans| {
+            let mut line = vec![Span::from(CODE_MARGIN).dim()];
+            line.extend(spans);
+            Line::from(line)
+        })
+        .collect()
+}
+
+#[cfg(feature = "highlight")]
+mod highlight {
+    use std::sync::OnceLock;
+
+    use ratatui::style::{Color, Style, Stylize};
+    use ratatui::text::Span;
+    use syntect::easy::HighlightLines;
+    use syntect::highlighting::{FontStyle, Theme, ThemeSet};
+    use syntect::parsing::SyntaxSet;
+    use syntect::util::LinesWithEndings;
+
+    /// Loaded on the first code block, it takes a moment.
+    static SYNTAXES: OnceLock<(SyntaxSet, Theme)> = OnceLock::new();
+
+    /// The spans of each line, none for languages syntect doesn't know.
+    pub fn highlight(language: &str, code: &str) -> Option<Vec<Vec<Span<'static>>>> {
+        let (syntaxes, theme) = SYNTAXES.get_or_init(|| {
+            let mut themes = ThemeSet::load_defaults().themes;
+            let theme = themes.remove("base16-ocean.dark").unwrap_or_default();
+            (SyntaxSet::load_defaults_newlines(), theme)
+        });
+        let syntax = syntaxes.find_syntax_by_token(language)?;
+        let mut highlighter = HighlightLines::new(syntax, theme);
+        LinesWithEndings::from(code)
+            .map(|line| {
+                let ranges = highlighter.highlight_line(line, syntaxes).ok()?;
+                let spans = ranges
+                    .into_iter()
+                    .map(|(highlight, text)| {
+                        let color = highlight.foreground;
+                        let mut style = Style::new().fg(Color::Rgb(color.r, color.g, color.b));
+                        if highlight.font_style.contains(FontStyle::BOLD) {
+                            style = style.bold();
+                        }
+                        if highlight.font_style.contains(FontStyle::ITALIC) {
+                            style = style.italic();
+                        }
+                        Span::styled(text.trim_end_matches('\n').to_string(), style)
+                    })
+                    .collect();
+                Some(spans)
+            })
+            .collect()
+    }
+}
+
+#[cfg(not(feature = "highlight"))]
+mod highlight {
+    use ratatui::text::Span;
+
+    pub fn highlight(_language: &str, _code: &str) -> Option<Vec<Vec<Span<'static>>>> {
+        None
+    }
+}
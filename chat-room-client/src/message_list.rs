@@ -0,0 +1,328 @@
+//! The messages of the current room. Only the ones in view are laid out, starting from the
+//! selected one, so drawing takes as long with a hundred thousand messages loaded as with ten.
+use qu_chat_models::markup::{parse, Block as MarkupBlock};
+use qu_chat_models::Message;
+use ratatui::prelude::{Buffer, Rect};
+use ratatui::style::{Style, Stylize};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::Widget;
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+use crate::markup;
+use crate::render::pretty_date;
+use crate::state::{CurrentRoomState, Delivery};
+
+/// Shown next to the name of bots, so their messages stand out from people's.
+const BOT_BADGE: &str = "BOT";
+
+/// Messages a sender sends within this many seconds of their previous one are shown under it,
+/// without repeating the name.
+const GROUP_SECONDS: i64 = 5 * 60;
+
+pub struct MessageList<'a, 'r> {
+    pub room: &'a CurrentRoomState<'r>,
+    /// Id of the signed in user, their messages are aligned right.
+    pub me: Option<&'a str>,
+}
+
+impl Widget for MessageList<'_, '_> {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        let messages = &self.room.messages;
+        if messages.is_empty() || area.is_empty() {
+            return;
+        }
+        let width = area.width as usize;
+        let height = area.height as usize;
+        let selected = self.room.selected_message.min(messages.len() - 1);
+
+        // the selected message at the bottom, with the ones before it above
+        let mut above = vec![];
+        let mut used = 0;
+        let mut index = selected + 1;
+        while index > 0 && used < height {
+            index -= 1;
+            let lines = self.message_lines(index, width);
+            used += lines.len();
+            above.push(lines);
+        }
+        let mut lines = above.into_iter().rev().flatten().collect::<Vec<Line>>();
+        if lines.len() > height {
+            lines.drain(..lines.len() - height);
+        }
+        // the first messages fit, the space left goes to the ones after the selected one
+        let mut index = selected + 1;
+        while lines.len() < height && index < messages.len() {
+            lines.extend(self.message_lines(index, width));
+            index += 1;
+        }
+        lines.truncate(height);
+
+        for (row, line) in lines.iter().enumerate() {
+            let area = Rect {
+                y: area.y + row as u16,
+                height: 1,
+                ..area
+            };
+            line.render(area, buf);
+        }
+    }
+}
+
+impl MessageList<'_, '_> {
+    /// The lines of a message wrapped to `width`. The first of a group starts with a border and
+    /// the sender, the last ends with a border.
+    fn message_lines(&self, index: usize, width: usize) -> Vec<Line<'static>> {
+        let messages = &self.room.messages;
+        let message = &messages[index];
+        let first = index == 0 || !grouped(&messages[index - 1], message);
+        let last = messages
+            .get(index + 1)
+            .is_none_or(|next| !grouped(message, next));
+        let mine = self.me == Some(message.sender_id.as_str());
+
+        let mut lines = message_content(message, first);
+        let mut style = Style::new().cyan();
+        if mine {
+            let date = lines.len() - 1;
+            style = match self.room.delivery(message) {
+                Some(Delivery::Pending) => {
+                    lines[date] = Line::from("sending...").italic();
+                    Style::new().dim()
+                }
+                Some(Delivery::Queued) => {
+                    lines[date] = Line::from("queued, sent once back online").italic();
+                    Style::new().dim()
+                }
+                Some(Delivery::Failed(err)) => {
+                    lines[date] = Line::from(format!("failed: {} (^t retry)", err)).italic();
+                    Style::new().red()
+                }
+                None => Style::new(),
+            };
+        }
+
+        let date_width = lines.last().map_or(0, Line::width);
+        let mut lines = lines
+            .into_iter()
+            .flat_map(|line| wrap(line, width))
+            .collect::<Vec<Line>>();
+        if first {
+            let content_width = lines.iter().map(Line::width).max().unwrap_or(0);
+            lines.insert(0, Line::from(message_border(content_width)));
+        }
+        if last {
+            lines.push(Line::from(message_border(date_width.min(width))));
+        }
+        lines
+            .into_iter()
+            .map(|line| {
+                let line = line.patch_style(style);
+                if mine {
+                    line.right_aligned()
+                } else {
+                    line
+                }
+            })
+            .collect()
+    }
+}
+
+fn grouped(previous: &Message, message: &Message) -> bool {
+    previous.sender_id == message.sender_id
+        && message.create_date - previous.create_date < GROUP_SECONDS
+}
+
+/// The content followed by the date, after the sender for the first message of a group.
+fn message_content(message: &Message, with_sender: bool) -> Vec<Line<'static>> {
+    let blocks = parse(&message.content);
+    // a code block starts on its own line
+    let inline = matches!(blocks.first(), Some(MarkupBlock::Line(_)));
+    let mut content = markup::lines(blocks).into_iter();
+    let mut lines = vec![];
+    if with_sender {
+        let mut sender = vec![Span::from(message.sender_name.clone()).bold()];
+        if message.is_bot {
+            sender.push(Span::from(" "));
+            sender.push(Span::from(BOT_BADGE).black().on_cyan());
+        }
+        sender.push(Span::from(" : "));
+        if inline {
+            sender.extend(content.next().map(|line| line.spans).unwrap_or_default());
+        }
+        lines.push(Line::from(sender));
+    }
+    lines.extend(content);
+    lines.push(Line::from(pretty_date(message.create_date)).italic());
+    lines
+}
+
+fn message_border(size: usize) -> String {
+    "-".repeat(size)
+}
+
+/// Splits the line at spaces so that each part is at most `width` columns wide, words wider than
+/// that are split anywhere. Widths are the ones terminals use, e.g. 2 for CJK characters.
+pub fn wrap(line: Line<'static>, width: usize) -> Vec<Line<'static>> {
+    if width == 0 || line.width() <= width {
+        return vec![line];
+    }
+    let mut wrapped = Wrapped {
+        lines: vec![],
+        spans: vec![],
+        used: 0,
+        template: Line::default().style(line.style),
+    };
+    if let Some(alignment) = line.alignment {
+        wrapped.template = wrapped.template.alignment(alignment);
+    }
+
+    for span in line.spans {
+        for word in span.content.split_inclusive(' ') {
+            let word_width = word.trim_end().width();
+            if wrapped.used + word_width > width && wrapped.used > 0 {
+                wrapped.break_line();
+            }
+            if word_width <= width {
+                wrapped.push(word, span.style, word.width());
+                continue;
+            }
+            for (i, ch) in word.char_indices() {
+                let ch_width = ch.width().unwrap_or(0);
+                if wrapped.used + ch_width > width && wrapped.used > 0 {
+                    wrapped.break_line();
+                }
+                wrapped.push(&word[i..i + ch.len_utf8()], span.style, ch_width);
+            }
+        }
+    }
+    if !wrapped.spans.is_empty() {
+        wrapped.break_line();
+    }
+    wrapped.lines
+}
+
+struct Wrapped {
+    lines: Vec<Line<'static>>,
+    spans: Vec<Span<'static>>,
+    /// Columns taken on the line being filled.
+    used: usize,
+    /// Style and alignment of the line being wrapped.
+    template: Line<'static>,
+}
+
+impl Wrapped {
+    fn push(&mut self, text: &str, style: Style, width: usize) {
+        match self.spans.last_mut() {
+            Some(span) if span.style == style => span.content.to_mut().push_str(text),
+            _ => self.spans.push(Span::styled(text.to_string(), style)),
+        }
+        self.used += width;
+    }
+
+    fn break_line(&mut self) {
+        let mut spans = std::mem::take(&mut self.spans);
+        // the space a line was broken at isn't shown
+        if let Some(span) = spans.last_mut() {
+            let trimmed = span.content.trim_end_matches(' ').len();
+            span.content.to_mut().truncate(trimmed);
+        }
+        let mut line = self.template.clone();
+        line.spans = spans;
+        self.lines.push(line);
+        self.used = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use ratatui::style::{Style, Stylize};
+
+    use crate::message_list::*;
+    use crate::state::Textfield;
+
+    fn texts(lines: &[Line]) -> Vec<String> {
+        lines.iter().map(|line| line.to_string()).collect()
+    }
+
+    #[test]
+    fn test_wrap() {
+        let line = Line::from(vec![
+            Span::from("hello "),
+            Span::from("big").bold(),
+            Span::from(" world"),
+        ]);
+        let lines = wrap(line, 9);
+        assert_eq!(texts(&lines), vec!["hello big", "world"]);
+        assert_eq!(lines[0].spans[1].style, Style::new().bold());
+
+        assert_eq!(
+            texts(&wrap(Line::from("abcdefghij k"), 4)),
+            vec!["abcd", "efgh", "ij k"]
+        );
+        // 2 columns each
+        assert_eq!(
+            texts(&wrap(Line::from("你好世界 早上好"), 5)),
+            vec!["你好", "世界", "早上", "好"]
+        );
+        assert_eq!(texts(&wrap(Line::from("short"), 0)), vec!["short"]);
+    }
+
+    #[test]
+    fn test_visible_window() {
+        let messages = (0..100_000)
+            .map(|i| Message {
+                id: i.to_string(),
+                content: format!("message {}", i),
+                sender_id: (i / 2).to_string(),
+                room_id: "room".to_string(),
+                create_date: i,
+                sender_name: format!("user{}", i / 2),
+                client_id: None,
+                is_bot: false,
+            })
+            .collect::<Vec<Message>>();
+        let mut room = CurrentRoomState {
+            messages,
+            message_field: Textfield::new("message"),
+            name: "room".to_string(),
+            id: "room".to_string(),
+            topic: String::new(),
+            archived: false,
+            members: Vec::new(),
+            selected_message: 99_999,
+            admin_menu: None,
+            typing: HashMap::new(),
+            typing_sent_at: None,
+            deliveries: HashMap::new(),
+            commands: Vec::new(),
+            popup: None,
+        };
+        let area = Rect::new(0, 0, 30, 8);
+        let rows = |room: &CurrentRoomState| {
+            let mut buf = Buffer::empty(area);
+            MessageList { room, me: None }.render(area, &mut buf);
+            (0..area.height)
+                .map(|y| {
+                    let row = (0..area.width)
+                        .map(|x| buf[(x, y)].symbol())
+                        .collect::<String>();
+                    row.trim_end().to_string()
+                })
+                .collect::<Vec<String>>()
+        };
+
+        // the newest at the bottom, grouped with the previous message of the sender
+        let newest = rows(&room);
+        assert_eq!(newest[2], "-".repeat(25));
+        assert_eq!(newest[3], "user49999 : message 99998");
+        assert_eq!(newest[5], "message 99999");
+        assert_eq!(newest[7], "-".repeat(11));
+
+        room.selected_message = 0;
+        let oldest = rows(&room);
+        assert_eq!(oldest[0], "-".repeat(17));
+        assert_eq!(oldest[1], "user0 : message 0");
+    }
+}
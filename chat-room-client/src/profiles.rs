@@ -0,0 +1,125 @@
+//! Saved accounts, one per server and user name, and the one in use. Their tokens are kept
+//! apart, see `token`.
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::data_files;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Profile {
+    pub server: String,
+    pub username: String,
+}
+
+impl Profile {
+    /// Unique per account, e.g. `john@http://127.0.0.1:8000`.
+    pub fn name(&self) -> String {
+        format!("{}@{}", self.username, self.server)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Profiles {
+    /// Name of the profile in use.
+    pub active: Option<String>,
+    pub profiles: Vec<Profile>,
+}
+
+fn file() -> PathBuf {
+    data_files::config_path().join("profiles.json")
+}
+
+impl Profiles {
+    /// The saved profiles, none when the file is missing or unreadable.
+    pub fn load() -> Self {
+        Self::load_from(&file())
+    }
+
+    pub fn save(&self) -> anyhow::Result<()> {
+        self.save_to(&file())
+    }
+
+    fn load_from(path: &Path) -> Self {
+        fs::read_to_string(path)
+            .ok()
+            .and_then(|profiles| serde_json::from_str(&profiles).ok())
+            .unwrap_or_default()
+    }
+
+    fn save_to(&self, path: &Path) -> anyhow::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    pub fn active(&self) -> Option<&Profile> {
+        let active = self.active.as_ref()?;
+        self.profiles.iter().find(|p| &p.name() == active)
+    }
+
+    /// Saves the profile unless it already is.
+    pub fn add(&mut self, profile: Profile) {
+        if !self.profiles.iter().any(|p| p.name() == profile.name()) {
+            self.profiles.push(profile);
+        }
+    }
+
+    /// Makes the profile the one in use, saving it first if it's new.
+    pub fn activate(&mut self, profile: Profile) {
+        self.active = Some(profile.name());
+        self.add(profile);
+    }
+
+    /// The other profiles, starting after the active one and wrapping around.
+    pub fn after_active(&self) -> impl Iterator<Item = &Profile> {
+        let index = self
+            .active
+            .as_ref()
+            .and_then(|active| self.profiles.iter().position(|p| &p.name() == active));
+        let skip = index.map_or(0, |index| index + 1);
+        let take = self.profiles.len() - usize::from(index.is_some());
+        self.profiles.iter().cycle().skip(skip).take(take)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::profiles::*;
+
+    fn profile(username: &str, server: &str) -> Profile {
+        Profile {
+            server: server.to_string(),
+            username: username.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_activate_and_switch() {
+        let path = std::env::temp_dir()
+            .join(uuid::Uuid::new_v4().to_string())
+            .join("profiles.json");
+        assert!(Profiles::load_from(&path).active().is_none());
+
+        let mut profiles = Profiles::default();
+        profiles.activate(profile("john", "http://a"));
+        profiles.activate(profile("jane", "http://a"));
+        profiles.activate(profile("john", "http://b"));
+        profiles.activate(profile("jane", "http://a"));
+        profiles.save_to(&path).unwrap();
+
+        let profiles = Profiles::load_from(&path);
+        assert_eq!(profiles.profiles.len(), 3);
+        assert_eq!(profiles.active(), Some(&profile("jane", "http://a")));
+        let others = profiles
+            .after_active()
+            .map(Profile::name)
+            .collect::<Vec<_>>();
+        assert_eq!(others, vec!["john@http://b", "john@http://a"]);
+
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+    }
+}
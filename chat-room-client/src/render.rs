@@ -1,6 +1,11 @@
 use crate::asciiart;
-use crate::state::{App, AuthenticatedState, CreateRoomState, SignedOutState, State, Textfield};
-use qu_chat_models::Message;
+use crate::commands::BUILTINS;
+use crate::message_list::MessageList;
+use crate::state::{
+    AdminMenuAction, AdminMenuState, App, AuthenticatedState, Connection, CreateRoomState,
+    CurrentRoomState, Popup, SignedOutState, State, Textfield,
+};
+use qu_chat_models::markup::plain_text;
 use ratatui::crossterm::style::style;
 use ratatui::layout::{Constraint, Flex, Layout};
 use ratatui::prelude::{Buffer, Rect};
@@ -12,9 +17,10 @@ use ratatui::text::{Line, Span, Text};
 use ratatui::widgets::ListItem;
 use ratatui::widgets::{
     Block, BorderType, Borders, Clear, HighlightSpacing, List, ListState, Padding, Paragraph,
-    Scrollbar, ScrollbarState, StatefulWidget, Widget,
+    Scrollbar, ScrollbarState, StatefulWidget, Widget, Wrap,
 };
 use ratatui::DefaultTerminal;
+use std::time::Instant;
 
 pub fn draw(app: &App, terminal: &mut DefaultTerminal) -> anyhow::Result<()> {
     terminal.draw(|frame| frame.render_widget(app, frame.area()))?;
@@ -36,13 +42,16 @@ impl Widget for &App {
 
         let inner = block.inner(area);
 
-        block
+        let mut block = block
             .border_type(BorderType::Rounded)
             .border_style(Style::default().fg(Color::White))
             .style(Style::default().bg(Color::Black))
             .title_bottom(spans)
-            .title_alignment(ratatui::layout::Alignment::Right)
-            .render(area, buf);
+            .title_alignment(ratatui::layout::Alignment::Right);
+        if let State::Authenticated(ref state) = self.state {
+            block = block.title_bottom(connection_status(&state.connection).left_aligned());
+        }
+        block.render(area, buf);
 
         self.state.render(inner, buf);
 
@@ -71,6 +80,32 @@ impl Widget for &App {
     }
 }
 
+fn connection_status(connection: &Connection) -> Line<'static> {
+    let retry_in = |retry_at: &Instant| {
+        retry_at
+            .saturating_duration_since(Instant::now())
+            .as_secs_f32()
+            .ceil()
+    };
+    let (status, color) = match connection {
+        Connection::Connected => ("Connected".to_string(), Color::Green),
+        Connection::Reconnecting { attempt: 0, .. } => ("Connecting".to_string(), Color::Yellow),
+        Connection::Reconnecting { retry_at, .. } => (
+            format!("Reconnecting in {}s", retry_in(retry_at)),
+            Color::Yellow,
+        ),
+        Connection::Offline { retry_at } => (
+            format!("Offline, retrying in {}s", retry_in(retry_at)),
+            Color::Red,
+        ),
+    };
+    Line::from(vec![
+        Span::raw(" "),
+        Span::raw("●").fg(color),
+        Span::raw(format!(" {} ", status)),
+    ])
+}
+
 impl<'r> Widget for &State<'r> {
     fn render(self, area: Rect, buf: &mut Buffer)
     where
@@ -88,14 +123,20 @@ impl<'r> Widget for &SignedOutState<'r> {
     where
         Self: Sized,
     {
+        let accounts_height = match self.accounts.len() {
+            0 => 0,
+            len => len as u16 + 2,
+        };
         let layout = Layout::vertical([
             Constraint::Fill(1),
+            Constraint::Length(accounts_height),
             Constraint::Length(3),
             Constraint::Length(3),
             Constraint::Length(3),
         ]);
 
-        let [welcome_area, server_field_area, username_are, password_area] = layout.areas(area);
+        let [welcome_area, accounts_area, server_field_area, username_are, password_area] =
+            layout.areas(area);
 
         self.server_field.render(server_field_area, buf);
         self.username_field.render(username_are, buf);
@@ -103,6 +144,27 @@ impl<'r> Widget for &SignedOutState<'r> {
 
         let content = Text::raw(asciiart::WELCOME).style(Style::new());
         Paragraph::new(content).centered().render(welcome_area, buf);
+
+        if !self.accounts.is_empty() {
+            let items = self
+                .accounts
+                .iter()
+                .map(|(profile, token)| match token {
+                    Some(_) => ListItem::new(format!("{} (signed in)", profile.name())),
+                    None => ListItem::new(profile.name()).dim(),
+                })
+                .collect::<Vec<_>>();
+            let mut list_state = ListState::default().with_selected(self.selected_account);
+            StatefulWidget::render(
+                List::new(items)
+                    .block(Block::bordered().title("Accounts"))
+                    .highlight_symbol("> ")
+                    .highlight_spacing(HighlightSpacing::Always),
+                accounts_area,
+                buf,
+                &mut list_state,
+            );
+        }
     }
 }
 
@@ -133,20 +195,33 @@ trait Instructable<'r> {
 impl<'r> Instructable<'r> for State<'r> {
     fn instructions(&self) -> Vec<Instructions<'static>> {
         match self {
-            State::SignedOut(_) => vec![
-                Instructions {
-                    name: "Register",
-                    key: "^r",
-                },
-                Instructions {
-                    name: "Signin",
-                    key: "^s",
-                },
-                Instructions {
-                    name: "Change text field",
-                    key: "TAB",
-                },
-            ],
+            State::SignedOut(s) => {
+                let mut instructions = vec![
+                    Instructions {
+                        name: "Register",
+                        key: "^r",
+                    },
+                    Instructions {
+                        name: "Signin",
+                        key: "^s",
+                    },
+                    Instructions {
+                        name: "Change text field",
+                        key: "TAB",
+                    },
+                ];
+                if !s.accounts.is_empty() {
+                    instructions.push(Instructions {
+                        name: "Accounts",
+                        key: "↑↓",
+                    });
+                    instructions.push(Instructions {
+                        name: "Open account",
+                        key: "Enter",
+                    });
+                }
+                instructions
+            }
             State::Authenticated(s) => {
                 if s.current_room.is_some() {
                     vec![
@@ -166,6 +241,18 @@ impl<'r> Instructable<'r> for State<'r> {
                             name: "Create Room",
                             key: "^r",
                         },
+                        Instructions {
+                            name: "Moderate",
+                            key: "^a",
+                        },
+                        Instructions {
+                            name: "Retry",
+                            key: "^t",
+                        },
+                        Instructions {
+                            name: "Commands",
+                            key: "/help",
+                        },
                     ]
                 } else {
                     vec![
@@ -185,6 +272,10 @@ impl<'r> Instructable<'r> for State<'r> {
                             name: "Create Room",
                             key: "^r",
                         },
+                        Instructions {
+                            name: "Switch account",
+                            key: "^p",
+                        },
                     ]
                 }
             }
@@ -207,6 +298,34 @@ impl<'r> Instructable<'r> for CreateRoomState<'r> {
     }
 }
 
+impl<'r> Instructable<'r> for AdminMenuState<'r> {
+    fn instructions(&self) -> Vec<Instructions<'static>> {
+        vec![
+            Instructions {
+                name: "Select",
+                key: "↑↓",
+            },
+            Instructions {
+                name: "Apply",
+                key: "Enter",
+            },
+            Instructions {
+                name: "Cancel",
+                key: "ESC",
+            },
+        ]
+    }
+}
+
+impl Instructable<'_> for Popup {
+    fn instructions(&self) -> Vec<Instructions<'static>> {
+        vec![Instructions {
+            name: "Close",
+            key: "ESC",
+        }]
+    }
+}
+
 impl<'r> Widget for &Textfield<'r> {
     fn render(self, area: Rect, buf: &mut Buffer)
     where
@@ -240,6 +359,8 @@ impl<'r> Widget for &AuthenticatedState<'r> {
         self.render_menu(menu, buf);
         self.render_main(main, buf);
         self.render_create_room(area, buf);
+        self.render_admin_menu(area, buf);
+        self.render_popup(area, buf);
     }
 }
 
@@ -320,6 +441,132 @@ impl<'r> AuthenticatedState<'r> {
         }
     }
 
+    fn render_admin_menu(&self, area: Rect, buf: &mut Buffer) {
+        let Some(menu) = self
+            .current_room
+            .as_ref()
+            .and_then(|r| r.admin_menu.as_ref())
+        else {
+            return;
+        };
+
+        let block = Block::bordered()
+            .border_type(BorderType::Plain)
+            .title(format!("Moderate {}", menu.message.sender_name))
+            .title_bottom(
+                menu.instructions()
+                    .iter()
+                    .flat_map(|i| i.spans())
+                    .collect::<Vec<Span>>(),
+            );
+        let area = center(
+            area,
+            Constraint::Percentage(50),
+            Constraint::Length(AdminMenuAction::ALL.len() as u16 + 5), // actions + reason field + borders
+        );
+        Clear.render(area, buf);
+        let inner = block.inner(area);
+        block.render(area, buf);
+
+        let layout = Layout::vertical([Constraint::Fill(1), Constraint::Length(3)]);
+        let [actions_area, reason_area] = layout.areas(inner);
+
+        let items: Vec<ListItem> = AdminMenuAction::ALL
+            .iter()
+            .map(|action| ListItem::from(action.title()))
+            .collect();
+        let list = List::new(items)
+            .highlight_symbol("> ")
+            .highlight_style(Style::new().bold())
+            .highlight_spacing(HighlightSpacing::Always);
+        let mut list_state = ListState::default().with_selected(Some(menu.selected_action));
+        StatefulWidget::render(&list, actions_area, buf, &mut list_state);
+
+        menu.reason_field.render(reason_area, buf);
+    }
+
+    fn render_popup(&self, area: Rect, buf: &mut Buffer) {
+        let Some(room) = self.current_room.as_ref() else {
+            return;
+        };
+        let Some(ref popup) = room.popup else {
+            return;
+        };
+
+        let command = |usage: String, description: String| {
+            Line::from(vec![
+                Span::from(format!("{:<16}", usage)).bold(),
+                Span::from(description),
+            ])
+        };
+        let (title, lines) = match popup {
+            Popup::Help => {
+                let mut lines = BUILTINS
+                    .iter()
+                    .map(|b| command(b.usage.to_string(), b.description.to_string()))
+                    .collect::<Vec<Line>>();
+                if !room.commands.is_empty() {
+                    lines.push(Line::from(""));
+                    lines.push(Line::from("Answered by bots").italic());
+                }
+                for c in &room.commands {
+                    let description = format!("{} ({})", c.description, c.bot_name);
+                    lines.push(command(format!("/{}", c.name), description));
+                }
+                lines.push(Line::from(""));
+                lines.push(
+                    Line::from(
+                        "TAB completes commands, rooms and members, // starts a message with /",
+                    )
+                    .dim(),
+                );
+                ("Commands".to_string(), lines)
+            }
+            Popup::Search { query, results } => {
+                let lines = match results {
+                    None => vec![Line::from("Searching...").italic()],
+                    Some(messages) if messages.is_empty() => {
+                        vec![Line::from("No messages found").italic()]
+                    }
+                    Some(messages) => messages
+                        .iter()
+                        .map(|m| {
+                            Line::from(vec![
+                                Span::from(pretty_date(m.create_date)).dim(),
+                                Span::from(" "),
+                                Span::from(m.sender_name.clone()).bold(),
+                                Span::from(" : "),
+                                Span::from(plain_text(&m.content).replace('\n', " ")),
+                            ])
+                        })
+                        .collect(),
+                };
+                (format!("Messages with \"{}\"", query), lines)
+            }
+        };
+
+        let block = Block::bordered()
+            .border_type(BorderType::Plain)
+            .title(title)
+            .title_bottom(
+                popup
+                    .instructions()
+                    .iter()
+                    .flat_map(|i| i.spans())
+                    .collect::<Vec<Span>>(),
+            );
+        let area = center(
+            area,
+            Constraint::Percentage(70),
+            Constraint::Length(lines.len() as u16 + 2), // lines + borders
+        );
+        Clear.render(area, buf);
+        Paragraph::new(lines)
+            .block(block)
+            .wrap(Wrap { trim: false })
+            .render(area, buf);
+    }
+
     fn render_main(&self, area: Rect, buf: &mut Buffer) {
         let main_block = Block::bordered()
             .border_set(border::THICK)
@@ -333,36 +580,22 @@ impl<'r> AuthenticatedState<'r> {
         //current room
 
         if let Some(ref room) = self.current_room {
-            let layout = Layout::vertical([Constraint::Fill(1), Constraint::Length(3)]);
-            let [messages_are, textfield_area] = layout.areas(main_inner);
+            let layout = Layout::vertical([
+                Constraint::Length(2),
+                Constraint::Fill(1),
+                Constraint::Length(3),
+            ]);
+            let [header_area, messages_are, textfield_area] = layout.areas(main_inner);
 
-            let items: Vec<ListItem> = room
-                .messages
-                .iter()
-                .enumerate()
-                .map(|(_, message)| {
-                    if let Some(ref profile) = self.profile {
-                        if message.sender_id == profile.id {
-                            let text =
-                                Text::from(message_content(message).to_vec()).right_aligned();
-                            return ListItem::new(text);
-                        }
-                    }
+            room_header(room, header_area, buf);
 
-                    let text = Text::from(message_content(message).to_vec()).cyan();
-                    return ListItem::new(text);
-                })
-                .collect();
+            let me = self.profile.as_ref().map(|p| p.id.as_str());
+            MessageList { room, me }.render(messages_are, buf);
 
             let mut scrollbar_state = ScrollbarState::default()
                 .content_length(room.messages.len())
                 .position(room.selected_message);
 
-            let mut list_state = ListState::default().with_selected(Some(room.selected_message));
-            let list = List::new(items);
-
-            StatefulWidget::render(list, messages_are, buf, &mut list_state);
-
             let scroll = Scrollbar::default();
 
             StatefulWidget::render(scroll, area, buf, &mut scrollbar_state);
@@ -374,23 +607,33 @@ impl<'r> AuthenticatedState<'r> {
             text.render(main_inner, buf);
         }
 
-        fn message_content(message: &Message) -> [Line; 4] {
-            [
-                Line::from(message_border(
-                    message.content.len() + message.sender_name.len() + 3,
-                )),
-                Line::from(vec![
-                    Span::from(message.sender_name.clone()).bold(),
-                    Span::from(" : "),
-                    Span::from(message.content.clone()),
-                ]),
-                Line::from(format!("{}", pretty_date(message.create_date))).italic(),
-                Line::from(message_border(pretty_date(message.create_date).len())),
-            ]
-        }
+        fn room_header(room: &CurrentRoomState, area: Rect, buf: &mut Buffer) {
+            let block = Block::new().borders(Borders::BOTTOM);
+            let inner = block.inner(area);
+            block.render(area, buf);
+
+            let members = format!("{} members", room.members.len());
+            let layout = Layout::horizontal([
+                Constraint::Fill(1),
+                Constraint::Length(members.len() as u16),
+            ]);
+            let [title_area, members_area] = layout.areas(inner);
 
-        fn message_border(size: usize) -> String {
-            (0..size).into_iter().map(|_| '-').collect::<String>()
+            let mut spans = vec![Span::from(room.name.clone()).bold()];
+            if room.archived {
+                spans.push(Span::from(" (archived)").italic());
+            }
+            if !room.topic.is_empty() {
+                spans.push(Span::from(" | "));
+                spans.push(Span::from(room.topic.clone()).italic());
+            }
+            let typing = room.typing_names();
+            if !typing.is_empty() {
+                spans.push(Span::from(" | "));
+                spans.push(Span::from(format!("{} typing...", typing.join(", "))).dim());
+            }
+            Paragraph::new(Line::from(spans)).render(title_area, buf);
+            Paragraph::new(Line::from(members).right_aligned()).render(members_area, buf);
         }
     }
 }
@@ -407,7 +650,7 @@ fn center(area: Rect, horizontal: Constraint, vertical: Constraint) -> Rect {
     area
 }
 
-fn pretty_date(timestamp: i64) -> String {
+pub fn pretty_date(timestamp: i64) -> String {
     let utc: chrono::DateTime<chrono::Utc> =
         chrono::DateTime::from_timestamp(timestamp, 0).unwrap();
     let local: chrono::DateTime<chrono::Local> = utc.with_timezone(&chrono::Local);
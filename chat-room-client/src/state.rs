@@ -1,8 +1,12 @@
 use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use qu_chat_models::{Message, Room, RoomState, UserProfile};
+use qu_chat_models::{Message, Room, RoomCommand, RoomEvent, RoomState, UserProfile};
 
+use crate::cache::Cache;
 use crate::chat_room_client;
+use crate::profiles::Profile;
 
 pub struct App {
     pub state: State<'static>,
@@ -36,9 +40,44 @@ pub struct AuthenticatedState<'r> {
     pub current_room: Option<CurrentRoomState<'r>>,
     pub create_room: Option<CreateRoomState<'r>>,
     pub profile: Option<UserProfile>,
+    /// Set once events are followed over WebSocket, SSE and plain requests are used otherwise.
+    pub socket: Option<Arc<chat_room_client::Socket>>,
+    /// Tasks following the events of every room, aborted on sign out.
+    pub join_handles: Vec<tokio::task::JoinHandle<()>>,
+    pub cache: Option<Arc<Cache>>,
+    /// Whether the outbox is being sent, and whether it has to be sent again after that.
+    pub flushing_outbox: bool,
+    pub flush_outbox_again: bool,
+    pub connection: Connection,
     //settings
 }
 
+impl<'r> AuthenticatedState<'r> {
+    pub fn abort_join_handles(&self) {
+        for join_handle in &self.join_handles {
+            join_handle.abort();
+        }
+    }
+
+    /// Puts the room on top of the list unless it's already listed, keeping the same room
+    /// selected. Returns whether it was added.
+    pub fn add_room(&mut self, room: Room) -> bool {
+        if self.rooms.iter().any(|r| r.id == room.id) {
+            return false;
+        }
+        self.rooms.insert(0, room);
+        self.selected_room_index = self.selected_room_index.map(|index| index + 1);
+        self.current_room_index = self.current_room_index.map(|index| index + 1);
+        true
+    }
+}
+
+impl<'r> Drop for AuthenticatedState<'r> {
+    fn drop(&mut self) {
+        self.abort_join_handles();
+    }
+}
+
 impl<'r> AuthenticatedState<'r> {
     pub fn new(token: String) -> Self {
         AuthenticatedState {
@@ -50,6 +89,15 @@ impl<'r> AuthenticatedState<'r> {
             profile: None,
             create_room: None,
             rooms_states: HashMap::new(),
+            socket: None,
+            join_handles: Vec::new(),
+            cache: None,
+            flushing_outbox: false,
+            flush_outbox_again: false,
+            connection: Connection::Reconnecting {
+                attempt: 0,
+                retry_at: Instant::now(),
+            },
         }
     }
 }
@@ -59,21 +107,165 @@ pub struct CurrentRoomState<'r> {
     pub message_field: Textfield<'r>,
     pub name: String,
     pub id: String,
+    pub topic: String,
+    pub archived: bool,
+    pub members: Vec<UserProfile>,
     pub selected_message: usize,
-    pub join_handles: Vec<tokio::task::JoinHandle<()>>,
+    pub admin_menu: Option<AdminMenuState<'r>>,
+    /// Who is typing by user id, with when they were last seen typing.
+    pub typing: HashMap<String, (String, Instant)>,
+    pub typing_sent_at: Option<Instant>,
+    /// Own messages the server hasn't confirmed yet, by their client id.
+    pub deliveries: HashMap<String, Delivery>,
+    /// Commands bots registered for the room, run from the message field.
+    pub commands: Vec<RoomCommand>,
+    pub popup: Option<Popup>,
 }
 
+/// Shown over the room until closed with Esc.
+pub enum Popup {
+    Help,
+    /// Results are none while the search is made.
+    Search {
+        query: String,
+        results: Option<Vec<Message>>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Delivery {
+    Pending,
+    /// Kept in the outbox until the server can be reached.
+    Queued,
+    Failed(String),
+}
+
+/// State of the stream of room events, kept up by the connection supervisor.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Connection {
+    Connected,
+    /// Waiting to try again, `attempt` counts the tries that failed since the last connection.
+    Reconnecting {
+        attempt: u32,
+        retry_at: Instant,
+    },
+    /// Tries kept failing, they go on at the longest delay.
+    Offline {
+        retry_at: Instant,
+    },
+}
+
+/// How long someone is shown as typing after their last typing event.
+pub const TYPING_TIMEOUT: Duration = Duration::from_secs(5);
+
 impl<'r> CurrentRoomState<'r> {
-    pub fn abort_join_handles(&self) {
-        for join_handle in &self.join_handles {
-            join_handle.abort();
+    pub fn delivery(&self, message: &Message) -> Option<&Delivery> {
+        message
+            .client_id
+            .as_ref()
+            .and_then(|client_id| self.deliveries.get(client_id))
+    }
+
+    /// Adds messages from the cache or the server, those already shown are replaced, either by
+    /// id or by client id for the ones still being sent.
+    pub fn merge_messages(&mut self, messages: Vec<Message>) {
+        // where the shown messages are by id and by client id, rooms can have many loaded
+        let mut ids = HashMap::new();
+        let mut client_ids = HashMap::new();
+        for (index, message) in self.messages.iter().enumerate() {
+            if !message.id.is_empty() {
+                ids.insert(message.id.clone(), index);
+            }
+            if let Some(ref client_id) = message.client_id {
+                client_ids.insert(client_id.clone(), index);
+            }
         }
+        for message in messages {
+            let existing = ids.get(&message.id).or_else(|| {
+                message
+                    .client_id
+                    .as_ref()
+                    .and_then(|client_id| client_ids.get(client_id))
+            });
+            match existing.copied() {
+                Some(index) => {
+                    if !message.id.is_empty() {
+                        if let Some(ref client_id) = message.client_id {
+                            self.deliveries.remove(client_id);
+                        }
+                        ids.insert(message.id.clone(), index);
+                        self.messages[index] = message;
+                    }
+                }
+                None => {
+                    let index = self.messages.len();
+                    if !message.id.is_empty() {
+                        ids.insert(message.id.clone(), index);
+                    }
+                    if let Some(ref client_id) = message.client_id {
+                        client_ids.insert(client_id.clone(), index);
+                    }
+                    self.messages.push(message);
+                }
+            }
+        }
+        self.messages.sort_by_key(|m| m.create_date);
+    }
+
+    pub fn typing_names(&self) -> Vec<&str> {
+        self.typing
+            .values()
+            .filter(|(_, at)| at.elapsed() < TYPING_TIMEOUT)
+            .map(|(name, _)| name.as_str())
+            .collect()
     }
 }
 
-impl<'r> Drop for CurrentRoomState<'r> {
-    fn drop(&mut self) {
-        self.abort_join_handles();
+/// Moderation actions on the selected message and its sender.
+pub struct AdminMenuState<'r> {
+    pub message: Message,
+    pub selected_action: usize,
+    pub reason_field: Textfield<'r>,
+}
+
+impl AdminMenuState<'_> {
+    pub fn new(message: Message) -> Self {
+        AdminMenuState {
+            message,
+            selected_action: 0,
+            reason_field: Textfield::new_focused("reason", true),
+        }
+    }
+
+    pub fn action(&self) -> AdminMenuAction {
+        AdminMenuAction::ALL[self.selected_action]
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum AdminMenuAction {
+    RemoveMessage,
+    KickSender,
+    BanSender,
+    MuteSender(u32),
+}
+
+impl AdminMenuAction {
+    pub const ALL: [AdminMenuAction; 5] = [
+        AdminMenuAction::RemoveMessage,
+        AdminMenuAction::KickSender,
+        AdminMenuAction::BanSender,
+        AdminMenuAction::MuteSender(10),
+        AdminMenuAction::MuteSender(60),
+    ];
+
+    pub fn title(&self) -> String {
+        match self {
+            AdminMenuAction::RemoveMessage => String::from("Remove message"),
+            AdminMenuAction::KickSender => String::from("Kick sender"),
+            AdminMenuAction::BanSender => String::from("Ban sender"),
+            AdminMenuAction::MuteSender(minutes) => format!("Mute sender for {} minutes", minutes),
+        }
     }
 }
 
@@ -93,6 +285,9 @@ pub struct SignedOutState<'r> {
     pub server_field: Textfield<'r>,
     pub username_field: Textfield<'r>,
     pub password_field: Textfield<'r>,
+    /// Saved accounts, with their token when still signed in.
+    pub accounts: Vec<(Profile, Option<String>)>,
+    pub selected_account: Option<usize>,
 }
 
 #[derive(Debug)]
@@ -110,6 +305,10 @@ pub enum SignedOutAction {
     Text(TextFieldAction),
     NextFocus,
     UnFocus,
+    SelectNextAccount,
+    SelectPrevAccount,
+    /// Signs in to the selected account if it still has a token.
+    OpenAccount,
     SignedIn(anyhow::Result<String>),
     Registered(anyhow::Result<String>),
 }
@@ -121,6 +320,28 @@ pub enum AuthenticatedAction {
     EnterRoom,
     ExitRoom,
     SendMessage,
+    MessageSent {
+        client_id: String,
+        result: chat_room_client::Result<()>,
+    },
+    RetryMessage,
+    OpenCache,
+    CacheOpened(Arc<Cache>),
+    CachedRoomsLoaded(Vec<Room>),
+    LoadCachedMessages,
+    CachedMessagesLoaded {
+        room_id: String,
+        messages: Vec<Message>,
+        queued: Vec<Message>,
+    },
+    FlushOutbox,
+    OutboxFlushed,
+    SocketConnected(Arc<chat_room_client::Socket>),
+    ConnectionChanged(Connection),
+    /// Opens the next saved account still signed in, or the welcome screen to add one.
+    SwitchAccount,
+    /// Catches up on what was missed while the connection was down.
+    Resync,
 
     ChatText(TextFieldAction),
     CreateRoomName(TextFieldAction),
@@ -129,9 +350,10 @@ pub enum AuthenticatedAction {
     LoadRooms,
     RoomsLoaded(chat_room_client::Result<Vec<Room>>),
     PrevMessagesLoaded(chat_room_client::Result<Vec<Message>>),
-    SyncMessages,
     LoadPrevMessages,
-    MessageChanged(Message),
+    RoomEventReceived(RoomEvent),
+    LoadRoomMembers,
+    RoomMembersLoaded(chat_room_client::Result<Vec<UserProfile>>),
     Signout,
     SignoutCompleted(chat_room_client::Result<()>),
     ScrollMessagesUp,
@@ -140,10 +362,45 @@ pub enum AuthenticatedAction {
     CreateNewRoom,
     CancelNewRoom,
     NewRoomIsCreated(chat_room_client::Result<Room>),
-    ListenForRoomStateChanges,
+    ListenForEvents,
     UpdateRoomStates,
     RoomStatesUpdated(chat_room_client::Result<HashMap<String, RoomState>>),
     MakeRoomAsSeen,
+    OpenAdminMenu,
+    CloseAdminMenu,
+    SelectNextAdminAction,
+    SelectPrevAdminAction,
+    AdminReason(TextFieldAction),
+    ApplyAdminAction,
+    AdminActionApplied(chat_room_client::Result<()>),
+
+    /// Completes the command typed in the message field, see `commands::complete`.
+    CompleteCommand,
+    LoadRoomCommands,
+    RoomCommandsLoaded(chat_room_client::Result<Vec<RoomCommand>>),
+    /// Opens the room of that name, or the only one whose name starts so.
+    JoinRoom(String),
+    LeaveRoom,
+    RoomLeft(chat_room_client::Result<()>),
+    Rename(String),
+    Renamed(chat_room_client::Result<UserProfile>),
+    SetTopic(String),
+    TopicSet(chat_room_client::Result<()>),
+    /// Sends what the user is doing, as `/me` does.
+    SendEmote(String),
+    SearchMessages(String),
+    MessagesFound(chat_room_client::Result<Vec<Message>>),
+    /// Opens the direct room of the user and a member of the current room, which only the two of
+    /// them can read, creating it if needed.
+    OpenDirectRoom(String),
+    DirectRoomOpened(chat_room_client::Result<Room>),
+    InvokeCommand {
+        name: String,
+        args: String,
+    },
+    CommandInvoked(chat_room_client::Result<()>),
+    ShowHelp,
+    ClosePopup,
 }
 
 impl<'r> SignedOutState<'r> {
@@ -152,6 +409,8 @@ impl<'r> SignedOutState<'r> {
             server_field: Textfield::new_focused("server", true),
             username_field: Textfield::new("username"),
             password_field: Textfield::new("password"),
+            accounts: Vec::new(),
+            selected_account: None,
         }
     }
 }
@@ -214,6 +473,8 @@ impl<'r> AuthenticatedState<'r> {
                 name: name.to_string(),
                 create_date: 0,
                 creator_id: "".to_string(),
+                topic: "".to_string(),
+                archived: false,
             })
             .collect::<Vec<Room>>();
 
@@ -227,6 +488,8 @@ impl<'r> AuthenticatedState<'r> {
                 room_id: name.to_string(),
                 create_date: 0,
                 sender_name: name.to_string(),
+                client_id: None,
+                is_bot: false,
             })
             .collect::<Vec<Message>>();
 
@@ -241,13 +504,30 @@ impl<'r> AuthenticatedState<'r> {
                 message_field: Textfield::new("message"),
                 name: room_name,
                 id: room_id,
+                topic: String::new(),
+                archived: false,
+                members: Vec::new(),
                 selected_message: 0,
-                join_handles: Vec::new(),
+                admin_menu: None,
+                typing: HashMap::new(),
+                typing_sent_at: None,
+                deliveries: HashMap::new(),
+                commands: Vec::new(),
+                popup: None,
             }),
             selected_room_index: Some(0),
             profile: None,
             create_room: None,
             rooms_states: HashMap::new(),
+            socket: None,
+            join_handles: Vec::new(),
+            cache: None,
+            flushing_outbox: false,
+            flush_outbox_again: false,
+            connection: Connection::Reconnecting {
+                attempt: 0,
+                retry_at: Instant::now(),
+            },
         }
     }
 }
@@ -1,14 +1,23 @@
 use anyhow::bail;
 use chat_room_client::Client;
-use qu_chat_models::{Message, RoomState};
-use std::{collections::HashMap, ops::Deref, sync::Arc};
-use tokio::sync::mpsc::UnboundedSender;
+use qu_chat_models::{ClientRequest, Message, Room, RoomEvent, RoomState, UserProfile};
+use std::{
+    collections::HashMap,
+    ops::Deref,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::sync::mpsc::{self, UnboundedSender};
 
 use crate::{
+    cache::Cache,
     chat_room_client::{self},
+    commands::{self, Command, Input},
+    profiles::{Profile, Profiles},
     state::{
-        Action, App, AuthenticatedAction, AuthenticatedState, CreateRoomState, CurrentRoomState,
-        SignedOutAction, SignedOutState, State, Textfield,
+        Action, AdminMenuAction, AdminMenuState, App, AuthenticatedAction, AuthenticatedState,
+        Connection, CreateRoomState, CurrentRoomState, Delivery, Popup, SignedOutAction,
+        SignedOutState, State, TextFieldAction, Textfield, TYPING_TIMEOUT,
     },
     token,
 };
@@ -22,7 +31,14 @@ pub fn handle_action(
     if app.loading {
         return;
     }
-    if app.error.is_some() {
+    // the connection keeps being tracked behind an error
+    let connection = matches!(
+        action,
+        Action::Authenticated(
+            AuthenticatedAction::ConnectionChanged(_) | AuthenticatedAction::SocketConnected(_)
+        )
+    );
+    if app.error.is_some() && !connection {
         if matches!(action, Action::CloseError) {
             app.error = None;
         }
@@ -41,16 +57,20 @@ pub fn handle_action(
         Action::SigneOut(signed_out_action) => match signed_out_action {
             SignedOutAction::Signin => {
                 if let State::SignedOut(ref state) = app.state {
-                    chat_room_client::wite_base_url(&state.server_field.text);
+                    client.set_base_url(&state.server_field.text);
                     let username = state.username_field.text.clone();
                     let password = state.password_field.text.clone();
+                    let profile = Profile {
+                        server: state.server_field.text.clone(),
+                        username: username.clone(),
+                    };
                     let tx = sideeffect.clone();
 
                     tokio::spawn(async move {
                         let res = chat_room_client::sigin(client.deref(), &username, &password)
                             .await
                             .map_err(|e| anyhow::Error::from(e))
-                            .and_then(|res| match token::persist_token(&res.token) {
+                            .and_then(|res| match save_account(&profile, &res.token) {
                                 Ok(_) => Ok(res.token),
                                 Err(err) => bail!(err.to_string()),
                             });
@@ -61,16 +81,20 @@ pub fn handle_action(
             }
             SignedOutAction::Register => {
                 if let State::SignedOut(ref state) = app.state {
-                    chat_room_client::wite_base_url(&state.server_field.text);
+                    client.set_base_url(&state.server_field.text);
                     let username = state.username_field.text.clone();
                     let password = state.password_field.text.clone();
+                    let profile = Profile {
+                        server: state.server_field.text.clone(),
+                        username: username.clone(),
+                    };
                     let tx = sideeffect.clone();
 
                     tokio::spawn(async move {
                         let res = chat_room_client::register(client.deref(), &username, &password)
                             .await
                             .map_err(|e| anyhow::Error::from(e))
-                            .and_then(|res| match token::persist_token(&res.token) {
+                            .and_then(|res| match save_account(&profile, &res.token) {
                                 Ok(_) => Ok(res.token),
                                 Err(err) => bail!(err.to_string()),
                             });
@@ -79,6 +103,30 @@ pub fn handle_action(
                     });
                 }
             }
+            SignedOutAction::SelectNextAccount | SignedOutAction::SelectPrevAccount => {
+                if let State::SignedOut(ref mut state) = app.state {
+                    let last = state.accounts.len().saturating_sub(1);
+                    let index = match (signed_out_action, state.selected_account) {
+                        (_, None) => 0,
+                        (SignedOutAction::SelectNextAccount, Some(index)) => (index + 1).min(last),
+                        (_, Some(index)) => index.saturating_sub(1),
+                    };
+                    if let Some((profile, _)) = state.accounts.get(index) {
+                        state.server_field.text = profile.server.clone();
+                        state.username_field.text = profile.username.clone();
+                        state.password_field.text.clear();
+                        state.selected_account = Some(index);
+                    }
+                }
+            }
+            SignedOutAction::OpenAccount => {
+                if let State::SignedOut(ref state) = app.state {
+                    let account = state.selected_account.and_then(|i| state.accounts.get(i));
+                    if let Some((profile, Some(token))) = account.cloned() {
+                        open_account(app, profile, token, client, sideeffect);
+                    }
+                }
+            }
             SignedOutAction::Text(text_action) => {
                 if let State::SignedOut(ref mut state) = app.state {
                     if state.server_field.focused {
@@ -173,26 +221,39 @@ pub fn handle_action(
                     }
                     AuthenticatedAction::EnterRoom => {
                         if let Some(index) = state.selected_room_index {
-                            if let Some(ref prev_room) = state.current_room {
-                                prev_room.abort_join_handles();
-                            }
                             let mut message_field = Textfield::new("message");
                             message_field.focused = true;
                             message_field.hint = "<Enter> Send";
                             state.current_room_index = Some(index);
                             state.current_room = Some(CurrentRoomState {
                                 messages: Vec::new(),
-                                join_handles: Vec::new(),
                                 message_field,
                                 name: state.rooms[index].name.to_string(),
                                 id: state.rooms[index].id.to_string(),
+                                topic: state.rooms[index].topic.to_string(),
+                                archived: state.rooms[index].archived,
+                                members: Vec::new(),
                                 selected_message: 0,
+                                admin_menu: None,
+                                typing: HashMap::new(),
+                                typing_sent_at: None,
+                                deliveries: HashMap::new(),
+                                commands: Vec::new(),
+                                popup: None,
                             });
+                            sideeffect
+                                .send(Action::Authenticated(
+                                    AuthenticatedAction::LoadCachedMessages,
+                                ))
+                                .unwrap();
                             sideeffect
                                 .send(Action::Authenticated(AuthenticatedAction::LoadPrevMessages))
                                 .unwrap();
                             sideeffect
-                                .send(Action::Authenticated(AuthenticatedAction::SyncMessages))
+                                .send(Action::Authenticated(AuthenticatedAction::LoadRoomMembers))
+                                .unwrap();
+                            sideeffect
+                                .send(Action::Authenticated(AuthenticatedAction::LoadRoomCommands))
                                 .unwrap();
                             sideeffect
                                 .send(Action::Authenticated(AuthenticatedAction::MakeRoomAsSeen))
@@ -206,26 +267,227 @@ pub fn handle_action(
                     AuthenticatedAction::ChatText(text_field_action) => {
                         if let Some(ref mut room) = state.current_room {
                             room.message_field.handle_action(&text_field_action);
+
+                            // typing is only shared over WebSocket, at most twice per timeout
+                            let typed =
+                                matches!(text_field_action, TextFieldAction::TextChanged(_));
+                            let due = room
+                                .typing_sent_at
+                                .is_none_or(|at| at.elapsed() > TYPING_TIMEOUT / 2);
+                            if let (true, true, Some(socket)) = (typed, due, state.socket.clone()) {
+                                room.typing_sent_at = Some(Instant::now());
+                                let room_id = room.id.clone();
+                                tokio::spawn(async move {
+                                    let _ = socket.request(ClientRequest::Typing { room_id }).await;
+                                });
+                            }
                         }
                     }
                     AuthenticatedAction::SendMessage => {
                         if let Some(ref mut room) = state.current_room {
-                            let message = room.message_field.text.clone();
-                            let token = state.token.clone();
-                            let room_id = room.id.clone();
-                            if message.is_empty() {
+                            let text = room.message_field.text.clone();
+                            if text.is_empty() {
                                 return;
                             }
+                            // a mistyped command stays in the field to be fixed
+                            let text = match commands::parse(&text, &room.commands) {
+                                Ok(Input::Message(text)) => text,
+                                Ok(Input::Command(command)) => {
+                                    room.message_field.text.clear();
+                                    sideeffect
+                                        .send(Action::Authenticated(command_action(command)))
+                                        .unwrap();
+                                    return;
+                                }
+                                Err(err) => {
+                                    app.error = Some(err);
+                                    return;
+                                }
+                            };
                             room.message_field.text.clear();
-                            tokio::spawn(async move {
-                                let _ = chat_room_client::send_message(
-                                    client.deref(),
-                                    &token,
-                                    &message,
-                                    &room_id,
+                            room.typing_sent_at = None;
+                            send_text(state, text, client, sideeffect);
+                        }
+                    }
+                    AuthenticatedAction::MessageSent { client_id, result } => {
+                        if let Some(ref mut room) = state.current_room {
+                            match result {
+                                // the echo may still be on its way, the message is sent either way
+                                Ok(_) => {
+                                    room.deliveries.remove(&client_id);
+                                }
+                                Err(err) => {
+                                    if let Some(delivery) = room.deliveries.get_mut(&client_id) {
+                                        // queued messages go out with the next flush of the outbox
+                                        *delivery = if err.is_transient() && state.cache.is_some() {
+                                            Delivery::Queued
+                                        } else {
+                                            Delivery::Failed(err.to_string())
+                                        };
+                                    }
+                                }
+                            }
+                        }
+                    }
+                    AuthenticatedAction::RetryMessage => {
+                        if let Some(ref mut room) = state.current_room {
+                            let unsent = room.messages.get(room.selected_message).filter(|m| {
+                                matches!(
+                                    room.delivery(m),
+                                    Some(Delivery::Failed(_) | Delivery::Queued)
                                 )
-                                .await;
                             });
+                            if let Some(message) = unsent.cloned() {
+                                if let Some(client_id) = message.client_id.clone() {
+                                    room.deliveries.insert(client_id, Delivery::Pending);
+                                }
+                                queue(
+                                    client,
+                                    state.token.clone(),
+                                    state.socket.clone(),
+                                    state.cache.clone(),
+                                    message,
+                                    sideeffect,
+                                );
+                            }
+                        }
+                    }
+                    AuthenticatedAction::SocketConnected(socket) => {
+                        let room_ids = state.rooms.iter().map(|r| r.id.clone()).collect();
+                        subscribe(socket.clone(), room_ids);
+                        state.socket = Some(socket);
+                        sideeffect
+                            .send(Action::Authenticated(AuthenticatedAction::FlushOutbox))
+                            .unwrap();
+                    }
+                    AuthenticatedAction::ConnectionChanged(connection) => {
+                        if connection != Connection::Connected {
+                            state.socket = None;
+                        }
+                        state.connection = connection;
+                    }
+                    AuthenticatedAction::Resync => {
+                        sideeffect
+                            .send(Action::Authenticated(AuthenticatedAction::LoadRooms))
+                            .unwrap();
+                        if state.current_room.is_some() {
+                            sideeffect
+                                .send(Action::Authenticated(AuthenticatedAction::LoadPrevMessages))
+                                .unwrap();
+                        }
+                    }
+                    AuthenticatedAction::OpenCache => {
+                        let base_url = client.base_url();
+                        let user_id = token::user_id(&state.token);
+                        if let (Some(base_url), Some(user_id)) = (base_url, user_id) {
+                            let sideeffect = sideeffect.clone();
+                            tokio::spawn(async move {
+                                // without a cache everything still works, only online
+                                if let Ok(cache) = Cache::open(&base_url, &user_id).await {
+                                    sideeffect
+                                        .send(Action::Authenticated(
+                                            AuthenticatedAction::CacheOpened(Arc::new(cache)),
+                                        ))
+                                        .unwrap();
+                                }
+                            });
+                        }
+                    }
+                    AuthenticatedAction::CacheOpened(cache) => {
+                        state.cache = Some(cache.clone());
+                        let rooms = state.rooms.clone();
+                        let sideeffect = sideeffect.clone();
+                        tokio::spawn(async move {
+                            if rooms.is_empty() {
+                                if let Ok(rooms) = cache.rooms().await {
+                                    sideeffect
+                                        .send(Action::Authenticated(
+                                            AuthenticatedAction::CachedRoomsLoaded(rooms),
+                                        ))
+                                        .unwrap();
+                                }
+                            } else {
+                                let _ = cache.save_rooms(&rooms).await;
+                            }
+                            sideeffect
+                                .send(Action::Authenticated(AuthenticatedAction::FlushOutbox))
+                                .unwrap();
+                        });
+                    }
+                    AuthenticatedAction::CachedRoomsLoaded(rooms) => {
+                        // the server's list wins when it came first
+                        if state.rooms.is_empty() {
+                            state.rooms = rooms;
+                        }
+                    }
+                    AuthenticatedAction::LoadCachedMessages => {
+                        if let (Some(room), Some(cache)) = (&state.current_room, &state.cache) {
+                            let cache = cache.clone();
+                            let room_id = room.id.clone();
+                            let sideeffect = sideeffect.clone();
+                            tokio::spawn(async move {
+                                let messages = cache.messages(&room_id).await.unwrap_or_default();
+                                let queued = cache
+                                    .outbox()
+                                    .await
+                                    .unwrap_or_default()
+                                    .into_iter()
+                                    .filter(|m| m.room_id == room_id)
+                                    .collect();
+                                sideeffect
+                                    .send(Action::Authenticated(
+                                        AuthenticatedAction::CachedMessagesLoaded {
+                                            room_id,
+                                            messages,
+                                            queued,
+                                        },
+                                    ))
+                                    .unwrap();
+                            });
+                        }
+                    }
+                    AuthenticatedAction::CachedMessagesLoaded {
+                        room_id,
+                        messages,
+                        queued,
+                    } => {
+                        if let Some(ref mut room) = state.current_room {
+                            if room.id == room_id {
+                                for message in &queued {
+                                    if let Some(ref client_id) = message.client_id {
+                                        room.deliveries
+                                            .entry(client_id.clone())
+                                            .or_insert(Delivery::Queued);
+                                    }
+                                }
+                                room.merge_messages(messages);
+                                room.merge_messages(queued);
+                            }
+                        }
+                    }
+                    AuthenticatedAction::FlushOutbox => {
+                        if state.flushing_outbox {
+                            state.flush_outbox_again = true;
+                            return;
+                        }
+                        if let Some(cache) = state.cache.clone() {
+                            state.flushing_outbox = true;
+                            flush_outbox(
+                                client,
+                                state.token.clone(),
+                                state.socket.clone(),
+                                cache,
+                                sideeffect,
+                            );
+                        }
+                    }
+                    AuthenticatedAction::OutboxFlushed => {
+                        state.flushing_outbox = false;
+                        if state.flush_outbox_again {
+                            state.flush_outbox_again = false;
+                            sideeffect
+                                .send(Action::Authenticated(AuthenticatedAction::FlushOutbox))
+                                .unwrap();
                         }
                     }
                     AuthenticatedAction::LoadRooms => {
@@ -238,79 +500,643 @@ pub fn handle_action(
                     }
                     AuthenticatedAction::RoomsLoaded(res) => match res {
                         Ok(rooms) => {
+                            if let Some(cache) = state.cache.clone() {
+                                let rooms = rooms.clone();
+                                tokio::spawn(async move {
+                                    let _ = cache.save_rooms(&rooms).await;
+                                });
+                                sideeffect
+                                    .send(Action::Authenticated(AuthenticatedAction::FlushOutbox))
+                                    .unwrap();
+                            }
+                            // reloaded after a reconnection, rooms created meanwhile are followed too
+                            if let Some(socket) = state.socket.clone() {
+                                subscribe(socket, rooms.iter().map(|r| r.id.clone()).collect());
+                            }
                             state.rooms = rooms;
                             sideeffect
                                 .send(Action::Authenticated(AuthenticatedAction::UpdateRoomStates))
                                 .unwrap();
 
-                            sideeffect
-                                .send(Action::Authenticated(
-                                    AuthenticatedAction::ListenForRoomStateChanges,
-                                ))
-                                .unwrap();
+                            if state.join_handles.is_empty() {
+                                sideeffect
+                                    .send(Action::Authenticated(
+                                        AuthenticatedAction::ListenForEvents,
+                                    ))
+                                    .unwrap();
+                            }
+                        }
+                        Err(error) => {
+                            // the supervisor loads them again once the server is back
+                            if state.join_handles.is_empty() {
+                                sideeffect
+                                    .send(Action::Authenticated(
+                                        AuthenticatedAction::ListenForEvents,
+                                    ))
+                                    .unwrap();
+                            }
+                            // offline with cached rooms, they are shown as they were
+                            if !error.is_transient() || state.rooms.is_empty() {
+                                app.error = Some(error.to_string());
+                            }
                         }
-                        Err(error) => app.error = Some(error.to_string()),
                     },
 
-                    AuthenticatedAction::SyncMessages => {
+                    AuthenticatedAction::LoadPrevMessages => {
                         if let Some(ref mut room) = state.current_room {
-                            let (tx, mut rx) = tokio::sync::mpsc::channel::<Message>(10);
                             let token = state.token.clone();
-                            let sideeffect: UnboundedSender<Action> = sideeffect.clone();
+                            let sideeffect = sideeffect.clone();
                             let room_id = room.id.clone();
-                            let join_handle1 = tokio::spawn(async move {
-                                chat_room_client::messages(
+                            tokio::spawn(async move {
+                                let result = chat_room_client::last_messages(
                                     client.deref(),
-                                    &token,
                                     &room_id,
-                                    tx.clone(),
+                                    &token,
                                 )
                                 .await;
+                                sideeffect.send(Action::Authenticated(
+                                    AuthenticatedAction::PrevMessagesLoaded(result),
+                                ))
                             });
-                            let join_handle2 = tokio::spawn(async move {
-                                loop {
-                                    if let Some(msg) = rx.recv().await {
-                                        sideeffect
-                                            .send(Action::Authenticated(
-                                                AuthenticatedAction::MessageChanged(msg),
-                                            ))
-                                            .unwrap();
+                        }
+                    }
+                    AuthenticatedAction::PrevMessagesLoaded(result) => {
+                        if let Some(ref mut room) = state.current_room {
+                            match result {
+                                Ok(prev_messages) => {
+                                    if let Some(cache) = state.cache.clone() {
+                                        let room_id = room.id.clone();
+                                        let messages = prev_messages.clone();
+                                        tokio::spawn(async move {
+                                            let _ = cache.save_messages(&room_id, &messages).await;
+                                        });
+                                    }
+                                    room.merge_messages(prev_messages);
+                                }
+                                Err(err) if err.is_transient() && state.cache.is_some() => {}
+                                Err(err) => app.error = Some(err.to_string()),
+                            }
+                        }
+                    }
+                    AuthenticatedAction::RoomEventReceived(event) => {
+                        let cached = state.cache.clone().map(|cache| (cache, event.clone()));
+                        match event {
+                            RoomEvent::Message(msg) => match state.current_room {
+                                Some(ref mut room) if room.id == msg.room_id => {
+                                    room.typing.remove(&msg.sender_id);
+                                    let sent = msg.client_id.as_ref().and_then(|client_id| {
+                                        room.messages
+                                            .iter()
+                                            .position(|m| m.client_id.as_ref() == Some(client_id))
+                                    });
+                                    match sent {
+                                        Some(index) => {
+                                            if let Some(ref client_id) = msg.client_id {
+                                                room.deliveries.remove(client_id);
+                                            }
+                                            room.messages[index] = msg;
+                                        }
+                                        None => room.messages.push(msg),
+                                    }
+                                    sideeffect
+                                        .send(Action::Authenticated(
+                                            AuthenticatedAction::MakeRoomAsSeen,
+                                        ))
+                                        .unwrap();
+                                }
+                                _ => {
+                                    let room_id = msg.room_id;
+                                    state
+                                        .rooms_states
+                                        .entry(room_id.clone())
+                                        .or_insert(RoomState {
+                                            room_id,
+                                            has_unread: true,
+                                        })
+                                        .has_unread = true;
+                                }
+                            },
+                            RoomEvent::Renamed { room_id, name } => {
+                                if let Some(room) = state.rooms.iter_mut().find(|r| r.id == room_id)
+                                {
+                                    room.name = name.clone();
+                                }
+                                if let Some(ref mut room) = state.current_room {
+                                    if room.id == room_id {
+                                        room.name = name;
                                     }
                                 }
+                            }
+                            RoomEvent::TopicChanged { room_id, topic } => {
+                                if let Some(room) = state.rooms.iter_mut().find(|r| r.id == room_id)
+                                {
+                                    room.topic = topic.clone();
+                                }
+                                if let Some(ref mut room) = state.current_room {
+                                    if room.id == room_id {
+                                        room.topic = topic;
+                                    }
+                                }
+                            }
+                            RoomEvent::Archived { room_id } => {
+                                if let Some(room) = state.rooms.iter_mut().find(|r| r.id == room_id)
+                                {
+                                    room.archived = true;
+                                }
+                                if let Some(ref mut room) = state.current_room {
+                                    if room.id == room_id {
+                                        room.archived = true;
+                                    }
+                                }
+                            }
+                            RoomEvent::MessageRemoved {
+                                room_id,
+                                message_id,
+                            } => {
+                                if let Some(ref mut room) = state.current_room {
+                                    if room.id == room_id {
+                                        room.messages.retain(|m| m.id != message_id);
+                                    }
+                                }
+                            }
+                            RoomEvent::UserKicked { room_id, user_id }
+                            | RoomEvent::UserBanned { room_id, user_id } => {
+                                let is_me = state.profile.as_ref().is_some_and(|p| p.id == user_id);
+                                if let Some(ref mut room) = state.current_room {
+                                    if room.id == room_id {
+                                        room.members.retain(|m| m.id != user_id);
+                                        if is_me {
+                                            app.error = Some(format!(
+                                                "You have been removed from {}",
+                                                room.name
+                                            ));
+                                            state.current_room = None;
+                                            state.current_room_index = None;
+                                        }
+                                    }
+                                }
+                            }
+                            RoomEvent::UserMuted {
+                                room_id, user_id, ..
+                            } => {
+                                let is_me = state.profile.as_ref().is_some_and(|p| p.id == user_id);
+                                if let Some(ref room) = state.current_room {
+                                    if room.id == room_id && is_me {
+                                        app.error =
+                                            Some(format!("You have been muted in {}", room.name));
+                                    }
+                                }
+                            }
+                            RoomEvent::UserUnmuted { room_id, user_id } => {
+                                let is_me = state.profile.as_ref().is_some_and(|p| p.id == user_id);
+                                if let Some(ref room) = state.current_room {
+                                    let notice = format!("You have been muted in {}", room.name);
+                                    if room.id == room_id
+                                        && is_me
+                                        && app.error.as_ref() == Some(&notice)
+                                    {
+                                        app.error = None;
+                                    }
+                                }
+                            }
+                            RoomEvent::Typing {
+                                room_id,
+                                user_id,
+                                user_name,
+                            } => {
+                                let is_me = state.profile.as_ref().is_some_and(|p| p.id == user_id);
+                                if let Some(ref mut room) = state.current_room {
+                                    if room.id == room_id && !is_me {
+                                        room.typing.insert(user_id, (user_name, Instant::now()));
+                                    }
+                                }
+                            }
+                            RoomEvent::MemberJoined {
+                                room_id,
+                                user_id,
+                                user_name,
+                            } => {
+                                if let Some(ref mut room) = state.current_room {
+                                    if room.id == room_id
+                                        && !room.members.iter().any(|m| m.id == user_id)
+                                    {
+                                        room.members.push(UserProfile {
+                                            id: user_id,
+                                            name: user_name,
+                                        });
+                                    }
+                                }
+                            }
+                            // for the bot answering the command
+                            RoomEvent::CommandInvoked { .. } => {}
+                            // the room is listed again once reloaded
+                            RoomEvent::UserUnbanned { .. } => {}
+                            RoomEvent::Created { room } => {
+                                let room_id = room.id.clone();
+                                if state.add_room(room) {
+                                    // the SSE stream carries every room, the socket has to be told
+                                    if let Some(socket) = state.socket.clone() {
+                                        subscribe(socket, vec![room_id]);
+                                    }
+                                }
+                            }
+                            RoomEvent::Deleted { room_id } => {
+                                state.rooms.retain(|r| r.id != room_id);
+                                state.rooms_states.remove(&room_id);
+                                if state.current_room.as_ref().is_some_and(|r| r.id == room_id) {
+                                    state.current_room = None;
+                                }
+                                state.current_room_index =
+                                    state.current_room.as_ref().and_then(|current| {
+                                        state.rooms.iter().position(|r| r.id == current.id)
+                                    });
+                                if state
+                                    .selected_room_index
+                                    .is_some_and(|index| index >= state.rooms.len())
+                                {
+                                    state.selected_room_index = state.rooms.len().checked_sub(1);
+                                }
+                            }
+                        }
+                        if let Some((cache, event)) = cached {
+                            cache_event(cache, event, &state.rooms);
+                        }
+                    }
+                    AuthenticatedAction::OpenAdminMenu => {
+                        if let Some(ref mut room) = state.current_room {
+                            if let Some(message) = room.messages.get(room.selected_message) {
+                                room.admin_menu = Some(AdminMenuState::new(message.clone()));
+                            }
+                        }
+                    }
+                    AuthenticatedAction::CloseAdminMenu => {
+                        if let Some(ref mut room) = state.current_room {
+                            room.admin_menu = None;
+                        }
+                    }
+                    AuthenticatedAction::SelectNextAdminAction => {
+                        if let Some(menu) = state
+                            .current_room
+                            .as_mut()
+                            .and_then(|r| r.admin_menu.as_mut())
+                        {
+                            if menu.selected_action + 1 < AdminMenuAction::ALL.len() {
+                                menu.selected_action += 1;
+                            }
+                        }
+                    }
+                    AuthenticatedAction::SelectPrevAdminAction => {
+                        if let Some(menu) = state
+                            .current_room
+                            .as_mut()
+                            .and_then(|r| r.admin_menu.as_mut())
+                        {
+                            menu.selected_action = menu.selected_action.saturating_sub(1);
+                        }
+                    }
+                    AuthenticatedAction::AdminReason(action) => {
+                        if let Some(menu) = state
+                            .current_room
+                            .as_mut()
+                            .and_then(|r| r.admin_menu.as_mut())
+                        {
+                            menu.reason_field.handle_action(&action);
+                        }
+                    }
+                    AuthenticatedAction::ApplyAdminAction => {
+                        if let Some(ref room) = state.current_room {
+                            if let Some(ref menu) = room.admin_menu {
+                                let token = state.token.clone();
+                                let sideeffect = sideeffect.clone();
+                                let room_id = room.id.clone();
+                                let message = menu.message.clone();
+                                let action = menu.action();
+                                let reason =
+                                    Some(menu.reason_field.text.clone()).filter(|r| !r.is_empty());
+                                tokio::spawn(async move {
+                                    let result = match action {
+                                        AdminMenuAction::RemoveMessage => {
+                                            chat_room_client::remove_message(
+                                                &client,
+                                                &token,
+                                                &room_id,
+                                                &message.id,
+                                                reason.unwrap_or_default(),
+                                            )
+                                            .await
+                                        }
+                                        AdminMenuAction::KickSender => {
+                                            chat_room_client::kick_user(
+                                                &client,
+                                                &token,
+                                                &room_id,
+                                                &message.sender_id,
+                                                reason,
+                                            )
+                                            .await
+                                        }
+                                        AdminMenuAction::BanSender => {
+                                            chat_room_client::ban_user(
+                                                &client,
+                                                &token,
+                                                &room_id,
+                                                &message.sender_id,
+                                                reason,
+                                            )
+                                            .await
+                                        }
+                                        AdminMenuAction::MuteSender(minutes) => {
+                                            chat_room_client::mute_user(
+                                                &client,
+                                                &token,
+                                                &room_id,
+                                                &message.sender_id,
+                                                minutes,
+                                                reason,
+                                            )
+                                            .await
+                                        }
+                                    };
+                                    sideeffect.send(Action::Authenticated(
+                                        AuthenticatedAction::AdminActionApplied(result),
+                                    ))
+                                });
+                            }
+                        }
+                    }
+                    AuthenticatedAction::AdminActionApplied(result) => match result {
+                        Ok(_) => {
+                            if let Some(ref mut room) = state.current_room {
+                                room.admin_menu = None;
+                            }
+                        }
+                        Err(err) => app.error = Some(err.to_string()),
+                    },
+                    AuthenticatedAction::CompleteCommand => {
+                        if let Some(ref mut room) = state.current_room {
+                            let completed = commands::complete(
+                                &room.message_field.text,
+                                &room.commands,
+                                &state.rooms,
+                                &room.members,
+                            );
+                            if let Some(text) = completed {
+                                room.message_field.text = text;
+                            }
+                        }
+                    }
+                    AuthenticatedAction::LoadRoomCommands => {
+                        if let Some(ref room) = state.current_room {
+                            let token = state.token.clone();
+                            let sideeffect = sideeffect.clone();
+                            let room_id = room.id.clone();
+                            tokio::spawn(async move {
+                                let result =
+                                    chat_room_client::room_commands(&client, &token, &room_id)
+                                        .await;
+                                sideeffect.send(Action::Authenticated(
+                                    AuthenticatedAction::RoomCommandsLoaded(result),
+                                ))
+                            });
+                        }
+                    }
+                    AuthenticatedAction::RoomCommandsLoaded(result) => {
+                        // without them only the built in commands can be run
+                        if let (Some(ref mut room), Ok(commands)) =
+                            (&mut state.current_room, result)
+                        {
+                            room.commands = commands;
+                        }
+                    }
+                    AuthenticatedAction::JoinRoom(name) => {
+                        match commands::find_room(&state.rooms, &name) {
+                            Some(index) => {
+                                state.selected_room_index = Some(index);
+                                if state.current_room_index != Some(index) {
+                                    sideeffect
+                                        .send(Action::Authenticated(AuthenticatedAction::EnterRoom))
+                                        .unwrap();
+                                }
+                            }
+                            None => app.error = Some(format!("No room named {}", name)),
+                        }
+                    }
+                    AuthenticatedAction::LeaveRoom => {
+                        if let Some(ref room) = state.current_room {
+                            let token = state.token.clone();
+                            let sideeffect = sideeffect.clone();
+                            let room_id = room.id.clone();
+                            tokio::spawn(async move {
+                                let result =
+                                    chat_room_client::leave_room(&client, &token, &room_id).await;
+                                sideeffect.send(Action::Authenticated(
+                                    AuthenticatedAction::RoomLeft(result),
+                                ))
                             });
-
-                            room.join_handles = vec![join_handle1, join_handle2];
                         }
                     }
-                    AuthenticatedAction::LoadPrevMessages => {
+                    AuthenticatedAction::RoomLeft(result) => match result {
+                        Ok(_) => {
+                            state.current_room_index = None;
+                            state.current_room = None;
+                        }
+                        Err(err) => app.error = Some(err.to_string()),
+                    },
+                    AuthenticatedAction::Rename(name) => {
+                        let token = state.token.clone();
+                        let sideeffect = sideeffect.clone();
+                        tokio::spawn(async move {
+                            let result = chat_room_client::rename(&client, &token, &name).await;
+                            sideeffect
+                                .send(Action::Authenticated(AuthenticatedAction::Renamed(result)))
+                        });
+                    }
+                    AuthenticatedAction::Renamed(result) => match result {
+                        Ok(profile) => {
+                            if let Err(err) = rename_account(&state.token, &profile.name) {
+                                app.error = Some(err.to_string());
+                            }
+                            if let Some(ref mut room) = state.current_room {
+                                for message in room.messages.iter_mut() {
+                                    if message.sender_id == profile.id {
+                                        message.sender_name = profile.name.clone();
+                                    }
+                                }
+                                for member in room.members.iter_mut() {
+                                    if member.id == profile.id {
+                                        member.name = profile.name.clone();
+                                    }
+                                }
+                            }
+                            state.profile = Some(profile);
+                        }
+                        Err(err) => app.error = Some(err.to_string()),
+                    },
+                    AuthenticatedAction::SetTopic(topic) => {
+                        if let Some(ref room) = state.current_room {
+                            let token = state.token.clone();
+                            let sideeffect = sideeffect.clone();
+                            let room_id = room.id.clone();
+                            tokio::spawn(async move {
+                                let result = chat_room_client::set_room_topic(
+                                    &client, &token, &room_id, &topic,
+                                )
+                                .await;
+                                sideeffect.send(Action::Authenticated(
+                                    AuthenticatedAction::TopicSet(result),
+                                ))
+                            });
+                        }
+                    }
+                    // the room gets the new topic with its event
+                    AuthenticatedAction::TopicSet(result) => {
+                        if let Err(err) = result {
+                            app.error = Some(err.to_string());
+                        }
+                    }
+                    AuthenticatedAction::SendEmote(emote) => {
+                        if state.current_room.is_some() {
+                            let name = state
+                                .profile
+                                .as_ref()
+                                .map(|p| p.name.clone())
+                                .unwrap_or_default();
+                            send_text(state, format!("_{} {}_", name, emote), client, sideeffect);
+                        }
+                    }
+                    AuthenticatedAction::SearchMessages(query) => {
                         if let Some(ref mut room) = state.current_room {
+                            room.popup = Some(Popup::Search {
+                                query: query.clone(),
+                                results: None,
+                            });
                             let token = state.token.clone();
                             let sideeffect = sideeffect.clone();
                             let room_id = room.id.clone();
                             tokio::spawn(async move {
-                                let result = chat_room_client::last_messages(
-                                    client.deref(),
-                                    &room_id,
-                                    &token,
+                                let result = chat_room_client::search_messages(
+                                    &client, &token, &room_id, &query,
                                 )
                                 .await;
                                 sideeffect.send(Action::Authenticated(
-                                    AuthenticatedAction::PrevMessagesLoaded(result),
+                                    AuthenticatedAction::MessagesFound(result),
                                 ))
                             });
                         }
                     }
-                    AuthenticatedAction::PrevMessagesLoaded(result) => {
+                    AuthenticatedAction::MessagesFound(result) => {
                         if let Some(ref mut room) = state.current_room {
-                            match result {
-                                Ok(mut prev_messages) => room.messages.append(&mut prev_messages),
-                                Err(err) => app.error = Some(err.to_string()),
+                            match (result, &mut room.popup) {
+                                (Ok(messages), Some(Popup::Search { results, .. })) => {
+                                    *results = Some(messages);
+                                }
+                                // closed before the results came
+                                (Ok(_), _) => (),
+                                (Err(err), _) => {
+                                    room.popup = None;
+                                    app.error = Some(err.to_string());
+                                }
+                            }
+                        }
+                    }
+                    AuthenticatedAction::OpenDirectRoom(name) => {
+                        if let Some(ref room) = state.current_room {
+                            let me = state.profile.as_ref().map(|p| p.id.as_str());
+                            let member = room
+                                .members
+                                .iter()
+                                .find(|m| m.name.to_lowercase() == name.to_lowercase());
+                            match member {
+                                None => {
+                                    app.error =
+                                        Some(format!("No member named {} in {}", name, room.name))
+                                }
+                                Some(member) if Some(member.id.as_str()) == me => {
+                                    app.error = Some("That is you".to_string())
+                                }
+                                Some(member) => {
+                                    let token = state.token.clone();
+                                    let sideeffect = sideeffect.clone();
+                                    let user_id = member.id.clone();
+                                    tokio::spawn(async move {
+                                        let result = chat_room_client::open_direct_room(
+                                            &client, &token, &user_id,
+                                        )
+                                        .await;
+                                        sideeffect.send(Action::Authenticated(
+                                            AuthenticatedAction::DirectRoomOpened(result),
+                                        ))
+                                    });
+                                }
                             }
                         }
                     }
-                    AuthenticatedAction::MessageChanged(msg) => {
+                    AuthenticatedAction::DirectRoomOpened(result) => match result {
+                        // by id, a room of everyone can have the same name
+                        Ok(room) => {
+                            let id = room.id.clone();
+                            state.add_room(room);
+                            let index = state.rooms.iter().position(|r| r.id == id);
+                            state.selected_room_index = index;
+                            if state.current_room_index != index {
+                                sideeffect
+                                    .send(Action::Authenticated(AuthenticatedAction::EnterRoom))
+                                    .unwrap();
+                            }
+                        }
+                        Err(err) => app.error = Some(err.to_string()),
+                    },
+                    AuthenticatedAction::InvokeCommand { name, args } => {
+                        if let Some(ref room) = state.current_room {
+                            let token = state.token.clone();
+                            let sideeffect = sideeffect.clone();
+                            let room_id = room.id.clone();
+                            tokio::spawn(async move {
+                                let result = chat_room_client::invoke_command(
+                                    &client, &token, &room_id, &name, &args,
+                                )
+                                .await;
+                                sideeffect.send(Action::Authenticated(
+                                    AuthenticatedAction::CommandInvoked(result),
+                                ))
+                            });
+                        }
+                    }
+                    AuthenticatedAction::CommandInvoked(result) => {
+                        if let Err(err) = result {
+                            app.error = Some(err.to_string());
+                        }
+                    }
+                    AuthenticatedAction::ShowHelp => {
+                        if let Some(ref mut room) = state.current_room {
+                            room.popup = Some(Popup::Help);
+                        }
+                    }
+                    AuthenticatedAction::ClosePopup => {
+                        if let Some(ref mut room) = state.current_room {
+                            room.popup = None;
+                        }
+                    }
+                    AuthenticatedAction::LoadRoomMembers => {
+                        if let Some(ref room) = state.current_room {
+                            let token = state.token.clone();
+                            let sideeffect = sideeffect.clone();
+                            let room_id = room.id.clone();
+                            tokio::spawn(async move {
+                                let result =
+                                    chat_room_client::room_members(&client, &token, &room_id).await;
+                                sideeffect.send(Action::Authenticated(
+                                    AuthenticatedAction::RoomMembersLoaded(result),
+                                ))
+                            });
+                        }
+                    }
+                    AuthenticatedAction::RoomMembersLoaded(result) => {
                         if let Some(ref mut room) = state.current_room {
-                            room.messages.push(msg);
+                            match result {
+                                Ok(members) => room.members = members,
+                                Err(err) => app.error = Some(err.to_string()),
+                            }
                         }
                     }
                     AuthenticatedAction::LoadUserProfile => {
@@ -341,6 +1167,19 @@ pub fn handle_action(
                                 .unwrap();
                         });
                     }
+                    AuthenticatedAction::SwitchAccount => {
+                        // the current account stays signed in
+                        let profiles = Profiles::load();
+                        let next = profiles
+                            .after_active()
+                            .find_map(|p| token::read_token(p).map(|token| (p.clone(), token)));
+                        match next {
+                            Some((profile, token)) => {
+                                open_account(app, profile, token, client, sideeffect)
+                            }
+                            None => new_welcome(app),
+                        }
+                    }
                     AuthenticatedAction::SignoutCompleted(result) => match result {
                         Ok(_) => new_signout(app),
                         Err(error) => app.error = Some(error.to_string()),
@@ -368,7 +1207,7 @@ pub fn handle_action(
                     }
                     AuthenticatedAction::NewRoomIsCreated(res) => match res {
                         Ok(room) => {
-                            state.rooms.insert(0, room);
+                            state.add_room(room);
                             state.create_room = None;
                         }
                         Err(err) => app.error = Some(err.to_string()),
@@ -383,33 +1222,26 @@ pub fn handle_action(
                             state.create_room = None
                         }
                     }
-                    AuthenticatedAction::ListenForRoomStateChanges => {
-                        let token = state.token.clone();
-                        let ids = state
-                            .rooms
-                            .iter()
-                            .map(|r| r.id.clone())
-                            .collect::<Vec<String>>();
+                    AuthenticatedAction::ListenForEvents => {
+                        state.abort_join_handles();
+                        state.socket = None;
+                        let (tx, mut rx) = tokio::sync::mpsc::channel::<RoomEvent>(10);
+                        let join_handle1 = tokio::spawn(supervise(
+                            client,
+                            state.token.clone(),
+                            tx,
+                            sideeffect.clone(),
+                        ));
                         let sideeffect = sideeffect.clone();
-                        let (tx, mut rx) = tokio::sync::mpsc::channel::<()>(10);
-                        tokio::spawn(async move {
-                            chat_room_client::room_states_events(
-                                &client,
-                                &token,
-                                ids.iter().map(|i| i.as_str()).collect::<Vec<&str>>(),
-                                tx.clone(),
-                            )
-                            .await;
-                        });
-                        tokio::spawn(async move {
-                            while let Some(_) = rx.recv().await {
-                                sideeffect
-                                    .send(Action::Authenticated(
-                                        AuthenticatedAction::UpdateRoomStates,
-                                    ))
-                                    .unwrap();
+                        let join_handle2 = tokio::spawn(async move {
+                            while let Some(event) = rx.recv().await {
+                                let event = AuthenticatedAction::RoomEventReceived(event);
+                                if sideeffect.send(Action::Authenticated(event)).is_err() {
+                                    break;
+                                }
                             }
                         });
+                        state.join_handles = vec![join_handle1, join_handle2];
                     }
 
                     AuthenticatedAction::UpdateRoomStates => {
@@ -478,6 +1310,328 @@ pub fn handle_action(
     }
 }
 
+/// Delay before the first try after a failed one, doubled on every following failure.
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(30);
+/// Failed tries in a row before the connection shows as offline.
+const OFFLINE_AFTER: u32 = 5;
+/// How long a connection has to stay up for its drop to be retried at once. Shorter ones count
+/// as failed tries, so a server closing connections right away isn't hammered.
+const STABLE_AFTER: Duration = Duration::from_secs(30);
+
+/// The delay before the given try, picked at random in the upper half of the backoff so clients
+/// dropped together don't all come back at once.
+fn backoff(attempt: u32) -> Duration {
+    let delay = RECONNECT_DELAY
+        .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+        .min(MAX_RECONNECT_DELAY);
+    delay.mul_f64(0.5 + rand::random::<f64>() / 2.0)
+}
+
+/// Counts the try that just ended, `up` being how long it stayed connected if it did, and
+/// returns what the connection shows until the next try and when to make it.
+fn next_try(attempt: &mut u32, up: Option<Duration>) -> (Connection, Instant) {
+    match up {
+        // dropped after being connected a while, the first try is made at once
+        Some(up) if up >= STABLE_AFTER => {
+            *attempt = 0;
+            let retry_at = Instant::now();
+            (
+                Connection::Reconnecting {
+                    attempt: *attempt,
+                    retry_at,
+                },
+                retry_at,
+            )
+        }
+        _ => {
+            *attempt += 1;
+            let retry_at = Instant::now() + backoff(*attempt);
+            if *attempt >= OFFLINE_AFTER {
+                (Connection::Offline { retry_at }, retry_at)
+            } else {
+                (
+                    Connection::Reconnecting {
+                        attempt: *attempt,
+                        retry_at,
+                    },
+                    retry_at,
+                )
+            }
+        }
+    }
+}
+
+/// Keeps the events of every room coming, over WebSocket or else SSE, and connects again with
+/// a jittered exponential backoff whenever the connection drops or can't be made. Ends once the
+/// token is refused, the client signs out then.
+async fn supervise(
+    client: Arc<Client>,
+    token: String,
+    tx: mpsc::Sender<RoomEvent>,
+    sideeffect: UnboundedSender<Action>,
+) {
+    let notify = |action| sideeffect.send(Action::Authenticated(action)).is_ok();
+    let connected = |resumed: bool| {
+        notify(AuthenticatedAction::ConnectionChanged(
+            Connection::Connected,
+        )) && (!resumed || notify(AuthenticatedAction::Resync))
+    };
+    let mut attempt = 0;
+    let mut resumed = false;
+    loop {
+        let result = match chat_room_client::connect_socket(&client, &token, tx.clone()).await {
+            Ok(socket) => {
+                let socket = Arc::new(socket);
+                if !notify(AuthenticatedAction::SocketConnected(socket.clone()))
+                    || !connected(resumed || attempt > 0)
+                {
+                    return;
+                }
+                let since = Instant::now();
+                socket.closed().await;
+                Ok(since.elapsed())
+            }
+            Err(_) => match chat_room_client::events(&client, &token).await {
+                Ok(events) => {
+                    if !connected(resumed || attempt > 0) {
+                        return;
+                    }
+                    let since = Instant::now();
+                    events.forward(tx.clone()).await;
+                    Ok(since.elapsed())
+                }
+                Err(err) => Err(err),
+            },
+        };
+
+        resumed |= result.is_ok();
+        let up = match result {
+            Ok(up) => Some(up),
+            Err(chat_room_client::Error::Unauthorized) => return,
+            Err(_) => None,
+        };
+        let (connection, retry_at) = next_try(&mut attempt, up);
+        if !notify(AuthenticatedAction::ConnectionChanged(connection)) {
+            return;
+        }
+        tokio::time::sleep_until(retry_at.into()).await;
+    }
+}
+
+fn subscribe(socket: Arc<chat_room_client::Socket>, room_ids: Vec<String>) {
+    tokio::spawn(async move {
+        // refused rooms don't stop the others from being followed
+        let _ = socket.request(ClientRequest::Subscribe { room_ids }).await;
+    });
+}
+
+/// Sends the message with its client id, so sending it again can't duplicate it.
+async fn send(
+    client: &Client,
+    token: &str,
+    socket: Option<&chat_room_client::Socket>,
+    message: &Message,
+) -> chat_room_client::Result<()> {
+    let client_id = message.client_id.clone().unwrap_or_default();
+    match socket {
+        Some(socket) => {
+            socket
+                .request(ClientRequest::Send {
+                    room_id: message.room_id.clone(),
+                    text: message.content.clone(),
+                    client_id: Some(client_id),
+                })
+                .await
+        }
+        None => {
+            chat_room_client::send_message(
+                client,
+                token,
+                &message.content,
+                &message.room_id,
+                &client_id,
+            )
+            .await
+        }
+    }
+}
+
+fn deliver(
+    client: Arc<Client>,
+    token: String,
+    socket: Option<Arc<chat_room_client::Socket>>,
+    message: Message,
+    sideeffect: &UnboundedSender<Action>,
+) {
+    let sideeffect = sideeffect.clone();
+    tokio::spawn(async move {
+        let result = send(&client, &token, socket.as_deref(), &message).await;
+        sideeffect.send(Action::Authenticated(AuthenticatedAction::MessageSent {
+            client_id: message.client_id.unwrap_or_default(),
+            result,
+        }))
+    });
+}
+
+/// The action a command typed in the message field runs.
+fn command_action(command: Command) -> AuthenticatedAction {
+    match command {
+        Command::Join(name) => AuthenticatedAction::JoinRoom(name),
+        Command::Leave => AuthenticatedAction::LeaveRoom,
+        Command::Nick(name) => AuthenticatedAction::Rename(name),
+        Command::Topic(topic) => AuthenticatedAction::SetTopic(topic),
+        Command::Me(emote) => AuthenticatedAction::SendEmote(emote),
+        Command::Search(query) => AuthenticatedAction::SearchMessages(query),
+        Command::Dm(name) => AuthenticatedAction::OpenDirectRoom(name),
+        Command::Help => AuthenticatedAction::ShowHelp,
+        Command::Bot { name, args } => AuthenticatedAction::InvokeCommand { name, args },
+    }
+}
+
+/// Sends a message to the current room.
+fn send_text(
+    state: &mut AuthenticatedState,
+    text: String,
+    client: Arc<Client>,
+    sideeffect: &UnboundedSender<Action>,
+) {
+    let Some(ref mut room) = state.current_room else {
+        return;
+    };
+    // shown at once, replaced by the server echo carrying the same client id
+    let client_id = uuid::Uuid::new_v4().to_string();
+    let message = Message {
+        id: String::new(),
+        content: text,
+        sender_id: state
+            .profile
+            .as_ref()
+            .map(|p| p.id.clone())
+            .unwrap_or_default(),
+        room_id: room.id.clone(),
+        create_date: chrono::Utc::now().timestamp(),
+        sender_name: state
+            .profile
+            .as_ref()
+            .map(|p| p.name.clone())
+            .unwrap_or_default(),
+        client_id: Some(client_id.clone()),
+        is_bot: false,
+    };
+    room.deliveries.insert(client_id, Delivery::Pending);
+    room.messages.push(message.clone());
+    room.selected_message = room.messages.len() - 1;
+    queue(
+        client,
+        state.token.clone(),
+        state.socket.clone(),
+        state.cache.clone(),
+        message,
+        sideeffect,
+    );
+}
+
+/// Saves the account in use under its new name, its token moves along.
+fn rename_account(token: &str, username: &str) -> anyhow::Result<()> {
+    let mut profiles = Profiles::load();
+    let Some(old) = profiles.active().cloned() else {
+        return Ok(());
+    };
+    let new = Profile {
+        server: old.server.clone(),
+        username: username.to_string(),
+    };
+    token::persist_token(&new, token)?;
+    token::delete_token(&old)?;
+    profiles.profiles.retain(|p| p != &old);
+    profiles.activate(new);
+    profiles.save()
+}
+
+/// Puts the message in the outbox and flushes it, so it survives being offline or a restart.
+/// Without a cache it is sent right away.
+fn queue(
+    client: Arc<Client>,
+    token: String,
+    socket: Option<Arc<chat_room_client::Socket>>,
+    cache: Option<Arc<Cache>>,
+    message: Message,
+    sideeffect: &UnboundedSender<Action>,
+) {
+    let Some(cache) = cache else {
+        return deliver(client, token, socket, message, sideeffect);
+    };
+    let sideeffect = sideeffect.clone();
+    tokio::spawn(async move {
+        match cache.queue(&message).await {
+            Ok(_) => sideeffect.send(Action::Authenticated(AuthenticatedAction::FlushOutbox)),
+            Err(_) => {
+                deliver(client, token, socket, message, &sideeffect);
+                Ok(())
+            }
+        }
+    });
+}
+
+/// Sends the outbox in the order it was written, stopping at the first message that can't be
+/// sent yet. Messages the server refused leave the outbox and show as failed.
+fn flush_outbox(
+    client: Arc<Client>,
+    token: String,
+    socket: Option<Arc<chat_room_client::Socket>>,
+    cache: Arc<Cache>,
+    sideeffect: &UnboundedSender<Action>,
+) {
+    let sideeffect = sideeffect.clone();
+    tokio::spawn(async move {
+        for message in cache.outbox().await.unwrap_or_default() {
+            let result = send(&client, &token, socket.as_deref(), &message).await;
+            let client_id = message.client_id.unwrap_or_default();
+            let offline = result.as_ref().is_err_and(|err| err.is_transient());
+            if !offline {
+                let _ = cache.dequeue(&client_id).await;
+            }
+            sideeffect
+                .send(Action::Authenticated(AuthenticatedAction::MessageSent {
+                    client_id,
+                    result,
+                }))
+                .unwrap();
+            if offline {
+                break;
+            }
+        }
+        sideeffect
+            .send(Action::Authenticated(AuthenticatedAction::OutboxFlushed))
+            .unwrap();
+    });
+}
+
+/// Keeps the cache in line with what the streams report, once the event is applied to `rooms`.
+fn cache_event(cache: Arc<Cache>, event: RoomEvent, rooms: &[Room]) {
+    let room = rooms.iter().find(|r| r.id == event.room_id()).cloned();
+    tokio::spawn(async move {
+        let _ = match event {
+            RoomEvent::Message(message) => {
+                cache
+                    .save_messages(&message.room_id.clone(), &[message])
+                    .await
+            }
+            RoomEvent::MessageRemoved { message_id, .. } => cache.remove_message(&message_id).await,
+            RoomEvent::Created { room } => cache.save_room(&room).await,
+            RoomEvent::Deleted { room_id } => cache.remove_room(&room_id).await,
+            RoomEvent::Renamed { .. }
+            | RoomEvent::TopicChanged { .. }
+            | RoomEvent::Archived { .. } => match room {
+                Some(room) => cache.save_room(&room).await,
+                None => Ok(()),
+            },
+            _ => Ok(()),
+        };
+    });
+}
+
 pub fn new_authenticate(
     app: &mut App,
     token: String,
@@ -503,19 +1657,259 @@ pub fn new_authenticate(
         Arc::clone(&client),
         sideeffect,
     );
+
+    handle_action(
+        Action::Authenticated(AuthenticatedAction::OpenCache),
+        app,
+        Arc::clone(&client),
+        sideeffect,
+    );
+}
+
+/// Saves the token of the account and makes it the one in use.
+pub fn save_account(profile: &Profile, token: &str) -> anyhow::Result<()> {
+    token::persist_token(profile, token)?;
+    let mut profiles = Profiles::load();
+    profiles.activate(profile.clone());
+    profiles.save()
+}
+
+/// Signs in to a saved account with its token, leaving the current one as it is.
+fn open_account(
+    app: &mut App,
+    profile: Profile,
+    token: String,
+    client: Arc<chat_room_client::Client>,
+    sideeffect: &tokio::sync::mpsc::UnboundedSender<Action>,
+) {
+    let mut profiles = Profiles::load();
+    profiles.activate(profile.clone());
+    let _ = profiles.save();
+    client.set_base_url(&profile.server);
+    new_authenticate(app, token, client, sideeffect);
 }
 
 pub fn new_signout(app: &mut App) {
-    if token::read_token().is_some() {
-        let _ = token::delete_token();
+    if let Some(profile) = Profiles::load().active() {
+        let _ = token::delete_token(profile);
     }
+    new_welcome(app);
+}
+
+/// The welcome screen listing the saved accounts, filled in with the active one.
+pub fn new_welcome(app: &mut App) {
+    let profiles = Profiles::load();
     let mut signed_out = SignedOutState::new();
-    if let Some(base_url) = chat_room_client::read_base_url() {
-        signed_out.server_field.text = base_url;
+    if let Some(profile) = profiles.active() {
+        signed_out.server_field.text = profile.server.clone();
+        signed_out.username_field.text = profile.username.clone();
     }
+    signed_out.selected_account = profiles
+        .active
+        .as_ref()
+        .and_then(|active| profiles.profiles.iter().position(|p| &p.name() == active));
+    signed_out.accounts = profiles
+        .profiles
+        .into_iter()
+        .map(|profile| {
+            let token = token::read_token(&profile);
+            (profile, token)
+        })
+        .collect();
     let state = State::SignedOut(signed_out);
 
     app.state = state;
     app.error = None;
     app.loading = false;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::App;
+
+    fn room(name: &str) -> Room {
+        Room {
+            id: name.to_string(),
+            name: name.to_string(),
+            creator_id: String::new(),
+            create_date: 0,
+            topic: String::new(),
+            archived: false,
+        }
+    }
+
+    /// Signed in with the rooms listed in this order.
+    fn signed_in(rooms: &[&str]) -> App {
+        let mut state = AuthenticatedState::new("token".to_string());
+        state.rooms = rooms.iter().map(|name| room(name)).collect();
+        App {
+            state: State::Authenticated(state),
+            ..App::initial()
+        }
+    }
+
+    fn authenticated(app: &mut App) -> &mut AuthenticatedState<'static> {
+        match app.state {
+            State::Authenticated(ref mut state) => state,
+            State::SignedOut(_) => panic!("Signed out"),
+        }
+    }
+
+    /// Handles the action, dropping the ones it leads to.
+    fn handle(app: &mut App, action: AuthenticatedAction) {
+        let (unauthorized_tx, _) = mpsc::unbounded_channel();
+        let client = Arc::new(Client::new(reqwest::Client::new(), unauthorized_tx));
+        let (sideeffect, _rx) = mpsc::unbounded_channel();
+        handle_action(Action::Authenticated(action), app, client, &sideeffect);
+    }
+
+    fn enter(app: &mut App, index: usize) {
+        authenticated(app).selected_room_index = Some(index);
+        handle(app, AuthenticatedAction::EnterRoom);
+    }
+
+    fn receive(app: &mut App, event: RoomEvent) {
+        handle(app, AuthenticatedAction::RoomEventReceived(event));
+    }
+
+    fn room_ids(app: &mut App) -> Vec<String> {
+        let state = authenticated(app);
+        state.rooms.iter().map(|room| room.id.clone()).collect()
+    }
+
+    #[test]
+    fn test_created_room_keeps_selection() {
+        let mut app = signed_in(&["b", "a"]);
+        enter(&mut app, 1);
+
+        receive(&mut app, RoomEvent::Created { room: room("c") });
+        // sent again over the other stream
+        receive(&mut app, RoomEvent::Created { room: room("c") });
+        assert_eq!(room_ids(&mut app), ["c", "b", "a"]);
+        let state = authenticated(&mut app);
+        assert_eq!(state.selected_room_index, Some(2));
+        assert_eq!(state.current_room_index, Some(2));
+        assert_eq!(state.current_room.as_ref().unwrap().id, "a");
+    }
+
+    #[test]
+    fn test_renamed_room() {
+        let mut app = signed_in(&["b", "a"]);
+        enter(&mut app, 1);
+
+        for room_id in ["a", "b"] {
+            let name = format!("{} renamed", room_id);
+            let room_id = room_id.to_string();
+            receive(&mut app, RoomEvent::Renamed { room_id, name });
+        }
+        let state = authenticated(&mut app);
+        let names = state.rooms.iter().map(|room| room.name.as_str());
+        assert_eq!(names.collect::<Vec<_>>(), ["b renamed", "a renamed"]);
+        assert_eq!(state.current_room.as_ref().unwrap().name, "a renamed");
+    }
+
+    #[test]
+    fn test_deleted_current_room() {
+        let mut app = signed_in(&["c", "b", "a"]);
+        enter(&mut app, 1);
+
+        let room_id = "b".to_string();
+        receive(&mut app, RoomEvent::Deleted { room_id });
+        assert_eq!(room_ids(&mut app), ["c", "a"]);
+        let state = authenticated(&mut app);
+        assert!(state.current_room.is_none());
+        assert_eq!(state.current_room_index, None);
+        assert_eq!(state.selected_room_index, Some(1));
+    }
+
+    #[test]
+    fn test_deleted_room_before_the_current_one() {
+        let mut app = signed_in(&["c", "b", "a"]);
+        enter(&mut app, 2);
+
+        let room_id = "c".to_string();
+        receive(&mut app, RoomEvent::Deleted { room_id });
+        let state = authenticated(&mut app);
+        assert_eq!(state.current_room.as_ref().unwrap().id, "a");
+        assert_eq!(state.current_room_index, Some(1));
+    }
+
+    #[test]
+    fn test_deleted_last_selected_room() {
+        let mut app = signed_in(&["c", "b", "a"]);
+        enter(&mut app, 0);
+        authenticated(&mut app).selected_room_index = Some(2);
+
+        let room_id = "a".to_string();
+        receive(&mut app, RoomEvent::Deleted { room_id });
+        let state = authenticated(&mut app);
+        assert_eq!(state.selected_room_index, Some(1));
+        assert_eq!(state.current_room_index, Some(0));
+
+        for room_id in ["c", "b"] {
+            let room_id = room_id.to_string();
+            receive(&mut app, RoomEvent::Deleted { room_id });
+        }
+        let state = authenticated(&mut app);
+        assert!(state.rooms.is_empty());
+        assert_eq!(state.selected_room_index, None);
+        assert!(state.current_room.is_none());
+    }
+
+    #[test]
+    fn test_backoff_doubles_within_bounds() {
+        for attempt in 1..=10 {
+            let full = RECONNECT_DELAY
+                .saturating_mul(1 << (attempt - 1))
+                .min(MAX_RECONNECT_DELAY);
+            for _ in 0..50 {
+                let delay = backoff(attempt);
+                assert!(delay >= full / 2 && delay <= full, "{:?}", delay);
+                assert!(delay >= RECONNECT_DELAY / 2 && delay <= MAX_RECONNECT_DELAY);
+            }
+        }
+        assert!(backoff(u32::MAX) <= MAX_RECONNECT_DELAY);
+    }
+
+    #[test]
+    fn test_failed_tries_end_offline() {
+        let mut attempt = 0;
+        for expected in 1..OFFLINE_AFTER {
+            let (connection, _) = next_try(&mut attempt, None);
+            assert!(matches!(
+                connection,
+                Connection::Reconnecting { attempt, .. } if attempt == expected
+            ));
+        }
+        let (connection, _) = next_try(&mut attempt, None);
+        assert!(matches!(connection, Connection::Offline { .. }));
+        assert_eq!(attempt, OFFLINE_AFTER);
+    }
+
+    #[test]
+    fn test_only_stable_connections_reset_tries() {
+        let mut attempt = 0;
+        next_try(&mut attempt, None);
+        let short = STABLE_AFTER - Duration::from_secs(1);
+        let before = Instant::now();
+        let (connection, retry_at) = next_try(&mut attempt, Some(short));
+        assert_eq!(attempt, 2);
+        assert!(matches!(
+            connection,
+            Connection::Reconnecting { attempt: 2, .. }
+        ));
+        assert!(retry_at >= before + RECONNECT_DELAY);
+
+        let (connection, retry_at) = next_try(&mut attempt, Some(STABLE_AFTER));
+        assert_eq!(attempt, 0);
+        assert_eq!(
+            connection,
+            Connection::Reconnecting {
+                attempt: 0,
+                retry_at
+            }
+        );
+        assert!(retry_at < Instant::now() + RECONNECT_DELAY / 2);
+    }
+}
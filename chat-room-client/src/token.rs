@@ -1,25 +1,255 @@
-use std::{fs, path::PathBuf};
+use std::{
+    collections::HashMap,
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::RwLock,
+};
 
-use anyhow::Ok;
+use anyhow::{anyhow, bail};
+use argon2::Argon2;
+use base64::{prelude::BASE64_STANDARD, Engine};
+use chacha20poly1305::{
+    aead::{Aead, AeadCore, KeyInit, OsRng},
+    XChaCha20Poly1305, XNonce,
+};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
 
+use crate::data_files;
+use crate::profiles::Profile;
+
+/// Tokens of the saved accounts, readable by their owner only.
 fn get_token_file_path() -> PathBuf {
-    let mut path = std::env::current_dir().unwrap_or_else(|_| PathBuf::from("."));
-    path.push(".data");
-    fs::create_dir_all(&path).unwrap_or_default();
-    path.push("token");
-    path
+    data_files::path().join("credentials.json")
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "format", rename_all = "snake_case")]
+enum Credentials {
+    /// Tokens by profile name.
+    Plain { tokens: HashMap<String, String> },
+    /// The same map sealed with XChaCha20-Poly1305, under a key derived from a passphrase with
+    /// Argon2 and the salt.
+    Encrypted {
+        salt: String,
+        nonce: String,
+        ciphertext: String,
+    },
+}
+
+/// Key of the encrypted credentials, set once unlocked with the passphrase.
+struct Key {
+    salt: Vec<u8>,
+    cipher: XChaCha20Poly1305,
+}
+
+static KEY: RwLock<Option<Key>> = RwLock::new(None);
+
+fn derive_key(passphrase: &str, salt: &[u8]) -> anyhow::Result<Key> {
+    let mut key = [0u8; 32];
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|err| anyhow!("Can't derive the key: {}", err))?;
+    Ok(Key {
+        salt: salt.to_vec(),
+        cipher: XChaCha20Poly1305::new(&key.into()),
+    })
+}
+
+fn seal(key: &Key, tokens: &HashMap<String, String>) -> anyhow::Result<Credentials> {
+    let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let ciphertext = key
+        .cipher
+        .encrypt(&nonce, serde_json::to_vec(tokens)?.as_slice())
+        .map_err(|_| anyhow!("Can't encrypt the credentials"))?;
+    Ok(Credentials::Encrypted {
+        salt: BASE64_STANDARD.encode(&key.salt),
+        nonce: BASE64_STANDARD.encode(nonce),
+        ciphertext: BASE64_STANDARD.encode(ciphertext),
+    })
 }
 
-pub fn persist_token(token: &str) -> anyhow::Result<()> {
-    let _ = fs::write(get_token_file_path(), token)?;
+fn open(key: &Key, nonce: &str, ciphertext: &str) -> anyhow::Result<HashMap<String, String>> {
+    let nonce = BASE64_STANDARD.decode(nonce)?;
+    if nonce.len() != 24 {
+        bail!("Malformed credentials");
+    }
+    let plaintext = key
+        .cipher
+        .decrypt(
+            XNonce::from_slice(&nonce),
+            BASE64_STANDARD.decode(ciphertext)?.as_slice(),
+        )
+        .map_err(|_| anyhow!("Wrong passphrase"))?;
+    Ok(serde_json::from_slice(&plaintext)?)
+}
+
+fn read_credentials(path: &Path) -> Option<Credentials> {
+    fs::read_to_string(path)
+        .ok()
+        .and_then(|credentials| serde_json::from_str(&credentials).ok())
+}
+
+/// Replaces the file through a temporary one created with 0600, in a directory only the user
+/// can enter.
+fn write_credentials(path: &Path, credentials: &Credentials) -> anyhow::Result<()> {
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let mut builder = fs::DirBuilder::new();
+    builder.recursive(true);
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt, PermissionsExt};
+        builder.mode(0o700);
+        options.mode(0o600);
+        builder.create(dir)?;
+        fs::set_permissions(dir, fs::Permissions::from_mode(0o700))?;
+    }
+    #[cfg(not(unix))]
+    builder.create(dir)?;
+
+    let temp = path.with_extension("tmp");
+    let mut file = options.open(&temp)?;
+    file.write_all(&serde_json::to_vec(credentials)?)?;
+    file.sync_all()?;
+    fs::rename(temp, path)?;
     Ok(())
 }
 
-pub fn read_token() -> Option<String> {
-    fs::read_to_string(get_token_file_path()).ok()
+/// Tokens by profile name, none while the credentials are encrypted and still locked.
+fn read_tokens() -> HashMap<String, String> {
+    match read_credentials(&get_token_file_path()) {
+        Some(Credentials::Plain { tokens }) => tokens,
+        Some(Credentials::Encrypted {
+            nonce, ciphertext, ..
+        }) => KEY
+            .read()
+            .unwrap()
+            .as_ref()
+            .and_then(|key| open(key, &nonce, &ciphertext).ok())
+            .unwrap_or_default(),
+        None => HashMap::new(),
+    }
 }
 
-pub fn delete_token() -> anyhow::Result<()> {
-    let _ = fs::remove_file(get_token_file_path());
+fn write_tokens(tokens: &HashMap<String, String>) -> anyhow::Result<()> {
+    let credentials = match KEY.read().unwrap().as_ref() {
+        Some(key) => seal(key, tokens)?,
+        None if is_encrypted() => bail!("The credentials are locked"),
+        None => Credentials::Plain {
+            tokens: tokens.clone(),
+        },
+    };
+    write_credentials(&get_token_file_path(), &credentials)
+}
+
+pub fn persist_token(profile: &Profile, token: &str) -> anyhow::Result<()> {
+    let mut tokens = read_tokens();
+    tokens.insert(profile.name(), token.to_string());
+    write_tokens(&tokens)
+}
+
+pub fn read_token(profile: &Profile) -> Option<String> {
+    read_tokens().remove(&profile.name())
+}
+
+pub fn delete_token(profile: &Profile) -> anyhow::Result<()> {
+    let mut tokens = read_tokens();
+    if tokens.remove(&profile.name()).is_some() {
+        write_tokens(&tokens)?;
+    }
+    Ok(())
+}
+
+pub fn is_encrypted() -> bool {
+    matches!(
+        read_credentials(&get_token_file_path()),
+        Some(Credentials::Encrypted { .. })
+    )
+}
+
+/// Derives the key of the encrypted credentials, failing when the passphrase is wrong.
+pub fn unlock(passphrase: &str) -> anyhow::Result<()> {
+    let Some(Credentials::Encrypted {
+        salt,
+        nonce,
+        ciphertext,
+    }) = read_credentials(&get_token_file_path())
+    else {
+        return Ok(());
+    };
+    let key = derive_key(passphrase, &BASE64_STANDARD.decode(salt)?)?;
+    open(&key, &nonce, &ciphertext)?;
+    *KEY.write().unwrap() = Some(key);
     Ok(())
 }
+
+/// Encrypts the credentials from now on with a key derived from the passphrase.
+pub fn encrypt(passphrase: &str) -> anyhow::Result<()> {
+    let tokens = read_tokens();
+    let mut salt = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut salt);
+    *KEY.write().unwrap() = Some(derive_key(passphrase, &salt)?);
+    write_tokens(&tokens)
+}
+
+/// Stores the unlocked credentials in plain text again.
+pub fn decrypt() -> anyhow::Result<()> {
+    let tokens = read_tokens();
+    *KEY.write().unwrap() = None;
+    write_credentials(&get_token_file_path(), &Credentials::Plain { tokens })
+}
+
+/// The id of the user the token was issued to, read from its payload without verifying it.
+pub fn user_id(token: &str) -> Option<String> {
+    #[derive(serde::Deserialize)]
+    struct Body {
+        user_id: String,
+    }
+
+    let payload = token.split('.').nth(1)?;
+    let payload = BASE64_STANDARD.decode(payload).ok()?;
+    serde_json::from_slice::<Body>(&payload)
+        .ok()
+        .map(|body| body.user_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::token::*;
+
+    #[test]
+    fn test_sealed_credentials_need_the_passphrase() {
+        let dir = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        let path = dir.join("credentials.json");
+        let tokens = HashMap::from([("john@http://a".to_string(), "token".to_string())]);
+
+        let key = derive_key("correct horse", b"0123456789abcdef").unwrap();
+        write_credentials(&path, &seal(&key, &tokens).unwrap()).unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        let Some(Credentials::Encrypted {
+            salt,
+            nonce,
+            ciphertext,
+        }) = read_credentials(&path)
+        else {
+            panic!("Credentials aren't encrypted");
+        };
+        assert!(!ciphertext.contains("token"));
+        let salt = BASE64_STANDARD.decode(salt).unwrap();
+        let wrong = derive_key("battery staple", &salt).unwrap();
+        assert!(open(&wrong, &nonce, &ciphertext).is_err());
+        let key = derive_key("correct horse", &salt).unwrap();
+        assert_eq!(open(&key, &nonce, &ciphertext).unwrap(), tokens);
+
+        let _ = fs::remove_dir_all(dir);
+    }
+}
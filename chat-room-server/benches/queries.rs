@@ -0,0 +1,164 @@
+//! Times the hot queries on a seeded database before and after the constraints migration.
+//!
+//! `cargo bench --bench queries`, set `QUCHAT_BENCH_MESSAGES` to change the default of 1M messages.
+use std::time::{Duration, Instant};
+
+use chat_room::store::sqlite::MIGRATOR;
+use sqlx::migrate::Migrator;
+use sqlx::sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePoolOptions};
+use sqlx::SqlitePool;
+
+/// First migration that adds foreign keys and indexes.
+const CONSTRAINTS_VERSION: i64 = 20261019140000;
+const USERS: i64 = 1_000;
+const ROOMS: i64 = 100;
+const BLACKLISTED_TOKENS: i64 = 100_000;
+const ITERATIONS: u32 = 50;
+
+struct Query {
+    name: &'static str,
+    sql: &'static str,
+}
+
+const QUERIES: [Query; 4] = [
+    Query {
+        name: "message history",
+        sql: "SELECT messages.id, messages.content, messages.room_id, messages.sender_id, messages.create_date, users.name
+            FROM messages INNER JOIN users ON messages.sender_id = users.id
+            WHERE messages.room_id = 'room-42' ORDER BY create_date LIMIT 20",
+    },
+    Query {
+        name: "rooms last message",
+        sql: "SELECT room_id, MAX(create_date) FROM messages
+            WHERE room_id IN ('room-1', 'room-2', 'room-3', 'room-4', 'room-5', 'room-6', 'room-7', 'room-8', 'room-9', 'room-10')
+            AND sender_id != 'user-7' GROUP BY room_id",
+    },
+    Query {
+        name: "rooms last seen",
+        sql: "SELECT room_id, last_seen FROM room_state
+            WHERE room_id IN ('room-1', 'room-2', 'room-3', 'room-4', 'room-5', 'room-6', 'room-7', 'room-8', 'room-9', 'room-10')
+            AND user_id = 'user-7'",
+    },
+    Query {
+        name: "token blacklist",
+        sql: "SELECT * FROM token_blacklist WHERE token = 'token-99999'",
+    },
+];
+
+fn messages() -> i64 {
+    std::env::var("QUCHAT_BENCH_MESSAGES")
+        .ok()
+        .and_then(|count| count.parse().ok())
+        .unwrap_or(1_000_000)
+}
+
+async fn seed(pool: &SqlitePool, messages: i64) -> Result<(), sqlx::Error> {
+    let statements = [
+        format!(
+            "WITH RECURSIVE seq(n) AS (SELECT 0 UNION ALL SELECT n + 1 FROM seq WHERE n < {})
+            INSERT INTO users (id, name, secret) SELECT 'user-' || n, 'user ' || n, '' FROM seq",
+            USERS - 1
+        ),
+        format!(
+            "WITH RECURSIVE seq(n) AS (SELECT 0 UNION ALL SELECT n + 1 FROM seq WHERE n < {})
+            INSERT INTO rooms (id, name, creator_id, create_date) SELECT 'room-' || n, 'room ' || n, 'user-0', 0 FROM seq",
+            ROOMS - 1
+        ),
+        format!(
+            "WITH RECURSIVE seq(n) AS (SELECT 0 UNION ALL SELECT n + 1 FROM seq WHERE n < {})
+            INSERT INTO messages (id, content, room_id, sender_id, create_date)
+            SELECT 'message-' || n, 'hello world', 'room-' || (n % {}), 'user-' || (n % {}), n FROM seq",
+            messages - 1,
+            ROOMS,
+            USERS
+        ),
+        format!(
+            "WITH RECURSIVE seq(n) AS (SELECT 0 UNION ALL SELECT n + 1 FROM seq WHERE n < {})
+            INSERT INTO room_state (id, user_id, room_id, last_seen)
+            SELECT 'state-' || n, 'user-' || (n / 10), 'room-' || (n % 10), n FROM seq",
+            USERS * 10 - 1
+        ),
+        format!(
+            "WITH RECURSIVE seq(n) AS (SELECT 0 UNION ALL SELECT n + 1 FROM seq WHERE n < {})
+            INSERT INTO token_blacklist (token) SELECT 'token-' || n FROM seq",
+            BLACKLISTED_TOKENS - 1
+        ),
+    ];
+
+    for statement in statements {
+        sqlx::query(&statement).execute(pool).await?;
+    }
+    sqlx::query("ANALYZE").execute(pool).await?;
+    Ok(())
+}
+
+async fn measure(pool: &SqlitePool) -> Result<Vec<Duration>, sqlx::Error> {
+    let mut timings = Vec::new();
+    for query in &QUERIES {
+        // warm the page cache so both runs read from memory
+        sqlx::query(query.sql).fetch_all(pool).await?;
+
+        let start = Instant::now();
+        for _ in 0..ITERATIONS {
+            sqlx::query(query.sql).fetch_all(pool).await?;
+        }
+        timings.push(start.elapsed() / ITERATIONS);
+    }
+    Ok(timings)
+}
+
+#[rocket::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let path = std::env::temp_dir().join(format!("quchat-bench-{}.sqlite", uuid::Uuid::new_v4()));
+    let options = SqliteConnectOptions::new()
+        .filename(&path)
+        .create_if_missing(true)
+        .journal_mode(SqliteJournalMode::Wal);
+    let pool = SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect_with(options)
+        .await?;
+
+    let before_constraints = Migrator {
+        migrations: MIGRATOR
+            .iter()
+            .filter(|migration| migration.version < CONSTRAINTS_VERSION)
+            .cloned()
+            .collect::<Vec<_>>()
+            .into(),
+        ..Migrator::DEFAULT
+    };
+    before_constraints.run(&pool).await?;
+
+    let messages = messages();
+    let start = Instant::now();
+    seed(&pool, messages).await?;
+    println!("Seeded {} messages in {:?}", messages, start.elapsed());
+
+    let before = measure(&pool).await?;
+    let start = Instant::now();
+    MIGRATOR.run(&pool).await?;
+    sqlx::query("ANALYZE").execute(&pool).await?;
+    println!("Applied constraints in {:?}", start.elapsed());
+    let after = measure(&pool).await?;
+
+    println!(
+        "{:<20} {:>14} {:>14} {:>9}",
+        "query", "before", "after", "speedup"
+    );
+    for ((query, before), after) in QUERIES.iter().zip(before).zip(after) {
+        println!(
+            "{:<20} {:>14.3?} {:>14.3?} {:>8.1}x",
+            query.name,
+            before,
+            after,
+            before.as_secs_f64() / after.as_secs_f64()
+        );
+    }
+
+    pool.close().await;
+    for suffix in ["", "-wal", "-shm"] {
+        let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+    }
+    Ok(())
+}
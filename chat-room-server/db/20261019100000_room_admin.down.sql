@@ -0,0 +1,5 @@
+-- Add down migration script here
+ALTER TABLE rooms DROP COLUMN topic;
+ALTER TABLE rooms DROP COLUMN archived;
+
+ALTER TABLE users DROP COLUMN is_admin;
@@ -0,0 +1,5 @@
+-- Add up migration script here
+ALTER TABLE rooms ADD COLUMN topic TEXT NOT NULL DEFAULT "";
+ALTER TABLE rooms ADD COLUMN archived BOOLEAN NOT NULL DEFAULT FALSE;
+
+ALTER TABLE users ADD COLUMN is_admin BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,3 @@
+-- Add down migration script here
+DROP TABLE IF EXISTS room_sanctions;
+DROP TABLE IF EXISTS moderation_log;
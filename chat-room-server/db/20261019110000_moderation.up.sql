@@ -0,0 +1,20 @@
+-- Add up migration script here
+CREATE TABLE IF NOT EXISTS room_sanctions (
+    id TEXT NOT NULL PRIMARY KEY,
+    room_id TEXT NOT NULL,
+    user_id TEXT NOT NULL,
+    kind TEXT NOT NULL,
+    until INT
+);
+
+CREATE TABLE IF NOT EXISTS moderation_log (
+    id TEXT NOT NULL PRIMARY KEY,
+    room_id TEXT NOT NULL,
+    moderator_id TEXT NOT NULL,
+    kind TEXT NOT NULL,
+    target_user_id TEXT,
+    target_message_id TEXT,
+    reason TEXT,
+    until INT,
+    create_date INT NOT NULL
+);
@@ -0,0 +1,4 @@
+-- Add down migration script here
+DROP INDEX IF EXISTS login_attempts_username;
+DROP INDEX IF EXISTS login_attempts_user_id;
+DROP TABLE IF EXISTS login_attempts;
@@ -0,0 +1,12 @@
+-- Add up migration script here
+CREATE TABLE IF NOT EXISTS login_attempts (
+    id TEXT NOT NULL PRIMARY KEY,
+    user_id TEXT,
+    username TEXT NOT NULL,
+    ip TEXT,
+    outcome TEXT NOT NULL,
+    create_date INT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS login_attempts_username ON login_attempts (username, create_date);
+CREATE INDEX IF NOT EXISTS login_attempts_user_id ON login_attempts (user_id, create_date);
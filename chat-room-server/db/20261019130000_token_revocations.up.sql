@@ -0,0 +1,5 @@
+-- Add up migration script here
+CREATE TABLE IF NOT EXISTS token_revocations (
+    user_id TEXT NOT NULL PRIMARY KEY,
+    revoked_at INT NOT NULL
+);
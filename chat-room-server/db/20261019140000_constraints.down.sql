@@ -0,0 +1,69 @@
+-- Add down migration script here
+DROP INDEX IF EXISTS token_blacklist_token;
+
+ALTER TABLE messages RENAME TO messages_new;
+ALTER TABLE room_state RENAME TO room_state_new;
+ALTER TABLE room_sanctions RENAME TO room_sanctions_new;
+ALTER TABLE moderation_log RENAME TO moderation_log_new;
+ALTER TABLE login_attempts RENAME TO login_attempts_new;
+
+CREATE TABLE messages (
+    id TEXT NOT NULL PRIMARY KEY,
+    content TEXT NOT NULL,
+    room_id TEXT NOT NULL,
+    sender_id TEXT NOT NULL,
+    create_date INT NOT NULL
+);
+-- messages of deleted users get an empty sender
+INSERT INTO messages SELECT id, content, room_id, COALESCE(sender_id, ''), create_date FROM messages_new;
+
+CREATE TABLE room_state (
+    id TEXT NOT NULL PRIMARY KEY,
+    user_id TEXT NOT NULL,
+    room_id TEXT NOT NULL,
+    last_seen INT
+);
+INSERT INTO room_state SELECT id, user_id, room_id, last_seen FROM room_state_new;
+
+CREATE TABLE room_sanctions (
+    id TEXT NOT NULL PRIMARY KEY,
+    room_id TEXT NOT NULL,
+    user_id TEXT NOT NULL,
+    kind TEXT NOT NULL,
+    until INT
+);
+INSERT INTO room_sanctions SELECT id, room_id, user_id, kind, until FROM room_sanctions_new;
+
+CREATE TABLE moderation_log (
+    id TEXT NOT NULL PRIMARY KEY,
+    room_id TEXT NOT NULL,
+    moderator_id TEXT NOT NULL,
+    kind TEXT NOT NULL,
+    target_user_id TEXT,
+    target_message_id TEXT,
+    reason TEXT,
+    until INT,
+    create_date INT NOT NULL
+);
+INSERT INTO moderation_log
+SELECT id, room_id, moderator_id, kind, target_user_id, target_message_id, reason, until, create_date
+FROM moderation_log_new;
+
+CREATE TABLE login_attempts (
+    id TEXT NOT NULL PRIMARY KEY,
+    user_id TEXT,
+    username TEXT NOT NULL,
+    ip TEXT,
+    outcome TEXT NOT NULL,
+    create_date INT NOT NULL
+);
+INSERT INTO login_attempts SELECT id, user_id, username, ip, outcome, create_date FROM login_attempts_new;
+
+DROP TABLE messages_new;
+DROP TABLE room_state_new;
+DROP TABLE room_sanctions_new;
+DROP TABLE moderation_log_new;
+DROP TABLE login_attempts_new;
+
+CREATE INDEX login_attempts_username ON login_attempts (username, create_date);
+CREATE INDEX login_attempts_user_id ON login_attempts (user_id, create_date);
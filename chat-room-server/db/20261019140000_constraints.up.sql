@@ -0,0 +1,112 @@
+-- Add up migration script here
+-- SQLite can't add foreign keys to existing tables, so every child table is rebuilt.
+-- Rooms outlive their creator and the moderation log keeps the ids of removed users
+-- and messages, so those columns stay unconstrained. Messages outlive their sender too,
+-- which is set to NULL.
+--
+-- Other rows pointing at rooms or users that no longer exist stop the migration rather
+-- than being dropped. Find them with the queries counted below, then fix or delete them.
+CREATE TEMP TABLE orphans (
+    count INT NOT NULL,
+    CONSTRAINT "rows point at missing rooms or users, see db/20261019140000_constraints.up.sql"
+        CHECK (count = 0)
+);
+INSERT INTO orphans (count) SELECT
+    (SELECT COUNT(*) FROM messages WHERE room_id NOT IN (SELECT id FROM rooms))
+    + (SELECT COUNT(*) FROM room_state
+        WHERE room_id NOT IN (SELECT id FROM rooms) OR user_id NOT IN (SELECT id FROM users))
+    + (SELECT COUNT(*) FROM room_sanctions
+        WHERE room_id NOT IN (SELECT id FROM rooms) OR user_id NOT IN (SELECT id FROM users))
+    + (SELECT COUNT(*) FROM moderation_log WHERE room_id NOT IN (SELECT id FROM rooms));
+DROP TABLE orphans;
+
+ALTER TABLE messages RENAME TO messages_old;
+ALTER TABLE room_state RENAME TO room_state_old;
+ALTER TABLE room_sanctions RENAME TO room_sanctions_old;
+ALTER TABLE moderation_log RENAME TO moderation_log_old;
+ALTER TABLE login_attempts RENAME TO login_attempts_old;
+
+CREATE TABLE messages (
+    id TEXT NOT NULL PRIMARY KEY,
+    content TEXT NOT NULL,
+    room_id TEXT NOT NULL REFERENCES rooms (id) ON DELETE CASCADE,
+    sender_id TEXT REFERENCES users (id) ON DELETE SET NULL,
+    create_date INT NOT NULL
+);
+
+INSERT INTO messages (id, content, room_id, sender_id, create_date)
+SELECT id, content, room_id, CASE WHEN sender_id IN (SELECT id FROM users) THEN sender_id END, create_date
+FROM messages_old;
+
+CREATE TABLE room_state (
+    id TEXT NOT NULL PRIMARY KEY,
+    user_id TEXT NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    room_id TEXT NOT NULL REFERENCES rooms (id) ON DELETE CASCADE,
+    last_seen INT,
+    UNIQUE (user_id, room_id)
+);
+
+-- duplicated states were created by every visit, the latest one wins
+INSERT INTO room_state (id, user_id, room_id, last_seen)
+SELECT MIN(id), user_id, room_id, MAX(last_seen) FROM room_state_old
+GROUP BY user_id, room_id;
+
+CREATE TABLE room_sanctions (
+    id TEXT NOT NULL PRIMARY KEY,
+    room_id TEXT NOT NULL REFERENCES rooms (id) ON DELETE CASCADE,
+    user_id TEXT NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    kind TEXT NOT NULL,
+    until INT
+);
+
+INSERT INTO room_sanctions (id, room_id, user_id, kind, until)
+SELECT id, room_id, user_id, kind, until FROM room_sanctions_old;
+
+CREATE TABLE moderation_log (
+    id TEXT NOT NULL PRIMARY KEY,
+    room_id TEXT NOT NULL REFERENCES rooms (id) ON DELETE CASCADE,
+    moderator_id TEXT NOT NULL,
+    kind TEXT NOT NULL,
+    target_user_id TEXT,
+    target_message_id TEXT,
+    reason TEXT,
+    until INT,
+    create_date INT NOT NULL
+);
+
+INSERT INTO moderation_log
+    (id, room_id, moderator_id, kind, target_user_id, target_message_id, reason, until, create_date)
+SELECT id, room_id, moderator_id, kind, target_user_id, target_message_id, reason, until, create_date
+FROM moderation_log_old;
+
+CREATE TABLE login_attempts (
+    id TEXT NOT NULL PRIMARY KEY,
+    user_id TEXT REFERENCES users (id) ON DELETE SET NULL,
+    username TEXT NOT NULL,
+    ip TEXT,
+    outcome TEXT NOT NULL,
+    create_date INT NOT NULL
+);
+
+INSERT INTO login_attempts (id, user_id, username, ip, outcome, create_date)
+SELECT id, CASE WHEN user_id IN (SELECT id FROM users) THEN user_id END, username, ip, outcome, create_date
+FROM login_attempts_old;
+
+DROP TABLE messages_old;
+DROP TABLE room_state_old;
+DROP TABLE room_sanctions_old;
+DROP TABLE moderation_log_old;
+DROP TABLE login_attempts_old;
+
+DELETE FROM token_blacklist WHERE id NOT IN (SELECT MIN(id) FROM token_blacklist GROUP BY token);
+
+-- covers the history page and the last message lookup of rooms_state
+CREATE INDEX messages_room_date ON messages (room_id, create_date, sender_id);
+CREATE INDEX messages_sender ON messages (sender_id);
+CREATE INDEX room_state_room ON room_state (room_id);
+CREATE INDEX room_sanctions_room_user ON room_sanctions (room_id, user_id, kind);
+CREATE INDEX room_sanctions_user ON room_sanctions (user_id);
+CREATE INDEX moderation_log_room_date ON moderation_log (room_id, create_date);
+CREATE INDEX login_attempts_username ON login_attempts (username, create_date);
+CREATE INDEX login_attempts_user_id ON login_attempts (user_id, create_date);
+CREATE UNIQUE INDEX token_blacklist_token ON token_blacklist (token);
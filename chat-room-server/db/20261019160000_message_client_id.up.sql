@@ -0,0 +1,5 @@
+-- Add up migration script here
+-- The idempotency key a client sends a message with, a retried send finds the stored message
+-- instead of creating another one. Messages without a key never collide.
+ALTER TABLE messages ADD COLUMN client_id TEXT;
+CREATE UNIQUE INDEX messages_sender_client_id ON messages (sender_id, client_id);
@@ -0,0 +1,15 @@
+-- Add up migration script here
+-- Bot accounts can't sign in, they act with the API tokens their owner, or the server admin
+-- when they have none, issues them. Only a hash of each token is kept.
+ALTER TABLE users ADD COLUMN is_bot BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE users ADD COLUMN owner_id TEXT;
+
+CREATE TABLE api_tokens (
+    id TEXT NOT NULL PRIMARY KEY,
+    bot_id TEXT NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    token_hash TEXT NOT NULL UNIQUE,
+    scopes TEXT NOT NULL,
+    create_date INT NOT NULL
+);
+CREATE INDEX api_tokens_bot ON api_tokens (bot_id);
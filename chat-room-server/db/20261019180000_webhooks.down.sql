@@ -0,0 +1,6 @@
+-- Add down migration script here
+DROP INDEX IF EXISTS webhook_deliveries_due;
+DROP INDEX IF EXISTS webhook_deliveries_webhook_date;
+DROP TABLE IF EXISTS webhook_deliveries;
+DROP INDEX IF EXISTS webhooks_room;
+DROP TABLE IF EXISTS webhooks;
@@ -0,0 +1,28 @@
+-- Add up migration script here
+-- Room events are queued in webhook_deliveries for each webhook of their room and POSTed by a
+-- background task, which retries failed deliveries and keeps the outcome as a delivery log.
+CREATE TABLE webhooks (
+    id TEXT NOT NULL PRIMARY KEY,
+    room_id TEXT NOT NULL REFERENCES rooms (id) ON DELETE CASCADE,
+    url TEXT NOT NULL,
+    secret TEXT NOT NULL,
+    events TEXT NOT NULL,
+    creator_id TEXT NOT NULL,
+    create_date INT NOT NULL
+);
+CREATE INDEX webhooks_room ON webhooks (room_id);
+
+CREATE TABLE webhook_deliveries (
+    id TEXT NOT NULL PRIMARY KEY,
+    webhook_id TEXT NOT NULL REFERENCES webhooks (id) ON DELETE CASCADE,
+    event TEXT NOT NULL,
+    payload TEXT NOT NULL,
+    status TEXT NOT NULL,
+    attempts INT NOT NULL,
+    response_status INT,
+    error TEXT,
+    next_attempt INT NOT NULL,
+    create_date INT NOT NULL
+);
+CREATE INDEX webhook_deliveries_webhook_date ON webhook_deliveries (webhook_id, create_date);
+CREATE INDEX webhook_deliveries_due ON webhook_deliveries (status, next_attempt);
@@ -0,0 +1,10 @@
+-- Add down migration script here
+DROP TABLE login_attempts;
+DROP TABLE moderation_log;
+DROP TABLE room_sanctions;
+DROP TABLE token_revocations;
+DROP TABLE token_blacklist;
+DROP TABLE room_state;
+DROP TABLE messages;
+DROP TABLE rooms;
+DROP TABLE users;
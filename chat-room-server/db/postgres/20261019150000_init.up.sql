@@ -0,0 +1,80 @@
+-- Add up migration script here
+CREATE TABLE users (
+    id TEXT NOT NULL PRIMARY KEY,
+    name TEXT NOT NULL UNIQUE,
+    secret TEXT NOT NULL,
+    is_admin BOOLEAN NOT NULL DEFAULT FALSE
+);
+
+CREATE TABLE rooms (
+    id TEXT NOT NULL PRIMARY KEY,
+    name TEXT NOT NULL DEFAULT '',
+    creator_id TEXT NOT NULL,
+    create_date BIGINT NOT NULL,
+    topic TEXT NOT NULL DEFAULT '',
+    archived BOOLEAN NOT NULL DEFAULT FALSE
+);
+
+CREATE TABLE messages (
+    id TEXT NOT NULL PRIMARY KEY,
+    content TEXT NOT NULL,
+    room_id TEXT NOT NULL REFERENCES rooms (id) ON DELETE CASCADE,
+    -- NULL once the sender is deleted, their messages stay
+    sender_id TEXT REFERENCES users (id) ON DELETE SET NULL,
+    create_date BIGINT NOT NULL
+);
+CREATE INDEX messages_room_date ON messages (room_id, create_date, sender_id);
+CREATE INDEX messages_sender ON messages (sender_id);
+
+CREATE TABLE room_state (
+    id TEXT NOT NULL PRIMARY KEY,
+    user_id TEXT NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    room_id TEXT NOT NULL REFERENCES rooms (id) ON DELETE CASCADE,
+    last_seen BIGINT,
+    UNIQUE (user_id, room_id)
+);
+CREATE INDEX room_state_room ON room_state (room_id);
+
+CREATE TABLE token_blacklist (
+    id BIGSERIAL PRIMARY KEY,
+    token TEXT NOT NULL UNIQUE
+);
+
+CREATE TABLE token_revocations (
+    user_id TEXT NOT NULL PRIMARY KEY,
+    revoked_at BIGINT NOT NULL
+);
+
+CREATE TABLE room_sanctions (
+    id TEXT NOT NULL PRIMARY KEY,
+    room_id TEXT NOT NULL REFERENCES rooms (id) ON DELETE CASCADE,
+    user_id TEXT NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    kind TEXT NOT NULL,
+    until BIGINT
+);
+CREATE INDEX room_sanctions_room_user ON room_sanctions (room_id, user_id, kind);
+CREATE INDEX room_sanctions_user ON room_sanctions (user_id);
+
+CREATE TABLE moderation_log (
+    id TEXT NOT NULL PRIMARY KEY,
+    room_id TEXT NOT NULL REFERENCES rooms (id) ON DELETE CASCADE,
+    moderator_id TEXT NOT NULL,
+    kind TEXT NOT NULL,
+    target_user_id TEXT,
+    target_message_id TEXT,
+    reason TEXT,
+    until BIGINT,
+    create_date BIGINT NOT NULL
+);
+CREATE INDEX moderation_log_room_date ON moderation_log (room_id, create_date);
+
+CREATE TABLE login_attempts (
+    id TEXT NOT NULL PRIMARY KEY,
+    user_id TEXT REFERENCES users (id) ON DELETE SET NULL,
+    username TEXT NOT NULL,
+    ip TEXT,
+    outcome TEXT NOT NULL,
+    create_date BIGINT NOT NULL
+);
+CREATE INDEX login_attempts_username ON login_attempts (username, create_date);
+CREATE INDEX login_attempts_user_id ON login_attempts (user_id, create_date);
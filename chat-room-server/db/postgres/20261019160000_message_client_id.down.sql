@@ -0,0 +1,3 @@
+-- Add down migration script here
+DROP INDEX IF EXISTS messages_sender_client_id;
+ALTER TABLE messages DROP COLUMN client_id;
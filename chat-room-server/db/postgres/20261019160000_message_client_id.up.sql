@@ -0,0 +1,3 @@
+-- Add up migration script here
+ALTER TABLE messages ADD COLUMN client_id TEXT;
+CREATE UNIQUE INDEX messages_sender_client_id ON messages (sender_id, client_id);
@@ -0,0 +1,5 @@
+-- Add down migration script here
+DROP INDEX IF EXISTS api_tokens_bot;
+DROP TABLE IF EXISTS api_tokens;
+ALTER TABLE users DROP COLUMN owner_id;
+ALTER TABLE users DROP COLUMN is_bot;
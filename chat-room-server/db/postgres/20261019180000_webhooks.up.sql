@@ -0,0 +1,26 @@
+-- Add up migration script here
+CREATE TABLE webhooks (
+    id TEXT NOT NULL PRIMARY KEY,
+    room_id TEXT NOT NULL REFERENCES rooms (id) ON DELETE CASCADE,
+    url TEXT NOT NULL,
+    secret TEXT NOT NULL,
+    events TEXT NOT NULL,
+    creator_id TEXT NOT NULL,
+    create_date BIGINT NOT NULL
+);
+CREATE INDEX webhooks_room ON webhooks (room_id);
+
+CREATE TABLE webhook_deliveries (
+    id TEXT NOT NULL PRIMARY KEY,
+    webhook_id TEXT NOT NULL REFERENCES webhooks (id) ON DELETE CASCADE,
+    event TEXT NOT NULL,
+    payload TEXT NOT NULL,
+    status TEXT NOT NULL,
+    attempts BIGINT NOT NULL,
+    response_status BIGINT,
+    error TEXT,
+    next_attempt BIGINT NOT NULL,
+    create_date BIGINT NOT NULL
+);
+CREATE INDEX webhook_deliveries_webhook_date ON webhook_deliveries (webhook_id, create_date);
+CREATE INDEX webhook_deliveries_due ON webhook_deliveries (status, next_attempt);
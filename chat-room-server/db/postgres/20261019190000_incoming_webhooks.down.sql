@@ -0,0 +1,3 @@
+-- Add down migration script here
+DROP INDEX IF EXISTS incoming_webhooks_room;
+DROP TABLE IF EXISTS incoming_webhooks;
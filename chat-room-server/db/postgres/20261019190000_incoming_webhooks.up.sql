@@ -0,0 +1,10 @@
+-- Add up migration script here
+CREATE TABLE incoming_webhooks (
+    id TEXT NOT NULL PRIMARY KEY,
+    room_id TEXT NOT NULL REFERENCES rooms (id) ON DELETE CASCADE,
+    bot_id TEXT NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    token_hash TEXT NOT NULL UNIQUE,
+    creator_id TEXT NOT NULL,
+    create_date BIGINT NOT NULL
+);
+CREATE INDEX incoming_webhooks_room ON incoming_webhooks (room_id);
@@ -0,0 +1,8 @@
+-- Add up migration script here
+CREATE TABLE room_commands (
+    room_id TEXT NOT NULL REFERENCES rooms (id) ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    bot_id TEXT NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    description TEXT NOT NULL,
+    PRIMARY KEY (room_id, name)
+);
@@ -0,0 +1,4 @@
+-- Add down migration script here
+-- direct rooms would be readable by anyone without the table
+DELETE FROM rooms WHERE id IN (SELECT room_id FROM direct_rooms);
+DROP TABLE IF EXISTS direct_rooms;
@@ -0,0 +1,9 @@
+-- Add up migration script here
+-- Rooms only two users can read, opened with `/dm`. Ids of deleted users are set to NULL
+-- rather than removing the row, so that the room stays private.
+CREATE TABLE direct_rooms (
+    room_id TEXT NOT NULL PRIMARY KEY REFERENCES rooms (id) ON DELETE CASCADE,
+    first_user_id TEXT REFERENCES users (id) ON DELETE SET NULL,
+    second_user_id TEXT REFERENCES users (id) ON DELETE SET NULL,
+    UNIQUE (first_user_id, second_user_id)
+);
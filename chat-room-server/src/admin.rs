@@ -0,0 +1,310 @@
+//! Maintenance tasks behind the `chat-room-admin` binary. They run against the same
+//! database as the server, read from `databases.main` in `Rocket.toml`.
+use std::fmt::Display;
+
+use qu_chat_models::{ApiScope, ApiToken, Bot, CreatedApiToken, Message, Room};
+use sqlx::migrate::MigrateError;
+
+use crate::authentication::hash;
+use crate::bots;
+use crate::store::{self, Db, DbConfig, RoomSummary};
+use crate::user::User;
+
+#[derive(Debug)]
+pub enum AdminError {
+    /// Boxed, figment errors are large enough to bloat every result.
+    Config(Box<rocket::figment::Error>),
+    Db(sqlx::Error),
+    Migrate(MigrateError),
+    UserExists(String),
+    UserNotFound(String),
+    NotABot(String),
+    UnknownScope(String),
+    TokenNotFound(String),
+    Password(std::io::Error),
+    EmptyPassword,
+}
+
+impl Display for AdminError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AdminError::Config(err) => write!(f, "Unable to read database config: {}", err),
+            AdminError::Db(err) => write!(f, "Database error: {}", err),
+            AdminError::Migrate(err) => write!(f, "Migration failed: {}", err),
+            AdminError::UserExists(name) => write!(f, "User {} already exists", name),
+            AdminError::UserNotFound(name) => write!(f, "User {} not found", name),
+            AdminError::NotABot(name) => write!(f, "{} is not a bot", name),
+            AdminError::UnknownScope(scope) => {
+                write!(f, "Unknown scope {}, use read or send", scope)
+            }
+            AdminError::TokenNotFound(id) => write!(f, "Token {} not found", id),
+            AdminError::Password(err) => write!(f, "Unable to read the password: {}", err),
+            AdminError::EmptyPassword => write!(f, "The password can't be empty"),
+        }
+    }
+}
+
+impl From<sqlx::Error> for AdminError {
+    fn from(value: sqlx::Error) -> Self {
+        AdminError::Db(value)
+    }
+}
+
+impl From<MigrateError> for AdminError {
+    fn from(value: MigrateError) -> Self {
+        AdminError::Migrate(value)
+    }
+}
+
+/// Config of the main database, resolved the same way the server does.
+pub fn database_config() -> Result<DbConfig, AdminError> {
+    rocket::Config::figment()
+        .extract_inner::<DbConfig>("databases.main")
+        .map_err(|err| AdminError::Config(Box::new(err)))
+}
+
+pub async fn connect(config: &DbConfig) -> Result<Db, AdminError> {
+    let config = DbConfig {
+        url: config.url.clone(),
+        max_connections: 1,
+    };
+    Ok(store::connect(&config).await?)
+}
+
+pub async fn migrate(db: &Db) -> Result<(), AdminError> {
+    db.migrate().await?;
+    Ok(())
+}
+
+pub async fn list_users(db: &Db) -> Result<Vec<User>, AdminError> {
+    Ok(db.users().await?)
+}
+
+async fn user_id(db: &Db, name: &str) -> Result<String, AdminError> {
+    db.user_by_name(name)
+        .await?
+        .map(|user| user.id)
+        .ok_or_else(|| AdminError::UserNotFound(name.to_string()))
+}
+
+pub async fn create_user(
+    db: &Db,
+    name: &str,
+    password: &str,
+    is_admin: bool,
+) -> Result<String, AdminError> {
+    if password.is_empty() {
+        return Err(AdminError::EmptyPassword);
+    }
+    if user_id(db, name).await.is_ok() {
+        return Err(AdminError::UserExists(name.to_string()));
+    }
+
+    let user = User {
+        id: uuid::Uuid::new_v4().to_string(),
+        name: name.to_string(),
+        secret: hash(password),
+        is_admin,
+        is_bot: false,
+        owner_id: None,
+    };
+    db.create_user(&user).await?;
+    Ok(user.id)
+}
+
+/// Removes the user together with their room states and sanctions, their messages stay as sent
+/// by "Deleted user".
+pub async fn delete_user(db: &Db, name: &str) -> Result<(), AdminError> {
+    let id = user_id(db, name).await?;
+    let now = chrono::Utc::now().timestamp();
+
+    // tokens are only checked against revocations, not against the users table
+    db.revoke_user_tokens(&id, now).await?;
+    db.delete_user(&id).await?;
+    Ok(())
+}
+
+/// Sets a new password and signs the user out everywhere.
+pub async fn reset_password(db: &Db, name: &str, password: &str) -> Result<(), AdminError> {
+    if password.is_empty() {
+        return Err(AdminError::EmptyPassword);
+    }
+    let id = user_id(db, name).await?;
+    db.set_secret(&id, &hash(password)).await?;
+    db.revoke_user_tokens(&id, chrono::Utc::now().timestamp())
+        .await?;
+    Ok(())
+}
+
+/// Revokes the tokens the user signed in with, or the API tokens of a bot.
+pub async fn revoke_tokens(db: &Db, name: &str) -> Result<(), AdminError> {
+    let id = user_id(db, name).await?;
+    db.revoke_user_tokens(&id, chrono::Utc::now().timestamp())
+        .await?;
+    for token in db.api_tokens(&id).await? {
+        db.delete_api_token(&id, &token.id).await?;
+    }
+    Ok(())
+}
+
+pub async fn revoke_token(db: &Db, token: &str) -> Result<(), AdminError> {
+    db.blacklist_token(token).await?;
+    Ok(())
+}
+
+pub async fn list_bots(db: &Db) -> Result<Vec<User>, AdminError> {
+    let users = db.users().await?;
+    Ok(users.into_iter().filter(|user| user.is_bot).collect())
+}
+
+async fn bot_id(db: &Db, name: &str) -> Result<String, AdminError> {
+    match db.user_by_name(name).await? {
+        Some(user) if user.is_bot => Ok(user.id),
+        Some(_) => Err(AdminError::NotABot(name.to_string())),
+        None => Err(AdminError::UserNotFound(name.to_string())),
+    }
+}
+
+/// Creates a bot managed by `owner` as well, or by admins only.
+pub async fn create_bot(db: &Db, name: &str, owner: Option<&str>) -> Result<Bot, AdminError> {
+    if user_id(db, name).await.is_ok() {
+        return Err(AdminError::UserExists(name.to_string()));
+    }
+    let owner_id = match owner {
+        Some(owner) => Some(user_id(db, owner).await?),
+        None => None,
+    };
+    Ok(bots::create_bot(db, name, owner_id.as_deref()).await?)
+}
+
+/// Removes the bot together with its tokens, its messages stay.
+pub async fn delete_bot(db: &Db, name: &str) -> Result<(), AdminError> {
+    let id = bot_id(db, name).await?;
+    db.delete_user(&id).await?;
+    Ok(())
+}
+
+pub async fn issue_token(
+    db: &Db,
+    bot: &str,
+    name: &str,
+    scopes: &[String],
+) -> Result<CreatedApiToken, AdminError> {
+    let id = bot_id(db, bot).await?;
+    let mut parsed = Vec::new();
+    for scope in scopes {
+        match scope.parse::<ApiScope>() {
+            Ok(scope) => parsed.push(scope),
+            Err(_) => return Err(AdminError::UnknownScope(scope.clone())),
+        }
+    }
+    Ok(bots::issue_token(db, &id, name, parsed).await?)
+}
+
+pub async fn list_tokens(db: &Db, bot: &str) -> Result<Vec<ApiToken>, AdminError> {
+    let id = bot_id(db, bot).await?;
+    Ok(db.api_tokens(&id).await?)
+}
+
+pub async fn revoke_api_token(db: &Db, bot: &str, token_id: &str) -> Result<(), AdminError> {
+    let id = bot_id(db, bot).await?;
+    if !db.delete_api_token(&id, token_id).await? {
+        return Err(AdminError::TokenNotFound(token_id.to_string()));
+    }
+    Ok(())
+}
+
+pub async fn list_rooms(db: &Db) -> Result<Vec<RoomSummary>, AdminError> {
+    Ok(db.room_summaries().await?)
+}
+
+/// Deletes rooms with no activity since `inactive_since`, or only lists them on a dry run.
+pub async fn prune_rooms(
+    db: &Db,
+    inactive_since: i64,
+    archived_only: bool,
+    dry_run: bool,
+) -> Result<Vec<RoomSummary>, AdminError> {
+    let stale = list_rooms(db)
+        .await?
+        .into_iter()
+        .filter(|summary| summary.last_activity < inactive_since)
+        .filter(|summary| !archived_only || summary.room.archived)
+        .collect::<Vec<RoomSummary>>();
+
+    if !dry_run {
+        for summary in &stale {
+            db.delete_room(&summary.room.id).await?;
+        }
+    }
+    Ok(stale)
+}
+
+pub struct SeedSummary {
+    pub user_name: String,
+    pub rooms: usize,
+    pub messages: usize,
+}
+
+const DEMO_USER: &str = "demo";
+const DEMO_ROOMS: [&str; 10] = [
+    "luna", "zara", "kai", "finn", "nova", "arlo", "sage", "milo", "iris", "jett",
+];
+const DEMO_MESSAGES: [&str; 10] = [
+    "hello world",
+    "quick fox",
+    "blue sky",
+    "coding fun",
+    "tech magic",
+    "data flow",
+    "swift code",
+    "green leaf",
+    "soft breeze",
+    "night star",
+];
+
+/// Adds rooms full of messages owned by a `demo` user, whose password is `demo` too.
+pub async fn seed(
+    db: &Db,
+    rooms: usize,
+    messages_per_room: usize,
+) -> Result<SeedSummary, AdminError> {
+    let user_id = match user_id(db, DEMO_USER).await {
+        Ok(id) => id,
+        Err(AdminError::UserNotFound(_)) => create_user(db, DEMO_USER, DEMO_USER, false).await?,
+        Err(err) => return Err(err),
+    };
+    let now = chrono::Utc::now().timestamp();
+
+    for i in 0..rooms {
+        let room = Room {
+            id: uuid::Uuid::new_v4().to_string(),
+            name: format!("{} {}", DEMO_ROOMS[i % DEMO_ROOMS.len()], i),
+            creator_id: user_id.clone(),
+            create_date: now - (rooms * messages_per_room) as i64 * 60,
+            topic: String::new(),
+            archived: false,
+        };
+        db.create_room(&room).await?;
+
+        for j in 0..messages_per_room {
+            let message = Message {
+                id: uuid::Uuid::new_v4().to_string(),
+                content: DEMO_MESSAGES[(i + j) % DEMO_MESSAGES.len()].to_string(),
+                sender_id: user_id.clone(),
+                room_id: room.id.clone(),
+                create_date: room.create_date + ((i * messages_per_room + j) as i64) * 60,
+                sender_name: DEMO_USER.to_string(),
+                client_id: None,
+                is_bot: false,
+            };
+            db.insert_message(&message).await?;
+        }
+    }
+
+    Ok(SeedSummary {
+        user_name: DEMO_USER.to_string(),
+        rooms,
+        messages: rooms * messages_per_room,
+    })
+}
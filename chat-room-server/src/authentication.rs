@@ -6,26 +6,43 @@
 #![allow(unused_macros)]
 #![allow(unused_results)]
 #![allow(unreachable_code)]
-use qu_chat_models::{RegisterParams, RegisterResponse, SignInParams, SignInResponse};
+use qu_chat_models::{
+    error_codes, ApiScope, LoginOutcome, RegisterParams, RegisterResponse, SignInParams,
+    SignInResponse,
+};
 use rocket::fairing::AdHoc;
 use rocket::http::Status;
 
 use rocket::outcome::try_outcome;
 use rocket::request::{FromRequest, Outcome, Request};
 use rocket::serde::json::Json;
-use rocket_db_pools::Connection;
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
+use std::net::IpAddr;
 
-use crate::base::{ApiResult, ApiResultBuilder, Db};
+use crate::base::{ApiResult, ApiResultBuilder, Error};
+use crate::bots::{required_scope, API_TOKEN_PREFIX};
 use crate::jwt::*;
+use crate::rate_limit::RateLimit;
+use crate::store::{Db, LoginRecord};
 use crate::user::User;
 
-static SECRET_KEY_MINE: &[u8] = b"hello";
+pub static SECRET_KEY_MINE: &[u8] = b"hello";
 
 pub struct UserId {
     pub id: String,
     pub token: String,
+    /// What the API token of a bot may do, none for users who can do everything.
+    pub scopes: Option<Vec<ApiScope>>,
+}
+
+impl UserId {
+    pub fn can(&self, scope: ApiScope) -> bool {
+        match &self.scopes {
+            Some(scopes) => scopes.contains(&scope),
+            None => true,
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -33,6 +50,7 @@ pub enum ApiKeyError {
     Missing,
     Invalid,
     Expired,
+    MissingScope,
     Db,
 }
 
@@ -61,7 +79,7 @@ impl<'r> FromRequest<'r> for UserId {
     async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
         //TODO: use my error
         let db = try_outcome!(req
-            .guard::<Connection<Db>>()
+            .guard::<Db>()
             .await
             .map_error(|_| (Status::InternalServerError, ApiKeyError::Db)));
 
@@ -75,37 +93,72 @@ impl<'r> FromRequest<'r> for UserId {
             Err(_) => return Outcome::Error((Status::Unauthorized, ApiKeyError::Invalid)),
         };
 
-        let token = match check_token_expired(token, db).await {
+        if token.starts_with(API_TOKEN_PREFIX) {
+            return api_token_user(req, &db, token).await;
+        }
+
+        let token = match check_token_expired(token, &db).await {
             Ok(token) => token,
             Err(err) => return Outcome::Error((Status::Unauthorized, err)),
         };
 
-        match validate_jwt(&token, SECRET_KEY_MINE) {
-            Ok(body) => Outcome::Success(UserId {
+        let body = match validate_jwt(&token, SECRET_KEY_MINE) {
+            Ok(body) => body,
+            Err(_) => return Outcome::Error((Status::Unauthorized, ApiKeyError::Invalid)),
+        };
+
+        match db.tokens_revoked_at(&body.user_id).await {
+            // a token issued in the second of the revocation, e.g. on signing in right after a
+            // password reset, is still accepted
+            Ok(Some(revoked_at)) if body.iat < revoked_at => {
+                Outcome::Error((Status::Unauthorized, ApiKeyError::Expired))
+            }
+            Ok(_) => Outcome::Success(UserId {
                 id: body.user_id.to_string(),
                 token: token,
+                scopes: None,
             }),
-            Err(_) => Outcome::Error((Status::Unauthorized, ApiKeyError::Invalid)),
+            Err(_) => Outcome::Error((Status::InternalServerError, ApiKeyError::Db)),
         }
     }
 }
 
+/// The bot an API token was issued to, as long as the token may make the request.
+async fn api_token_user(req: &Request<'_>, db: &Db, token: &str) -> Outcome<UserId, ApiKeyError> {
+    let api_token = match db.api_token(&hash(token)).await {
+        Ok(Some(api_token)) => api_token,
+        Ok(None) => return Outcome::Error((Status::Unauthorized, ApiKeyError::Invalid)),
+        Err(_) => return Outcome::Error((Status::InternalServerError, ApiKeyError::Db)),
+    };
+
+    match required_scope(req.method(), req.uri().path().as_str()) {
+        Some(scope) if api_token.scopes.contains(&scope) => Outcome::Success(UserId {
+            id: api_token.bot_id,
+            token: token.to_string(),
+            scopes: Some(api_token.scopes),
+        }),
+        _ => Outcome::Error((Status::Forbidden, ApiKeyError::MissingScope)),
+    }
+}
+
 #[post("/register", data = "<param>")]
 async fn register<'r>(
-    mut db: Connection<Db>,
+    _limit: RateLimit,
+    db: Db,
     param: Json<RegisterParams>,
 ) -> ApiResult<RegisterResponse> {
     //write on db
     let id = uuid::Uuid::new_v4().to_string();
-    let secret = hash(&param.password);
-    let insert_result = sqlx::query!(
-        "INSERT INTO users (id, name, secret) VALUES ($1, $2, $3)",
-        id,
-        param.username,
-        secret,
-    )
-    .execute(&mut **db)
-    .await;
+    let insert_result = db
+        .create_user(&User {
+            id: id.clone(),
+            name: param.username.clone(),
+            secret: hash(&param.password),
+            is_admin: false,
+            is_bot: false,
+            owner_id: None,
+        })
+        .await;
     //TODO: if user exists
 
     match insert_result {
@@ -117,55 +170,126 @@ async fn register<'r>(
     }
 }
 
-pub async fn check_token_expired(
-    token: &str,
-    mut db: Connection<Db>,
-) -> Result<String, ApiKeyError> {
-    #[derive(Serialize, Deserialize, Debug)]
-    struct Record {
-        id: i64,
-        token: String,
-    }
+pub async fn check_token_expired(token: &str, db: &Db) -> Result<String, ApiKeyError> {
     //TODO internal server if db failed
-    let res = sqlx::query_as!(
-        Record,
-        "SELECT * FROM token_blacklist WHERE token=($1)",
-        token
-    )
-    .fetch_optional(&mut **db)
-    .await
-    .map_err(|e| ApiKeyError::Db)?;
-
-    match res {
-        Some(_) => Err(ApiKeyError::Expired),
-        None => Ok(token.to_string()),
+    match db.is_blacklisted(token).await {
+        Ok(true) => Err(ApiKeyError::Expired),
+        Ok(false) => Ok(token.to_string()),
+        Err(_) => Err(ApiKeyError::Db),
     }
 }
 
-fn hash(str: &str) -> String {
+pub(crate) fn hash(str: &str) -> String {
     hex::encode(Sha256::digest(&str).to_ascii_lowercase())
 }
 
+/// Failed sign-ins in a row a username gets before it is locked.
+const MAX_FAILED_SIGNINS: i64 = 5;
+/// The first lock lasts this long and doubles with every further failure.
+const BASE_LOCK_SECS: i64 = 60;
+const MAX_LOCK_SECS: i64 = 60 * 60;
+/// Failures older than this don't count towards a lock.
+const FAILURE_WINDOW_SECS: i64 = 24 * 60 * 60;
+
+fn locked_until(failures: i64, last_failure: i64) -> Option<i64> {
+    if failures < MAX_FAILED_SIGNINS {
+        return None;
+    }
+    let doublings = (failures - MAX_FAILED_SIGNINS).min(16) as u32;
+    let lock = (BASE_LOCK_SECS * 2_i64.pow(doublings)).min(MAX_LOCK_SECS);
+    Some(last_failure + lock)
+}
+
+/// Failed attempts for the username since its last successful sign-in, with the time of the last one.
+async fn failed_signins(db: &Db, username: &str, now: i64) -> Result<(i64, i64), sqlx::Error> {
+    let (failures, last_failure) = db
+        .failed_logins(username, now - FAILURE_WINDOW_SECS)
+        .await?;
+    Ok((failures, last_failure.unwrap_or(0)))
+}
+
+async fn record_signin(
+    db: &Db,
+    user_id: Option<&str>,
+    username: &str,
+    ip: Option<&str>,
+    outcome: LoginOutcome,
+    now: i64,
+) -> Result<(), sqlx::Error> {
+    db.record_login(&LoginRecord {
+        user_id,
+        username,
+        ip,
+        outcome,
+        create_date: now,
+    })
+    .await
+}
+
 #[post("/signin", data = "<params>")]
-async fn signin(mut db: Connection<Db>, params: Json<SignInParams>) -> ApiResult<SignInResponse> {
-    let res = sqlx::query_as!(
-        User,
-        "SELECT * FROM users WHERE name = ($1)",
-        params.username
-    )
-    .fetch_one(&mut **db)
-    .await;
+async fn signin(
+    _limit: RateLimit,
+    ip: Option<IpAddr>,
+    db: Db,
+    params: Json<SignInParams>,
+) -> ApiResult<SignInResponse> {
+    let now = chrono::Utc::now().timestamp();
+    let ip = ip.map(|ip| ip.to_string());
 
-    let user: User;
-    match res {
-        Ok(usr) => user = usr,
-        Err(_) => return ApiResultBuilder::err("User not found"),
+    let res = db.user_by_name(&params.username).await;
+    let Ok(user) = res else {
+        return Err(Error::Internal(()));
+    };
+    let user_id = user.as_ref().map(|user| user.id.clone());
+
+    let Ok((failures, last_failure)) = failed_signins(&db, &params.username, now).await else {
+        return Err(Error::Internal(()));
     };
+    if locked_until(failures, last_failure).is_some_and(|until| until > now) {
+        let _ = record_signin(
+            &db,
+            user_id.as_deref(),
+            &params.username,
+            ip.as_deref(),
+            LoginOutcome::Locked,
+            now,
+        )
+        .await;
+        return Err(Error::forbidden_with_code(
+            error_codes::ACCOUNT_LOCKED,
+            "Too many failed sign-ins, this account is locked for a while.",
+        ));
+    }
 
-    if hash(&params.password) != user.secret {
-        return ApiResultBuilder::err("Invalid password");
+    let user = match user {
+        Some(user) if hash(&params.password) == user.secret => user,
+        _ => {
+            let _ = record_signin(
+                &db,
+                user_id.as_deref(),
+                &params.username,
+                ip.as_deref(),
+                LoginOutcome::BadCredentials,
+                now,
+            )
+            .await;
+            return Err(Error::logical_with_code(
+                error_codes::BAD_CREDENTIALS,
+                "Invalid username or password.",
+            ));
+        }
     };
 
+    let _ = record_signin(
+        &db,
+        Some(&user.id),
+        &params.username,
+        ip.as_deref(),
+        LoginOutcome::Success,
+        now,
+    )
+    .await;
+
     let token_res = generate_jwt(&user.id, SECRET_KEY_MINE);
 
     match token_res {
@@ -175,13 +299,8 @@ async fn signin(mut db: Connection<Db>, params: Json<SignInParams>) -> ApiResult
 }
 
 #[post("/signout")]
-async fn signout(mut db: Connection<Db>, user_id: UserId) -> ApiResult<String> {
-    let res = sqlx::query!(
-        "INSERT into token_blacklist (token) VALUES ($1)",
-        user_id.token
-    )
-    .execute(&mut **db)
-    .await;
+async fn signout(db: Db, user_id: UserId) -> ApiResult<String> {
+    let res = db.blacklist_token(&user_id.token).await;
 
     match res {
         Ok(_) => ApiResultBuilder::data("Successfully signed out".to_string()),
@@ -194,3 +313,58 @@ pub fn stage() -> AdHoc {
         rocket.mount("/auth", routes![register, signin, signout])
     })
 }
+
+#[cfg(test)]
+mod test {
+    use rocket::http::{ContentType, Header};
+    use rocket::local::asynchronous::Client;
+
+    use super::*;
+    use crate::test_util::{data, register, TempDatabase};
+
+    #[rocket::async_test]
+    async fn test_signin_after_revocation() {
+        let database = TempDatabase::new();
+        let client = Client::untracked(crate::build_with(database.figment()))
+            .await
+            .unwrap();
+        let db = client.rocket().state::<Db>().unwrap().clone();
+        let (_, id) = register(&client, "john").await;
+
+        // as a password reset does
+        let now = chrono::Utc::now().timestamp();
+        db.revoke_user_tokens(&id, now).await.unwrap();
+        let body = client
+            .post("/auth/signin")
+            .header(ContentType::JSON)
+            .body(r#"{"username":"john","password":"doe"}"#)
+            .dispatch()
+            .await
+            .into_string()
+            .await
+            .unwrap();
+        let auth = Header::new("Authorization", format!("Bearer {}", data(&body, "token")));
+        let whoami = || client.get("/users/whoami").header(auth.clone()).dispatch();
+        assert_eq!(whoami().await.status(), Status::Ok);
+
+        db.revoke_user_tokens(&id, now + 1).await.unwrap();
+        assert_eq!(whoami().await.status(), Status::Unauthorized);
+    }
+
+    #[test]
+    fn test_locked_until() {
+        assert_eq!(locked_until(MAX_FAILED_SIGNINS - 1, 1000), None);
+        assert_eq!(
+            locked_until(MAX_FAILED_SIGNINS, 1000),
+            Some(1000 + BASE_LOCK_SECS)
+        );
+        assert_eq!(
+            locked_until(MAX_FAILED_SIGNINS + 2, 1000),
+            Some(1000 + BASE_LOCK_SECS * 4)
+        );
+        assert_eq!(
+            locked_until(MAX_FAILED_SIGNINS + 100, 1000),
+            Some(1000 + MAX_LOCK_SECS)
+        );
+    }
+}
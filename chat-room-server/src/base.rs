@@ -1,15 +1,12 @@
-use qu_chat_models::Message;
+use qu_chat_models::RoomEvent;
+use rocket::http::Header;
 use rocket::response::Responder;
 use rocket::serde::json::Json;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
 pub struct Tx<T>(pub flume::Sender<T>);
 pub struct Rx<T>(pub flume::Receiver<T>);
 
-#[derive(rocket_db_pools::Database)]
-#[database("main")]
-pub struct Db(pub sqlx::SqlitePool);
-
 pub type ApiResult<T> = Result<Json<BaseRes<T>>, Error<'static>>;
 pub struct ApiResultBuilder;
 impl ApiResultBuilder {
@@ -18,7 +15,7 @@ impl ApiResultBuilder {
     }
 
     pub fn err<T>(msg: &'static str) -> ApiResult<T> {
-        Err(Error::Logical(Json(SimpleError { msg })))
+        Err(Error::Logical(Json(SimpleError::new(msg))))
     }
 
     pub fn from<T, E>(result: Result<T, E>, msg: &'static str) -> ApiResult<T> {
@@ -37,6 +34,21 @@ pub struct BaseRes<T> {
 #[derive(Serialize)]
 pub struct SimpleError<'r> {
     pub msg: &'r str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<&'r str>,
+}
+
+impl<'r> SimpleError<'r> {
+    pub fn new(msg: &'r str) -> Self {
+        SimpleError { msg, code: None }
+    }
+
+    pub fn with_code(code: &'r str, msg: &'r str) -> Self {
+        SimpleError {
+            msg,
+            code: Some(code),
+        }
+    }
 }
 
 #[derive(Responder)]
@@ -45,17 +57,44 @@ pub enum Error<'r> {
     Logical(Json<SimpleError<'r>>),
     #[response(status = 401)]
     Unauthorized(Json<SimpleError<'r>>),
+    #[response(status = 403)]
+    Forbidden(Json<SimpleError<'r>>),
+    #[response(status = 429)]
+    TooManyRequests(Json<SimpleError<'r>>, Header<'static>),
     #[response(status = 500)]
     Internal(()),
 }
 
 impl<'r> Error<'r> {
     pub fn logical(msg: &'r str) -> Error<'r> {
-        Error::Logical(Json(SimpleError { msg }))
+        Error::Logical(Json(SimpleError::new(msg)))
+    }
+
+    pub fn logical_with_code(code: &'r str, msg: &'r str) -> Error<'r> {
+        Error::Logical(Json(SimpleError::with_code(code, msg)))
+    }
+
+    pub fn forbidden(msg: &'r str) -> Error<'r> {
+        Error::Forbidden(Json(SimpleError::new(msg)))
+    }
+
+    pub fn forbidden_with_code(code: &'r str, msg: &'r str) -> Error<'r> {
+        Error::Forbidden(Json(SimpleError::with_code(code, msg)))
+    }
+
+    /// What the client is told, for transports that can't carry the status code.
+    pub fn msg(&self) -> &str {
+        match self {
+            Error::Logical(err)
+            | Error::Unauthorized(err)
+            | Error::Forbidden(err)
+            | Error::TooManyRequests(err, _) => err.msg,
+            Error::Internal(_) => "Internal server error.",
+        }
     }
 }
 
-#[derive(Debug, Serialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct RoomChange {
-    pub message: Message,
+    pub event: RoomEvent,
 }
@@ -0,0 +1,279 @@
+use std::process::ExitCode;
+
+use chat_room::admin::{self, AdminError};
+use chat_room::store::{Db, RoomSummary};
+use clap::{Parser, Subcommand};
+
+/// Administration of a quchat server, working on the database named in `Rocket.toml`.
+#[derive(Parser)]
+#[command(name = "chat-room-admin")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Manage user accounts
+    #[command(subcommand)]
+    Users(UsersCommand),
+    /// Inspect and clean up rooms
+    #[command(subcommand)]
+    Rooms(RoomsCommand),
+    /// Revoke issued tokens
+    #[command(subcommand)]
+    Tokens(TokensCommand),
+    /// Manage bot accounts and their API tokens
+    #[command(subcommand)]
+    Bots(BotsCommand),
+    /// Apply pending migrations
+    Migrate,
+    /// Fill the database with demo rooms and messages
+    Seed {
+        #[arg(long, default_value_t = 10)]
+        rooms: usize,
+        #[arg(long, default_value_t = 10)]
+        messages: usize,
+    },
+}
+
+#[derive(Subcommand)]
+enum UsersCommand {
+    List,
+    Create {
+        name: String,
+        /// Prompted for when missing. Given here, it ends up in the shell history and is shown
+        /// by `ps` to other users of the machine
+        #[arg(long)]
+        password: Option<String>,
+        #[arg(long)]
+        admin: bool,
+    },
+    Delete {
+        name: String,
+    },
+    /// Set a new password and sign the user out everywhere
+    ResetPassword {
+        name: String,
+        /// Prompted for when missing. Given here, it ends up in the shell history and is shown
+        /// by `ps` to other users of the machine
+        #[arg(long)]
+        password: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum RoomsCommand {
+    List,
+    /// Delete rooms without messages in the last days
+    Prune {
+        #[arg(long)]
+        inactive_days: u32,
+        #[arg(long)]
+        archived_only: bool,
+        #[arg(long)]
+        dry_run: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum TokensCommand {
+    /// Revoke every token issued to a user
+    RevokeUser { name: String },
+    /// Revoke a single token
+    Revoke { token: String },
+}
+
+#[derive(Subcommand)]
+enum BotsCommand {
+    List,
+    Create {
+        name: String,
+        /// User who can manage the bot too
+        #[arg(long)]
+        owner: Option<String>,
+    },
+    /// Delete a bot with its tokens, keeping its messages
+    Delete {
+        name: String,
+    },
+    /// Issue an API token, printed once
+    Token {
+        bot: String,
+        /// What the token is for
+        #[arg(long)]
+        name: String,
+        /// read, send, or both separated by a comma
+        #[arg(long, value_delimiter = ',', required = true)]
+        scopes: Vec<String>,
+    },
+    /// List the API tokens of a bot
+    Tokens {
+        bot: String,
+    },
+    /// Revoke an API token by its id
+    Revoke {
+        bot: String,
+        token_id: String,
+    },
+}
+
+#[rocket::main]
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let res = match admin::database_config() {
+        Ok(config) => match admin::connect(&config).await {
+            Ok(db) => {
+                let res = run(cli.command, &db).await;
+                db.close().await;
+                res
+            }
+            Err(err) => Err(err),
+        },
+        Err(err) => Err(err),
+    };
+
+    match res {
+        Ok(_) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("{}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn run(command: Command, db: &Db) -> Result<(), AdminError> {
+    match command {
+        Command::Users(UsersCommand::List) => {
+            for user in admin::list_users(db).await? {
+                let role = match (user.is_admin, user.is_bot) {
+                    (true, _) => "admin",
+                    (_, true) => "bot",
+                    _ => "",
+                };
+                println!("{:<36}  {:<20}  {}", user.id, user.name, role);
+            }
+        }
+        Command::Users(UsersCommand::Create {
+            name,
+            password,
+            admin,
+        }) => {
+            let password = password_or_prompt(password).map_err(AdminError::Password)?;
+            let id = admin::create_user(db, &name, &password, admin).await?;
+            println!("Created user {} ({})", name, id);
+        }
+        Command::Users(UsersCommand::Delete { name }) => {
+            admin::delete_user(db, &name).await?;
+            println!("Deleted user {}", name);
+        }
+        Command::Users(UsersCommand::ResetPassword { name, password }) => {
+            let password = password_or_prompt(password).map_err(AdminError::Password)?;
+            admin::reset_password(db, &name, &password).await?;
+            println!("Reset password of {}", name);
+        }
+        Command::Rooms(RoomsCommand::List) => {
+            for summary in admin::list_rooms(db).await? {
+                print_room(&summary);
+            }
+        }
+        Command::Rooms(RoomsCommand::Prune {
+            inactive_days,
+            archived_only,
+            dry_run,
+        }) => {
+            let inactive_since =
+                chrono::Utc::now().timestamp() - i64::from(inactive_days) * 24 * 60 * 60;
+            let pruned = admin::prune_rooms(db, inactive_since, archived_only, dry_run).await?;
+            for summary in &pruned {
+                print_room(summary);
+            }
+            let verb = if dry_run { "Would prune" } else { "Pruned" };
+            println!("{} {} rooms", verb, pruned.len());
+        }
+        Command::Tokens(TokensCommand::RevokeUser { name }) => {
+            admin::revoke_tokens(db, &name).await?;
+            println!("Revoked all tokens of {}", name);
+        }
+        Command::Tokens(TokensCommand::Revoke { token }) => {
+            admin::revoke_token(db, &token).await?;
+            println!("Revoked token");
+        }
+        Command::Bots(BotsCommand::List) => {
+            for bot in admin::list_bots(db).await? {
+                println!("{:<36}  {}", bot.id, bot.name);
+            }
+        }
+        Command::Bots(BotsCommand::Create { name, owner }) => {
+            let bot = admin::create_bot(db, &name, owner.as_deref()).await?;
+            println!("Created bot {} ({})", bot.name, bot.id);
+        }
+        Command::Bots(BotsCommand::Delete { name }) => {
+            admin::delete_bot(db, &name).await?;
+            println!("Deleted bot {}", name);
+        }
+        Command::Bots(BotsCommand::Token { bot, name, scopes }) => {
+            let created = admin::issue_token(db, &bot, &name, &scopes).await?;
+            println!("{}", created.token);
+            eprintln!(
+                "Issued token {} to {}, it won't be shown again",
+                created.info.id, bot
+            );
+        }
+        Command::Bots(BotsCommand::Tokens { bot }) => {
+            for token in admin::list_tokens(db, &bot).await? {
+                let scopes = token
+                    .scopes
+                    .iter()
+                    .map(|scope| scope.as_str())
+                    .collect::<Vec<_>>()
+                    .join(",");
+                println!("{:<36}  {:<20}  {}", token.id, token.name, scopes);
+            }
+        }
+        Command::Bots(BotsCommand::Revoke { bot, token_id }) => {
+            admin::revoke_api_token(db, &bot, &token_id).await?;
+            println!("Revoked token {}", token_id);
+        }
+        Command::Migrate => {
+            admin::migrate(db).await?;
+            println!("Migrations are up to date");
+        }
+        Command::Seed { rooms, messages } => {
+            let summary = admin::seed(db, rooms, messages).await?;
+            println!(
+                "Added {} rooms and {} messages owned by {}",
+                summary.rooms, summary.messages, summary.user_name
+            );
+        }
+    }
+    Ok(())
+}
+
+fn print_room(summary: &RoomSummary) {
+    let last_activity = chrono::DateTime::from_timestamp(summary.last_activity, 0)
+        .map(|date| date.format("%Y-%m-%d %H:%M").to_string())
+        .unwrap_or_default();
+    let archived = if summary.room.archived {
+        "archived"
+    } else {
+        ""
+    };
+    println!(
+        "{:<36}  {:<20}  {:>6} messages  {}  {}",
+        summary.room.id, summary.room.name, summary.messages, last_activity, archived
+    );
+}
+
+/// The `--password`, or else one typed without being shown.
+fn password_or_prompt(password: Option<String>) -> std::io::Result<String> {
+    match password {
+        Some(password) => {
+            eprintln!(
+                "Warning: --password is kept in the shell history and shown by `ps`, leave it out to be prompted"
+            );
+            Ok(password)
+        }
+        None => rpassword::prompt_password("Password: "),
+    }
+}
@@ -0,0 +1,297 @@
+//! Bot accounts, which can't sign in and act with scoped API tokens instead. Users manage the
+//! bots they create, admins every bot. Tokens don't expire, they're revoked one by one or
+//! together with their bot.
+use qu_chat_models::{
+    ApiScope, ApiToken, Bot, CreateApiTokenParams, CreateBotParams, CreatedApiToken,
+};
+use rocket::fairing::AdHoc;
+use rocket::http::Method;
+use rocket::serde::json::Json;
+
+use crate::authentication::{hash, UserId};
+use crate::base::{ApiResult, ApiResultBuilder, Error};
+use crate::store::{Db, StoreResult};
+use crate::user::User;
+
+/// Tells API tokens apart from the JWTs users sign in with.
+pub const API_TOKEN_PREFIX: &str = "qbt_";
+
+impl From<User> for Bot {
+    fn from(user: User) -> Self {
+        Bot {
+            id: user.id,
+            name: user.name,
+            owner_id: user.owner_id,
+        }
+    }
+}
+
+/// The scope a request made with an API token needs, none when tokens can't make it at all.
+pub fn required_scope(method: Method, path: &str) -> Option<ApiScope> {
+    let segments = path.trim_matches('/').split('/').collect::<Vec<_>>();
+    match (method, segments.as_slice()) {
+        (Method::Get, _) => Some(ApiScope::Read),
+        (Method::Post, ["messages", "send"]) => Some(ApiScope::Send),
+        // registering and running room commands
+        (Method::Post | Method::Delete, ["rooms", _, "commands", ..]) => Some(ApiScope::Send),
+        _ => None,
+    }
+}
+
+/// Creates a bot, `owner_id` is none for bots of the server admin.
+pub async fn create_bot(db: &Db, name: &str, owner_id: Option<&str>) -> StoreResult<Bot> {
+    let bot = User {
+        id: uuid::Uuid::new_v4().to_string(),
+        name: name.to_string(),
+        // no password hashes to it, so it can't sign in
+        secret: String::new(),
+        is_admin: false,
+        is_bot: true,
+        owner_id: owner_id.map(str::to_string),
+    };
+    db.create_user(&bot).await?;
+    Ok(Bot::from(bot))
+}
+
+/// Issues a token for the bot. Only its hash is stored, so it can't be shown again.
+pub async fn issue_token(
+    db: &Db,
+    bot_id: &str,
+    name: &str,
+    scopes: Vec<ApiScope>,
+) -> StoreResult<CreatedApiToken> {
+    let token = format!(
+        "{}{}{}",
+        API_TOKEN_PREFIX,
+        uuid::Uuid::new_v4().simple(),
+        uuid::Uuid::new_v4().simple()
+    );
+    let info = ApiToken {
+        id: uuid::Uuid::new_v4().to_string(),
+        bot_id: bot_id.to_string(),
+        name: name.to_string(),
+        scopes,
+        create_date: chrono::Utc::now().timestamp(),
+    };
+    db.create_api_token(&info, &hash(&token)).await?;
+    Ok(CreatedApiToken { token, info })
+}
+
+/// The bot, if the user can manage it.
+pub(crate) async fn managed_bot(
+    db: &Db,
+    user_id: &UserId,
+    bot_id: &str,
+) -> Result<User, Error<'static>> {
+    let bot = db.user(bot_id).await;
+    let user = db.user(&user_id.id).await;
+
+    match (bot, user) {
+        (Ok(Some(bot)), Ok(Some(user)))
+            if bot.is_bot && (bot.owner_id.as_ref() == Some(&user.id) || user.is_admin) =>
+        {
+            Ok(bot)
+        }
+        (Ok(Some(bot)), Ok(_)) if bot.is_bot => Err(Error::forbidden(
+            "Only the owner of the bot or an admin can do this.",
+        )),
+        (Ok(_), Ok(_)) => Err(Error::logical("Bot doesn't exists.")),
+        _ => Err(Error::Internal(())),
+    }
+}
+
+#[post("/", data = "<params>")]
+async fn create(params: Json<CreateBotParams>, user_id: UserId, db: Db) -> ApiResult<Bot> {
+    if params.name.trim().is_empty() {
+        return ApiResultBuilder::err("Bot name can't be empty.");
+    }
+    match db.user_by_name(&params.name).await {
+        Ok(None) => (),
+        Ok(Some(_)) => return ApiResultBuilder::err("This name is taken."),
+        Err(_) => return Err(Error::Internal(())),
+    }
+
+    let result = create_bot(&db, &params.name, Some(&user_id.id)).await;
+    ApiResultBuilder::from(result, "Unable to create bot")
+}
+
+/// The bots of the user, or every bot for admins.
+#[get("/")]
+async fn list(user_id: UserId, db: Db) -> ApiResult<Vec<Bot>> {
+    let (Ok(Some(user)), Ok(users)) = (db.user(&user_id.id).await, db.users().await) else {
+        return Err(Error::Internal(()));
+    };
+    let bots = users
+        .into_iter()
+        .filter(|bot| bot.is_bot && (user.is_admin || bot.owner_id.as_ref() == Some(&user.id)))
+        .map(Bot::from)
+        .collect();
+    ApiResultBuilder::data(bots)
+}
+
+/// Deletes the bot along with its tokens, its messages stay as sent by "Deleted user".
+#[delete("/<id>")]
+async fn delete(id: &str, user_id: UserId, db: Db) -> ApiResult<String> {
+    managed_bot(&db, &user_id, id).await?;
+
+    let result = db.delete_user(id).await;
+    ApiResultBuilder::from(
+        result.map(|_| "Deleted bot".to_string()),
+        "Unable to delete bot",
+    )
+}
+
+#[post("/<id>/tokens", data = "<params>")]
+async fn create_token(
+    id: &str,
+    params: Json<CreateApiTokenParams>,
+    user_id: UserId,
+    db: Db,
+) -> ApiResult<CreatedApiToken> {
+    managed_bot(&db, &user_id, id).await?;
+    if params.scopes.is_empty() {
+        return ApiResultBuilder::err("A token needs at least one scope.");
+    }
+
+    let params = params.into_inner();
+    let result = issue_token(&db, id, &params.name, params.scopes).await;
+    ApiResultBuilder::from(result, "Unable to create token")
+}
+
+#[get("/<id>/tokens")]
+async fn tokens(id: &str, user_id: UserId, db: Db) -> ApiResult<Vec<ApiToken>> {
+    managed_bot(&db, &user_id, id).await?;
+
+    let result = db.api_tokens(id).await;
+    ApiResultBuilder::from(result, "Unable to fetch tokens")
+}
+
+#[delete("/<id>/tokens/<token_id>")]
+async fn revoke_token(id: &str, token_id: &str, user_id: UserId, db: Db) -> ApiResult<String> {
+    managed_bot(&db, &user_id, id).await?;
+
+    match db.delete_api_token(id, token_id).await {
+        Ok(true) => ApiResultBuilder::data("Revoked token".to_string()),
+        Ok(false) => ApiResultBuilder::err("Token doesn't exists."),
+        Err(_) => Err(Error::Internal(())),
+    }
+}
+
+pub fn stage() -> AdHoc {
+    AdHoc::on_ignite("Bots Stage", |rocket| async {
+        rocket.mount(
+            "/bots",
+            routes![create, list, delete, create_token, tokens, revoke_token],
+        )
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use rocket::http::{ContentType, Header, Status};
+    use rocket::local::asynchronous::Client;
+
+    use crate::test_util::{data, TempDatabase};
+
+    #[rocket::async_test]
+    async fn test_scoped_token() {
+        let database = TempDatabase::new();
+        let client = Client::untracked(crate::build_with(database.figment()))
+            .await
+            .unwrap();
+
+        let body = client
+            .post("/auth/register")
+            .header(ContentType::JSON)
+            .body(r#"{"username":"john","password":"doe"}"#)
+            .dispatch()
+            .await
+            .into_string()
+            .await
+            .unwrap();
+        let auth = Header::new("Authorization", format!("Bearer {}", data(&body, "token")));
+        let body = client
+            .post("/rooms")
+            .header(ContentType::JSON)
+            .header(auth.clone())
+            .body(r#"{"name":"builds"}"#)
+            .dispatch()
+            .await
+            .into_string()
+            .await
+            .unwrap();
+        let room_id = data(&body, "id");
+        let body = client
+            .post("/bots")
+            .header(ContentType::JSON)
+            .header(auth.clone())
+            .body(r#"{"name":"ci"}"#)
+            .dispatch()
+            .await
+            .into_string()
+            .await
+            .unwrap();
+        let bot_id = data(&body, "id");
+
+        let mut tokens = Vec::new();
+        for scopes in [r#"["read"]"#, r#"["read","send"]"#] {
+            let body = client
+                .post(format!("/bots/{}/tokens", bot_id))
+                .header(ContentType::JSON)
+                .header(auth.clone())
+                .body(format!(r#"{{"name":"deploys","scopes":{}}}"#, scopes))
+                .dispatch()
+                .await
+                .into_string()
+                .await
+                .unwrap();
+            let token = data(&body, "token");
+            assert!(token.starts_with(super::API_TOKEN_PREFIX));
+            let id = data(&body, "id");
+            tokens.push((
+                Header::new("Authorization", format!("Bearer {}", token)),
+                id,
+            ));
+        }
+        let (reader, _) = tokens[0].clone();
+        let (sender, sender_id) = tokens[1].clone();
+
+        let send = |auth: Header<'static>| {
+            client
+                .post("/messages/send")
+                .header(ContentType::JSON)
+                .header(auth)
+                .body(format!(r#"{{"text":"green","room_id":"{}"}}"#, room_id))
+                .dispatch()
+        };
+        assert_eq!(send(reader.clone()).await.status(), Status::Forbidden);
+        assert_eq!(send(sender.clone()).await.status(), Status::Ok);
+        let history = client
+            .get(format!("/messages/{}", room_id))
+            .header(reader.clone())
+            .dispatch()
+            .await
+            .into_string()
+            .await
+            .unwrap();
+        assert!(history.contains(r#""is_bot":true"#));
+        let status = client
+            .post("/rooms")
+            .header(ContentType::JSON)
+            .header(sender.clone())
+            .body(r#"{"name":"spam"}"#)
+            .dispatch()
+            .await
+            .status();
+        assert_eq!(status, Status::Forbidden);
+
+        let status = client
+            .delete(format!("/bots/{}/tokens/{}", bot_id, sender_id))
+            .header(auth)
+            .dispatch()
+            .await
+            .status();
+        assert_eq!(status, Status::Ok);
+        assert_eq!(send(sender).await.status(), Status::Unauthorized);
+    }
+}
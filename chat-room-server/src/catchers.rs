@@ -1,12 +1,30 @@
-use rocket::{fairing::AdHoc, serde::json::Json};
+use qu_chat_models::error_codes;
+use rocket::{fairing::AdHoc, http::Header, serde::json::Json, Request};
 
 use crate::base::{ApiResult, Error, SimpleError};
+use crate::rate_limit::RetryAfter;
 
 #[catch(401)]
 pub fn unauthorized() -> ApiResult<()> {
-    Err(Error::Unauthorized(Json(SimpleError {
-        msg: "Unauthorised",
-    })))
+    Err(Error::Unauthorized(Json(SimpleError::new("Unauthorised"))))
+}
+
+/// Refused by a guard, e.g. an API token without the scope the request needs.
+#[catch(403)]
+pub fn forbidden() -> ApiResult<()> {
+    Err(Error::forbidden_with_code(
+        error_codes::MISSING_SCOPE,
+        "The token can't do this.",
+    ))
+}
+
+#[catch(429)]
+pub fn too_many_requests(req: &Request) -> ApiResult<()> {
+    let RetryAfter(secs) = req.local_cache(|| RetryAfter(1));
+    Err(Error::TooManyRequests(
+        Json(SimpleError::new("Too many requests")),
+        Header::new("Retry-After", secs.to_string()),
+    ))
 }
 
 #[catch(500)]
@@ -16,11 +34,20 @@ pub fn internal_server() -> ApiResult<()> {
 
 #[catch(404)]
 pub fn notfound() -> ApiResult<()> {
-    Err(Error::Logical(Json(SimpleError { msg: "Not Found" })))
+    Err(Error::Logical(Json(SimpleError::new("Not Found"))))
 }
 
 pub fn stage() -> AdHoc {
     AdHoc::on_ignite("catcher", |rocket| async {
-        rocket.register("/", catchers![notfound, internal_server, unauthorized])
+        rocket.register(
+            "/",
+            catchers![
+                notfound,
+                internal_server,
+                unauthorized,
+                forbidden,
+                too_many_requests
+            ],
+        )
     })
 }
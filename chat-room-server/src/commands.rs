@@ -0,0 +1,309 @@
+//! Slash commands bots register for a room. Running one, e.g. `/deploy main`, doesn't send a
+//! message, it publishes a `RoomEvent::CommandInvoked` the bot follows on the room streams or
+//! receives through an outgoing webhook.
+use qu_chat_models::{InvokeCommandParams, RegisterCommandParams, RoomCommand, RoomEvent};
+use rocket::fairing::AdHoc;
+use rocket::serde::json::Json;
+use rocket::State;
+
+use crate::authentication::UserId;
+use crate::base::{ApiResult, ApiResultBuilder, Error, RoomChange};
+use crate::bots::managed_bot;
+use crate::moderation::ensure_not_sanctioned;
+use crate::pubsub::Changes;
+use crate::rate_limit::RateLimit;
+use crate::rooms::ensure_room_admin;
+use crate::store::Db;
+
+/// Commands the terminal client runs itself, bots can't take them.
+pub const RESERVED: [&str; 8] = [
+    "join", "leave", "nick", "topic", "me", "search", "dm", "help",
+];
+
+fn valid_name(name: &str) -> bool {
+    (1..=32).contains(&name.len())
+        && name
+            .chars()
+            .all(|ch| ch.is_ascii_alphanumeric() || ch == '-' || ch == '_')
+}
+
+/// Registers the command for a bot, by a room admin. Only admins choose which bot answers a
+/// command, so they are also the only ones taking a name over from another bot.
+#[post("/<room_id>/commands", data = "<params>", rank = 2)]
+async fn register(
+    room_id: &str,
+    params: Json<RegisterCommandParams>,
+    user_id: UserId,
+    db: Db,
+) -> ApiResult<RoomCommand> {
+    ensure_room_admin(&db, &user_id, room_id).await?;
+    let bot = match params.bot_id {
+        Some(ref bot_id) => managed_bot(&db, &user_id, bot_id).await?,
+        None => match db.user(&user_id.id).await {
+            Ok(Some(user)) if user.is_bot => user,
+            Ok(_) => return ApiResultBuilder::err("Name the bot answering the command."),
+            Err(_) => return Err(Error::Internal(())),
+        },
+    };
+    match db.room(room_id).await {
+        Ok(Some(room)) if room.archived => return ApiResultBuilder::err("Room is archived."),
+        Ok(Some(_)) => (),
+        Ok(None) => return ApiResultBuilder::err("Room doesn't exists."),
+        Err(_) => return Err(Error::Internal(())),
+    }
+    let name = params.name.trim_start_matches('/').to_lowercase();
+    if !valid_name(&name) {
+        return ApiResultBuilder::err(
+            "A command name is up to 32 letters, digits, dashes and underscores.",
+        );
+    }
+    if RESERVED.contains(&name.as_str()) {
+        return ApiResultBuilder::err("This command is built in.");
+    }
+
+    let command = RoomCommand {
+        room_id: room_id.to_string(),
+        name,
+        description: params.description.trim().to_string(),
+        bot_id: bot.id,
+        bot_name: bot.name,
+    };
+    let result = db.register_command(&command).await;
+    ApiResultBuilder::from(result.map(|_| command), "Unable to register command")
+}
+
+#[get("/<room_id>/commands")]
+async fn list(room_id: &str, _user_id: UserId, db: Db) -> ApiResult<Vec<RoomCommand>> {
+    let result = db.room_commands(room_id).await;
+    ApiResultBuilder::from(result, "Unable to fetch commands")
+}
+
+async fn command(db: &Db, room_id: &str, name: &str) -> Result<RoomCommand, Error<'static>> {
+    match db.room_commands(room_id).await {
+        Ok(commands) => commands
+            .into_iter()
+            .find(|command| command.name == name)
+            .ok_or(Error::logical("Unknown command.")),
+        Err(_) => Err(Error::Internal(())),
+    }
+}
+
+/// Removes the command, by the bot answering it or a room admin.
+#[delete("/<room_id>/commands/<name>")]
+async fn unregister(room_id: &str, name: &str, user_id: UserId, db: Db) -> ApiResult<String> {
+    let command = command(&db, room_id, name).await?;
+    if command.bot_id != user_id.id {
+        ensure_room_admin(&db, &user_id, room_id).await?;
+    }
+
+    match db.delete_command(room_id, name).await {
+        Ok(_) => ApiResultBuilder::data("Removed command".to_string()),
+        Err(_) => Err(Error::Internal(())),
+    }
+}
+
+#[post("/<room_id>/commands/<name>", data = "<params>")]
+async fn invoke(
+    _limit: RateLimit,
+    room_id: &str,
+    name: &str,
+    params: Json<InvokeCommandParams>,
+    changes: &State<Changes>,
+    user_id: UserId,
+    db: Db,
+) -> ApiResult<String> {
+    match db.room(room_id).await {
+        Ok(Some(room)) if room.archived => return ApiResultBuilder::err("Room is archived."),
+        Ok(Some(_)) => (),
+        Ok(None) => return ApiResultBuilder::err("Room doesn't exists."),
+        Err(_) => return Err(Error::Internal(())),
+    }
+    ensure_not_sanctioned(&db, room_id, &user_id.id, false).await?;
+    let command = command(&db, room_id, name).await?;
+    let user = match db.user(&user_id.id).await {
+        Ok(Some(user)) => user,
+        _ => return Err(Error::Internal(())),
+    };
+
+    let change = RoomChange {
+        event: RoomEvent::CommandInvoked {
+            room_id: command.room_id,
+            bot_id: command.bot_id,
+            command: command.name,
+            args: params.args.trim().to_string(),
+            user_id: user.id,
+            user_name: user.name,
+        },
+    };
+    match changes.publish(change).await {
+        Ok(_) => ApiResultBuilder::data(format!("Sent to {}", command.bot_name)),
+        Err(_) => Err(Error::Internal(())),
+    }
+}
+
+pub fn stage() -> AdHoc {
+    AdHoc::on_ignite("Commands Stage", |rocket| async {
+        rocket.mount("/rooms", routes![register, list, unregister, invoke])
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use qu_chat_models::RoomEvent;
+    use rocket::http::{ContentType, Header, Status};
+    use rocket::local::asynchronous::Client;
+
+    use crate::pubsub::Changes;
+    use crate::test_util::{data, TempDatabase};
+
+    #[rocket::async_test]
+    async fn test_bot_command() {
+        let database = TempDatabase::new();
+        let client = Client::untracked(crate::build_with(database.figment()))
+            .await
+            .unwrap();
+
+        let body = client
+            .post("/auth/register")
+            .header(ContentType::JSON)
+            .body(r#"{"username":"john","password":"doe"}"#)
+            .dispatch()
+            .await
+            .into_string()
+            .await
+            .unwrap();
+        let auth = Header::new("Authorization", format!("Bearer {}", data(&body, "token")));
+        let body = client
+            .post("/rooms")
+            .header(ContentType::JSON)
+            .header(auth.clone())
+            .body(r#"{"name":"releases"}"#)
+            .dispatch()
+            .await
+            .into_string()
+            .await
+            .unwrap();
+        let room_id = data(&body, "id");
+        let body = client
+            .post("/bots")
+            .header(ContentType::JSON)
+            .header(auth.clone())
+            .body(r#"{"name":"deployer"}"#)
+            .dispatch()
+            .await
+            .into_string()
+            .await
+            .unwrap();
+        let bot_id = data(&body, "id");
+        let body = client
+            .post(format!("/bots/{}/tokens", bot_id))
+            .header(ContentType::JSON)
+            .header(auth.clone())
+            .body(r#"{"name":"commands","scopes":["read","send"]}"#)
+            .dispatch()
+            .await
+            .into_string()
+            .await
+            .unwrap();
+        let bot = Header::new("Authorization", format!("Bearer {}", data(&body, "token")));
+
+        let body = client
+            .post("/auth/register")
+            .header(ContentType::JSON)
+            .body(r#"{"username":"alice","password":"doe"}"#)
+            .dispatch()
+            .await
+            .into_string()
+            .await
+            .unwrap();
+        let alice = Header::new("Authorization", format!("Bearer {}", data(&body, "token")));
+        let body = client
+            .post("/bots")
+            .header(ContentType::JSON)
+            .header(alice.clone())
+            .body(r#"{"name":"thief"}"#)
+            .dispatch()
+            .await
+            .into_string()
+            .await
+            .unwrap();
+        let thief_id = data(&body, "id");
+
+        let register = |auth: Header<'static>, name: &str, bot_id: Option<&str>| {
+            let bot_id = bot_id.map_or(String::new(), |id| format!(r#","bot_id":"{}""#, id));
+            client
+                .post(format!("/rooms/{}/commands", room_id))
+                .header(ContentType::JSON)
+                .header(auth)
+                .body(format!(
+                    r#"{{"name":"{}","description":"Deploys"{}}}"#,
+                    name, bot_id
+                ))
+                .dispatch()
+        };
+        // only room admins register commands, for bots they manage
+        let status = register(bot.clone(), "deploy", None).await.status();
+        assert_eq!(status, Status::Forbidden);
+        let status = register(alice, "deploy", Some(&thief_id)).await.status();
+        assert_eq!(status, Status::Forbidden);
+        let status = register(auth.clone(), "deploy", Some(&thief_id))
+            .await
+            .status();
+        assert_eq!(status, Status::Forbidden);
+        let body = register(auth.clone(), "nick", Some(&bot_id))
+            .await
+            .into_string()
+            .await
+            .unwrap();
+        assert!(body.contains("built in"));
+        let body = register(auth.clone(), "/Deploy", Some(&bot_id))
+            .await
+            .into_string()
+            .await
+            .unwrap();
+        assert!(body.contains(r#""name":"deploy""#));
+
+        let mut events = client.rocket().state::<Changes>().unwrap().subscribe();
+        let status = client
+            .post(format!("/rooms/{}/commands/deploy", room_id))
+            .header(ContentType::JSON)
+            .header(auth.clone())
+            .body(r#"{"args":" main "}"#)
+            .dispatch()
+            .await
+            .status();
+        assert_eq!(status, Status::Ok);
+        match events.recv().await.unwrap().event {
+            RoomEvent::CommandInvoked {
+                bot_id: to,
+                command,
+                args,
+                user_name,
+                ..
+            } => {
+                assert_eq!(to, bot_id);
+                assert_eq!(command, "deploy");
+                assert_eq!(args, "main");
+                assert_eq!(user_name, "john");
+            }
+            event => panic!("unexpected event {:?}", event),
+        }
+
+        let status = client
+            .delete(format!("/rooms/{}/commands/deploy", room_id))
+            .header(bot)
+            .dispatch()
+            .await
+            .status();
+        assert_eq!(status, Status::Ok);
+        let body = client
+            .get(format!("/rooms/{}/commands", room_id))
+            .header(auth)
+            .dispatch()
+            .await
+            .into_string()
+            .await
+            .unwrap();
+        assert_eq!(body, r#"{"data":[]}"#);
+    }
+}
@@ -0,0 +1,222 @@
+//! One stream per session carrying the events of every room, clients route them by room id.
+use std::collections::HashMap;
+
+use qu_chat_models::RoomEvent;
+use rocket::fairing::AdHoc;
+use rocket::response::stream::{Event, EventStream};
+use rocket::tokio::sync::broadcast::error::RecvError;
+use rocket::{Shutdown, State};
+
+use crate::authentication::UserId;
+use crate::moderation::{active_sanction, Sanction};
+use crate::pubsub::Changes;
+use crate::store::Db;
+
+#[get("/")]
+async fn events(
+    changes: &State<Changes>,
+    user_id: UserId,
+    db: Db,
+    mut shutdown: Shutdown,
+) -> EventStream![] {
+    let mut rx = changes.subscribe();
+    // whether the user is banned from the rooms events came from, until a ban or unban of theirs
+    let mut banned: HashMap<String, bool> = HashMap::new();
+
+    EventStream! {
+        loop {
+            let change = rocket::tokio::select! {
+                change = rx.recv() => match change {
+                    Ok(change) => change,
+                    Err(RecvError::Closed) => break,
+                    Err(RecvError::Lagged(_)) => continue,
+                },
+                _ = &mut shutdown => break,
+            };
+
+            let room_id = change.event.room_id().to_string();
+            match &change.event {
+                // told to the user, so clients close the room
+                RoomEvent::UserBanned { user_id: target, .. }
+                | RoomEvent::UserUnbanned { user_id: target, .. }
+                    if *target == user_id.id =>
+                {
+                    banned.remove(&room_id);
+                    yield Event::json(&change.event);
+                    continue;
+                }
+                _ => (),
+            }
+            let is_banned = match banned.get(&room_id) {
+                Some(is_banned) => *is_banned,
+                None => match active_sanction(&db, &room_id, &user_id.id).await {
+                    Ok(sanction) => {
+                        let is_banned =
+                            matches!(sanction, Some(Sanction::Banned | Sanction::Outsider));
+                        banned.insert(room_id, is_banned);
+                        is_banned
+                    }
+                    Err(_) => continue,
+                },
+            };
+            if is_banned {
+                continue;
+            }
+
+            yield Event::json(&change.event);
+        }
+    }
+}
+
+pub fn stage() -> AdHoc {
+    AdHoc::on_ignite("Events Stage", |rocket| async {
+        rocket.mount("/events", routes![events])
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use rocket::http::{ContentType, Header, Status};
+    use rocket::local::asynchronous::Client;
+    use rocket::tokio::time::timeout;
+    use std::time::Duration;
+
+    use crate::test_util::{data, read_until, TempDatabase};
+
+    #[rocket::async_test]
+    async fn test_events_of_every_room_on_one_stream() {
+        let database = TempDatabase::new();
+        let client = Client::untracked(crate::build_with(database.figment()))
+            .await
+            .unwrap();
+
+        let body = client
+            .post("/auth/register")
+            .header(ContentType::JSON)
+            .body(r#"{"username":"john","password":"doe"}"#)
+            .dispatch()
+            .await
+            .into_string()
+            .await
+            .unwrap();
+        let auth = Header::new("Authorization", format!("Bearer {}", data(&body, "token")));
+
+        let status = client.get("/events").dispatch().await.status();
+        assert_eq!(status, Status::Unauthorized);
+
+        let mut room_ids = Vec::new();
+        for name in ["lobby", "random"] {
+            let body = client
+                .post("/rooms")
+                .header(ContentType::JSON)
+                .header(auth.clone())
+                .body(format!(r#"{{"name":"{}"}}"#, name))
+                .dispatch()
+                .await
+                .into_string()
+                .await
+                .unwrap();
+            room_ids.push(data(&body, "id"));
+        }
+
+        let mut stream = client.get("/events").header(auth.clone()).dispatch().await;
+        assert_eq!(stream.status(), Status::Ok);
+
+        for room_id in &room_ids {
+            let status = client
+                .post("/messages/send")
+                .header(ContentType::JSON)
+                .header(auth.clone())
+                .body(format!(
+                    r#"{{"text":"hello {}","room_id":"{}"}}"#,
+                    room_id, room_id
+                ))
+                .dispatch()
+                .await
+                .status();
+            assert_eq!(status, Status::Ok);
+        }
+
+        let status = client
+            .post("/rooms")
+            .header(ContentType::JSON)
+            .header(auth.clone())
+            .body(r#"{"name":"general"}"#)
+            .dispatch()
+            .await
+            .status();
+        assert_eq!(status, Status::Ok);
+
+        let mut needles = room_ids
+            .iter()
+            .map(|room_id| format!("hello {}", room_id))
+            .collect::<Vec<_>>();
+        needles.push(r#""type":"created""#.to_string());
+        needles.push("general".to_string());
+        let needles = needles.iter().map(|n| n.as_str()).collect::<Vec<_>>();
+        timeout(Duration::from_secs(5), read_until(&mut stream, &needles))
+            .await
+            .expect("Messages didn't reach the stream");
+
+        drop(stream);
+
+        // a banned user doesn't get the room's events, whenever they connect
+        let body = client
+            .post("/auth/register")
+            .header(ContentType::JSON)
+            .body(r#"{"username":"alice","password":"doe"}"#)
+            .dispatch()
+            .await
+            .into_string()
+            .await
+            .unwrap();
+        let alice = Header::new("Authorization", format!("Bearer {}", data(&body, "token")));
+        let body = client
+            .get("/users/whoami")
+            .header(alice.clone())
+            .dispatch()
+            .await
+            .into_string()
+            .await
+            .unwrap();
+        let alice_id = data(&body, "id");
+        let status = client
+            .post(format!("/moderation/{}/ban", room_ids[0]))
+            .header(ContentType::JSON)
+            .header(auth.clone())
+            .body(format!(r#"{{"user_id":"{}"}}"#, alice_id))
+            .dispatch()
+            .await
+            .status();
+        assert_eq!(status, Status::Ok);
+
+        let status = client
+            .get(format!("/messages/events/{}", room_ids[0]))
+            .header(alice.clone())
+            .dispatch()
+            .await
+            .status();
+        assert_eq!(status, Status::Forbidden);
+
+        let mut stream = client.get("/events").header(alice).dispatch().await;
+        for room_id in &room_ids {
+            let status = client
+                .post("/messages/send")
+                .header(ContentType::JSON)
+                .header(auth.clone())
+                .body(format!(
+                    r#"{{"text":"again {}","room_id":"{}"}}"#,
+                    room_id, room_id
+                ))
+                .dispatch()
+                .await
+                .status();
+            assert_eq!(status, Status::Ok);
+        }
+        let needle = format!("again {}", room_ids[1]);
+        let events = timeout(Duration::from_secs(5), read_until(&mut stream, &[&needle]))
+            .await
+            .expect("Messages didn't reach the stream");
+        assert!(!events.contains(&format!("again {}", room_ids[0])));
+    }
+}
@@ -0,0 +1,241 @@
+//! Incoming webhooks, urls scripts post to a room with, e.g.
+//! `curl -d 'Build passed' https://<server>/hooks/<token>`. Messages are sent as a bot, named
+//! when the webhook is created, and go through `message::send_message` like any other.
+use qu_chat_models::{
+    CreateIncomingWebhookParams, CreatedIncomingWebhook, IncomingMessage, IncomingWebhook,
+};
+use rocket::fairing::AdHoc;
+use rocket::serde::json::Json;
+use rocket::State;
+
+use crate::authentication::{hash, UserId};
+use crate::base::{ApiResult, ApiResultBuilder, Error, SimpleError};
+use crate::bots::{create_bot, managed_bot};
+use crate::message::send_message;
+use crate::pubsub::Changes;
+use crate::rate_limit::RateLimit;
+use crate::rooms::ensure_room_admin;
+use crate::store::Db;
+
+/// Tells incoming webhook tokens apart from API tokens.
+pub const INCOMING_TOKEN_PREFIX: &str = "qih_";
+
+/// The text of the message, with the title as a bold first line.
+fn format_message(message: IncomingMessage) -> String {
+    match message.title.as_deref().map(str::trim) {
+        Some(title) if !title.is_empty() => format!("**{}**\n{}", title, message.text.trim()),
+        _ => message.text.trim().to_string(),
+    }
+}
+
+async fn post(db: &Db, changes: &Changes, token: &str, text: String) -> ApiResult<String> {
+    let webhook = match db.incoming_webhook(&hash(token)).await {
+        Ok(Some(webhook)) => webhook,
+        Ok(None) => {
+            return Err(Error::Unauthorized(Json(SimpleError::new(
+                "Unknown or revoked webhook.",
+            ))))
+        }
+        Err(_) => return Err(Error::Internal(())),
+    };
+    if text.trim().is_empty() {
+        return ApiResultBuilder::err("The message is empty.");
+    }
+
+    let message = send_message(db, changes, &webhook.bot_id, &webhook.room_id, &text, None).await?;
+    ApiResultBuilder::data(message.id)
+}
+
+#[post("/<token>", format = "json", data = "<message>")]
+async fn post_json(
+    _limit: RateLimit,
+    token: &str,
+    message: Json<IncomingMessage>,
+    changes: &State<Changes>,
+    db: Db,
+) -> ApiResult<String> {
+    post(&db, changes, token, format_message(message.into_inner())).await
+}
+
+/// Any other body is sent as plain text.
+#[post("/<token>", data = "<text>", rank = 2)]
+async fn post_text(
+    _limit: RateLimit,
+    token: &str,
+    text: String,
+    changes: &State<Changes>,
+    db: Db,
+) -> ApiResult<String> {
+    post(&db, changes, token, text.trim().to_string()).await
+}
+
+/// Creates a bot of that name, or reuses it when the user manages it.
+async fn integration_bot(db: &Db, user_id: &UserId, name: &str) -> Result<String, Error<'static>> {
+    match db.user_by_name(name).await {
+        Ok(None) => create_bot(db, name, Some(&user_id.id))
+            .await
+            .map(|bot| bot.id)
+            .map_err(|_| Error::Internal(())),
+        Ok(Some(user)) if user.is_bot => managed_bot(db, user_id, &user.id).await.map(|bot| bot.id),
+        Ok(Some(_)) => Err(Error::logical("This name is taken.")),
+        Err(_) => Err(Error::Internal(())),
+    }
+}
+
+#[post("/<room_id>/incoming-webhooks", data = "<params>", rank = 2)]
+async fn create(
+    room_id: &str,
+    params: Json<CreateIncomingWebhookParams>,
+    user_id: UserId,
+    db: Db,
+) -> ApiResult<CreatedIncomingWebhook> {
+    ensure_room_admin(&db, &user_id, room_id).await?;
+    let name = params.name.trim();
+    if name.is_empty() {
+        return ApiResultBuilder::err("Webhook name can't be empty.");
+    }
+    let bot_id = integration_bot(&db, &user_id, name).await?;
+
+    let token = format!(
+        "{}{}{}",
+        INCOMING_TOKEN_PREFIX,
+        uuid::Uuid::new_v4().simple(),
+        uuid::Uuid::new_v4().simple()
+    );
+    let info = IncomingWebhook {
+        id: uuid::Uuid::new_v4().to_string(),
+        room_id: room_id.to_string(),
+        bot_id,
+        name: name.to_string(),
+        creator_id: user_id.id,
+        create_date: chrono::Utc::now().timestamp(),
+    };
+    let result = db.create_incoming_webhook(&info, &hash(&token)).await;
+    ApiResultBuilder::from(
+        result.map(|_| CreatedIncomingWebhook { token, info }),
+        "Unable to create webhook",
+    )
+}
+
+#[get("/<room_id>/incoming-webhooks")]
+async fn list(room_id: &str, user_id: UserId, db: Db) -> ApiResult<Vec<IncomingWebhook>> {
+    ensure_room_admin(&db, &user_id, room_id).await?;
+
+    let result = db.incoming_webhooks(room_id).await;
+    ApiResultBuilder::from(result, "Unable to fetch webhooks")
+}
+
+/// Revokes the webhook, the messages it sent and its bot are kept.
+#[delete("/<room_id>/incoming-webhooks/<id>")]
+async fn revoke(room_id: &str, id: &str, user_id: UserId, db: Db) -> ApiResult<String> {
+    ensure_room_admin(&db, &user_id, room_id).await?;
+
+    match db.delete_incoming_webhook(room_id, id).await {
+        Ok(true) => ApiResultBuilder::data("Revoked webhook".to_string()),
+        Ok(false) => ApiResultBuilder::err("Webhook doesn't exists."),
+        Err(_) => Err(Error::Internal(())),
+    }
+}
+
+pub fn stage() -> AdHoc {
+    AdHoc::on_ignite("Incoming Webhooks Stage", |rocket| async {
+        rocket
+            .mount("/hooks", routes![post_json, post_text])
+            .mount("/rooms", routes![create, list, revoke])
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use rocket::http::{ContentType, Header, Status};
+    use rocket::local::asynchronous::Client;
+
+    use crate::test_util::{data, TempDatabase};
+
+    #[rocket::async_test]
+    async fn test_post_to_room() {
+        let database = TempDatabase::new();
+        let client = Client::untracked(crate::build_with(database.figment()))
+            .await
+            .unwrap();
+
+        let body = client
+            .post("/auth/register")
+            .header(ContentType::JSON)
+            .body(r#"{"username":"john","password":"doe"}"#)
+            .dispatch()
+            .await
+            .into_string()
+            .await
+            .unwrap();
+        let auth = Header::new("Authorization", format!("Bearer {}", data(&body, "token")));
+        let body = client
+            .post("/rooms")
+            .header(ContentType::JSON)
+            .header(auth.clone())
+            .body(r#"{"name":"builds"}"#)
+            .dispatch()
+            .await
+            .into_string()
+            .await
+            .unwrap();
+        let room_id = data(&body, "id");
+        let body = client
+            .post(format!("/rooms/{}/incoming-webhooks", room_id))
+            .header(ContentType::JSON)
+            .header(auth.clone())
+            .body(r#"{"name":"CI"}"#)
+            .dispatch()
+            .await
+            .into_string()
+            .await
+            .unwrap();
+        let token = data(&body, "token");
+        let id = data(&body, "id");
+
+        let status = client
+            .post(format!("/hooks/{}", token))
+            .header(ContentType::JSON)
+            .body(r#"{"title":"Build 42","text":"passed"}"#)
+            .dispatch()
+            .await
+            .status();
+        assert_eq!(status, Status::Ok);
+        let status = client
+            .post(format!("/hooks/{}", token))
+            .header(ContentType::Form)
+            .body("deployed")
+            .dispatch()
+            .await
+            .status();
+        assert_eq!(status, Status::Ok);
+
+        let history = client
+            .get(format!("/messages/{}", room_id))
+            .header(auth.clone())
+            .dispatch()
+            .await
+            .into_string()
+            .await
+            .unwrap();
+        assert!(history.contains(r#""content":"**Build 42**\npassed""#));
+        assert!(history.contains(r#""content":"deployed""#));
+        assert!(history.contains(r#""sender_name":"CI""#));
+        assert!(history.contains(r#""is_bot":true"#));
+
+        let status = client
+            .delete(format!("/rooms/{}/incoming-webhooks/{}", room_id, id))
+            .header(auth)
+            .dispatch()
+            .await
+            .status();
+        assert_eq!(status, Status::Ok);
+        let status = client
+            .post(format!("/hooks/{}", token))
+            .body("revoked")
+            .dispatch()
+            .await
+            .status();
+        assert_eq!(status, Status::Unauthorized);
+    }
+}
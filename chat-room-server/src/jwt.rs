@@ -9,6 +9,9 @@ use sha2::Sha256;
 pub struct Body {
     pub user_id: String,
     exp: i64,
+    /// Seconds since epoch the token was issued at, zero for tokens issued before it was added.
+    #[serde(default)]
+    pub iat: i64,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -36,7 +39,8 @@ pub fn token_from_barear(str: &str) -> Result<&str, TokenError> {
 }
 
 pub fn generate_jwt(user_id: &str, secret_key: &[u8]) -> Result<String, TokenError> {
-    let exp = match chrono::Utc::now().checked_add_months(Months::new(1)) {
+    let now = chrono::Utc::now();
+    let exp = match now.checked_add_months(Months::new(1)) {
         Some(date) => date,
         None => return Err(TokenError::ExpireTimeCalculation),
     };
@@ -44,6 +48,7 @@ pub fn generate_jwt(user_id: &str, secret_key: &[u8]) -> Result<String, TokenErr
     let body = Body {
         user_id: user_id.to_string(),
         exp: exp.timestamp_millis(),
+        iat: now.timestamp(),
     };
 
     let header = Header {
@@ -116,6 +121,9 @@ mod test {
             id: "123".to_string(),
             name: "john doe".to_string(),
             secret: "secret".to_string(),
+            is_admin: false,
+            is_bot: false,
+            owner_id: None,
         };
 
         let s = b"secret";
@@ -0,0 +1,51 @@
+pub mod admin;
+pub mod authentication;
+pub mod base;
+pub mod bots;
+pub mod catchers;
+pub mod commands;
+pub mod events;
+pub mod incoming_webhooks;
+pub mod jwt;
+pub mod message;
+pub mod migrations;
+pub mod moderation;
+pub mod pubsub;
+pub mod rate_limit;
+pub mod rooms;
+pub mod store;
+#[cfg(test)]
+mod test_util;
+pub mod user;
+pub mod webhooks;
+pub mod ws;
+
+use rocket::figment::Figment;
+use rocket::{Build, Rocket};
+
+#[macro_use]
+extern crate rocket;
+
+pub fn build() -> Rocket<Build> {
+    build_with(rocket::Config::figment())
+}
+
+pub fn build_with(figment: Figment) -> Rocket<Build> {
+    rocket::custom(figment)
+        .attach(store::stage())
+        .attach(migrations::stage())
+        .attach(pubsub::stage())
+        .attach(rate_limit::stage())
+        .attach(rooms::stage())
+        .attach(user::stage())
+        .attach(authentication::stage())
+        .attach(message::stage())
+        .attach(bots::stage())
+        .attach(commands::stage())
+        .attach(events::stage())
+        .attach(moderation::stage())
+        .attach(webhooks::stage())
+        .attach(incoming_webhooks::stage())
+        .attach(ws::stage())
+        .attach(catchers::stage())
+}
@@ -1,25 +1,13 @@
-pub mod authentication;
-pub mod base;
-pub mod catchers;
-pub mod jwt;
-pub mod message;
-pub mod rooms;
-pub mod user;
-
-use rocket_db_pools::Database;
-
-#[macro_use]
-extern crate rocket;
-
-use crate::base::Db;
-
-#[launch]
-fn rocket() -> _ {
-    rocket::build()
-        .attach(Db::init())
-        .attach(rooms::stage())
-        .attach(user::stage())
-        .attach(authentication::stage())
-        .attach(message::stage())
-        .attach(catchers::stage())
+/// Applies pending migrations and exits without serving.
+const MIGRATE_ONLY: &str = "--migrate-only";
+
+#[rocket::main]
+async fn main() -> Result<(), Box<rocket::Error>> {
+    let rocket = chat_room::build();
+    if std::env::args().any(|arg| arg == MIGRATE_ONLY) {
+        rocket.ignite().await?;
+        return Ok(());
+    }
+    rocket.launch().await?;
+    Ok(())
 }
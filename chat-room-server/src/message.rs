@@ -1,25 +1,20 @@
-use qu_chat_models::{Message, SendMessageParams};
+use qu_chat_models::{Message, RoomEvent, SendMessageParams};
 use rocket::{
     fairing::AdHoc,
     response::stream::{Event, EventStream},
     serde::json::Json,
-    tokio::sync::broadcast::{channel, error::RecvError, Sender},
+    tokio::sync::broadcast::error::RecvError,
     Shutdown, State,
 };
-use rocket_db_pools::Connection;
-use serde::{Deserialize, Serialize};
-use sqlx::Row;
+use serde::Serialize;
 
-use crate::{authentication::UserId, base::*};
+use crate::{
+    authentication::UserId, base::*, moderation::ensure_not_sanctioned, pubsub::Changes,
+    rate_limit::RateLimit, store::Db,
+};
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
-struct MessageDM {
-    id: String,
-    content: String,
-    sender_id: String,
-    room_id: String,
-    create_date: i64,
-}
+/// Characters a message can have, longer ones are refused.
+pub const MAX_MESSAGE_LENGTH: usize = 4_000;
 
 #[derive(Debug, Serialize, Clone)]
 #[allow(dead_code)]
@@ -30,77 +25,119 @@ struct RoomListChange {
 
 #[post("/send", data = "<params>")]
 async fn send(
+    _limit: RateLimit,
     params: Json<SendMessageParams>,
-    changes: &State<Sender<RoomChange>>,
+    changes: &State<Changes>,
     user_id: UserId,
-    mut db: Connection<Db>,
+    db: Db,
 ) -> ApiResult<String> {
-    let room_not_exists = sqlx::query!("SELECT * FROM rooms WHERE (id)=($1)", params.room_id)
-        .fetch_one(&mut **db)
-        .await;
+    let message = send_message(
+        &db,
+        changes,
+        &user_id.id,
+        &params.room_id,
+        &params.text,
+        params.client_id.as_deref(),
+    )
+    .await?;
+    ApiResultBuilder::data(message.id)
+}
+
+/// Stores the message and publishes it to the room streams, for every transport. A message
+/// already sent to the room with the same `client_id` is returned as is, so retries don't
+/// duplicate it, provided the user can still send there.
+pub async fn send_message(
+    db: &Db,
+    changes: &Changes,
+    user_id: &str,
+    room_id: &str,
+    text: &str,
+    client_id: Option<&str>,
+) -> Result<Message, Error<'static>> {
+    if text.chars().count() > MAX_MESSAGE_LENGTH {
+        return Err(Error::logical("The message is too long."));
+    }
+    match db.room(room_id).await {
+        Ok(Some(room)) if room.archived => return Err(Error::logical("Room is archived.")),
+        Ok(Some(_)) => (),
+        _ => return Err(Error::logical("Room doesn't exists.")),
+    };
+    ensure_not_sanctioned(db, room_id, user_id, false).await?;
 
-    let message = MessageDM {
+    if let Some(client_id) = client_id {
+        if let Some(message) = sent_before(db, user_id, room_id, client_id).await? {
+            return Ok(message);
+        }
+    }
+
+    let sender = match db.user(user_id).await {
+        Ok(Some(sender)) => sender,
+        _ => return Err(Error::logical("Can't find sender name")),
+    };
+    let message = Message {
         id: uuid::Uuid::new_v4().to_string(),
-        content: params.text.to_string(),
-        sender_id: user_id.id,
-        room_id: params.room_id.to_string(),
+        content: text.to_string(),
+        sender_id: user_id.to_string(),
+        room_id: room_id.to_string(),
         create_date: chrono::Utc::now().timestamp(),
+        sender_name: sender.name,
+        client_id: client_id.map(str::to_string),
+        is_bot: sender.is_bot,
     };
 
-    let insert_res = sqlx::query!(
-        "Insert INTO messages (id, content, room_id, sender_id, create_date) VALUES ($1, $2, $3, $4, $5)",
-        message.id,
-        message.content,
-        message.room_id,
-        message.sender_id,
-        message.create_date
-    )
-    .execute(&mut **db)
-    .await;
-
-    let sender_id = message.sender_id;
-    let name_result = sqlx::query!("SELECT name FROM users WHERE id = ($1)", sender_id)
-        .fetch_one(&mut **db)
-        .await
-        .map(|r| r.name);
-
-    let name = match (insert_res, name_result, room_not_exists) {
-        (Ok(_), Ok(name), Ok(_)) => name,
-        (_, _, Err(_)) => return ApiResultBuilder::err("Room doesn't exists."),
-        (_, Err(_), _) => return ApiResultBuilder::err("Can't find sender name"),
-        (Err(_), _, _) => return ApiResultBuilder::err("Can't send message."),
-    };
+    if db.insert_message(&message).await.is_err() {
+        // a retry that raced the first attempt
+        if let Some(client_id) = client_id {
+            if let Some(message) = sent_before(db, user_id, room_id, client_id).await? {
+                return Ok(message);
+            }
+        }
+        return Err(Error::logical("Can't send message."));
+    }
 
     let change = RoomChange {
-        message: Message {
-            id: message.id,
-            content: message.content,
-            sender_id: sender_id,
-            room_id: message.room_id,
-            create_date: message.create_date,
-            sender_name: name,
-        },
+        event: RoomEvent::Message(message.clone()),
     };
 
-    let id = change.message.id.clone();
-    match changes.send(change) {
-        Ok(_) => ApiResultBuilder::data(id),
+    match changes.publish(change).await {
+        Ok(_) => Ok(message),
+        Err(_) => Err(Error::Internal(())),
+    }
+}
+
+/// The message the user already sent to the room with this `client_id`. Keys are unique per
+/// user, one used in another room is refused rather than answered with that room's message.
+async fn sent_before(
+    db: &Db,
+    user_id: &str,
+    room_id: &str,
+    client_id: &str,
+) -> Result<Option<Message>, Error<'static>> {
+    match db.message_by_client_id(user_id, client_id).await {
+        Ok(Some(message)) if message.room_id != room_id => Err(Error::logical(
+            "This client_id was used for a message to another room.",
+        )),
+        Ok(message) => Ok(message),
         Err(_) => Err(Error::Internal(())),
     }
 }
 
+/// The events of one room, ending with the ban of the user.
 #[get("/events/<room_id>")]
 async fn events(
-    changes: &State<Sender<RoomChange>>,
+    changes: &State<Changes>,
     room_id: String,
+    user_id: UserId,
+    db: Db,
     mut shutdown: Shutdown,
-) -> EventStream![] {
+) -> Result<EventStream![], Error<'static>> {
+    ensure_not_sanctioned(&db, &room_id, &user_id.id, true).await?;
     let mut rx = changes.subscribe();
-    EventStream! {
+    Ok(EventStream! {
         loop {
             let change = rocket::tokio::select! {
                 change = rx.recv() => match change {
-                    Ok(change) if change.message.room_id == room_id => { change},
+                    Ok(change) if change.event.room_id() == room_id => { change},
                     Ok(_) => continue,
                     Err(RecvError::Closed) => break,
                     Err(RecvError::Lagged(_)) => continue,
@@ -108,61 +145,179 @@ async fn events(
                 _ = &mut shutdown => break,
             };
 
-            yield Event::json(&change.message);
+            yield Event::json(&change.event);
+            if matches!(&change.event, RoomEvent::UserBanned { user_id: banned, .. } if *banned == user_id.id) {
+                break;
+            }
         }
-    }
+    })
 }
 
 // async fn unread(
-//     changes: &State<Sender<RoomChange>>,
+//     changes: &State<Changes>,
 
 // )
 
-#[get("/<room_id>?<size>")]
+#[get("/<room_id>?<size>&<since>")]
 async fn messages(
     room_id: String,
     size: Option<u32>,
-    _user_id: UserId,
-    mut db: Connection<Db>,
+    since: Option<i64>,
+    user_id: UserId,
+    db: Db,
 ) -> ApiResult<Vec<Message>> {
+    ensure_not_sanctioned(&db, &room_id, &user_id.id, true).await?;
     let size = size.unwrap_or(20);
-    let rows = sqlx::query(
-        r#"
-        SELECT messages.id, messages.content, messages.room_id, messages.sender_id, messages.create_date, users.name
-        FROM messages
-        INNER JOIN users ON messages.sender_id = users.id
-        WHERE messages.room_id = ($1) ORDER BY create_date LIMIT ($2);
-        "#
-    )
-    .bind(&room_id)
-    .bind(size)
-    .fetch_all(&mut **db)
-    .await;
-
-    let result = match rows {
-        Ok(ref a) => Ok(a
-            .iter()
-            .map(|b| Message {
-                id: b.get(0),
-                content: b.get(1),
-                sender_id: b.get(3),
-                room_id: b.get(2),
-                create_date: b.get(4),
-                sender_name: b.get(5),
-            })
-            .collect::<Vec<Message>>()),
-        Err(e) => Err(e),
-    };
+    let result = db
+        .messages(&room_id, since.unwrap_or(0), i64::from(size))
+        .await;
+
+    ApiResultBuilder::from(result, "Unable to fetch messages")
+}
+
+/// The last messages of the room, newest first.
+#[get("/<room_id>/latest?<size>", rank = 2)]
+async fn latest(
+    room_id: &str,
+    size: Option<u32>,
+    user_id: UserId,
+    db: Db,
+) -> ApiResult<Vec<Message>> {
+    ensure_not_sanctioned(&db, room_id, &user_id.id, true).await?;
+    let size = size.unwrap_or(20);
+    let result = db.latest_messages(room_id, i64::from(size)).await;
 
     ApiResultBuilder::from(result, "Unable to fetch messages")
 }
 
+/// Latest messages of the room containing `q`, newest first.
+#[get("/<room_id>/search?<q>&<size>", rank = 2)]
+async fn search(
+    room_id: &str,
+    q: &str,
+    size: Option<u32>,
+    user_id: UserId,
+    db: Db,
+) -> ApiResult<Vec<Message>> {
+    ensure_not_sanctioned(&db, room_id, &user_id.id, true).await?;
+    if q.trim().is_empty() {
+        return ApiResultBuilder::err("Nothing to search for.");
+    }
+    let size = size.unwrap_or(20).min(100);
+    let result = db.search_messages(room_id, q.trim(), i64::from(size)).await;
+
+    ApiResultBuilder::from(result, "Unable to search messages")
+}
+
 pub fn stage() -> AdHoc {
     AdHoc::on_ignite("Messages Stage", |rocket| async {
-        let (tx, rx) = channel::<RoomChange>(1024);
-        rocket
-            .mount("/messages", routes![events, send, messages])
-            .manage(tx)
-            .manage(rx)
+        rocket.mount("/messages", routes![events, send, messages, latest, search])
     })
 }
+
+#[cfg(test)]
+mod test {
+    use rocket::http::{ContentType, Header, Status};
+    use rocket::local::asynchronous::Client;
+
+    use crate::test_util::{data, TempDatabase};
+
+    #[rocket::async_test]
+    async fn test_retried_send_is_not_duplicated() {
+        let database = TempDatabase::new();
+        let client = Client::untracked(crate::build_with(database.figment()))
+            .await
+            .unwrap();
+
+        let body = client
+            .post("/auth/register")
+            .header(ContentType::JSON)
+            .body(r#"{"username":"john","password":"doe"}"#)
+            .dispatch()
+            .await
+            .into_string()
+            .await
+            .unwrap();
+        let auth = Header::new("Authorization", format!("Bearer {}", data(&body, "token")));
+        let body = client
+            .post("/rooms")
+            .header(ContentType::JSON)
+            .header(auth.clone())
+            .body(r#"{"name":"lobby"}"#)
+            .dispatch()
+            .await
+            .into_string()
+            .await
+            .unwrap();
+        let room_id = data(&body, "id");
+
+        let mut ids = Vec::new();
+        for _ in 0..2 {
+            let response = client
+                .post("/messages/send")
+                .header(ContentType::JSON)
+                .header(auth.clone())
+                .body(format!(
+                    r#"{{"text":"once","room_id":"{}","client_id":"key"}}"#,
+                    room_id
+                ))
+                .dispatch()
+                .await;
+            assert_eq!(response.status(), Status::Ok);
+            ids.push(response.into_string().await.unwrap());
+        }
+        assert_eq!(ids[0], ids[1]);
+
+        let body = client
+            .post("/rooms")
+            .header(ContentType::JSON)
+            .header(auth.clone())
+            .body(r#"{"name":"elsewhere"}"#)
+            .dispatch()
+            .await
+            .into_string()
+            .await
+            .unwrap();
+        let body = client
+            .post("/messages/send")
+            .header(ContentType::JSON)
+            .header(auth.clone())
+            .body(format!(
+                r#"{{"text":"once","room_id":"{}","client_id":"key"}}"#,
+                data(&body, "id")
+            ))
+            .dispatch()
+            .await
+            .into_string()
+            .await
+            .unwrap();
+        assert!(body.contains("another room"));
+
+        let body = client
+            .post("/messages/send")
+            .header(ContentType::JSON)
+            .header(auth.clone())
+            .body(format!(
+                r#"{{"text":"{}","room_id":"{}"}}"#,
+                "a".repeat(super::MAX_MESSAGE_LENGTH + 1),
+                room_id
+            ))
+            .dispatch()
+            .await
+            .into_string()
+            .await
+            .unwrap();
+        assert!(body.contains("too long"));
+
+        let history = client
+            .get(format!("/messages/{}", room_id))
+            .header(auth)
+            .dispatch()
+            .await
+            .into_string()
+            .await
+            .unwrap();
+        assert_eq!(history.matches("\"once\"").count(), 1);
+        assert!(history.contains(r#""client_id":"key""#));
+    }
+}
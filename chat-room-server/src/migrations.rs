@@ -0,0 +1,83 @@
+use rocket::fairing::AdHoc;
+
+use crate::store::Db;
+
+/// Applies pending migrations of the configured backend as soon as the store is up.
+/// A failed migration aborts the launch.
+pub fn stage() -> AdHoc {
+    AdHoc::try_on_ignite("Migrations", |rocket| async {
+        let Some(db) = rocket.state::<Db>() else {
+            error!("Database is not initialised, unable to run migrations");
+            return Err(rocket);
+        };
+
+        match db.migrate().await {
+            Ok(_) => Ok(rocket),
+            Err(err) => {
+                error!("Unable to apply migrations: {}", err);
+                Err(rocket)
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use crate::store::sqlite::MIGRATOR;
+    use rocket::error::ErrorKind;
+    use rocket::http::{ContentType, Status};
+    use rocket::local::asynchronous::Client;
+    use sqlx::SqlitePool;
+
+    use crate::test_util::TempDatabase;
+
+    #[rocket::async_test]
+    async fn test_ignite_migrates_empty_database() {
+        let database = TempDatabase::new();
+        let client = Client::tracked(crate::build_with(database.figment()))
+            .await
+            .expect("Server should start on an empty database");
+
+        let db = SqlitePool::connect(database.url()).await.unwrap();
+        let applied: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM _sqlx_migrations")
+            .fetch_one(&db)
+            .await
+            .unwrap();
+        let migrations = MIGRATOR
+            .iter()
+            .filter(|migration| migration.migration_type.is_up_migration())
+            .count();
+        assert_eq!(applied as usize, migrations);
+
+        let status = client
+            .post("/auth/register")
+            .header(ContentType::JSON)
+            .body(r#"{"username":"john","password":"doe"}"#)
+            .dispatch()
+            .await
+            .status();
+        assert_eq!(status, Status::Ok);
+
+        db.close().await;
+    }
+
+    #[rocket::async_test]
+    async fn test_failed_migration_refuses_to_start() {
+        let database = TempDatabase::new();
+        let client = Client::tracked(crate::build_with(database.figment()))
+            .await
+            .unwrap();
+        let db = SqlitePool::connect(database.url()).await.unwrap();
+        sqlx::query("UPDATE _sqlx_migrations SET checksum = x'00'")
+            .execute(&db)
+            .await
+            .unwrap();
+        db.close().await;
+        drop(client);
+
+        match Client::tracked(crate::build_with(database.figment())).await {
+            Ok(_) => panic!("Server started with a modified migration"),
+            Err(err) => assert!(matches!(err.kind(), ErrorKind::FailedFairings(_))),
+        }
+    }
+}
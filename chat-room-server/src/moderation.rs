@@ -0,0 +1,581 @@
+use qu_chat_models::{
+    error_codes, ModerationAction, ModerationKind, ModerationParams, MuteParams,
+    RemoveMessageParams, RoomEvent,
+};
+use rocket::fairing::AdHoc;
+use rocket::serde::json::Json;
+use rocket::State;
+
+use crate::authentication::UserId;
+use crate::base::{ApiResult, ApiResultBuilder, Error, RoomChange};
+use crate::pubsub::Changes;
+use crate::rooms::ensure_room_admin;
+use crate::store::Db;
+
+pub enum Sanction {
+    /// The room is a direct room of other users, they are kept out like banned ones.
+    Outsider,
+    Banned,
+    Muted,
+}
+
+/// Returns what keeps the user out of the room or mutes them, bans first.
+pub async fn active_sanction(
+    db: &Db,
+    room_id: &str,
+    user_id: &str,
+) -> Result<Option<Sanction>, sqlx::Error> {
+    if !db.can_read_room(room_id, user_id).await? {
+        return Ok(Some(Sanction::Outsider));
+    }
+    let now = chrono::Utc::now().timestamp();
+    let kinds = db.active_sanctions(room_id, user_id, now).await?;
+
+    let mut sanction = None;
+    for kind in kinds {
+        match kind {
+            ModerationKind::Ban => return Ok(Some(Sanction::Banned)),
+            ModerationKind::Mute => sanction = Some(Sanction::Muted),
+            _ => (),
+        }
+    }
+    Ok(sanction)
+}
+
+/// Rejects banned users and the ones outside a direct room, and muted ones too unless
+/// `read_only` is set.
+pub async fn ensure_not_sanctioned(
+    db: &Db,
+    room_id: &str,
+    user_id: &str,
+    read_only: bool,
+) -> Result<(), Error<'static>> {
+    match active_sanction(db, room_id, user_id).await {
+        Ok(Some(Sanction::Outsider)) => Err(Error::forbidden(
+            "This direct room is only open to its two users.",
+        )),
+        Ok(Some(Sanction::Banned)) => Err(Error::forbidden_with_code(
+            error_codes::BANNED,
+            "You are banned from this room.",
+        )),
+        Ok(Some(Sanction::Muted)) if !read_only => Err(Error::forbidden_with_code(
+            error_codes::MUTED,
+            "You are muted in this room.",
+        )),
+        Ok(_) => Ok(()),
+        Err(_) => Err(Error::Internal(())),
+    }
+}
+
+/// Rejects sanctions on the users who could lift them, the room creator and admins.
+async fn ensure_not_room_admin(
+    db: &Db,
+    room_id: &str,
+    target_id: &str,
+) -> Result<(), Error<'static>> {
+    let room = db.room(room_id).await;
+    let user = db.user(target_id).await;
+
+    match (room, user) {
+        (Ok(Some(room)), Ok(Some(user))) if room.creator_id == user.id || user.is_admin => Err(
+            Error::forbidden("The room creator and admins can't be sanctioned."),
+        ),
+        (Ok(_), Ok(_)) => Ok(()),
+        _ => Err(Error::Internal(())),
+    }
+}
+
+struct LogEntry<'r> {
+    kind: ModerationKind,
+    target_user_id: Option<&'r str>,
+    target_message_id: Option<&'r str>,
+    reason: Option<&'r str>,
+    until: Option<i64>,
+}
+
+impl<'r> LogEntry<'r> {
+    fn user(kind: ModerationKind, user_id: &'r str, reason: Option<&'r str>) -> Self {
+        LogEntry {
+            kind,
+            target_user_id: Some(user_id),
+            target_message_id: None,
+            reason,
+            until: None,
+        }
+    }
+}
+
+/// Applies the action and logs it, see [`crate::store::ModerationStore::moderate`].
+async fn moderate(
+    db: &Db,
+    room_id: &str,
+    moderator_id: &str,
+    entry: LogEntry<'_>,
+) -> Result<bool, sqlx::Error> {
+    db.moderate(&ModerationAction {
+        id: uuid::Uuid::new_v4().to_string(),
+        room_id: room_id.to_string(),
+        moderator_id: moderator_id.to_string(),
+        kind: entry.kind,
+        target_user_id: entry.target_user_id.map(str::to_string),
+        target_message_id: entry.target_message_id.map(str::to_string),
+        reason: entry.reason.map(str::to_string),
+        until: entry.until,
+        create_date: chrono::Utc::now().timestamp(),
+    })
+    .await
+}
+
+/// Removes the user from the members of the room. Unlike a ban it doesn't keep them out, they
+/// can open the room again right away.
+#[post("/<room_id>/kick", data = "<params>")]
+async fn kick(
+    room_id: &str,
+    params: Json<ModerationParams>,
+    changes: &State<Changes>,
+    user_id: UserId,
+    db: Db,
+) -> ApiResult<String> {
+    ensure_room_admin(&db, &user_id, room_id).await?;
+    ensure_not_room_admin(&db, room_id, &params.user_id).await?;
+
+    let entry = LogEntry::user(
+        ModerationKind::Kick,
+        &params.user_id,
+        params.reason.as_deref(),
+    );
+    if moderate(&db, room_id, &user_id.id, entry).await.is_err() {
+        return ApiResultBuilder::err("Unable to kick user");
+    }
+
+    let _ = changes
+        .publish(RoomChange {
+            event: RoomEvent::UserKicked {
+                room_id: room_id.to_string(),
+                user_id: params.user_id.clone(),
+            },
+        })
+        .await;
+    ApiResultBuilder::data("Successfully kicked user".to_string())
+}
+
+#[post("/<room_id>/ban", data = "<params>")]
+async fn ban(
+    room_id: &str,
+    params: Json<ModerationParams>,
+    changes: &State<Changes>,
+    user_id: UserId,
+    db: Db,
+) -> ApiResult<String> {
+    ensure_room_admin(&db, &user_id, room_id).await?;
+    ensure_not_room_admin(&db, room_id, &params.user_id).await?;
+
+    let entry = LogEntry::user(
+        ModerationKind::Ban,
+        &params.user_id,
+        params.reason.as_deref(),
+    );
+    if moderate(&db, room_id, &user_id.id, entry).await.is_err() {
+        return ApiResultBuilder::err("Unable to ban user");
+    }
+
+    let _ = changes
+        .publish(RoomChange {
+            event: RoomEvent::UserBanned {
+                room_id: room_id.to_string(),
+                user_id: params.user_id.clone(),
+            },
+        })
+        .await;
+    ApiResultBuilder::data("Successfully banned user".to_string())
+}
+
+#[post("/<room_id>/unban", data = "<params>")]
+async fn unban(
+    room_id: &str,
+    params: Json<ModerationParams>,
+    changes: &State<Changes>,
+    user_id: UserId,
+    db: Db,
+) -> ApiResult<String> {
+    ensure_room_admin(&db, &user_id, room_id).await?;
+
+    let entry = LogEntry::user(
+        ModerationKind::Unban,
+        &params.user_id,
+        params.reason.as_deref(),
+    );
+    if moderate(&db, room_id, &user_id.id, entry).await.is_err() {
+        return ApiResultBuilder::err("Unable to unban user");
+    }
+
+    let _ = changes
+        .publish(RoomChange {
+            event: RoomEvent::UserUnbanned {
+                room_id: room_id.to_string(),
+                user_id: params.user_id.clone(),
+            },
+        })
+        .await;
+    ApiResultBuilder::data("Successfully unbanned user".to_string())
+}
+
+#[post("/<room_id>/mute", data = "<params>")]
+async fn mute(
+    room_id: &str,
+    params: Json<MuteParams>,
+    changes: &State<Changes>,
+    user_id: UserId,
+    db: Db,
+) -> ApiResult<String> {
+    ensure_room_admin(&db, &user_id, room_id).await?;
+    ensure_not_room_admin(&db, room_id, &params.user_id).await?;
+    if params.minutes == 0 {
+        return ApiResultBuilder::err("Mute duration should be at least a minute.");
+    }
+
+    let until = chrono::Utc::now().timestamp() + i64::from(params.minutes) * 60;
+    let entry = LogEntry {
+        until: Some(until),
+        ..LogEntry::user(
+            ModerationKind::Mute,
+            &params.user_id,
+            params.reason.as_deref(),
+        )
+    };
+    if moderate(&db, room_id, &user_id.id, entry).await.is_err() {
+        return ApiResultBuilder::err("Unable to mute user");
+    }
+
+    let _ = changes
+        .publish(RoomChange {
+            event: RoomEvent::UserMuted {
+                room_id: room_id.to_string(),
+                user_id: params.user_id.clone(),
+                until,
+            },
+        })
+        .await;
+    ApiResultBuilder::data("Successfully muted user".to_string())
+}
+
+#[post("/<room_id>/unmute", data = "<params>")]
+async fn unmute(
+    room_id: &str,
+    params: Json<ModerationParams>,
+    changes: &State<Changes>,
+    user_id: UserId,
+    db: Db,
+) -> ApiResult<String> {
+    ensure_room_admin(&db, &user_id, room_id).await?;
+
+    let entry = LogEntry::user(
+        ModerationKind::Unmute,
+        &params.user_id,
+        params.reason.as_deref(),
+    );
+    if moderate(&db, room_id, &user_id.id, entry).await.is_err() {
+        return ApiResultBuilder::err("Unable to unmute user");
+    }
+
+    let _ = changes
+        .publish(RoomChange {
+            event: RoomEvent::UserUnmuted {
+                room_id: room_id.to_string(),
+                user_id: params.user_id.clone(),
+            },
+        })
+        .await;
+    ApiResultBuilder::data("Successfully unmuted user".to_string())
+}
+
+#[post("/<room_id>/messages/<message_id>/remove", data = "<params>")]
+async fn remove_message(
+    room_id: &str,
+    message_id: &str,
+    params: Json<RemoveMessageParams>,
+    changes: &State<Changes>,
+    user_id: UserId,
+    db: Db,
+) -> ApiResult<String> {
+    ensure_room_admin(&db, &user_id, room_id).await?;
+    if params.reason.trim().is_empty() {
+        return ApiResultBuilder::err("A reason is required to remove a message.");
+    }
+
+    let entry = LogEntry {
+        kind: ModerationKind::RemoveMessage,
+        target_user_id: None,
+        target_message_id: Some(message_id),
+        reason: Some(&params.reason),
+        until: None,
+    };
+    match moderate(&db, room_id, &user_id.id, entry).await {
+        Ok(false) => return ApiResultBuilder::err("Message doesn't exists."),
+        Ok(_) => (),
+        Err(_) => return ApiResultBuilder::err("Unable to remove message"),
+    };
+
+    let _ = changes
+        .publish(RoomChange {
+            event: RoomEvent::MessageRemoved {
+                room_id: room_id.to_string(),
+                message_id: message_id.to_string(),
+            },
+        })
+        .await;
+    ApiResultBuilder::data("Successfully removed message".to_string())
+}
+
+#[get("/<room_id>/log")]
+async fn log(room_id: &str, user_id: UserId, db: Db) -> ApiResult<Vec<ModerationAction>> {
+    ensure_room_admin(&db, &user_id, room_id).await?;
+
+    let result = db.moderation_log(room_id).await;
+
+    ApiResultBuilder::from(result, "Unable to fetch moderation log")
+}
+
+pub fn stage() -> AdHoc {
+    AdHoc::on_ignite("Moderation Stage", |rocket| async {
+        rocket.mount(
+            "/moderation",
+            routes![kick, ban, unban, mute, unmute, remove_message, log],
+        )
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use qu_chat_models::ModerationKind;
+    use rocket::http::{ContentType, Header, Status};
+    use rocket::local::asynchronous::{Client, LocalResponse};
+    use rocket::serde::json::{serde_json, Value};
+    use rocket::tokio::time::timeout;
+    use std::time::Duration;
+
+    use crate::store::Db;
+    use crate::test_util::{data, read_until, register, TempDatabase};
+
+    async fn create_room(client: &Client, auth: &Header<'static>) -> String {
+        let body = client
+            .post("/rooms")
+            .header(ContentType::JSON)
+            .header(auth.clone())
+            .body(r#"{"name":"lobby"}"#)
+            .dispatch()
+            .await
+            .into_string()
+            .await
+            .unwrap();
+        data(&body, "id")
+    }
+
+    async fn send<'c>(
+        client: &'c Client,
+        auth: &Header<'static>,
+        room_id: &str,
+    ) -> LocalResponse<'c> {
+        client
+            .post("/messages/send")
+            .header(ContentType::JSON)
+            .header(auth.clone())
+            .body(format!(r#"{{"text":"hi","room_id":"{}"}}"#, room_id))
+            .dispatch()
+            .await
+    }
+
+    async fn moderate(
+        client: &Client,
+        auth: &Header<'static>,
+        uri: String,
+        body: String,
+    ) -> Status {
+        client
+            .post(uri)
+            .header(ContentType::JSON)
+            .header(auth.clone())
+            .body(body)
+            .dispatch()
+            .await
+            .status()
+    }
+
+    async fn log(client: &Client, auth: &Header<'static>, room_id: &str) -> Vec<Value> {
+        let body = client
+            .get(format!("/moderation/{}/log", room_id))
+            .header(auth.clone())
+            .dispatch()
+            .await
+            .into_string()
+            .await
+            .unwrap();
+        let value = serde_json::from_str::<Value>(&body).unwrap();
+        value["data"].as_array().unwrap().clone()
+    }
+
+    #[rocket::async_test]
+    async fn test_sanctions() {
+        let database = TempDatabase::new();
+        let client = Client::untracked(crate::build_with(database.figment()))
+            .await
+            .unwrap();
+        let db = client.rocket().state::<Db>().unwrap().clone();
+        let (owner, owner_id) = register(&client, "owner").await;
+        let (troll, troll_id) = register(&client, "troll").await;
+        let room_id = create_room(&client, &owner).await;
+        let mut stream = client.get("/events").header(owner.clone()).dispatch().await;
+        assert_eq!(send(&client, &troll, &room_id).await.status(), Status::Ok);
+
+        let uri = |action: &str| format!("/moderation/{}/{}", room_id, action);
+        let params =
+            |reason: &str| format!(r#"{{"user_id":"{}","reason":"{}"}}"#, troll_id, reason);
+
+        let body = format!(
+            r#"{{"user_id":"{}","minutes":5,"reason":"flood"}}"#,
+            troll_id
+        );
+        assert_eq!(
+            moderate(&client, &owner, uri("mute"), body).await,
+            Status::Ok
+        );
+        let response = send(&client, &troll, &room_id).await;
+        assert_eq!(response.status(), Status::Forbidden);
+        assert!(response
+            .into_string()
+            .await
+            .unwrap()
+            .contains(r#""code":"muted""#));
+        let until = log(&client, &owner, &room_id).await[0]["until"]
+            .as_i64()
+            .unwrap();
+        let muted = db.active_sanctions(&room_id, &troll_id, until - 1).await;
+        assert_eq!(muted.unwrap(), vec![ModerationKind::Mute]);
+        let expired = db.active_sanctions(&room_id, &troll_id, until).await;
+        assert!(expired.unwrap().is_empty());
+
+        let status = moderate(&client, &owner, uri("unmute"), params("served")).await;
+        assert_eq!(status, Status::Ok);
+        assert_eq!(send(&client, &troll, &room_id).await.status(), Status::Ok);
+
+        let status = moderate(&client, &owner, uri("kick"), params("rude")).await;
+        assert_eq!(status, Status::Ok);
+        let seen = db.last_seen(&troll_id, &[&room_id]).await.unwrap();
+        assert!(seen.is_empty());
+
+        let status = moderate(&client, &owner, uri("ban"), params("spam")).await;
+        assert_eq!(status, Status::Ok);
+        let response = send(&client, &troll, &room_id).await;
+        assert_eq!(response.status(), Status::Forbidden);
+        assert!(response
+            .into_string()
+            .await
+            .unwrap()
+            .contains(r#""code":"banned""#));
+        let status = moderate(&client, &owner, uri("unban"), params("forgiven")).await;
+        assert_eq!(status, Status::Ok);
+
+        // neither the room creator nor an admin can be sanctioned, by each other either
+        crate::admin::create_user(&db, "root", "doe", true)
+            .await
+            .unwrap();
+        let body = client
+            .post("/auth/signin")
+            .header(ContentType::JSON)
+            .body(r#"{"username":"root","password":"doe"}"#)
+            .dispatch()
+            .await
+            .into_string()
+            .await
+            .unwrap();
+        let root = Header::new("Authorization", format!("Bearer {}", data(&body, "token")));
+        let root_id = db.user_by_name("root").await.unwrap().unwrap().id;
+        let body = format!(r#"{{"user_id":"{}"}}"#, owner_id);
+        let status = moderate(&client, &root, uri("ban"), body).await;
+        assert_eq!(status, Status::Forbidden);
+        for action in ["kick", "ban"] {
+            let body = format!(r#"{{"user_id":"{}"}}"#, root_id);
+            let status = moderate(&client, &owner, uri(action), body).await;
+            assert_eq!(status, Status::Forbidden, "{}", action);
+        }
+        let body = format!(r#"{{"user_id":"{}","minutes":5}}"#, root_id);
+        let status = moderate(&client, &owner, uri("mute"), body).await;
+        assert_eq!(status, Status::Forbidden);
+
+        let mut actions = log(&client, &owner, &room_id)
+            .await
+            .iter()
+            .map(|action| {
+                assert_eq!(action["moderator_id"], owner_id.as_str());
+                assert_eq!(action["target_user_id"], troll_id.as_str());
+                format!("{} {}", action["kind"], action["reason"])
+            })
+            .collect::<Vec<_>>();
+        actions.sort();
+        let expected = [
+            r#""ban" "spam""#,
+            r#""kick" "rude""#,
+            r#""mute" "flood""#,
+            r#""unban" "forgiven""#,
+            r#""unmute" "served""#,
+        ];
+        assert_eq!(actions, expected);
+
+        let needles = [
+            r#""type":"user_muted""#,
+            r#""type":"user_unmuted""#,
+            r#""type":"user_kicked""#,
+            r#""type":"user_banned""#,
+            r#""type":"user_unbanned""#,
+        ];
+        timeout(Duration::from_secs(5), read_until(&mut stream, &needles))
+            .await
+            .expect("Sanctions didn't reach the stream");
+    }
+
+    #[rocket::async_test]
+    async fn test_remove_message() {
+        let database = TempDatabase::new();
+        let client = Client::untracked(crate::build_with(database.figment()))
+            .await
+            .unwrap();
+        let db = client.rocket().state::<Db>().unwrap().clone();
+        let (owner, _) = register(&client, "owner").await;
+        let (troll, _) = register(&client, "troll").await;
+        let room_id = create_room(&client, &owner).await;
+        let mut stream = client.get("/events").header(owner.clone()).dispatch().await;
+        let body = send(&client, &troll, &room_id)
+            .await
+            .into_string()
+            .await
+            .unwrap();
+        let value = serde_json::from_str::<Value>(&body).unwrap();
+        let message_id = value["data"].as_str().unwrap().to_string();
+
+        let uri = format!("/moderation/{}/messages/{}/remove", room_id, message_id);
+        let blank = r#"{"reason":" "}"#.to_string();
+        let status = moderate(&client, &troll, uri.clone(), blank.clone()).await;
+        assert_eq!(status, Status::Forbidden);
+        let status = moderate(&client, &owner, uri.clone(), blank).await;
+        assert_eq!(status, Status::BadRequest);
+
+        let reason = r#"{"reason":"spam"}"#.to_string();
+        let status = moderate(&client, &owner, uri.clone(), reason.clone()).await;
+        assert_eq!(status, Status::Ok);
+        assert!(db.messages(&room_id, 0, 10).await.unwrap().is_empty());
+        let removed = format!(r#""message_id":"{}""#, message_id);
+        let needles = [r#""type":"message_removed""#, removed.as_str()];
+        timeout(Duration::from_secs(5), read_until(&mut stream, &needles))
+            .await
+            .expect("The removal didn't reach the stream");
+
+        // a message already gone is neither removed nor logged again
+        let status = moderate(&client, &owner, uri, reason).await;
+        assert_eq!(status, Status::BadRequest);
+        let log = log(&client, &owner, &room_id).await;
+        assert_eq!(log.len(), 1);
+        assert_eq!(log[0]["kind"], "remove_message");
+        assert_eq!(log[0]["target_message_id"], message_id.as_str());
+        assert_eq!(log[0]["reason"], "spam");
+    }
+}
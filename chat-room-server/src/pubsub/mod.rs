@@ -0,0 +1,137 @@
+//! Delivery of room changes to the live streams. In-process by default, through Redis with the
+//! `redis` feature so that every instance behind a load balancer sees every change.
+use std::fmt::Display;
+use std::ops::Deref;
+use std::sync::Arc;
+
+use rocket::fairing::AdHoc;
+use rocket::tokio::sync::broadcast::{self, Receiver, Sender};
+use serde::Deserialize;
+
+use crate::base::RoomChange;
+
+#[cfg(feature = "redis")]
+pub mod redis;
+
+/// Changes a subscriber can fall behind by before it starts missing them.
+const CAPACITY: usize = 1024;
+
+#[derive(Debug)]
+pub enum PubSubError {
+    Backend(String),
+}
+
+impl Display for PubSubError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PubSubError::Backend(err) => write!(f, "Unable to publish room change: {}", err),
+        }
+    }
+}
+
+#[rocket::async_trait]
+pub trait PubSub: Send + Sync {
+    /// Delivers the change to the subscribers of every instance, this one included.
+    async fn publish(&self, change: RoomChange) -> Result<(), PubSubError>;
+    /// Changes published from now on.
+    fn subscribe(&self) -> Receiver<RoomChange>;
+    /// Changes published from now on by this instance only, for work that must happen once per
+    /// change however many instances there are.
+    fn published(&self) -> Receiver<RoomChange>;
+}
+
+/// Fan-out within a single server process.
+pub struct LocalPubSub {
+    tx: Sender<RoomChange>,
+}
+
+impl LocalPubSub {
+    pub fn new() -> Self {
+        let (tx, _) = broadcast::channel(CAPACITY);
+        LocalPubSub { tx }
+    }
+}
+
+impl Default for LocalPubSub {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[rocket::async_trait]
+impl PubSub for LocalPubSub {
+    async fn publish(&self, change: RoomChange) -> Result<(), PubSubError> {
+        // no subscribers just means nobody is listening
+        let _ = self.tx.send(change);
+        Ok(())
+    }
+
+    fn subscribe(&self) -> Receiver<RoomChange> {
+        self.tx.subscribe()
+    }
+
+    fn published(&self) -> Receiver<RoomChange> {
+        self.tx.subscribe()
+    }
+}
+
+/// Handle to the pub/sub, managed by Rocket.
+#[derive(Clone)]
+pub struct Changes(pub Arc<dyn PubSub>);
+
+impl Deref for Changes {
+    type Target = dyn PubSub;
+
+    fn deref(&self) -> &Self::Target {
+        &*self.0
+    }
+}
+
+/// `pubsub` in `Rocket.toml`, the in-process pub/sub is used without a url.
+#[derive(Deserialize, Default)]
+pub struct PubSubConfig {
+    pub url: Option<String>,
+    #[serde(default = "default_channel")]
+    pub channel: String,
+}
+
+fn default_channel() -> String {
+    "quchat:room_changes".to_string()
+}
+
+pub async fn connect(config: &PubSubConfig) -> Result<Changes, PubSubError> {
+    match &config.url {
+        None => Ok(Changes(Arc::new(LocalPubSub::new()))),
+        #[cfg(feature = "redis")]
+        Some(url) => Ok(Changes(Arc::new(
+            redis::RedisPubSub::connect(url, &config.channel).await?,
+        ))),
+        #[cfg(not(feature = "redis"))]
+        Some(_) => Err(PubSubError::Backend(
+            "the server is built without the redis feature".to_string(),
+        )),
+    }
+}
+
+pub fn stage() -> AdHoc {
+    AdHoc::try_on_ignite("PubSub", |rocket| async {
+        let config = match rocket.figment().find_value("pubsub") {
+            Ok(value) => match value.deserialize::<PubSubConfig>() {
+                Ok(config) => config,
+                Err(err) => {
+                    error!("Invalid pubsub config: {}", err);
+                    return Err(rocket);
+                }
+            },
+            Err(_) => PubSubConfig::default(),
+        };
+
+        match connect(&config).await {
+            Ok(changes) => Ok(rocket.manage(changes)),
+            Err(err) => {
+                error!("Unable to connect to the pubsub: {}", err);
+                Err(rocket)
+            }
+        }
+    })
+}
@@ -0,0 +1,337 @@
+use std::time::Duration;
+
+use redis::aio::{MultiplexedConnection, PubSub as RedisSubscription};
+use redis::AsyncCommands;
+use rocket::futures::StreamExt;
+use rocket::serde::json::serde_json;
+use rocket::tokio::sync::broadcast::{self, Receiver, Sender};
+
+use super::{PubSub, PubSubError, CAPACITY};
+use crate::base::RoomChange;
+
+/// Wait before subscribing again after the connection to Redis is lost.
+const RESUBSCRIBE_DELAY: Duration = Duration::from_secs(1);
+
+/// Publishes changes to a Redis channel all instances subscribe to. Changes published while an
+/// instance is resubscribing don't reach its streams.
+pub struct RedisPubSub {
+    connection: MultiplexedConnection,
+    channel: String,
+    local: Sender<RoomChange>,
+    published: Sender<RoomChange>,
+}
+
+impl From<redis::RedisError> for PubSubError {
+    fn from(value: redis::RedisError) -> Self {
+        PubSubError::Backend(value.to_string())
+    }
+}
+
+impl RedisPubSub {
+    /// Subscribes before returning, so changes published once this resolves are received.
+    pub async fn connect(url: &str, channel: &str) -> Result<Self, PubSubError> {
+        let client = redis::Client::open(url)?;
+        let connection = client.get_multiplexed_tokio_connection().await?;
+        let subscription = subscribe(&client, channel).await?;
+
+        let (local, _) = broadcast::channel(CAPACITY);
+        rocket::tokio::spawn(forward(
+            client,
+            subscription,
+            channel.to_string(),
+            local.clone(),
+        ));
+
+        let (published, _) = broadcast::channel(CAPACITY);
+        Ok(RedisPubSub {
+            connection,
+            channel: channel.to_string(),
+            local,
+            published,
+        })
+    }
+}
+
+async fn subscribe(
+    client: &redis::Client,
+    channel: &str,
+) -> Result<RedisSubscription, redis::RedisError> {
+    let mut subscription = client.get_async_pubsub().await?;
+    subscription.subscribe(channel).await?;
+    Ok(subscription)
+}
+
+/// Hands every change from Redis to the local subscribers, resubscribing when the
+/// connection drops.
+async fn forward(
+    client: redis::Client,
+    mut subscription: RedisSubscription,
+    channel: String,
+    local: Sender<RoomChange>,
+) {
+    loop {
+        let mut messages = subscription.into_on_message();
+        while let Some(message) = messages.next().await {
+            let payload = message.get_payload_bytes();
+            match serde_json::from_slice::<RoomChange>(payload) {
+                Ok(change) => {
+                    let _ = local.send(change);
+                }
+                Err(err) => error!("Dropping malformed room change: {}", err),
+            }
+        }
+
+        error!("Lost the Redis subscription, resubscribing");
+        subscription = loop {
+            rocket::tokio::time::sleep(RESUBSCRIBE_DELAY).await;
+            match subscribe(&client, &channel).await {
+                Ok(subscription) => break subscription,
+                Err(err) => error!("Unable to subscribe to Redis: {}", err),
+            }
+        };
+    }
+}
+
+#[rocket::async_trait]
+impl PubSub for RedisPubSub {
+    async fn publish(&self, change: RoomChange) -> Result<(), PubSubError> {
+        let payload =
+            serde_json::to_vec(&change).map_err(|err| PubSubError::Backend(err.to_string()))?;
+        // the multiplexed connection is cheap to clone and shares the socket
+        let mut connection = self.connection.clone();
+        connection
+            .publish::<_, _, ()>(&self.channel, payload)
+            .await?;
+        let _ = self.published.send(change);
+        Ok(())
+    }
+
+    fn subscribe(&self) -> Receiver<RoomChange> {
+        self.local.subscribe()
+    }
+
+    fn published(&self) -> Receiver<RoomChange> {
+        self.published.subscribe()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use qu_chat_models::RoomEvent;
+    use rocket::figment::Figment;
+    use rocket::http::{ContentType, Header, Status};
+    use rocket::local::asynchronous::{Client, LocalResponse};
+    use rocket::tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+    use rocket::tokio::net::{TcpListener, TcpStream};
+    use rocket::tokio::sync::{mpsc, Mutex};
+    use rocket::tokio::time::timeout;
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    use crate::test_util::{data, TempDatabase};
+
+    type Subscribers = Arc<Mutex<HashMap<String, Vec<mpsc::UnboundedSender<Vec<u8>>>>>>;
+
+    fn bulk(value: &[u8]) -> Vec<u8> {
+        let mut out = format!("${}\r\n", value.len()).into_bytes();
+        out.extend_from_slice(value);
+        out.extend_from_slice(b"\r\n");
+        out
+    }
+
+    fn push(kind: &[u8], channel: &[u8], last: Vec<u8>) -> Vec<u8> {
+        let mut out = b"*3\r\n".to_vec();
+        out.extend(bulk(kind));
+        out.extend(bulk(channel));
+        out.extend(last);
+        out
+    }
+
+    /// Reads one command, sent by clients as an array of bulk strings.
+    async fn command(
+        reader: &mut BufReader<rocket::tokio::net::tcp::OwnedReadHalf>,
+    ) -> Option<Vec<Vec<u8>>> {
+        let mut line = String::new();
+        reader.read_line(&mut line).await.ok()?;
+        let count = line.trim_end().strip_prefix('*')?.parse::<usize>().ok()?;
+        let mut args = Vec::with_capacity(count);
+        for _ in 0..count {
+            line.clear();
+            reader.read_line(&mut line).await.ok()?;
+            let len = line.trim_end().strip_prefix('$')?.parse::<usize>().ok()?;
+            let mut arg = vec![0; len + 2];
+            reader.read_exact(&mut arg).await.ok()?;
+            arg.truncate(len);
+            args.push(arg);
+        }
+        Some(args)
+    }
+
+    async fn serve(stream: TcpStream, subscribers: Subscribers) {
+        let (reader, mut writer) = stream.into_split();
+        let (tx, mut rx) = mpsc::unbounded_channel::<Vec<u8>>();
+        rocket::tokio::spawn(async move {
+            while let Some(bytes) = rx.recv().await {
+                if writer.write_all(&bytes).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        let mut reader = BufReader::new(reader);
+        while let Some(args) = command(&mut reader).await {
+            let name = String::from_utf8_lossy(&args[0]).to_uppercase();
+            let reply = match (name.as_str(), &args[1..]) {
+                ("SUBSCRIBE", channels) => {
+                    let mut reply = Vec::new();
+                    for (i, channel) in channels.iter().enumerate() {
+                        let channel_name = String::from_utf8_lossy(channel).to_string();
+                        subscribers
+                            .lock()
+                            .await
+                            .entry(channel_name)
+                            .or_default()
+                            .push(tx.clone());
+                        reply.extend(push(
+                            b"subscribe",
+                            channel,
+                            format!(":{}\r\n", i + 1).into_bytes(),
+                        ));
+                    }
+                    reply
+                }
+                ("PUBLISH", [channel, payload]) => {
+                    let channel_name = String::from_utf8_lossy(channel).to_string();
+                    let mut subscribers = subscribers.lock().await;
+                    let receivers = subscribers.entry(channel_name).or_default();
+                    receivers.retain(|receiver| {
+                        receiver
+                            .send(push(b"message", channel, bulk(payload)))
+                            .is_ok()
+                    });
+                    format!(":{}\r\n", receivers.len()).into_bytes()
+                }
+                ("PING", _) => b"+PONG\r\n".to_vec(),
+                _ => b"+OK\r\n".to_vec(),
+            };
+            if tx.send(reply).is_err() {
+                break;
+            }
+        }
+    }
+
+    /// Just enough of Redis for pub/sub, returns its url.
+    async fn stand_in_server() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("redis://{}/", listener.local_addr().unwrap());
+        let subscribers = Subscribers::default();
+        rocket::tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                rocket::tokio::spawn(serve(stream, subscribers.clone()));
+            }
+        });
+        url
+    }
+
+    #[rocket::async_test]
+    async fn test_publish_reaches_other_instance() {
+        let url = stand_in_server().await;
+        let first = RedisPubSub::connect(&url, "changes").await.unwrap();
+        let second = RedisPubSub::connect(&url, "changes").await.unwrap();
+        let mut first_rx = first.subscribe();
+        let mut second_rx = second.subscribe();
+
+        let change = RoomChange {
+            event: RoomEvent::Archived {
+                room_id: "room".to_string(),
+            },
+        };
+        first.publish(change).await.unwrap();
+
+        for rx in [&mut first_rx, &mut second_rx] {
+            let received = timeout(Duration::from_secs(5), rx.recv())
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(received.event.room_id(), "room");
+        }
+    }
+
+    fn figment(database: &TempDatabase, pubsub: &str) -> Figment {
+        database.figment().merge(("pubsub.url", pubsub))
+    }
+
+    async fn read_until(response: &mut LocalResponse<'_>, needle: &str) -> String {
+        let mut events = String::new();
+        let mut buf = [0; 1024];
+        while !events.contains(needle) {
+            let read = response.read(&mut buf).await.unwrap();
+            assert!(read > 0, "Stream ended before {}", needle);
+            events.push_str(&String::from_utf8_lossy(&buf[..read]));
+        }
+        events
+    }
+
+    #[rocket::async_test]
+    async fn test_message_reaches_stream_on_other_instance() {
+        let url = stand_in_server().await;
+        let database = TempDatabase::new();
+        let first = Client::untracked(crate::build_with(figment(&database, &url)))
+            .await
+            .unwrap();
+        let second = Client::untracked(crate::build_with(figment(&database, &url)))
+            .await
+            .unwrap();
+
+        let body = first
+            .post("/auth/register")
+            .header(ContentType::JSON)
+            .body(r#"{"username":"john","password":"doe"}"#)
+            .dispatch()
+            .await
+            .into_string()
+            .await
+            .unwrap();
+        let auth = Header::new("Authorization", format!("Bearer {}", data(&body, "token")));
+        let body = first
+            .post("/rooms")
+            .header(ContentType::JSON)
+            .header(auth.clone())
+            .body(r#"{"name":"lobby"}"#)
+            .dispatch()
+            .await
+            .into_string()
+            .await
+            .unwrap();
+        let room_id = data(&body, "id");
+
+        let mut stream = second
+            .get(format!("/messages/events/{}", room_id))
+            .header(auth.clone())
+            .dispatch()
+            .await;
+        assert_eq!(stream.status(), Status::Ok);
+
+        let status = first
+            .post("/messages/send")
+            .header(ContentType::JSON)
+            .header(auth)
+            .body(format!(
+                r#"{{"text":"hello from first","room_id":"{}"}}"#,
+                room_id
+            ))
+            .dispatch()
+            .await
+            .status();
+        assert_eq!(status, Status::Ok);
+
+        let events = timeout(
+            Duration::from_secs(5),
+            read_until(&mut stream, "hello from first"),
+        )
+        .await
+        .expect("Message didn't reach the other instance");
+        assert!(events.contains(&room_id));
+    }
+}
@@ -0,0 +1,275 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use rocket::fairing::AdHoc;
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome, Request};
+use serde::Deserialize;
+
+use crate::authentication::{hash, SECRET_KEY_MINE};
+use crate::bots::API_TOKEN_PREFIX;
+use crate::jwt::{token_from_barear, validate_jwt};
+
+/// Token bucket settings of a route group, read from `rate_limits.<group>` in `Rocket.toml`.
+/// The group of a route is the path it is mounted on, e.g. `auth` or `messages`.
+#[derive(Deserialize, Debug, Clone, Copy)]
+pub struct BucketConfig {
+    pub capacity: f64,
+    pub refill_per_second: f64,
+}
+
+struct Bucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl Bucket {
+    fn new(config: &BucketConfig, now: Instant) -> Self {
+        Bucket {
+            tokens: config.capacity,
+            last_refill: now,
+        }
+    }
+
+    /// Adds the tokens refilled since the last call, then returns whether one is left, or else
+    /// how many seconds until one is.
+    fn refill(&mut self, config: &BucketConfig, now: Instant) -> Result<(), u64> {
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * config.refill_per_second).min(config.capacity);
+        self.last_refill = now;
+
+        if self.tokens >= 1.0 {
+            return Ok(());
+        }
+        let secs = if config.refill_per_second > 0.0 {
+            ((1.0 - self.tokens) / config.refill_per_second).ceil() as u64
+        } else {
+            u64::MAX
+        };
+        Err(secs.min(MAX_RETRY_AFTER))
+    }
+}
+
+pub struct RateLimiter {
+    groups: HashMap<String, BucketConfig>,
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+const MAX_BUCKETS: usize = 10_000;
+/// Buckets untouched for this long are full again and can be dropped.
+const IDLE_BUCKET_TTL: Duration = Duration::from_secs(60 * 60);
+/// Longest `Retry-After` sent, e.g. for buckets that never refill.
+const MAX_RETRY_AFTER: u64 = IDLE_BUCKET_TTL.as_secs();
+
+impl RateLimiter {
+    pub fn new(groups: HashMap<String, BucketConfig>) -> Self {
+        RateLimiter {
+            groups,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Every key has to have a token left for the call to go through, only then is one taken
+    /// from each.
+    pub fn check(&self, group: &str, keys: &[String], now: Instant) -> Result<(), u64> {
+        let Some(config) = self.groups.get(group) else {
+            return Ok(());
+        };
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() > MAX_BUCKETS {
+            buckets.retain(|_, bucket| now.duration_since(bucket.last_refill) < IDLE_BUCKET_TTL);
+        }
+
+        let mut retry_after = None;
+        for key in keys {
+            let bucket = buckets
+                .entry(format!("{}:{}", group, key))
+                .or_insert_with(|| Bucket::new(config, now));
+            if let Err(secs) = bucket.refill(config, now) {
+                retry_after = Some(retry_after.unwrap_or(0).max(secs));
+            }
+        }
+        if let Some(secs) = retry_after {
+            return Err(secs);
+        }
+
+        for key in keys {
+            if let Some(bucket) = buckets.get_mut(&format!("{}:{}", group, key)) {
+                bucket.tokens -= 1.0;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Seconds the client should wait, kept on the request for the 429 catcher.
+pub struct RetryAfter(pub u64);
+
+/// Guard that throttles a route per client ip and per caller, see `caller_key`.
+pub struct RateLimit;
+
+/// Who calls besides their ip: the user of a valid JWT, the bot of an API token or the incoming
+/// webhook posted to, the tokens hashed as they are stored.
+fn caller_key(req: &Request<'_>, group: &str) -> Option<String> {
+    if group == "hooks" {
+        return req
+            .routed_segment(0)
+            .map(|token| format!("hook:{}", hash(token)));
+    }
+    let token = token_from_barear(req.headers().get_one("Authorization")?).ok()?;
+    if token.starts_with(API_TOKEN_PREFIX) {
+        return Some(format!("token:{}", hash(token)));
+    }
+    validate_jwt(token, SECRET_KEY_MINE)
+        .ok()
+        .map(|body| format!("user:{}", body.user_id))
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for RateLimit {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let (Some(limiter), Some(route)) = (req.rocket().state::<RateLimiter>(), req.route())
+        else {
+            return Outcome::Success(RateLimit);
+        };
+        let group = route.uri.base().trim_start_matches('/');
+
+        let mut keys = Vec::new();
+        if let Some(ip) = req.client_ip() {
+            keys.push(format!("ip:{}", ip));
+        }
+        if let Some(key) = caller_key(req, group) {
+            keys.push(key);
+        }
+
+        match limiter.check(group, &keys, Instant::now()) {
+            Ok(_) => Outcome::Success(RateLimit),
+            Err(secs) => {
+                req.local_cache(|| RetryAfter(secs));
+                Outcome::Error((Status::TooManyRequests, ()))
+            }
+        }
+    }
+}
+
+pub fn stage() -> AdHoc {
+    AdHoc::on_ignite("Rate Limit", |rocket| async {
+        let groups = rocket
+            .figment()
+            .extract_inner::<HashMap<String, BucketConfig>>("rate_limits")
+            .unwrap_or_default();
+        rocket.manage(RateLimiter::new(groups))
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use rocket::http::{ContentType, Header};
+    use rocket::local::asynchronous::Client;
+
+    use super::*;
+    use crate::test_util::TempDatabase;
+
+    fn limiter() -> RateLimiter {
+        let config = BucketConfig {
+            capacity: 2.0,
+            refill_per_second: 0.5,
+        };
+        RateLimiter::new(HashMap::from([("messages".to_string(), config)]))
+    }
+
+    #[test]
+    fn test_bucket_empties_and_refills() {
+        let limiter = limiter();
+        let keys = vec!["ip:127.0.0.1".to_string()];
+        let now = Instant::now();
+
+        assert!(limiter.check("messages", &keys, now).is_ok());
+        assert!(limiter.check("messages", &keys, now).is_ok());
+        assert_eq!(limiter.check("messages", &keys, now), Err(2));
+
+        let later = now + Duration::from_secs(2);
+        assert!(limiter.check("messages", &keys, later).is_ok());
+    }
+
+    #[test]
+    fn test_refused_call_takes_no_token() {
+        let limiter = limiter();
+        let user = "user:john".to_string();
+        let now = Instant::now();
+
+        for _ in 0..2 {
+            let keys = vec!["ip:10.0.0.1".to_string(), user.clone()];
+            assert!(limiter.check("messages", &keys, now).is_ok());
+        }
+        // refused for the user, the second ip keeps its tokens
+        let keys = vec!["ip:10.0.0.2".to_string(), user.clone()];
+        assert_eq!(limiter.check("messages", &keys, now), Err(2));
+        let keys = vec!["ip:10.0.0.2".to_string()];
+        assert!(limiter.check("messages", &keys, now).is_ok());
+        assert!(limiter.check("messages", &keys, now).is_ok());
+    }
+
+    #[test]
+    fn test_retry_after_is_bounded() {
+        let config = BucketConfig {
+            capacity: 1.0,
+            refill_per_second: 0.0,
+        };
+        let limiter = RateLimiter::new(HashMap::from([("auth".to_string(), config)]));
+        let keys = vec!["ip:127.0.0.1".to_string()];
+        let now = Instant::now();
+
+        assert!(limiter.check("auth", &keys, now).is_ok());
+        assert_eq!(limiter.check("auth", &keys, now), Err(MAX_RETRY_AFTER));
+    }
+
+    #[test]
+    fn test_unconfigured_group_is_unlimited() {
+        let limiter = limiter();
+        let keys = vec!["ip:127.0.0.1".to_string()];
+        let now = Instant::now();
+
+        for _ in 0..10 {
+            assert!(limiter.check("rooms", &keys, now).is_ok());
+        }
+    }
+
+    #[rocket::async_test]
+    async fn test_tokens_have_their_own_buckets() {
+        let database = TempDatabase::new();
+        let figment = database
+            .figment()
+            .merge(("rate_limits.hooks.capacity", 1.0))
+            .merge(("rate_limits.hooks.refill_per_second", 0.0))
+            .merge(("rate_limits.messages.capacity", 1.0))
+            .merge(("rate_limits.messages.refill_per_second", 0.0));
+        let client = Client::untracked(crate::build_with(figment)).await.unwrap();
+
+        // unknown tokens are refused, but only after taking a token from their bucket
+        let post = |token: &str| {
+            client
+                .post(format!("/hooks/{}", token))
+                .body("hi")
+                .dispatch()
+        };
+        assert_eq!(post("qih_first").await.status(), Status::Unauthorized);
+        assert_eq!(post("qih_first").await.status(), Status::TooManyRequests);
+        assert_eq!(post("qih_second").await.status(), Status::Unauthorized);
+
+        let send = |token: &str| {
+            client
+                .post("/messages/send")
+                .header(ContentType::JSON)
+                .header(Header::new("Authorization", format!("Bearer {}", token)))
+                .body(r#"{"text":"hi","room_id":"lobby"}"#)
+                .dispatch()
+        };
+        assert_eq!(send("qbt_first").await.status(), Status::Unauthorized);
+        assert_eq!(send("qbt_first").await.status(), Status::TooManyRequests);
+        assert_eq!(send("qbt_second").await.status(), Status::Unauthorized);
+    }
+}
@@ -1,20 +1,21 @@
 use chrono::Utc;
-use qu_chat_models::{CreateRoomParam, Room, RoomState};
+use qu_chat_models::{
+    CreateRoomParam, DirectRoomParams, RenameRoomParams, Room, RoomEvent, RoomState,
+    RoomTopicParams, UserProfile,
+};
 use rocket::fairing::AdHoc;
-use rocket::futures::TryStreamExt;
 use rocket::http::Status;
-use rocket::response::stream::{Event, EventStream};
 use rocket::serde::json::Json;
-use rocket::tokio::sync::broadcast::{error::RecvError, Sender};
-use rocket::{Shutdown, State};
-use rocket_db_pools::Connection;
-use serde::{Deserialize, Serialize};
-use sqlx::Row;
-use std::collections::HashMap;
+use rocket::State;
+use serde::Serialize;
 
 use crate::authentication::UserId;
-use crate::base::{ApiResult, ApiResultBuilder, RoomChange};
-use crate::base::{Db, Rx, Tx};
+use crate::base::{ApiResult, ApiResultBuilder, Error, RoomChange};
+use crate::base::{Rx, Tx};
+use crate::moderation::ensure_not_sanctioned;
+use crate::pubsub::Changes;
+use crate::rate_limit::RateLimit;
+use crate::store::Db;
 
 #[derive(Serialize)]
 pub struct RoomsStatus {
@@ -65,27 +66,27 @@ fn remove_online_user(
 }
 
 #[get("/")]
-async fn get_all(mut db: Connection<Db>) -> ApiResult<Vec<Room>> {
-    let rooms = sqlx::query_as!(Room, "SELECT * FROM rooms ORDER BY create_date DESC")
-        .fetch(&mut **db)
-        .try_collect::<Vec<_>>()
-        .await;
+async fn get_all(user_id: UserId, db: Db) -> ApiResult<Vec<Room>> {
+    let rooms = db.rooms(&user_id.id).await;
 
     ApiResultBuilder::from(rooms, "Failed to fetch rooms")
 }
 
 #[get("/<id>")]
-async fn get_room(id: &str, mut db: Connection<Db>) -> ApiResult<Room> {
-    let result = sqlx::query_as!(Room, "SELECT * FROM rooms WHERE (id)=($1)", id)
-        .fetch_one(&mut **db)
-        .await;
+async fn get_room(id: &str, db: Db) -> ApiResult<Room> {
+    let result = db
+        .room(id)
+        .await
+        .and_then(|room| room.ok_or(sqlx::Error::RowNotFound));
 
     ApiResultBuilder::from(result, "Failed to fetch rooms")
 }
 
 #[post("/", data = "<param>")]
 async fn insert(
-    mut db: Connection<Db>,
+    _limit: RateLimit,
+    changes: &State<Changes>,
+    db: Db,
     user_id: UserId,
     param: Json<CreateRoomParam>,
 ) -> ApiResult<Room> {
@@ -94,103 +95,210 @@ async fn insert(
         name: param.name.clone(),
         creator_id: user_id.id,
         create_date: Utc::now().timestamp(),
+        topic: String::new(),
+        archived: false,
     };
-    let result = sqlx::query!(
-        "INSERT INTO rooms (id, name, creator_id, create_date) VALUES ($1, $2, $3, $4)",
-        room.id,
-        room.name,
-        room.creator_id,
-        room.create_date
-    )
-    .execute(&mut **db)
-    .await;
+    let result = db.create_room(&room).await;
     match result {
-        Ok(_) => ApiResultBuilder::data(room),
+        Ok(_) => {
+            let _ = changes
+                .publish(RoomChange {
+                    event: RoomEvent::Created { room: room.clone() },
+                })
+                .await;
+            ApiResultBuilder::data(room)
+        }
         Err(_) => ApiResultBuilder::err("Failed to create room"),
     }
 }
 
-#[get("/states/events?<room_ids>")]
-async fn state_events(
-    changes: &State<Sender<RoomChange>>,
-    room_ids: String,
-    mut shutdown: Shutdown,
-) -> EventStream![] {
-    let mut rx = changes.subscribe();
-    let room_ids = room_ids
-        .split(',')
-        .map(|s| s.to_owned())
-        .collect::<std::collections::HashSet<String>>();
-
-    EventStream! {
-        loop {
-            let _ = rocket::tokio::select! {
-                change = rx.recv() => {
-                     match change {
-
-                    Ok(change) if room_ids.contains(&change.message.room_id) => {
-                        change
-                    }
-                    Ok(_) => continue,
-                    Err(RecvError::Closed) => break,
-                    Err(RecvError::Lagged(_)) => continue,
-                }},
-                _ = &mut shutdown => break,
-            };
-
-            yield Event::empty();
+/// Name of a direct room, the same whoever of the two opens it.
+fn direct_room_name(name: &str, other_name: &str) -> String {
+    let mut names = [name, other_name];
+    names.sort();
+    format!("{} & {}", names[0], names[1])
+}
+
+/// The room of the user and another one, created the first time. Only the two of them can read
+/// it, join it or see it in the list of rooms.
+#[post("/direct", data = "<params>")]
+async fn direct(
+    _limit: RateLimit,
+    changes: &State<Changes>,
+    db: Db,
+    user_id: UserId,
+    params: Json<DirectRoomParams>,
+) -> ApiResult<Room> {
+    if params.user_id == user_id.id {
+        return ApiResultBuilder::err("A direct room needs another user.");
+    }
+    match db.direct_room(&user_id.id, &params.user_id).await {
+        Ok(Some(room)) => return ApiResultBuilder::data(room),
+        Ok(None) => (),
+        Err(_) => return Err(Error::Internal(())),
+    }
+    let (user, other) = match (db.user(&user_id.id).await, db.user(&params.user_id).await) {
+        (Ok(Some(user)), Ok(Some(other))) => (user, other),
+        (Ok(_), Ok(None)) => return ApiResultBuilder::err("User doesn't exists."),
+        _ => return Err(Error::Internal(())),
+    };
+
+    let room = Room {
+        id: uuid::Uuid::new_v4().to_string(),
+        name: direct_room_name(&user.name, &other.name),
+        creator_id: user.id,
+        create_date: Utc::now().timestamp(),
+        topic: String::new(),
+        archived: false,
+    };
+    if db
+        .create_direct_room(&room, &room.creator_id, &other.id)
+        .await
+        .is_err()
+    {
+        // the other user opened it at the same time
+        let result = db
+            .direct_room(&room.creator_id, &other.id)
+            .await
+            .and_then(|room| room.ok_or(sqlx::Error::RowNotFound));
+        return ApiResultBuilder::from(result, "Failed to create room");
+    }
+    let _ = changes
+        .publish(RoomChange {
+            event: RoomEvent::Created { room: room.clone() },
+        })
+        .await;
+    ApiResultBuilder::data(room)
+}
+
+/// Checks that the user is either the creator of the room or an admin.
+pub async fn ensure_room_admin(
+    db: &Db,
+    user_id: &UserId,
+    room_id: &str,
+) -> Result<(), Error<'static>> {
+    let room = db.room(room_id).await;
+    let user = db.user(&user_id.id).await;
+
+    match (room, user) {
+        (Ok(Some(room)), Ok(Some(user))) if room.creator_id == user_id.id || user.is_admin => {
+            Ok(())
         }
+        (Ok(Some(_)), Ok(_)) => Err(Error::forbidden(
+            "Only the room creator or an admin can do this.",
+        )),
+        (Ok(None), _) => Err(Error::logical("Room doesn't exists.")),
+        _ => Err(Error::Internal(())),
     }
 }
 
-#[get("/states?<room_ids>")]
-async fn rooms_state(
-    mut db: Connection<Db>,
+#[post("/<id>/rename", data = "<params>", rank = 2)]
+async fn rename(
+    id: &str,
+    params: Json<RenameRoomParams>,
+    changes: &State<Changes>,
     user_id: UserId,
-    room_ids: String,
-) -> ApiResult<Vec<RoomState>> {
-    let room_ids = room_ids.split(',').collect::<Vec<&str>>();
-    let placeholders = std::iter::repeat("?")
-        .take(room_ids.len())
-        .collect::<Vec<_>>()
-        .join(",");
-    let query = format!(
-        "SELECT room_id, last_seen FROM room_state WHERE room_id IN ({}) AND user_id = (?)",
-        placeholders
-    );
-
-    let mut query_builder = sqlx::query(&query);
-    for id in room_ids.iter() {
-        query_builder = query_builder.bind(id);
+    db: Db,
+) -> ApiResult<String> {
+    ensure_room_admin(&db, &user_id, id).await?;
+    if params.name.trim().is_empty() {
+        return ApiResultBuilder::err("Room name can't be empty.");
     }
 
-    let last_seen_res = query_builder
-        .bind(&user_id.id)
-        .fetch_all(&mut **db)
-        .await
-        .map(|res| {
-            res.iter()
-                .map(|row| (row.get(0), row.try_get(1).ok()))
-                .collect::<HashMap<String, Option<i32>>>()
-        });
-
-    let query = format!(
-        "SELECT room_id, MAX(create_date) FROM messages WHERE room_id IN ({}) AND sender_id != (?) GROUP BY room_id",
-        placeholders
-    );
-    let mut query_builder = sqlx::query(&query);
-    for id in room_ids.iter() {
-        query_builder = query_builder.bind(id);
+    let result = db.rename_room(id, &params.name).await;
+    if result.is_err() {
+        return ApiResultBuilder::err("Unable to rename room");
     }
-    let last_message_result = query_builder
-        .bind(&user_id.id)
-        .fetch_all(&mut **db)
-        .await
-        .map(|res| {
-            res.iter()
-                .map(|row| (row.get(0), row.get(1)))
-                .collect::<HashMap<String, i32>>()
-        });
+
+    let _ = changes
+        .publish(RoomChange {
+            event: RoomEvent::Renamed {
+                room_id: id.to_string(),
+                name: params.name.clone(),
+            },
+        })
+        .await;
+    ApiResultBuilder::data("Successfully renamed room".to_string())
+}
+
+#[post("/<id>/topic", data = "<params>", rank = 2)]
+async fn set_topic(
+    id: &str,
+    params: Json<RoomTopicParams>,
+    changes: &State<Changes>,
+    user_id: UserId,
+    db: Db,
+) -> ApiResult<String> {
+    ensure_room_admin(&db, &user_id, id).await?;
+
+    let result = db.set_room_topic(id, &params.topic).await;
+    if result.is_err() {
+        return ApiResultBuilder::err("Unable to change room topic");
+    }
+
+    let _ = changes
+        .publish(RoomChange {
+            event: RoomEvent::TopicChanged {
+                room_id: id.to_string(),
+                topic: params.topic.clone(),
+            },
+        })
+        .await;
+    ApiResultBuilder::data("Successfully changed room topic".to_string())
+}
+
+#[post("/<id>/archive", rank = 2)]
+async fn archive(id: &str, changes: &State<Changes>, user_id: UserId, db: Db) -> ApiResult<String> {
+    ensure_room_admin(&db, &user_id, id).await?;
+
+    let result = db.archive_room(id).await;
+    if result.is_err() {
+        return ApiResultBuilder::err("Unable to archive room");
+    }
+
+    let _ = changes
+        .publish(RoomChange {
+            event: RoomEvent::Archived {
+                room_id: id.to_string(),
+            },
+        })
+        .await;
+    ApiResultBuilder::data("Successfully archived room".to_string())
+}
+
+#[delete("/<id>")]
+async fn delete(id: &str, changes: &State<Changes>, user_id: UserId, db: Db) -> ApiResult<String> {
+    ensure_room_admin(&db, &user_id, id).await?;
+
+    match db.delete_room(id).await {
+        Ok(_) => {
+            let _ = changes
+                .publish(RoomChange {
+                    event: RoomEvent::Deleted {
+                        room_id: id.to_string(),
+                    },
+                })
+                .await;
+            ApiResultBuilder::data("Successfully deleted room".to_string())
+        }
+        _ => ApiResultBuilder::err("Unable to delete room"),
+    }
+}
+
+/// Members of a room are the users who have opened it at least once.
+#[get("/<id>/members")]
+async fn members(id: &str, user_id: UserId, db: Db) -> ApiResult<Vec<UserProfile>> {
+    ensure_not_sanctioned(&db, id, &user_id.id, true).await?;
+    let result = db.room_members(id).await;
+
+    ApiResultBuilder::from(result, "Unable to fetch room members")
+}
+
+#[get("/states?<room_ids>")]
+async fn rooms_state(db: Db, user_id: UserId, room_ids: String) -> ApiResult<Vec<RoomState>> {
+    let room_ids = room_ids.split(',').collect::<Vec<&str>>();
+    let last_seen_res = db.last_seen(&user_id.id, &room_ids).await;
+    let last_message_result = db.last_messages(&room_ids, &user_id.id).await;
 
     let mut states = Vec::new();
 
@@ -216,69 +324,50 @@ async fn rooms_state(
     }
 }
 
+/// Marks the room as seen, the first time the user joins the members of the room.
 #[post("/states/<room_id>")]
 pub async fn update_room_state(
-    mut db: Connection<Db>,
+    changes: &State<Changes>,
+    db: Db,
     user_id: UserId,
     room_id: String,
 ) -> ApiResult<String> {
-    #[derive(Deserialize)]
-    struct RoomStateDM {
-        id: String,
-        user_id: String,
-        room_id: String,
-        last_seen: Option<i64>,
-    }
-
-    let result = sqlx::query_as!(
-        RoomStateDM,
-        "SELECT * FROM room_state WHERE room_id = ($1) AND user_id = ($2)",
-        user_id.id,
-        room_id,
-    )
-    .fetch_optional(&mut **db)
-    .await;
+    ensure_not_sanctioned(&db, &room_id, &user_id.id, true).await?;
 
-    //check if result has item
-    let state = match result {
-        Ok(res) => res,
-        Err(_error) => return ApiResultBuilder::err("Unable to set state"),
+    let joined = match db.last_seen(&user_id.id, &[&room_id]).await {
+        Ok(seen) => !seen.contains_key(&room_id),
+        Err(_) => return Err(Error::Internal(())),
     };
-
     let now = chrono::Utc::now().timestamp();
-    // if has update
-    if let Some(state) = state {
-        let result = sqlx::query!(
-            "UPDATE room_state SET last_seen = ($1) WHERE id = ($2)",
-            now,
-            state.id,
-        )
-        .execute(&mut **db)
-        .await;
+    let result = db.mark_seen(&user_id.id, &room_id, now).await;
 
-        ApiResultBuilder::from(
-            result.map(|_| ("Successfully set state".to_string())),
-            "Unable to set state",
-        )
-    } else {
-        let id = uuid::Uuid::new_v4().to_string();
-        let result = sqlx::query!(
-            "INSERT INTO room_state (id, user_id, room_id, last_seen) VALUES ($1, $2, $3, $4)",
-            id,
-            user_id.id,
-            room_id,
-            now,
-        )
-        .execute(&mut **db)
-        .await;
-
-        ApiResultBuilder::from(
-            result.map(|_| ("Successfully set state".to_string())),
-            "Unable to set state",
-        )
+    if result.is_ok() && joined {
+        if let Ok(Some(user)) = db.user(&user_id.id).await {
+            let _ = changes
+                .publish(RoomChange {
+                    event: RoomEvent::MemberJoined {
+                        room_id: room_id.clone(),
+                        user_id: user.id,
+                        user_name: user.name,
+                    },
+                })
+                .await;
+        }
     }
+    ApiResultBuilder::from(
+        result.map(|_| "Successfully set state".to_string()),
+        "Unable to set state",
+    )
+}
 
-    //if doesn't have then insert
+/// Leaves the room, it's no longer listed in its members until the user opens it again.
+#[delete("/states/<room_id>")]
+async fn leave(room_id: &str, user_id: UserId, db: Db) -> ApiResult<String> {
+    let result = db.leave_room(&user_id.id, room_id).await;
+    ApiResultBuilder::from(
+        result.map(|_| "Left room".to_string()),
+        "Unable to leave room",
+    )
 }
 
 pub fn stage() -> AdHoc {
@@ -295,12 +384,270 @@ pub fn stage() -> AdHoc {
                     rooms_state,
                     get_all,
                     insert,
+                    direct,
                     get_room,
-                    state_events,
-                    update_room_state
+                    update_room_state,
+                    leave,
+                    rename,
+                    set_topic,
+                    archive,
+                    delete,
+                    members
                 ],
             )
             .manage(Tx(tx))
             .manage(Rx(rx))
     })
 }
+
+#[cfg(test)]
+mod test {
+    use rocket::http::{ContentType, Header, Status};
+    use rocket::local::asynchronous::Client;
+    use rocket::tokio::time::timeout;
+    use std::time::Duration;
+
+    use crate::store::Db;
+    use crate::test_util::{data, read_until, register, TempDatabase};
+
+    async fn open_direct_room(client: &Client, auth: &Header<'static>, user_id: &str) -> String {
+        client
+            .post("/rooms/direct")
+            .header(ContentType::JSON)
+            .header(auth.clone())
+            .body(format!(r#"{{"user_id":"{}"}}"#, user_id))
+            .dispatch()
+            .await
+            .into_string()
+            .await
+            .unwrap()
+    }
+
+    #[rocket::async_test]
+    async fn test_only_room_admins_change_rooms() {
+        let database = TempDatabase::new();
+        let client = Client::untracked(crate::build_with(database.figment()))
+            .await
+            .unwrap();
+        let db = client.rocket().state::<Db>().unwrap().clone();
+        let (owner, _) = register(&client, "owner").await;
+        let (guest, guest_id) = register(&client, "guest").await;
+        let (troll, troll_id) = register(&client, "troll").await;
+        let body = client
+            .post("/rooms")
+            .header(ContentType::JSON)
+            .header(owner.clone())
+            .body(r#"{"name":"lobby"}"#)
+            .dispatch()
+            .await
+            .into_string()
+            .await
+            .unwrap();
+        let room_id = data(&body, "id");
+        let mut stream = client.get("/events").header(owner.clone()).dispatch().await;
+
+        let status = client
+            .post(format!("/rooms/states/{}", room_id))
+            .header(guest.clone())
+            .dispatch()
+            .await
+            .status();
+        assert_eq!(status, Status::Ok);
+        let send = |auth: &Header<'static>, text: &str| {
+            client
+                .post("/messages/send")
+                .header(ContentType::JSON)
+                .header(auth.clone())
+                .body(format!(r#"{{"text":"{}","room_id":"{}"}}"#, text, room_id))
+                .dispatch()
+        };
+        assert_eq!(send(&guest, "hello").await.status(), Status::Ok);
+        let members = client
+            .get(format!("/rooms/{}/members", room_id))
+            .header(guest.clone())
+            .dispatch()
+            .await
+            .into_string()
+            .await
+            .unwrap();
+        assert!(members.contains(&guest_id));
+
+        // a banned user can't list the members
+        let status = client
+            .post(format!("/moderation/{}/ban", room_id))
+            .header(ContentType::JSON)
+            .header(owner.clone())
+            .body(format!(r#"{{"user_id":"{}"}}"#, troll_id))
+            .dispatch()
+            .await
+            .status();
+        assert_eq!(status, Status::Ok);
+        let status = client
+            .get(format!("/rooms/{}/members", room_id))
+            .header(troll)
+            .dispatch()
+            .await
+            .status();
+        assert_eq!(status, Status::Forbidden);
+
+        let changes = [
+            ("rename", r#"{"name":"hall"}"#),
+            ("topic", r#"{"topic":"news"}"#),
+            ("archive", ""),
+        ];
+        for (action, body) in changes {
+            let status = client
+                .post(format!("/rooms/{}/{}", room_id, action))
+                .header(ContentType::JSON)
+                .header(guest.clone())
+                .body(body)
+                .dispatch()
+                .await
+                .status();
+            assert_eq!(status, Status::Forbidden, "{}", action);
+        }
+        let status = client
+            .delete(format!("/rooms/{}", room_id))
+            .header(guest.clone())
+            .dispatch()
+            .await
+            .status();
+        assert_eq!(status, Status::Forbidden);
+
+        for (action, body) in changes {
+            let status = client
+                .post(format!("/rooms/{}/{}", room_id, action))
+                .header(ContentType::JSON)
+                .header(owner.clone())
+                .body(body)
+                .dispatch()
+                .await
+                .status();
+            assert_eq!(status, Status::Ok, "{}", action);
+        }
+        let room = db.room(&room_id).await.unwrap().unwrap();
+        assert_eq!((room.name.as_str(), room.topic.as_str()), ("hall", "news"));
+        assert!(room.archived);
+        let response = send(&owner, "anyone?").await;
+        assert_eq!(response.status(), Status::BadRequest);
+        assert!(response.into_string().await.unwrap().contains("archived"));
+
+        let status = client
+            .delete(format!("/rooms/{}", room_id))
+            .header(owner)
+            .dispatch()
+            .await
+            .status();
+        assert_eq!(status, Status::Ok);
+        assert!(db.room(&room_id).await.unwrap().is_none());
+        assert!(db.messages(&room_id, 0, 10).await.unwrap().is_empty());
+        let seen = db.last_seen(&guest_id, &[&room_id]).await.unwrap();
+        assert!(seen.is_empty());
+
+        let needles = [
+            r#""type":"member_joined""#,
+            r#""type":"renamed""#,
+            r#""type":"topic_changed""#,
+            r#""type":"archived""#,
+            r#""type":"deleted""#,
+        ];
+        timeout(Duration::from_secs(5), read_until(&mut stream, &needles))
+            .await
+            .expect("Room changes didn't reach the stream");
+    }
+
+    #[rocket::async_test]
+    async fn test_direct_room_is_private() {
+        let database = TempDatabase::new();
+        let client = Client::untracked(crate::build_with(database.figment()))
+            .await
+            .unwrap();
+        let (ann, ann_id) = register(&client, "ann").await;
+        let (ben, ben_id) = register(&client, "ben").await;
+        let (eli, _) = register(&client, "eli").await;
+        let mut stream = client.get("/events").header(eli.clone()).dispatch().await;
+
+        let body = open_direct_room(&client, &ann, &ben_id).await;
+        let room_id = data(&body, "id");
+        assert_eq!(data(&body, "name"), "ann & ben");
+        let body = open_direct_room(&client, &ben, &ann_id).await;
+        assert_eq!(data(&body, "id"), room_id);
+        let body = open_direct_room(&client, &ann, &ann_id).await;
+        assert!(body.contains("needs another user"));
+
+        let status = client
+            .post("/messages/send")
+            .header(ContentType::JSON)
+            .header(ben.clone())
+            .body(format!(r#"{{"text":"psst","room_id":"{}"}}"#, room_id))
+            .dispatch()
+            .await
+            .status();
+        assert_eq!(status, Status::Ok);
+
+        // the others can't find, join, read or write to it
+        let body = client
+            .get("/rooms")
+            .header(eli.clone())
+            .dispatch()
+            .await
+            .into_string()
+            .await
+            .unwrap();
+        assert!(!body.contains(&room_id));
+        let body = client
+            .get("/rooms")
+            .header(ann.clone())
+            .dispatch()
+            .await
+            .into_string()
+            .await
+            .unwrap();
+        assert!(body.contains(&room_id));
+        for uri in [
+            format!("/messages/{}", room_id),
+            format!("/messages/{}/latest", room_id),
+            format!("/messages/{}/search?q=psst", room_id),
+            format!("/messages/events/{}", room_id),
+        ] {
+            let status = client
+                .get(uri)
+                .header(eli.clone())
+                .dispatch()
+                .await
+                .status();
+            assert_eq!(status, Status::Forbidden);
+        }
+        let status = client
+            .post(format!("/rooms/states/{}", room_id))
+            .header(eli.clone())
+            .dispatch()
+            .await
+            .status();
+        assert_eq!(status, Status::Forbidden);
+        let status = client
+            .post("/messages/send")
+            .header(ContentType::JSON)
+            .header(eli.clone())
+            .body(format!(r#"{{"text":"hi","room_id":"{}"}}"#, room_id))
+            .dispatch()
+            .await
+            .status();
+        assert_eq!(status, Status::Forbidden);
+
+        // nor get its events, the room created after it shows they were sent
+        let status = client
+            .post("/rooms")
+            .header(ContentType::JSON)
+            .header(ann)
+            .body(r#"{"name":"after"}"#)
+            .dispatch()
+            .await
+            .status();
+        assert_eq!(status, Status::Ok);
+        let events = timeout(Duration::from_secs(5), read_until(&mut stream, &["after"]))
+            .await
+            .expect("Events didn't reach the stream");
+        assert!(!events.contains(&room_id));
+    }
+}
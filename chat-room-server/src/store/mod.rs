@@ -0,0 +1,370 @@
+//! Storage behind the handlers, one trait per aggregate. SQLite is always built in, PostgreSQL
+//! with the `postgres` feature. The scheme of `databases.main.url` picks the backend.
+use std::collections::HashMap;
+use std::ops::Deref;
+use std::sync::Arc;
+
+use qu_chat_models::{
+    ApiScope, ApiToken, IncomingWebhook, LoginAttempt, LoginOutcome, Message, ModerationAction,
+    ModerationKind, Room, RoomCommand, UserProfile, Webhook, WebhookDelivery,
+};
+use rocket::fairing::AdHoc;
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome, Request};
+use serde::Deserialize;
+use sqlx::migrate::MigrateError;
+
+use crate::user::User;
+
+#[cfg(feature = "postgres")]
+pub mod postgres;
+pub mod sqlite;
+#[cfg(test)]
+mod suite;
+
+pub type StoreResult<T> = Result<T, sqlx::Error>;
+
+#[rocket::async_trait]
+pub trait UserStore {
+    async fn create_user(&self, user: &User) -> StoreResult<()>;
+    async fn user(&self, id: &str) -> StoreResult<Option<User>>;
+    async fn user_by_name(&self, name: &str) -> StoreResult<Option<User>>;
+    /// All users ordered by name.
+    async fn users(&self) -> StoreResult<Vec<User>>;
+    async fn set_secret(&self, id: &str, secret: &str) -> StoreResult<()>;
+    async fn rename_user(&self, id: &str, name: &str) -> StoreResult<()>;
+    /// Removes the user together with their room states and sanctions. Their messages stay, with
+    /// an empty `sender_id`.
+    async fn delete_user(&self, id: &str) -> StoreResult<()>;
+
+    async fn record_login(&self, login: &LoginRecord<'_>) -> StoreResult<()>;
+    /// Failed attempts for the username after `since` and after its last successful one,
+    /// with the time of the last failure.
+    async fn failed_logins(&self, username: &str, since: i64) -> StoreResult<(i64, Option<i64>)>;
+    /// Latest sign-ins of the user, newest first.
+    async fn logins(&self, user_id: &str, limit: i64) -> StoreResult<Vec<LoginAttempt>>;
+}
+
+pub struct LoginRecord<'r> {
+    pub user_id: Option<&'r str>,
+    pub username: &'r str,
+    pub ip: Option<&'r str>,
+    pub outcome: LoginOutcome,
+    pub create_date: i64,
+}
+
+#[rocket::async_trait]
+pub trait RoomStore {
+    async fn create_room(&self, room: &Room) -> StoreResult<()>;
+    async fn room(&self, id: &str) -> StoreResult<Option<Room>>;
+    /// Rooms the user can see, all of them but the direct rooms of other users, newest first.
+    async fn rooms(&self, user_id: &str) -> StoreResult<Vec<Room>>;
+    /// All rooms with their message count, most recently active first.
+    async fn room_summaries(&self) -> StoreResult<Vec<RoomSummary>>;
+    async fn rename_room(&self, id: &str, name: &str) -> StoreResult<()>;
+    async fn set_room_topic(&self, id: &str, topic: &str) -> StoreResult<()>;
+    async fn archive_room(&self, id: &str) -> StoreResult<()>;
+    /// Deletes a room, its messages, states and moderation history go with it.
+    async fn delete_room(&self, id: &str) -> StoreResult<()>;
+    /// Members of a room are the users who have opened it at least once.
+    async fn room_members(&self, id: &str) -> StoreResult<Vec<UserProfile>>;
+    /// Creates a room only the two users can read, given in either order.
+    async fn create_direct_room(
+        &self,
+        room: &Room,
+        user_id: &str,
+        other_id: &str,
+    ) -> StoreResult<()>;
+    /// The direct room of the two users, given in either order.
+    async fn direct_room(&self, user_id: &str, other_id: &str) -> StoreResult<Option<Room>>;
+    /// False when the room is a direct room of other users.
+    async fn can_read_room(&self, room_id: &str, user_id: &str) -> StoreResult<bool>;
+
+    /// Replaces the command of the same name in the room, `bot_name` is ignored.
+    async fn register_command(&self, command: &RoomCommand) -> StoreResult<()>;
+    /// Commands bots registered for the room, by name.
+    async fn room_commands(&self, room_id: &str) -> StoreResult<Vec<RoomCommand>>;
+    /// Returns false when the room has no such command.
+    async fn delete_command(&self, room_id: &str, name: &str) -> StoreResult<bool>;
+}
+
+pub struct RoomSummary {
+    pub room: Room,
+    pub messages: i64,
+    /// Date of the last message, or of the room creation when it has none.
+    pub last_activity: i64,
+}
+
+#[rocket::async_trait]
+pub trait MessageStore {
+    /// Stores the message, `sender_name` is ignored.
+    async fn insert_message(&self, message: &Message) -> StoreResult<()>;
+    /// The message the user sent with this idempotency key.
+    async fn message_by_client_id(
+        &self,
+        sender_id: &str,
+        client_id: &str,
+    ) -> StoreResult<Option<Message>>;
+    /// The first messages of a room sent at or after `since`, oldest first. Messages of deleted
+    /// users have an empty `sender_id` and are sent by "Deleted user".
+    async fn messages(&self, room_id: &str, since: i64, limit: i64) -> StoreResult<Vec<Message>>;
+    /// The last messages of a room, newest first.
+    async fn latest_messages(&self, room_id: &str, limit: i64) -> StoreResult<Vec<Message>>;
+    /// Returns false when the room has no such message.
+    async fn remove_message(&self, room_id: &str, message_id: &str) -> StoreResult<bool>;
+    /// Messages of a room containing `text`, ignoring case, newest first.
+    async fn search_messages(
+        &self,
+        room_id: &str,
+        text: &str,
+        limit: i64,
+    ) -> StoreResult<Vec<Message>>;
+    /// Date of the last message in each room that wasn't sent by `user_id`.
+    async fn last_messages(
+        &self,
+        room_ids: &[&str],
+        user_id: &str,
+    ) -> StoreResult<HashMap<String, i64>>;
+}
+
+/// The users of a direct room in the order they are stored in.
+fn direct_pair<'a>(user_id: &'a str, other_id: &'a str) -> (&'a str, &'a str) {
+    if user_id <= other_id {
+        (user_id, other_id)
+    } else {
+        (other_id, user_id)
+    }
+}
+
+/// A `LIKE` pattern matching `text` anywhere, with `\` as the escape character.
+fn contains_pattern(text: &str) -> String {
+    let mut pattern = String::from("%");
+    for ch in text.chars() {
+        if matches!(ch, '%' | '_' | '\\') {
+            pattern.push('\\');
+        }
+        pattern.push(ch);
+    }
+    pattern.push('%');
+    pattern
+}
+
+#[rocket::async_trait]
+pub trait RoomStateStore {
+    async fn mark_seen(&self, user_id: &str, room_id: &str, now: i64) -> StoreResult<()>;
+    /// When the user last opened each of the rooms they have opened.
+    async fn last_seen(
+        &self,
+        user_id: &str,
+        room_ids: &[&str],
+    ) -> StoreResult<HashMap<String, Option<i64>>>;
+    async fn leave_room(&self, user_id: &str, room_id: &str) -> StoreResult<()>;
+}
+
+#[rocket::async_trait]
+pub trait TokenStore {
+    async fn is_blacklisted(&self, token: &str) -> StoreResult<bool>;
+    async fn blacklist_token(&self, token: &str) -> StoreResult<()>;
+    /// Tokens of the user issued before this time are no longer accepted.
+    async fn tokens_revoked_at(&self, user_id: &str) -> StoreResult<Option<i64>>;
+    async fn revoke_user_tokens(&self, user_id: &str, now: i64) -> StoreResult<()>;
+
+    async fn create_api_token(&self, token: &ApiToken, token_hash: &str) -> StoreResult<()>;
+    async fn api_token(&self, token_hash: &str) -> StoreResult<Option<ApiToken>>;
+    /// Tokens of the bot, oldest first.
+    async fn api_tokens(&self, bot_id: &str) -> StoreResult<Vec<ApiToken>>;
+    /// Returns false when the bot has no such token.
+    async fn delete_api_token(&self, bot_id: &str, id: &str) -> StoreResult<bool>;
+}
+
+/// Scopes of an API token as stored, comma separated.
+fn join_scopes(scopes: &[ApiScope]) -> String {
+    scopes
+        .iter()
+        .map(ApiScope::as_str)
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn split_scopes(scopes: &str) -> Vec<ApiScope> {
+    scopes
+        .split(',')
+        .filter_map(|scope| scope.parse().ok())
+        .collect()
+}
+
+#[rocket::async_trait]
+pub trait ModerationStore {
+    /// Kinds of the bans and mutes on the user in the room that haven't expired at `now`.
+    async fn active_sanctions(
+        &self,
+        room_id: &str,
+        user_id: &str,
+        now: i64,
+    ) -> StoreResult<Vec<ModerationKind>>;
+    /// Replaces any sanction of the same kind.
+    async fn add_sanction(
+        &self,
+        room_id: &str,
+        user_id: &str,
+        kind: ModerationKind,
+        until: Option<i64>,
+    ) -> StoreResult<()>;
+    async fn remove_sanction(
+        &self,
+        room_id: &str,
+        user_id: &str,
+        kind: ModerationKind,
+    ) -> StoreResult<()>;
+    /// Applies the action to its target and records it in the moderation log, both or neither.
+    /// Returns false, recording nothing, when the message it removes doesn't exist.
+    async fn moderate(&self, action: &ModerationAction) -> StoreResult<bool>;
+    /// Moderation log of a room, newest first.
+    async fn moderation_log(&self, room_id: &str) -> StoreResult<Vec<ModerationAction>>;
+}
+
+#[rocket::async_trait]
+pub trait WebhookStore {
+    async fn create_webhook(&self, webhook: &Webhook, secret: &str) -> StoreResult<()>;
+    /// Webhooks of the room, oldest first.
+    async fn webhooks(&self, room_id: &str) -> StoreResult<Vec<Webhook>>;
+    /// Returns false when the room has no such webhook.
+    async fn delete_webhook(&self, room_id: &str, id: &str) -> StoreResult<bool>;
+
+    async fn queue_delivery(&self, delivery: &WebhookDelivery) -> StoreResult<()>;
+    /// Claims the pending deliveries to attempt at `now`, oldest first: they aren't due again
+    /// before `lease_until`, so that other instances don't send them too. Past it, a delivery
+    /// whose attempt wasn't recorded is due again.
+    async fn claim_deliveries(
+        &self,
+        now: i64,
+        lease_until: i64,
+        limit: i64,
+    ) -> StoreResult<Vec<DueDelivery>>;
+    /// Records how the last attempt of the delivery went.
+    async fn update_delivery(&self, delivery: &WebhookDelivery) -> StoreResult<()>;
+    /// Latest deliveries of the webhook, newest first.
+    async fn deliveries(&self, webhook_id: &str, limit: i64) -> StoreResult<Vec<WebhookDelivery>>;
+
+    /// `name` is ignored, it's the name of the bot.
+    async fn create_incoming_webhook(
+        &self,
+        webhook: &IncomingWebhook,
+        token_hash: &str,
+    ) -> StoreResult<()>;
+    async fn incoming_webhook(&self, token_hash: &str) -> StoreResult<Option<IncomingWebhook>>;
+    /// Incoming webhooks of the room, oldest first.
+    async fn incoming_webhooks(&self, room_id: &str) -> StoreResult<Vec<IncomingWebhook>>;
+    /// Returns false when the room has no such incoming webhook.
+    async fn delete_incoming_webhook(&self, room_id: &str, id: &str) -> StoreResult<bool>;
+}
+
+/// A delivery with where to send it and the key to sign it with.
+pub struct DueDelivery {
+    pub delivery: WebhookDelivery,
+    pub url: String,
+    pub secret: String,
+}
+
+/// Kinds of events a webhook subscribes to as stored, comma separated.
+fn join_events(events: &[String]) -> String {
+    events.join(",")
+}
+
+fn split_events(events: &str) -> Vec<String> {
+    events
+        .split(',')
+        .filter(|event| !event.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+#[rocket::async_trait]
+pub trait Store:
+    UserStore
+    + RoomStore
+    + MessageStore
+    + RoomStateStore
+    + TokenStore
+    + ModerationStore
+    + WebhookStore
+    + Send
+    + Sync
+{
+    async fn migrate(&self) -> Result<(), MigrateError>;
+    async fn close(&self);
+}
+
+/// Handle to the store, managed by Rocket and usable as a request guard.
+#[derive(Clone)]
+pub struct Db(pub Arc<dyn Store>);
+
+impl Deref for Db {
+    type Target = dyn Store;
+
+    fn deref(&self) -> &Self::Target {
+        &*self.0
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Db {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        match req.rocket().state::<Db>() {
+            Some(db) => Outcome::Success(db.clone()),
+            None => Outcome::Error((Status::InternalServerError, ())),
+        }
+    }
+}
+
+/// `databases.main` in `Rocket.toml`.
+#[derive(Deserialize)]
+pub struct DbConfig {
+    pub url: String,
+    #[serde(default = "default_max_connections")]
+    pub max_connections: u32,
+}
+
+fn default_max_connections() -> u32 {
+    16
+}
+
+pub async fn connect(config: &DbConfig) -> StoreResult<Db> {
+    if config.url.starts_with("postgres:") || config.url.starts_with("postgresql:") {
+        #[cfg(feature = "postgres")]
+        return Ok(Db(Arc::new(
+            postgres::PostgresStore::connect(config).await?,
+        )));
+        #[cfg(not(feature = "postgres"))]
+        return Err(sqlx::Error::Configuration(
+            "the server is built without the postgres feature".into(),
+        ));
+    }
+    Ok(Db(Arc::new(sqlite::SqliteStore::connect(config).await?)))
+}
+
+pub fn stage() -> AdHoc {
+    AdHoc::try_on_ignite("Database", |rocket| async {
+        let config = match rocket.figment().extract_inner::<DbConfig>("databases.main") {
+            Ok(config) => config,
+            Err(err) => {
+                error!("Invalid database config: {}", err);
+                return Err(rocket);
+            }
+        };
+
+        match connect(&config).await {
+            Ok(db) => Ok(rocket
+                .manage(db.clone())
+                .attach(AdHoc::on_shutdown("Database Close", |_| {
+                    Box::pin(async move { db.close().await })
+                }))),
+            Err(err) => {
+                error!("Unable to connect to the database: {}", err);
+                Err(rocket)
+            }
+        }
+    })
+}
@@ -0,0 +1,1175 @@
+//! The `query!` macros check against the SQLite database in `DATABASE_URL`, so queries here
+//! are checked at runtime, by the shared store tests.
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use qu_chat_models::{
+    ApiToken, DeliveryStatus, IncomingWebhook, LoginAttempt, LoginOutcome, Message,
+    ModerationAction, ModerationKind, Room, RoomCommand, UserProfile, Webhook, WebhookDelivery,
+};
+use sqlx::migrate::{MigrateError, Migrator};
+use sqlx::postgres::PgPoolOptions;
+use sqlx::{FromRow, PgPool};
+
+use super::{
+    contains_pattern, direct_pair, join_events, join_scopes, split_events, split_scopes, DbConfig,
+    DueDelivery, LoginRecord, MessageStore, ModerationStore, RoomStateStore, RoomStore,
+    RoomSummary, Store, StoreResult, TokenStore, UserStore, WebhookStore,
+};
+use crate::user::User;
+
+/// Migrations in `db/postgres/`, embedded at build time.
+pub static MIGRATOR: Migrator = sqlx::migrate!("./db/postgres");
+
+pub struct PostgresStore {
+    pool: PgPool,
+}
+
+impl PostgresStore {
+    pub async fn connect(config: &DbConfig) -> StoreResult<Self> {
+        let pool = PgPoolOptions::new()
+            .max_connections(config.max_connections)
+            .connect(&config.url)
+            .await?;
+        Ok(PostgresStore { pool })
+    }
+}
+
+#[derive(FromRow)]
+struct RoomRow {
+    id: String,
+    name: String,
+    creator_id: String,
+    create_date: i64,
+    topic: String,
+    archived: bool,
+}
+
+impl From<RoomRow> for Room {
+    fn from(row: RoomRow) -> Self {
+        Room {
+            id: row.id,
+            name: row.name,
+            creator_id: row.creator_id,
+            create_date: row.create_date,
+            topic: row.topic,
+            archived: row.archived,
+        }
+    }
+}
+
+#[derive(FromRow)]
+struct MessageRow {
+    id: String,
+    content: String,
+    sender_id: String,
+    room_id: String,
+    create_date: i64,
+    sender_name: String,
+    client_id: Option<String>,
+    is_bot: bool,
+}
+
+impl From<MessageRow> for Message {
+    fn from(row: MessageRow) -> Self {
+        Message {
+            id: row.id,
+            content: row.content,
+            sender_id: row.sender_id,
+            room_id: row.room_id,
+            create_date: row.create_date,
+            sender_name: row.sender_name,
+            client_id: row.client_id,
+            is_bot: row.is_bot,
+        }
+    }
+}
+
+#[derive(FromRow)]
+struct ApiTokenRow {
+    id: String,
+    bot_id: String,
+    name: String,
+    scopes: String,
+    create_date: i64,
+}
+
+impl From<ApiTokenRow> for ApiToken {
+    fn from(row: ApiTokenRow) -> Self {
+        ApiToken {
+            id: row.id,
+            bot_id: row.bot_id,
+            name: row.name,
+            scopes: split_scopes(&row.scopes),
+            create_date: row.create_date,
+        }
+    }
+}
+
+#[derive(FromRow)]
+struct ModerationRow {
+    id: String,
+    room_id: String,
+    moderator_id: String,
+    kind: String,
+    target_user_id: Option<String>,
+    target_message_id: Option<String>,
+    reason: Option<String>,
+    until: Option<i64>,
+    create_date: i64,
+}
+
+#[derive(FromRow)]
+struct WebhookRow {
+    id: String,
+    room_id: String,
+    url: String,
+    events: String,
+    creator_id: String,
+    create_date: i64,
+}
+
+impl From<WebhookRow> for Webhook {
+    fn from(row: WebhookRow) -> Self {
+        Webhook {
+            id: row.id,
+            room_id: row.room_id,
+            url: row.url,
+            events: split_events(&row.events),
+            creator_id: row.creator_id,
+            create_date: row.create_date,
+        }
+    }
+}
+
+#[derive(FromRow)]
+struct IncomingWebhookRow {
+    id: String,
+    room_id: String,
+    bot_id: String,
+    name: String,
+    creator_id: String,
+    create_date: i64,
+}
+
+impl From<IncomingWebhookRow> for IncomingWebhook {
+    fn from(row: IncomingWebhookRow) -> Self {
+        IncomingWebhook {
+            id: row.id,
+            room_id: row.room_id,
+            bot_id: row.bot_id,
+            name: row.name,
+            creator_id: row.creator_id,
+            create_date: row.create_date,
+        }
+    }
+}
+
+#[derive(FromRow)]
+struct RoomCommandRow {
+    room_id: String,
+    name: String,
+    description: String,
+    bot_id: String,
+    bot_name: String,
+}
+
+impl From<RoomCommandRow> for RoomCommand {
+    fn from(row: RoomCommandRow) -> Self {
+        RoomCommand {
+            room_id: row.room_id,
+            name: row.name,
+            description: row.description,
+            bot_id: row.bot_id,
+            bot_name: row.bot_name,
+        }
+    }
+}
+
+#[derive(FromRow)]
+struct DeliveryRow {
+    id: String,
+    webhook_id: String,
+    event: String,
+    payload: String,
+    status: String,
+    attempts: i64,
+    response_status: Option<i64>,
+    error: Option<String>,
+    next_attempt: i64,
+    create_date: i64,
+}
+
+impl DeliveryRow {
+    fn into_delivery(self) -> Option<WebhookDelivery> {
+        Some(WebhookDelivery {
+            status: DeliveryStatus::from_str(&self.status).ok()?,
+            id: self.id,
+            webhook_id: self.webhook_id,
+            event: self.event,
+            payload: self.payload,
+            attempts: self.attempts,
+            response_status: self.response_status,
+            error: self.error,
+            next_attempt: self.next_attempt,
+            create_date: self.create_date,
+        })
+    }
+}
+
+#[derive(FromRow)]
+struct DueDeliveryRow {
+    #[sqlx(flatten)]
+    delivery: DeliveryRow,
+    url: String,
+    secret: String,
+}
+
+/// Postgres wants the ids of an `= ANY($1)` as a single array.
+fn owned(ids: &[&str]) -> Vec<String> {
+    ids.iter().map(|id| id.to_string()).collect()
+}
+
+#[rocket::async_trait]
+impl Store for PostgresStore {
+    async fn migrate(&self) -> Result<(), MigrateError> {
+        MIGRATOR.run(&self.pool).await
+    }
+
+    async fn close(&self) {
+        self.pool.close().await
+    }
+}
+
+#[rocket::async_trait]
+impl UserStore for PostgresStore {
+    async fn create_user(&self, user: &User) -> StoreResult<()> {
+        sqlx::query(
+            "INSERT INTO users (id, name, secret, is_admin, is_bot, owner_id)
+            VALUES ($1, $2, $3, $4, $5, $6)",
+        )
+        .bind(&user.id)
+        .bind(&user.name)
+        .bind(&user.secret)
+        .bind(user.is_admin)
+        .bind(user.is_bot)
+        .bind(&user.owner_id)
+        .execute(&self.pool)
+        .await
+        .map(|_| ())
+    }
+
+    async fn user(&self, id: &str) -> StoreResult<Option<User>> {
+        sqlx::query_as(
+            "SELECT id, name, secret, is_admin, is_bot, owner_id FROM users WHERE id = $1",
+        )
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    async fn user_by_name(&self, name: &str) -> StoreResult<Option<User>> {
+        sqlx::query_as(
+            "SELECT id, name, secret, is_admin, is_bot, owner_id FROM users WHERE name = $1",
+        )
+        .bind(name)
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    async fn users(&self) -> StoreResult<Vec<User>> {
+        sqlx::query_as(
+            "SELECT id, name, secret, is_admin, is_bot, owner_id FROM users ORDER BY name",
+        )
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    async fn set_secret(&self, id: &str, secret: &str) -> StoreResult<()> {
+        sqlx::query("UPDATE users SET secret = $1 WHERE id = $2")
+            .bind(secret)
+            .bind(id)
+            .execute(&self.pool)
+            .await
+            .map(|_| ())
+    }
+
+    async fn rename_user(&self, id: &str, name: &str) -> StoreResult<()> {
+        sqlx::query("UPDATE users SET name = $1 WHERE id = $2")
+            .bind(name)
+            .bind(id)
+            .execute(&self.pool)
+            .await
+            .map(|_| ())
+    }
+
+    async fn delete_user(&self, id: &str) -> StoreResult<()> {
+        sqlx::query("DELETE FROM users WHERE id = $1")
+            .bind(id)
+            .execute(&self.pool)
+            .await
+            .map(|_| ())
+    }
+
+    async fn record_login(&self, login: &LoginRecord<'_>) -> StoreResult<()> {
+        sqlx::query(
+            "INSERT INTO login_attempts (id, user_id, username, ip, outcome, create_date)
+            VALUES ($1, $2, $3, $4, $5, $6)",
+        )
+        .bind(uuid::Uuid::new_v4().to_string())
+        .bind(login.user_id)
+        .bind(login.username)
+        .bind(login.ip)
+        .bind(login.outcome.as_str())
+        .bind(login.create_date)
+        .execute(&self.pool)
+        .await
+        .map(|_| ())
+    }
+
+    async fn failed_logins(&self, username: &str, since: i64) -> StoreResult<(i64, Option<i64>)> {
+        sqlx::query_as(
+            "SELECT COUNT(*), MAX(create_date)
+            FROM login_attempts
+            WHERE username = $1 AND outcome = $2 AND create_date > GREATEST($3, COALESCE(
+                (SELECT MAX(create_date) FROM login_attempts WHERE username = $1 AND outcome = $4),
+                0
+            ))",
+        )
+        .bind(username)
+        .bind(LoginOutcome::BadCredentials.as_str())
+        .bind(since)
+        .bind(LoginOutcome::Success.as_str())
+        .fetch_one(&self.pool)
+        .await
+    }
+
+    async fn logins(&self, user_id: &str, limit: i64) -> StoreResult<Vec<LoginAttempt>> {
+        let rows: Vec<(Option<String>, String, i64)> = sqlx::query_as(
+            "SELECT ip, outcome, create_date FROM login_attempts
+            WHERE user_id = $1 ORDER BY create_date DESC LIMIT $2",
+        )
+        .bind(user_id)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .filter_map(|(ip, outcome, create_date)| {
+                Some(LoginAttempt {
+                    outcome: LoginOutcome::from_str(&outcome).ok()?,
+                    ip,
+                    create_date,
+                })
+            })
+            .collect())
+    }
+}
+
+#[rocket::async_trait]
+impl RoomStore for PostgresStore {
+    async fn create_room(&self, room: &Room) -> StoreResult<()> {
+        sqlx::query(
+            "INSERT INTO rooms (id, name, creator_id, create_date, topic, archived)
+            VALUES ($1, $2, $3, $4, $5, $6)",
+        )
+        .bind(&room.id)
+        .bind(&room.name)
+        .bind(&room.creator_id)
+        .bind(room.create_date)
+        .bind(&room.topic)
+        .bind(room.archived)
+        .execute(&self.pool)
+        .await
+        .map(|_| ())
+    }
+
+    async fn room(&self, id: &str) -> StoreResult<Option<Room>> {
+        sqlx::query_as::<_, RoomRow>("SELECT * FROM rooms WHERE id = $1")
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await
+            .map(|row| row.map(Room::from))
+    }
+
+    async fn rooms(&self, user_id: &str) -> StoreResult<Vec<Room>> {
+        sqlx::query_as::<_, RoomRow>(
+            "SELECT rooms.* FROM rooms LEFT JOIN direct_rooms ON direct_rooms.room_id = rooms.id
+            WHERE direct_rooms.room_id IS NULL
+                OR direct_rooms.first_user_id = $1 OR direct_rooms.second_user_id = $1
+            ORDER BY rooms.create_date DESC",
+        )
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await
+        .map(|rows| rows.into_iter().map(Room::from).collect())
+    }
+
+    async fn room_summaries(&self) -> StoreResult<Vec<RoomSummary>> {
+        #[derive(FromRow)]
+        struct Row {
+            #[sqlx(flatten)]
+            room: RoomRow,
+            messages: i64,
+            last_activity: i64,
+        }
+
+        let rows = sqlx::query_as::<_, Row>(
+            "SELECT rooms.*, COUNT(messages.id) AS messages,
+                COALESCE(MAX(messages.create_date), rooms.create_date) AS last_activity
+            FROM rooms LEFT JOIN messages ON messages.room_id = rooms.id
+            GROUP BY rooms.id
+            ORDER BY last_activity DESC",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| RoomSummary {
+                room: row.room.into(),
+                messages: row.messages,
+                last_activity: row.last_activity,
+            })
+            .collect())
+    }
+
+    async fn rename_room(&self, id: &str, name: &str) -> StoreResult<()> {
+        sqlx::query("UPDATE rooms SET name = $1 WHERE id = $2")
+            .bind(name)
+            .bind(id)
+            .execute(&self.pool)
+            .await
+            .map(|_| ())
+    }
+
+    async fn set_room_topic(&self, id: &str, topic: &str) -> StoreResult<()> {
+        sqlx::query("UPDATE rooms SET topic = $1 WHERE id = $2")
+            .bind(topic)
+            .bind(id)
+            .execute(&self.pool)
+            .await
+            .map(|_| ())
+    }
+
+    async fn archive_room(&self, id: &str) -> StoreResult<()> {
+        sqlx::query("UPDATE rooms SET archived = TRUE WHERE id = $1")
+            .bind(id)
+            .execute(&self.pool)
+            .await
+            .map(|_| ())
+    }
+
+    async fn delete_room(&self, id: &str) -> StoreResult<()> {
+        sqlx::query("DELETE FROM rooms WHERE id = $1")
+            .bind(id)
+            .execute(&self.pool)
+            .await
+            .map(|_| ())
+    }
+
+    async fn room_members(&self, id: &str) -> StoreResult<Vec<UserProfile>> {
+        let rows: Vec<(String, String)> = sqlx::query_as(
+            "SELECT users.id, users.name FROM room_state
+            INNER JOIN users ON room_state.user_id = users.id
+            WHERE room_state.room_id = $1",
+        )
+        .bind(id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|(id, name)| UserProfile { id, name })
+            .collect())
+    }
+
+    async fn create_direct_room(
+        &self,
+        room: &Room,
+        user_id: &str,
+        other_id: &str,
+    ) -> StoreResult<()> {
+        let (first, second) = direct_pair(user_id, other_id);
+        let mut tx = self.pool.begin().await?;
+        sqlx::query(
+            "INSERT INTO rooms (id, name, creator_id, create_date, topic, archived)
+            VALUES ($1, $2, $3, $4, $5, $6)",
+        )
+        .bind(&room.id)
+        .bind(&room.name)
+        .bind(&room.creator_id)
+        .bind(room.create_date)
+        .bind(&room.topic)
+        .bind(room.archived)
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query(
+            "INSERT INTO direct_rooms (room_id, first_user_id, second_user_id) VALUES ($1, $2, $3)",
+        )
+        .bind(&room.id)
+        .bind(first)
+        .bind(second)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await
+    }
+
+    async fn direct_room(&self, user_id: &str, other_id: &str) -> StoreResult<Option<Room>> {
+        let (first, second) = direct_pair(user_id, other_id);
+        sqlx::query_as::<_, RoomRow>(
+            "SELECT rooms.* FROM rooms INNER JOIN direct_rooms ON direct_rooms.room_id = rooms.id
+            WHERE direct_rooms.first_user_id = $1 AND direct_rooms.second_user_id = $2",
+        )
+        .bind(first)
+        .bind(second)
+        .fetch_optional(&self.pool)
+        .await
+        .map(|row| row.map(Room::from))
+    }
+
+    async fn can_read_room(&self, room_id: &str, user_id: &str) -> StoreResult<bool> {
+        let users: Option<(Option<String>, Option<String>)> = sqlx::query_as(
+            "SELECT first_user_id, second_user_id FROM direct_rooms WHERE room_id = $1",
+        )
+        .bind(room_id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(users.is_none_or(|(first, second)| {
+            first.as_deref() == Some(user_id) || second.as_deref() == Some(user_id)
+        }))
+    }
+
+    async fn register_command(&self, command: &RoomCommand) -> StoreResult<()> {
+        sqlx::query(
+            "INSERT INTO room_commands (room_id, name, bot_id, description) VALUES ($1, $2, $3, $4)
+            ON CONFLICT (room_id, name) DO UPDATE
+            SET bot_id = excluded.bot_id, description = excluded.description",
+        )
+        .bind(&command.room_id)
+        .bind(&command.name)
+        .bind(&command.bot_id)
+        .bind(&command.description)
+        .execute(&self.pool)
+        .await
+        .map(|_| ())
+    }
+
+    async fn room_commands(&self, room_id: &str) -> StoreResult<Vec<RoomCommand>> {
+        let rows = sqlx::query_as::<_, RoomCommandRow>(
+            "SELECT room_commands.room_id, room_commands.name, room_commands.description,
+                room_commands.bot_id, users.name AS bot_name
+            FROM room_commands INNER JOIN users ON users.id = room_commands.bot_id
+            WHERE room_commands.room_id = $1 ORDER BY room_commands.name",
+        )
+        .bind(room_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.into_iter().map(RoomCommand::from).collect())
+    }
+
+    async fn delete_command(&self, room_id: &str, name: &str) -> StoreResult<bool> {
+        sqlx::query("DELETE FROM room_commands WHERE room_id = $1 AND name = $2")
+            .bind(room_id)
+            .bind(name)
+            .execute(&self.pool)
+            .await
+            .map(|res| res.rows_affected() > 0)
+    }
+}
+
+#[rocket::async_trait]
+impl MessageStore for PostgresStore {
+    async fn insert_message(&self, message: &Message) -> StoreResult<()> {
+        sqlx::query(
+            "INSERT INTO messages (id, content, room_id, sender_id, create_date, client_id)
+            VALUES ($1, $2, $3, $4, $5, $6)",
+        )
+        .bind(&message.id)
+        .bind(&message.content)
+        .bind(&message.room_id)
+        .bind(&message.sender_id)
+        .bind(message.create_date)
+        .bind(&message.client_id)
+        .execute(&self.pool)
+        .await
+        .map(|_| ())
+    }
+
+    async fn message_by_client_id(
+        &self,
+        sender_id: &str,
+        client_id: &str,
+    ) -> StoreResult<Option<Message>> {
+        let row = sqlx::query_as::<_, MessageRow>(
+            "SELECT messages.id, messages.content, messages.room_id,
+                COALESCE(messages.sender_id, '') AS sender_id, messages.create_date,
+                COALESCE(users.name, 'Deleted user') AS sender_name, messages.client_id,
+                COALESCE(users.is_bot, FALSE) AS is_bot
+            FROM messages
+            LEFT JOIN users ON messages.sender_id = users.id
+            WHERE messages.sender_id = $1 AND messages.client_id = $2",
+        )
+        .bind(sender_id)
+        .bind(client_id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.map(Message::from))
+    }
+
+    async fn messages(&self, room_id: &str, since: i64, limit: i64) -> StoreResult<Vec<Message>> {
+        let rows = sqlx::query_as::<_, MessageRow>(
+            "SELECT messages.id, messages.content, messages.room_id,
+                COALESCE(messages.sender_id, '') AS sender_id, messages.create_date,
+                COALESCE(users.name, 'Deleted user') AS sender_name, messages.client_id,
+                COALESCE(users.is_bot, FALSE) AS is_bot
+            FROM messages
+            LEFT JOIN users ON messages.sender_id = users.id
+            WHERE messages.room_id = $1 AND messages.create_date >= $2
+            ORDER BY messages.create_date LIMIT $3",
+        )
+        .bind(room_id)
+        .bind(since)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.into_iter().map(Message::from).collect())
+    }
+
+    async fn latest_messages(&self, room_id: &str, limit: i64) -> StoreResult<Vec<Message>> {
+        let rows = sqlx::query_as::<_, MessageRow>(
+            "SELECT messages.id, messages.content, messages.room_id,
+                COALESCE(messages.sender_id, '') AS sender_id, messages.create_date,
+                COALESCE(users.name, 'Deleted user') AS sender_name, messages.client_id,
+                COALESCE(users.is_bot, FALSE) AS is_bot
+            FROM messages
+            LEFT JOIN users ON messages.sender_id = users.id
+            WHERE messages.room_id = $1
+            ORDER BY messages.create_date DESC LIMIT $2",
+        )
+        .bind(room_id)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.into_iter().map(Message::from).collect())
+    }
+
+    async fn remove_message(&self, room_id: &str, message_id: &str) -> StoreResult<bool> {
+        sqlx::query("DELETE FROM messages WHERE id = $1 AND room_id = $2")
+            .bind(message_id)
+            .bind(room_id)
+            .execute(&self.pool)
+            .await
+            .map(|res| res.rows_affected() > 0)
+    }
+
+    async fn search_messages(
+        &self,
+        room_id: &str,
+        text: &str,
+        limit: i64,
+    ) -> StoreResult<Vec<Message>> {
+        let rows = sqlx::query_as::<_, MessageRow>(
+            "SELECT messages.id, messages.content, messages.room_id,
+                COALESCE(messages.sender_id, '') AS sender_id, messages.create_date,
+                COALESCE(users.name, 'Deleted user') AS sender_name, messages.client_id,
+                COALESCE(users.is_bot, FALSE) AS is_bot
+            FROM messages
+            LEFT JOIN users ON messages.sender_id = users.id
+            WHERE messages.room_id = $1 AND messages.content ILIKE $2 ESCAPE '\\'
+            ORDER BY messages.create_date DESC LIMIT $3",
+        )
+        .bind(room_id)
+        .bind(contains_pattern(text))
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.into_iter().map(Message::from).collect())
+    }
+
+    async fn last_messages(
+        &self,
+        room_ids: &[&str],
+        user_id: &str,
+    ) -> StoreResult<HashMap<String, i64>> {
+        let rows: Vec<(String, i64)> = sqlx::query_as(
+            "SELECT room_id, MAX(create_date) FROM messages
+            WHERE room_id = ANY($1) AND sender_id IS DISTINCT FROM $2 GROUP BY room_id",
+        )
+        .bind(owned(room_ids))
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows.into_iter().collect())
+    }
+}
+
+#[rocket::async_trait]
+impl RoomStateStore for PostgresStore {
+    async fn mark_seen(&self, user_id: &str, room_id: &str, now: i64) -> StoreResult<()> {
+        sqlx::query(
+            "INSERT INTO room_state (id, user_id, room_id, last_seen) VALUES ($1, $2, $3, $4)
+            ON CONFLICT (user_id, room_id) DO UPDATE SET last_seen = excluded.last_seen",
+        )
+        .bind(uuid::Uuid::new_v4().to_string())
+        .bind(user_id)
+        .bind(room_id)
+        .bind(now)
+        .execute(&self.pool)
+        .await
+        .map(|_| ())
+    }
+
+    async fn last_seen(
+        &self,
+        user_id: &str,
+        room_ids: &[&str],
+    ) -> StoreResult<HashMap<String, Option<i64>>> {
+        let rows: Vec<(String, Option<i64>)> = sqlx::query_as(
+            "SELECT room_id, last_seen FROM room_state WHERE room_id = ANY($1) AND user_id = $2",
+        )
+        .bind(owned(room_ids))
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows.into_iter().collect())
+    }
+
+    async fn leave_room(&self, user_id: &str, room_id: &str) -> StoreResult<()> {
+        sqlx::query("DELETE FROM room_state WHERE room_id = $1 AND user_id = $2")
+            .bind(room_id)
+            .bind(user_id)
+            .execute(&self.pool)
+            .await
+            .map(|_| ())
+    }
+}
+
+#[rocket::async_trait]
+impl TokenStore for PostgresStore {
+    async fn is_blacklisted(&self, token: &str) -> StoreResult<bool> {
+        sqlx::query("SELECT id FROM token_blacklist WHERE token = $1")
+            .bind(token)
+            .fetch_optional(&self.pool)
+            .await
+            .map(|row| row.is_some())
+    }
+
+    async fn blacklist_token(&self, token: &str) -> StoreResult<()> {
+        sqlx::query("INSERT INTO token_blacklist (token) VALUES ($1) ON CONFLICT DO NOTHING")
+            .bind(token)
+            .execute(&self.pool)
+            .await
+            .map(|_| ())
+    }
+
+    async fn tokens_revoked_at(&self, user_id: &str) -> StoreResult<Option<i64>> {
+        sqlx::query_scalar("SELECT revoked_at FROM token_revocations WHERE user_id = $1")
+            .bind(user_id)
+            .fetch_optional(&self.pool)
+            .await
+    }
+
+    async fn revoke_user_tokens(&self, user_id: &str, now: i64) -> StoreResult<()> {
+        sqlx::query(
+            "INSERT INTO token_revocations (user_id, revoked_at) VALUES ($1, $2)
+            ON CONFLICT (user_id) DO UPDATE SET revoked_at = excluded.revoked_at",
+        )
+        .bind(user_id)
+        .bind(now)
+        .execute(&self.pool)
+        .await
+        .map(|_| ())
+    }
+
+    async fn create_api_token(&self, token: &ApiToken, token_hash: &str) -> StoreResult<()> {
+        sqlx::query(
+            "INSERT INTO api_tokens (id, bot_id, name, token_hash, scopes, create_date)
+            VALUES ($1, $2, $3, $4, $5, $6)",
+        )
+        .bind(&token.id)
+        .bind(&token.bot_id)
+        .bind(&token.name)
+        .bind(token_hash)
+        .bind(join_scopes(&token.scopes))
+        .bind(token.create_date)
+        .execute(&self.pool)
+        .await
+        .map(|_| ())
+    }
+
+    async fn api_token(&self, token_hash: &str) -> StoreResult<Option<ApiToken>> {
+        let row = sqlx::query_as::<_, ApiTokenRow>(
+            "SELECT id, bot_id, name, scopes, create_date FROM api_tokens WHERE token_hash = $1",
+        )
+        .bind(token_hash)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.map(ApiToken::from))
+    }
+
+    async fn api_tokens(&self, bot_id: &str) -> StoreResult<Vec<ApiToken>> {
+        let rows = sqlx::query_as::<_, ApiTokenRow>(
+            "SELECT id, bot_id, name, scopes, create_date FROM api_tokens
+            WHERE bot_id = $1 ORDER BY create_date",
+        )
+        .bind(bot_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.into_iter().map(ApiToken::from).collect())
+    }
+
+    async fn delete_api_token(&self, bot_id: &str, id: &str) -> StoreResult<bool> {
+        sqlx::query("DELETE FROM api_tokens WHERE id = $1 AND bot_id = $2")
+            .bind(id)
+            .bind(bot_id)
+            .execute(&self.pool)
+            .await
+            .map(|res| res.rows_affected() > 0)
+    }
+}
+
+#[rocket::async_trait]
+impl ModerationStore for PostgresStore {
+    async fn active_sanctions(
+        &self,
+        room_id: &str,
+        user_id: &str,
+        now: i64,
+    ) -> StoreResult<Vec<ModerationKind>> {
+        let kinds: Vec<String> = sqlx::query_scalar(
+            "SELECT kind FROM room_sanctions
+            WHERE room_id = $1 AND user_id = $2 AND (until IS NULL OR until > $3)",
+        )
+        .bind(room_id)
+        .bind(user_id)
+        .bind(now)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(kinds
+            .iter()
+            .filter_map(|kind| ModerationKind::from_str(kind).ok())
+            .collect())
+    }
+
+    async fn add_sanction(
+        &self,
+        room_id: &str,
+        user_id: &str,
+        kind: ModerationKind,
+        until: Option<i64>,
+    ) -> StoreResult<()> {
+        self.remove_sanction(room_id, user_id, kind).await?;
+
+        sqlx::query(
+            "INSERT INTO room_sanctions (id, room_id, user_id, kind, until) VALUES ($1, $2, $3, $4, $5)",
+        )
+        .bind(uuid::Uuid::new_v4().to_string())
+        .bind(room_id)
+        .bind(user_id)
+        .bind(kind.as_str())
+        .bind(until)
+        .execute(&self.pool)
+        .await
+        .map(|_| ())
+    }
+
+    async fn remove_sanction(
+        &self,
+        room_id: &str,
+        user_id: &str,
+        kind: ModerationKind,
+    ) -> StoreResult<()> {
+        sqlx::query("DELETE FROM room_sanctions WHERE room_id = $1 AND user_id = $2 AND kind = $3")
+            .bind(room_id)
+            .bind(user_id)
+            .bind(kind.as_str())
+            .execute(&self.pool)
+            .await
+            .map(|_| ())
+    }
+
+    async fn moderate(&self, action: &ModerationAction) -> StoreResult<bool> {
+        let mut tx = self.pool.begin().await?;
+        let user_id = action.target_user_id.as_deref().unwrap_or_default();
+
+        if let Some(sanction) = action.kind.sanction() {
+            sqlx::query(
+                "DELETE FROM room_sanctions WHERE room_id = $1 AND user_id = $2 AND kind = $3",
+            )
+            .bind(&action.room_id)
+            .bind(user_id)
+            .bind(sanction.as_str())
+            .execute(&mut *tx)
+            .await?;
+        }
+        if matches!(action.kind, ModerationKind::Ban | ModerationKind::Mute) {
+            sqlx::query(
+                "INSERT INTO room_sanctions (id, room_id, user_id, kind, until) VALUES ($1, $2, $3, $4, $5)",
+            )
+            .bind(uuid::Uuid::new_v4().to_string())
+            .bind(&action.room_id)
+            .bind(user_id)
+            .bind(action.kind.as_str())
+            .bind(action.until)
+            .execute(&mut *tx)
+            .await?;
+        }
+        if matches!(action.kind, ModerationKind::Kick | ModerationKind::Ban) {
+            sqlx::query("DELETE FROM room_state WHERE room_id = $1 AND user_id = $2")
+                .bind(&action.room_id)
+                .bind(user_id)
+                .execute(&mut *tx)
+                .await?;
+        }
+        if action.kind == ModerationKind::RemoveMessage {
+            let removed = sqlx::query("DELETE FROM messages WHERE id = $1 AND room_id = $2")
+                .bind(&action.target_message_id)
+                .bind(&action.room_id)
+                .execute(&mut *tx)
+                .await?;
+            if removed.rows_affected() == 0 {
+                return Ok(false);
+            }
+        }
+
+        sqlx::query(
+            "INSERT INTO moderation_log
+            (id, room_id, moderator_id, kind, target_user_id, target_message_id, reason, until, create_date)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)",
+        )
+        .bind(&action.id)
+        .bind(&action.room_id)
+        .bind(&action.moderator_id)
+        .bind(action.kind.as_str())
+        .bind(&action.target_user_id)
+        .bind(&action.target_message_id)
+        .bind(&action.reason)
+        .bind(action.until)
+        .bind(action.create_date)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await.map(|_| true)
+    }
+
+    async fn moderation_log(&self, room_id: &str) -> StoreResult<Vec<ModerationAction>> {
+        let rows = sqlx::query_as::<_, ModerationRow>(
+            "SELECT * FROM moderation_log WHERE room_id = $1 ORDER BY create_date DESC",
+        )
+        .bind(room_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .filter_map(|row| {
+                Some(ModerationAction {
+                    kind: ModerationKind::from_str(&row.kind).ok()?,
+                    id: row.id,
+                    room_id: row.room_id,
+                    moderator_id: row.moderator_id,
+                    target_user_id: row.target_user_id,
+                    target_message_id: row.target_message_id,
+                    reason: row.reason,
+                    until: row.until,
+                    create_date: row.create_date,
+                })
+            })
+            .collect())
+    }
+}
+
+#[rocket::async_trait]
+impl WebhookStore for PostgresStore {
+    async fn create_webhook(&self, webhook: &Webhook, secret: &str) -> StoreResult<()> {
+        sqlx::query(
+            "INSERT INTO webhooks (id, room_id, url, secret, events, creator_id, create_date)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)",
+        )
+        .bind(&webhook.id)
+        .bind(&webhook.room_id)
+        .bind(&webhook.url)
+        .bind(secret)
+        .bind(join_events(&webhook.events))
+        .bind(&webhook.creator_id)
+        .bind(webhook.create_date)
+        .execute(&self.pool)
+        .await
+        .map(|_| ())
+    }
+
+    async fn webhooks(&self, room_id: &str) -> StoreResult<Vec<Webhook>> {
+        let rows = sqlx::query_as::<_, WebhookRow>(
+            "SELECT id, room_id, url, events, creator_id, create_date FROM webhooks
+            WHERE room_id = $1 ORDER BY create_date",
+        )
+        .bind(room_id)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows.into_iter().map(Webhook::from).collect())
+    }
+
+    async fn delete_webhook(&self, room_id: &str, id: &str) -> StoreResult<bool> {
+        sqlx::query("DELETE FROM webhooks WHERE id = $1 AND room_id = $2")
+            .bind(id)
+            .bind(room_id)
+            .execute(&self.pool)
+            .await
+            .map(|res| res.rows_affected() > 0)
+    }
+
+    async fn queue_delivery(&self, delivery: &WebhookDelivery) -> StoreResult<()> {
+        sqlx::query(
+            "INSERT INTO webhook_deliveries (id, webhook_id, event, payload, status, attempts,
+                response_status, error, next_attempt, create_date)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)",
+        )
+        .bind(&delivery.id)
+        .bind(&delivery.webhook_id)
+        .bind(&delivery.event)
+        .bind(&delivery.payload)
+        .bind(delivery.status.as_str())
+        .bind(delivery.attempts)
+        .bind(delivery.response_status)
+        .bind(&delivery.error)
+        .bind(delivery.next_attempt)
+        .bind(delivery.create_date)
+        .execute(&self.pool)
+        .await
+        .map(|_| ())
+    }
+
+    async fn claim_deliveries(
+        &self,
+        now: i64,
+        lease_until: i64,
+        limit: i64,
+    ) -> StoreResult<Vec<DueDelivery>> {
+        // rows another instance is claiming are skipped rather than waited for
+        let rows = sqlx::query_as::<_, DueDeliveryRow>(
+            "UPDATE webhook_deliveries SET next_attempt = $2
+            FROM webhooks
+            WHERE webhooks.id = webhook_deliveries.webhook_id AND webhook_deliveries.id IN (
+                SELECT id FROM webhook_deliveries
+                WHERE status = 'pending' AND next_attempt <= $1
+                ORDER BY next_attempt, create_date
+                LIMIT $3
+                FOR UPDATE SKIP LOCKED
+            )
+            RETURNING webhook_deliveries.*, webhooks.url, webhooks.secret",
+        )
+        .bind(now)
+        .bind(lease_until)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .filter_map(|row| {
+                Some(DueDelivery {
+                    delivery: row.delivery.into_delivery()?,
+                    url: row.url,
+                    secret: row.secret,
+                })
+            })
+            .collect())
+    }
+
+    async fn update_delivery(&self, delivery: &WebhookDelivery) -> StoreResult<()> {
+        sqlx::query(
+            "UPDATE webhook_deliveries
+            SET status = $1, attempts = $2, response_status = $3, error = $4, next_attempt = $5
+            WHERE id = $6",
+        )
+        .bind(delivery.status.as_str())
+        .bind(delivery.attempts)
+        .bind(delivery.response_status)
+        .bind(&delivery.error)
+        .bind(delivery.next_attempt)
+        .bind(&delivery.id)
+        .execute(&self.pool)
+        .await
+        .map(|_| ())
+    }
+
+    async fn deliveries(&self, webhook_id: &str, limit: i64) -> StoreResult<Vec<WebhookDelivery>> {
+        let rows = sqlx::query_as::<_, DeliveryRow>(
+            "SELECT * FROM webhook_deliveries WHERE webhook_id = $1
+            ORDER BY create_date DESC LIMIT $2",
+        )
+        .bind(webhook_id)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows
+            .into_iter()
+            .filter_map(DeliveryRow::into_delivery)
+            .collect())
+    }
+
+    async fn create_incoming_webhook(
+        &self,
+        webhook: &IncomingWebhook,
+        token_hash: &str,
+    ) -> StoreResult<()> {
+        sqlx::query(
+            "INSERT INTO incoming_webhooks (id, room_id, bot_id, token_hash, creator_id, create_date)
+            VALUES ($1, $2, $3, $4, $5, $6)",
+        )
+        .bind(&webhook.id)
+        .bind(&webhook.room_id)
+        .bind(&webhook.bot_id)
+        .bind(token_hash)
+        .bind(&webhook.creator_id)
+        .bind(webhook.create_date)
+        .execute(&self.pool)
+        .await
+        .map(|_| ())
+    }
+
+    async fn incoming_webhook(&self, token_hash: &str) -> StoreResult<Option<IncomingWebhook>> {
+        let row = sqlx::query_as::<_, IncomingWebhookRow>(
+            "SELECT incoming_webhooks.id, room_id, bot_id, users.name, creator_id,
+                incoming_webhooks.create_date
+            FROM incoming_webhooks INNER JOIN users ON users.id = incoming_webhooks.bot_id
+            WHERE token_hash = $1",
+        )
+        .bind(token_hash)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(row.map(IncomingWebhook::from))
+    }
+
+    async fn incoming_webhooks(&self, room_id: &str) -> StoreResult<Vec<IncomingWebhook>> {
+        let rows = sqlx::query_as::<_, IncomingWebhookRow>(
+            "SELECT incoming_webhooks.id, room_id, bot_id, users.name, creator_id,
+                incoming_webhooks.create_date
+            FROM incoming_webhooks INNER JOIN users ON users.id = incoming_webhooks.bot_id
+            WHERE room_id = $1 ORDER BY incoming_webhooks.create_date",
+        )
+        .bind(room_id)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows.into_iter().map(IncomingWebhook::from).collect())
+    }
+
+    async fn delete_incoming_webhook(&self, room_id: &str, id: &str) -> StoreResult<bool> {
+        sqlx::query("DELETE FROM incoming_webhooks WHERE id = $1 AND room_id = $2")
+            .bind(id)
+            .bind(room_id)
+            .execute(&self.pool)
+            .await
+            .map(|res| res.rows_affected() > 0)
+    }
+}
@@ -0,0 +1,1078 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::time::Duration;
+
+use qu_chat_models::{
+    ApiToken, DeliveryStatus, IncomingWebhook, LoginAttempt, LoginOutcome, Message,
+    ModerationAction, ModerationKind, Room, RoomCommand, UserProfile, Webhook, WebhookDelivery,
+};
+use sqlx::migrate::{MigrateError, Migrator};
+use sqlx::sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePoolOptions};
+use sqlx::{QueryBuilder, Sqlite, SqlitePool};
+
+use super::{
+    contains_pattern, direct_pair, join_events, join_scopes, split_events, split_scopes, DbConfig,
+    DueDelivery, LoginRecord, MessageStore, ModerationStore, RoomStateStore, RoomStore,
+    RoomSummary, Store, StoreResult, TokenStore, UserStore, WebhookStore,
+};
+use crate::user::User;
+
+/// Migrations in `db/`, embedded at build time.
+pub static MIGRATOR: Migrator = sqlx::migrate!("./db");
+
+pub struct SqliteStore {
+    pool: SqlitePool,
+}
+
+impl SqliteStore {
+    /// WAL keeps readers going while messages are written, and the cascades in the schema
+    /// rely on `foreign_keys`.
+    pub async fn connect(config: &DbConfig) -> StoreResult<Self> {
+        let options = SqliteConnectOptions::from_str(&config.url)?
+            .create_if_missing(true)
+            .foreign_keys(true)
+            .journal_mode(SqliteJournalMode::Wal)
+            .busy_timeout(Duration::from_secs(5));
+        let pool = SqlitePoolOptions::new()
+            .max_connections(config.max_connections)
+            .connect_with(options)
+            .await?;
+        Ok(SqliteStore { pool })
+    }
+}
+
+#[rocket::async_trait]
+impl Store for SqliteStore {
+    async fn migrate(&self) -> Result<(), MigrateError> {
+        MIGRATOR.run(&self.pool).await
+    }
+
+    async fn close(&self) {
+        self.pool.close().await
+    }
+}
+
+#[rocket::async_trait]
+impl UserStore for SqliteStore {
+    async fn create_user(&self, user: &User) -> StoreResult<()> {
+        sqlx::query!(
+            "INSERT INTO users (id, name, secret, is_admin, is_bot, owner_id) VALUES ($1, $2, $3, $4, $5, $6)",
+            user.id,
+            user.name,
+            user.secret,
+            user.is_admin,
+            user.is_bot,
+            user.owner_id
+        )
+        .execute(&self.pool)
+        .await
+        .map(|_| ())
+    }
+
+    async fn user(&self, id: &str) -> StoreResult<Option<User>> {
+        sqlx::query_as!(User, "SELECT * FROM users WHERE id = ($1)", id)
+            .fetch_optional(&self.pool)
+            .await
+    }
+
+    async fn user_by_name(&self, name: &str) -> StoreResult<Option<User>> {
+        sqlx::query_as!(User, "SELECT * FROM users WHERE name = ($1)", name)
+            .fetch_optional(&self.pool)
+            .await
+    }
+
+    async fn users(&self) -> StoreResult<Vec<User>> {
+        sqlx::query_as!(User, "SELECT * FROM users ORDER BY name")
+            .fetch_all(&self.pool)
+            .await
+    }
+
+    async fn set_secret(&self, id: &str, secret: &str) -> StoreResult<()> {
+        sqlx::query!("UPDATE users SET secret = ($1) WHERE id = ($2)", secret, id)
+            .execute(&self.pool)
+            .await
+            .map(|_| ())
+    }
+
+    async fn rename_user(&self, id: &str, name: &str) -> StoreResult<()> {
+        sqlx::query!("UPDATE users SET name = ($1) WHERE id = ($2)", name, id)
+            .execute(&self.pool)
+            .await
+            .map(|_| ())
+    }
+
+    async fn delete_user(&self, id: &str) -> StoreResult<()> {
+        sqlx::query!("DELETE FROM users WHERE id = ($1)", id)
+            .execute(&self.pool)
+            .await
+            .map(|_| ())
+    }
+
+    async fn record_login(&self, login: &LoginRecord<'_>) -> StoreResult<()> {
+        let id = uuid::Uuid::new_v4().to_string();
+        let outcome = login.outcome.as_str();
+        sqlx::query!(
+            "INSERT INTO login_attempts (id, user_id, username, ip, outcome, create_date) VALUES ($1, $2, $3, $4, $5, $6)",
+            id,
+            login.user_id,
+            login.username,
+            login.ip,
+            outcome,
+            login.create_date
+        )
+        .execute(&self.pool)
+        .await
+        .map(|_| ())
+    }
+
+    async fn failed_logins(&self, username: &str, since: i64) -> StoreResult<(i64, Option<i64>)> {
+        let failed = LoginOutcome::BadCredentials.as_str();
+        let success = LoginOutcome::Success.as_str();
+        let row = sqlx::query!(
+            r#"
+            SELECT COUNT(*) AS "failures!: i64", MAX(create_date) AS "last_failure: i64"
+            FROM login_attempts
+            WHERE username = ($1) AND outcome = ($2) AND create_date > MAX(($3), COALESCE(
+                (SELECT MAX(create_date) FROM login_attempts WHERE username = ($1) AND outcome = ($4)),
+                0
+            ))
+            "#,
+            username,
+            failed,
+            since,
+            success
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok((row.failures, row.last_failure))
+    }
+
+    async fn logins(&self, user_id: &str, limit: i64) -> StoreResult<Vec<LoginAttempt>> {
+        let rows = sqlx::query!(
+            "SELECT ip, outcome, create_date FROM login_attempts
+            WHERE user_id = ($1) ORDER BY create_date DESC LIMIT ($2)",
+            user_id,
+            limit
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .filter_map(|row| {
+                Some(LoginAttempt {
+                    outcome: LoginOutcome::from_str(&row.outcome).ok()?,
+                    ip: row.ip,
+                    create_date: row.create_date,
+                })
+            })
+            .collect())
+    }
+}
+
+#[rocket::async_trait]
+impl RoomStore for SqliteStore {
+    async fn create_room(&self, room: &Room) -> StoreResult<()> {
+        sqlx::query!(
+            "INSERT INTO rooms (id, name, creator_id, create_date, topic, archived) VALUES ($1, $2, $3, $4, $5, $6)",
+            room.id,
+            room.name,
+            room.creator_id,
+            room.create_date,
+            room.topic,
+            room.archived
+        )
+        .execute(&self.pool)
+        .await
+        .map(|_| ())
+    }
+
+    async fn room(&self, id: &str) -> StoreResult<Option<Room>> {
+        sqlx::query_as!(Room, "SELECT * FROM rooms WHERE id = ($1)", id)
+            .fetch_optional(&self.pool)
+            .await
+    }
+
+    async fn rooms(&self, user_id: &str) -> StoreResult<Vec<Room>> {
+        sqlx::query_as!(
+            Room,
+            "SELECT rooms.id, rooms.name, rooms.creator_id, rooms.create_date, rooms.topic,
+                rooms.archived
+            FROM rooms LEFT JOIN direct_rooms ON direct_rooms.room_id = rooms.id
+            WHERE direct_rooms.room_id IS NULL
+                OR direct_rooms.first_user_id = ($1) OR direct_rooms.second_user_id = ($1)
+            ORDER BY rooms.create_date DESC",
+            user_id
+        )
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    async fn room_summaries(&self) -> StoreResult<Vec<RoomSummary>> {
+        let rows = sqlx::query!(
+            r#"
+            SELECT rooms.id AS "id!", rooms.name AS "name!", rooms.creator_id AS "creator_id!",
+                rooms.create_date AS "create_date!: i64", rooms.topic AS "topic!",
+                rooms.archived AS "archived!: bool",
+                COUNT(messages.id) AS "messages!: i64",
+                COALESCE(MAX(messages.create_date), rooms.create_date) AS "last_activity!: i64"
+            FROM rooms LEFT JOIN messages ON messages.room_id = rooms.id
+            GROUP BY rooms.id
+            ORDER BY COALESCE(MAX(messages.create_date), rooms.create_date) DESC
+            "#
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| RoomSummary {
+                room: Room {
+                    id: row.id,
+                    name: row.name,
+                    creator_id: row.creator_id,
+                    create_date: row.create_date,
+                    topic: row.topic,
+                    archived: row.archived,
+                },
+                messages: row.messages,
+                last_activity: row.last_activity,
+            })
+            .collect())
+    }
+
+    async fn rename_room(&self, id: &str, name: &str) -> StoreResult<()> {
+        sqlx::query!("UPDATE rooms SET name = ($1) WHERE id = ($2)", name, id)
+            .execute(&self.pool)
+            .await
+            .map(|_| ())
+    }
+
+    async fn set_room_topic(&self, id: &str, topic: &str) -> StoreResult<()> {
+        sqlx::query!("UPDATE rooms SET topic = ($1) WHERE id = ($2)", topic, id)
+            .execute(&self.pool)
+            .await
+            .map(|_| ())
+    }
+
+    async fn archive_room(&self, id: &str) -> StoreResult<()> {
+        sqlx::query!("UPDATE rooms SET archived = TRUE WHERE id = ($1)", id)
+            .execute(&self.pool)
+            .await
+            .map(|_| ())
+    }
+
+    async fn delete_room(&self, id: &str) -> StoreResult<()> {
+        sqlx::query!("DELETE FROM rooms WHERE id = ($1)", id)
+            .execute(&self.pool)
+            .await
+            .map(|_| ())
+    }
+
+    async fn room_members(&self, id: &str) -> StoreResult<Vec<UserProfile>> {
+        sqlx::query_as!(
+            UserProfile,
+            "SELECT users.id, users.name FROM room_state
+            INNER JOIN users ON room_state.user_id = users.id
+            WHERE room_state.room_id = ($1)",
+            id
+        )
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    async fn create_direct_room(
+        &self,
+        room: &Room,
+        user_id: &str,
+        other_id: &str,
+    ) -> StoreResult<()> {
+        let (first, second) = direct_pair(user_id, other_id);
+        let mut tx = self.pool.begin().await?;
+        sqlx::query!(
+            "INSERT INTO rooms (id, name, creator_id, create_date, topic, archived) VALUES ($1, $2, $3, $4, $5, $6)",
+            room.id,
+            room.name,
+            room.creator_id,
+            room.create_date,
+            room.topic,
+            room.archived
+        )
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query!(
+            "INSERT INTO direct_rooms (room_id, first_user_id, second_user_id) VALUES ($1, $2, $3)",
+            room.id,
+            first,
+            second
+        )
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await
+    }
+
+    async fn direct_room(&self, user_id: &str, other_id: &str) -> StoreResult<Option<Room>> {
+        let (first, second) = direct_pair(user_id, other_id);
+        sqlx::query_as!(
+            Room,
+            "SELECT rooms.id, rooms.name, rooms.creator_id, rooms.create_date, rooms.topic,
+                rooms.archived
+            FROM rooms INNER JOIN direct_rooms ON direct_rooms.room_id = rooms.id
+            WHERE direct_rooms.first_user_id = ($1) AND direct_rooms.second_user_id = ($2)",
+            first,
+            second
+        )
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    async fn can_read_room(&self, room_id: &str, user_id: &str) -> StoreResult<bool> {
+        let users = sqlx::query!(
+            "SELECT first_user_id, second_user_id FROM direct_rooms WHERE room_id = ($1)",
+            room_id
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(users.is_none_or(|users| {
+            users.first_user_id.as_deref() == Some(user_id)
+                || users.second_user_id.as_deref() == Some(user_id)
+        }))
+    }
+
+    async fn register_command(&self, command: &RoomCommand) -> StoreResult<()> {
+        sqlx::query!(
+            "INSERT INTO room_commands (room_id, name, bot_id, description) VALUES ($1, $2, $3, $4)
+            ON CONFLICT (room_id, name) DO UPDATE
+            SET bot_id = excluded.bot_id, description = excluded.description",
+            command.room_id,
+            command.name,
+            command.bot_id,
+            command.description
+        )
+        .execute(&self.pool)
+        .await
+        .map(|_| ())
+    }
+
+    async fn room_commands(&self, room_id: &str) -> StoreResult<Vec<RoomCommand>> {
+        sqlx::query_as!(
+            RoomCommand,
+            "SELECT room_commands.room_id, room_commands.name, room_commands.description,
+                room_commands.bot_id, users.name AS bot_name
+            FROM room_commands INNER JOIN users ON users.id = room_commands.bot_id
+            WHERE room_commands.room_id = ($1) ORDER BY room_commands.name",
+            room_id
+        )
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    async fn delete_command(&self, room_id: &str, name: &str) -> StoreResult<bool> {
+        let result = sqlx::query!(
+            "DELETE FROM room_commands WHERE room_id = ($1) AND name = ($2)",
+            room_id,
+            name
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+#[rocket::async_trait]
+impl MessageStore for SqliteStore {
+    async fn insert_message(&self, message: &Message) -> StoreResult<()> {
+        sqlx::query!(
+            "INSERT INTO messages (id, content, room_id, sender_id, create_date, client_id) VALUES ($1, $2, $3, $4, $5, $6)",
+            message.id,
+            message.content,
+            message.room_id,
+            message.sender_id,
+            message.create_date,
+            message.client_id
+        )
+        .execute(&self.pool)
+        .await
+        .map(|_| ())
+    }
+
+    async fn message_by_client_id(
+        &self,
+        sender_id: &str,
+        client_id: &str,
+    ) -> StoreResult<Option<Message>> {
+        sqlx::query_as!(
+            Message,
+            r#"
+            SELECT messages.id, messages.content, messages.room_id,
+                COALESCE(messages.sender_id, '') AS "sender_id!",
+                messages.create_date,
+                COALESCE(users.name, 'Deleted user') AS "sender_name!: String",
+                messages.client_id, COALESCE(users.is_bot, FALSE) AS "is_bot!: bool"
+            FROM messages
+            LEFT JOIN users ON messages.sender_id = users.id
+            WHERE messages.sender_id = ($1) AND messages.client_id = ($2)
+            "#,
+            sender_id,
+            client_id
+        )
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    async fn messages(&self, room_id: &str, since: i64, limit: i64) -> StoreResult<Vec<Message>> {
+        sqlx::query_as!(
+            Message,
+            r#"
+            SELECT messages.id, messages.content, messages.room_id,
+                COALESCE(messages.sender_id, '') AS "sender_id!",
+                messages.create_date,
+                COALESCE(users.name, 'Deleted user') AS "sender_name!: String",
+                messages.client_id, COALESCE(users.is_bot, FALSE) AS "is_bot!: bool"
+            FROM messages
+            LEFT JOIN users ON messages.sender_id = users.id
+            WHERE messages.room_id = ($1) AND messages.create_date >= ($2)
+            ORDER BY messages.create_date LIMIT ($3)
+            "#,
+            room_id,
+            since,
+            limit
+        )
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    async fn latest_messages(&self, room_id: &str, limit: i64) -> StoreResult<Vec<Message>> {
+        sqlx::query_as!(
+            Message,
+            r#"
+            SELECT messages.id, messages.content, messages.room_id,
+                COALESCE(messages.sender_id, '') AS "sender_id!",
+                messages.create_date,
+                COALESCE(users.name, 'Deleted user') AS "sender_name!: String",
+                messages.client_id, COALESCE(users.is_bot, FALSE) AS "is_bot!: bool"
+            FROM messages
+            LEFT JOIN users ON messages.sender_id = users.id
+            WHERE messages.room_id = ($1)
+            ORDER BY messages.create_date DESC LIMIT ($2)
+            "#,
+            room_id,
+            limit
+        )
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    async fn remove_message(&self, room_id: &str, message_id: &str) -> StoreResult<bool> {
+        sqlx::query!(
+            "DELETE FROM messages WHERE id = ($1) AND room_id = ($2)",
+            message_id,
+            room_id
+        )
+        .execute(&self.pool)
+        .await
+        .map(|res| res.rows_affected() > 0)
+    }
+
+    async fn search_messages(
+        &self,
+        room_id: &str,
+        text: &str,
+        limit: i64,
+    ) -> StoreResult<Vec<Message>> {
+        let pattern = contains_pattern(text);
+        sqlx::query_as!(
+            Message,
+            r#"
+            SELECT messages.id, messages.content, messages.room_id,
+                COALESCE(messages.sender_id, '') AS "sender_id!",
+                messages.create_date,
+                COALESCE(users.name, 'Deleted user') AS "sender_name!: String",
+                messages.client_id, COALESCE(users.is_bot, FALSE) AS "is_bot!: bool"
+            FROM messages
+            LEFT JOIN users ON messages.sender_id = users.id
+            WHERE messages.room_id = ($1) AND messages.content LIKE ($2) ESCAPE '\'
+            ORDER BY messages.create_date DESC LIMIT ($3)
+            "#,
+            room_id,
+            pattern,
+            limit
+        )
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    async fn last_messages(
+        &self,
+        room_ids: &[&str],
+        user_id: &str,
+    ) -> StoreResult<HashMap<String, i64>> {
+        let mut query = QueryBuilder::<Sqlite>::new(
+            "SELECT room_id, MAX(create_date) FROM messages WHERE room_id IN (",
+        );
+        let mut ids = query.separated(", ");
+        for id in room_ids {
+            ids.push_bind(*id);
+        }
+        query
+            .push(") AND sender_id IS NOT ")
+            .push_bind(user_id)
+            .push(" GROUP BY room_id");
+
+        let rows = query
+            .build_query_as::<(String, i64)>()
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(rows.into_iter().collect())
+    }
+}
+
+#[rocket::async_trait]
+impl RoomStateStore for SqliteStore {
+    async fn mark_seen(&self, user_id: &str, room_id: &str, now: i64) -> StoreResult<()> {
+        let id = uuid::Uuid::new_v4().to_string();
+        sqlx::query!(
+            "INSERT INTO room_state (id, user_id, room_id, last_seen) VALUES ($1, $2, $3, $4)
+            ON CONFLICT (user_id, room_id) DO UPDATE SET last_seen = excluded.last_seen",
+            id,
+            user_id,
+            room_id,
+            now,
+        )
+        .execute(&self.pool)
+        .await
+        .map(|_| ())
+    }
+
+    async fn last_seen(
+        &self,
+        user_id: &str,
+        room_ids: &[&str],
+    ) -> StoreResult<HashMap<String, Option<i64>>> {
+        let mut query = QueryBuilder::<Sqlite>::new(
+            "SELECT room_id, last_seen FROM room_state WHERE room_id IN (",
+        );
+        let mut ids = query.separated(", ");
+        for id in room_ids {
+            ids.push_bind(*id);
+        }
+        query.push(") AND user_id = ").push_bind(user_id);
+
+        let rows = query
+            .build_query_as::<(String, Option<i64>)>()
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(rows.into_iter().collect())
+    }
+
+    async fn leave_room(&self, user_id: &str, room_id: &str) -> StoreResult<()> {
+        sqlx::query!(
+            "DELETE FROM room_state WHERE room_id = ($1) AND user_id = ($2)",
+            room_id,
+            user_id
+        )
+        .execute(&self.pool)
+        .await
+        .map(|_| ())
+    }
+}
+
+#[rocket::async_trait]
+impl TokenStore for SqliteStore {
+    async fn is_blacklisted(&self, token: &str) -> StoreResult<bool> {
+        sqlx::query!("SELECT id FROM token_blacklist WHERE token = ($1)", token)
+            .fetch_optional(&self.pool)
+            .await
+            .map(|row| row.is_some())
+    }
+
+    async fn blacklist_token(&self, token: &str) -> StoreResult<()> {
+        sqlx::query!(
+            "INSERT OR IGNORE INTO token_blacklist (token) VALUES ($1)",
+            token
+        )
+        .execute(&self.pool)
+        .await
+        .map(|_| ())
+    }
+
+    async fn tokens_revoked_at(&self, user_id: &str) -> StoreResult<Option<i64>> {
+        sqlx::query_scalar!(
+            "SELECT revoked_at FROM token_revocations WHERE user_id = ($1)",
+            user_id
+        )
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    async fn revoke_user_tokens(&self, user_id: &str, now: i64) -> StoreResult<()> {
+        sqlx::query!(
+            "INSERT INTO token_revocations (user_id, revoked_at) VALUES ($1, $2)
+            ON CONFLICT (user_id) DO UPDATE SET revoked_at = excluded.revoked_at",
+            user_id,
+            now
+        )
+        .execute(&self.pool)
+        .await
+        .map(|_| ())
+    }
+
+    async fn create_api_token(&self, token: &ApiToken, token_hash: &str) -> StoreResult<()> {
+        let scopes = join_scopes(&token.scopes);
+        sqlx::query!(
+            "INSERT INTO api_tokens (id, bot_id, name, token_hash, scopes, create_date) VALUES ($1, $2, $3, $4, $5, $6)",
+            token.id,
+            token.bot_id,
+            token.name,
+            token_hash,
+            scopes,
+            token.create_date
+        )
+        .execute(&self.pool)
+        .await
+        .map(|_| ())
+    }
+
+    async fn api_token(&self, token_hash: &str) -> StoreResult<Option<ApiToken>> {
+        let row = sqlx::query!(
+            "SELECT id, bot_id, name, scopes, create_date FROM api_tokens WHERE token_hash = ($1)",
+            token_hash
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.map(|row| ApiToken {
+            id: row.id,
+            bot_id: row.bot_id,
+            name: row.name,
+            scopes: split_scopes(&row.scopes),
+            create_date: row.create_date,
+        }))
+    }
+
+    async fn api_tokens(&self, bot_id: &str) -> StoreResult<Vec<ApiToken>> {
+        let rows = sqlx::query!(
+            "SELECT id, bot_id, name, scopes, create_date FROM api_tokens
+            WHERE bot_id = ($1) ORDER BY create_date",
+            bot_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| ApiToken {
+                id: row.id,
+                bot_id: row.bot_id,
+                name: row.name,
+                scopes: split_scopes(&row.scopes),
+                create_date: row.create_date,
+            })
+            .collect())
+    }
+
+    async fn delete_api_token(&self, bot_id: &str, id: &str) -> StoreResult<bool> {
+        sqlx::query!(
+            "DELETE FROM api_tokens WHERE id = ($1) AND bot_id = ($2)",
+            id,
+            bot_id
+        )
+        .execute(&self.pool)
+        .await
+        .map(|res| res.rows_affected() > 0)
+    }
+}
+
+#[rocket::async_trait]
+impl ModerationStore for SqliteStore {
+    async fn active_sanctions(
+        &self,
+        room_id: &str,
+        user_id: &str,
+        now: i64,
+    ) -> StoreResult<Vec<ModerationKind>> {
+        let rows = sqlx::query!(
+            "SELECT kind FROM room_sanctions
+            WHERE room_id = ($1) AND user_id = ($2) AND (until IS NULL OR until > ($3))",
+            room_id,
+            user_id,
+            now
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .filter_map(|row| ModerationKind::from_str(&row.kind).ok())
+            .collect())
+    }
+
+    async fn add_sanction(
+        &self,
+        room_id: &str,
+        user_id: &str,
+        kind: ModerationKind,
+        until: Option<i64>,
+    ) -> StoreResult<()> {
+        self.remove_sanction(room_id, user_id, kind).await?;
+
+        let id = uuid::Uuid::new_v4().to_string();
+        let kind = kind.as_str();
+        sqlx::query!(
+            "INSERT INTO room_sanctions (id, room_id, user_id, kind, until) VALUES ($1, $2, $3, $4, $5)",
+            id,
+            room_id,
+            user_id,
+            kind,
+            until
+        )
+        .execute(&self.pool)
+        .await
+        .map(|_| ())
+    }
+
+    async fn remove_sanction(
+        &self,
+        room_id: &str,
+        user_id: &str,
+        kind: ModerationKind,
+    ) -> StoreResult<()> {
+        let kind = kind.as_str();
+        sqlx::query!(
+            "DELETE FROM room_sanctions WHERE room_id = ($1) AND user_id = ($2) AND kind = ($3)",
+            room_id,
+            user_id,
+            kind
+        )
+        .execute(&self.pool)
+        .await
+        .map(|_| ())
+    }
+
+    async fn moderate(&self, action: &ModerationAction) -> StoreResult<bool> {
+        let mut tx = self.pool.begin().await?;
+        let user_id = action.target_user_id.as_deref().unwrap_or_default();
+
+        if let Some(sanction) = action.kind.sanction() {
+            let sanction = sanction.as_str();
+            sqlx::query!(
+                "DELETE FROM room_sanctions WHERE room_id = ($1) AND user_id = ($2) AND kind = ($3)",
+                action.room_id,
+                user_id,
+                sanction
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+        if matches!(action.kind, ModerationKind::Ban | ModerationKind::Mute) {
+            let id = uuid::Uuid::new_v4().to_string();
+            let kind = action.kind.as_str();
+            sqlx::query!(
+                "INSERT INTO room_sanctions (id, room_id, user_id, kind, until) VALUES ($1, $2, $3, $4, $5)",
+                id,
+                action.room_id,
+                user_id,
+                kind,
+                action.until
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+        if matches!(action.kind, ModerationKind::Kick | ModerationKind::Ban) {
+            sqlx::query!(
+                "DELETE FROM room_state WHERE room_id = ($1) AND user_id = ($2)",
+                action.room_id,
+                user_id
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+        if action.kind == ModerationKind::RemoveMessage {
+            let message_id = action.target_message_id.as_deref().unwrap_or_default();
+            let removed = sqlx::query!(
+                "DELETE FROM messages WHERE id = ($1) AND room_id = ($2)",
+                message_id,
+                action.room_id
+            )
+            .execute(&mut *tx)
+            .await?;
+            if removed.rows_affected() == 0 {
+                return Ok(false);
+            }
+        }
+
+        let kind = action.kind.as_str();
+        sqlx::query!(
+            "INSERT INTO moderation_log
+            (id, room_id, moderator_id, kind, target_user_id, target_message_id, reason, until, create_date)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)",
+            action.id,
+            action.room_id,
+            action.moderator_id,
+            kind,
+            action.target_user_id,
+            action.target_message_id,
+            action.reason,
+            action.until,
+            action.create_date
+        )
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await.map(|_| true)
+    }
+
+    async fn moderation_log(&self, room_id: &str) -> StoreResult<Vec<ModerationAction>> {
+        let rows = sqlx::query!(
+            "SELECT * FROM moderation_log WHERE room_id = ($1) ORDER BY create_date DESC",
+            room_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .filter_map(|row| {
+                Some(ModerationAction {
+                    kind: ModerationKind::from_str(&row.kind).ok()?,
+                    id: row.id,
+                    room_id: row.room_id,
+                    moderator_id: row.moderator_id,
+                    target_user_id: row.target_user_id,
+                    target_message_id: row.target_message_id,
+                    reason: row.reason,
+                    until: row.until,
+                    create_date: row.create_date,
+                })
+            })
+            .collect())
+    }
+}
+
+#[rocket::async_trait]
+impl WebhookStore for SqliteStore {
+    async fn create_webhook(&self, webhook: &Webhook, secret: &str) -> StoreResult<()> {
+        let events = join_events(&webhook.events);
+        sqlx::query!(
+            "INSERT INTO webhooks (id, room_id, url, secret, events, creator_id, create_date)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)",
+            webhook.id,
+            webhook.room_id,
+            webhook.url,
+            secret,
+            events,
+            webhook.creator_id,
+            webhook.create_date
+        )
+        .execute(&self.pool)
+        .await
+        .map(|_| ())
+    }
+
+    async fn webhooks(&self, room_id: &str) -> StoreResult<Vec<Webhook>> {
+        let rows = sqlx::query!(
+            "SELECT id, room_id, url, events, creator_id, create_date FROM webhooks
+            WHERE room_id = ($1) ORDER BY create_date",
+            room_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| Webhook {
+                id: row.id,
+                room_id: row.room_id,
+                url: row.url,
+                events: split_events(&row.events),
+                creator_id: row.creator_id,
+                create_date: row.create_date,
+            })
+            .collect())
+    }
+
+    async fn delete_webhook(&self, room_id: &str, id: &str) -> StoreResult<bool> {
+        let result = sqlx::query!(
+            "DELETE FROM webhooks WHERE id = ($1) AND room_id = ($2)",
+            id,
+            room_id
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn queue_delivery(&self, delivery: &WebhookDelivery) -> StoreResult<()> {
+        let status = delivery.status.as_str();
+        sqlx::query!(
+            "INSERT INTO webhook_deliveries (id, webhook_id, event, payload, status, attempts,
+                response_status, error, next_attempt, create_date)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)",
+            delivery.id,
+            delivery.webhook_id,
+            delivery.event,
+            delivery.payload,
+            status,
+            delivery.attempts,
+            delivery.response_status,
+            delivery.error,
+            delivery.next_attempt,
+            delivery.create_date
+        )
+        .execute(&self.pool)
+        .await
+        .map(|_| ())
+    }
+
+    async fn claim_deliveries(
+        &self,
+        now: i64,
+        lease_until: i64,
+        limit: i64,
+    ) -> StoreResult<Vec<DueDelivery>> {
+        let rows = sqlx::query!(
+            r#"UPDATE webhook_deliveries SET next_attempt = ($2)
+            WHERE id IN (
+                SELECT id FROM webhook_deliveries
+                WHERE status = 'pending' AND next_attempt <= ($1)
+                ORDER BY next_attempt, create_date
+                LIMIT ($3)
+            )
+            RETURNING id, webhook_id, event, payload, status, attempts, response_status, error,
+                next_attempt, create_date,
+                (SELECT url FROM webhooks WHERE webhooks.id = webhook_id) AS "url!: String",
+                (SELECT secret FROM webhooks WHERE webhooks.id = webhook_id) AS "secret!: String""#,
+            now,
+            lease_until,
+            limit
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .filter_map(|row| {
+                Some(DueDelivery {
+                    delivery: WebhookDelivery {
+                        status: DeliveryStatus::from_str(&row.status).ok()?,
+                        id: row.id,
+                        webhook_id: row.webhook_id,
+                        event: row.event,
+                        payload: row.payload,
+                        attempts: row.attempts,
+                        response_status: row.response_status,
+                        error: row.error,
+                        next_attempt: row.next_attempt,
+                        create_date: row.create_date,
+                    },
+                    url: row.url,
+                    secret: row.secret,
+                })
+            })
+            .collect())
+    }
+
+    async fn update_delivery(&self, delivery: &WebhookDelivery) -> StoreResult<()> {
+        let status = delivery.status.as_str();
+        sqlx::query!(
+            "UPDATE webhook_deliveries
+            SET status = ($1), attempts = ($2), response_status = ($3), error = ($4),
+                next_attempt = ($5)
+            WHERE id = ($6)",
+            status,
+            delivery.attempts,
+            delivery.response_status,
+            delivery.error,
+            delivery.next_attempt,
+            delivery.id
+        )
+        .execute(&self.pool)
+        .await
+        .map(|_| ())
+    }
+
+    async fn deliveries(&self, webhook_id: &str, limit: i64) -> StoreResult<Vec<WebhookDelivery>> {
+        let rows = sqlx::query!(
+            "SELECT * FROM webhook_deliveries WHERE webhook_id = ($1)
+            ORDER BY create_date DESC LIMIT ($2)",
+            webhook_id,
+            limit
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .filter_map(|row| {
+                Some(WebhookDelivery {
+                    status: DeliveryStatus::from_str(&row.status).ok()?,
+                    id: row.id,
+                    webhook_id: row.webhook_id,
+                    event: row.event,
+                    payload: row.payload,
+                    attempts: row.attempts,
+                    response_status: row.response_status,
+                    error: row.error,
+                    next_attempt: row.next_attempt,
+                    create_date: row.create_date,
+                })
+            })
+            .collect())
+    }
+
+    async fn create_incoming_webhook(
+        &self,
+        webhook: &IncomingWebhook,
+        token_hash: &str,
+    ) -> StoreResult<()> {
+        sqlx::query!(
+            "INSERT INTO incoming_webhooks (id, room_id, bot_id, token_hash, creator_id, create_date)
+            VALUES ($1, $2, $3, $4, $5, $6)",
+            webhook.id,
+            webhook.room_id,
+            webhook.bot_id,
+            token_hash,
+            webhook.creator_id,
+            webhook.create_date
+        )
+        .execute(&self.pool)
+        .await
+        .map(|_| ())
+    }
+
+    async fn incoming_webhook(&self, token_hash: &str) -> StoreResult<Option<IncomingWebhook>> {
+        sqlx::query_as!(
+            IncomingWebhook,
+            "SELECT incoming_webhooks.id, room_id, bot_id, users.name, creator_id, create_date
+            FROM incoming_webhooks INNER JOIN users ON users.id = incoming_webhooks.bot_id
+            WHERE token_hash = ($1)",
+            token_hash
+        )
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    async fn incoming_webhooks(&self, room_id: &str) -> StoreResult<Vec<IncomingWebhook>> {
+        sqlx::query_as!(
+            IncomingWebhook,
+            "SELECT incoming_webhooks.id, room_id, bot_id, users.name, creator_id, create_date
+            FROM incoming_webhooks INNER JOIN users ON users.id = incoming_webhooks.bot_id
+            WHERE room_id = ($1) ORDER BY create_date",
+            room_id
+        )
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    async fn delete_incoming_webhook(&self, room_id: &str, id: &str) -> StoreResult<bool> {
+        let result = sqlx::query!(
+            "DELETE FROM incoming_webhooks WHERE id = ($1) AND room_id = ($2)",
+            id,
+            room_id
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+}
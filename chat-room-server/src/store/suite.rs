@@ -0,0 +1,726 @@
+//! Tests every backend has to pass. Each backend runs them on a fresh, migrated database.
+use qu_chat_models::{
+    ApiScope, ApiToken, DeliveryStatus, IncomingWebhook, LoginOutcome, Message, ModerationAction,
+    ModerationKind, Room, RoomCommand, Webhook, WebhookDelivery,
+};
+
+use super::{connect, Db, DbConfig, LoginRecord};
+use crate::test_util::TempDatabase;
+use crate::user::User;
+
+fn user(name: &str) -> User {
+    User {
+        id: uuid::Uuid::new_v4().to_string(),
+        name: name.to_string(),
+        secret: "secret".to_string(),
+        is_admin: false,
+        is_bot: false,
+        owner_id: None,
+    }
+}
+
+fn room(name: &str, creator: &User, create_date: i64) -> Room {
+    Room {
+        id: uuid::Uuid::new_v4().to_string(),
+        name: name.to_string(),
+        creator_id: creator.id.clone(),
+        create_date,
+        topic: String::new(),
+        archived: false,
+    }
+}
+
+fn message(room: &Room, sender: &User, create_date: i64) -> Message {
+    Message {
+        id: uuid::Uuid::new_v4().to_string(),
+        content: format!("message at {}", create_date),
+        sender_id: sender.id.clone(),
+        room_id: room.id.clone(),
+        create_date,
+        sender_name: String::new(),
+        client_id: None,
+        is_bot: false,
+    }
+}
+
+async fn users(db: &Db) {
+    let john = user("john");
+    let alice = user("alice");
+    db.create_user(&john).await.unwrap();
+    db.create_user(&alice).await.unwrap();
+    assert!(db.create_user(&user("john")).await.is_err());
+
+    let names = db
+        .users()
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|user| user.name)
+        .collect::<Vec<_>>();
+    assert_eq!(names, vec!["alice", "john"]);
+    assert_eq!(db.user_by_name("john").await.unwrap().unwrap().id, john.id);
+    assert!(db.user("missing").await.unwrap().is_none());
+
+    db.set_secret(&john.id, "changed").await.unwrap();
+    assert_eq!(db.user(&john.id).await.unwrap().unwrap().secret, "changed");
+
+    db.rename_user(&john.id, "johnny").await.unwrap();
+    assert_eq!(db.user(&john.id).await.unwrap().unwrap().name, "johnny");
+    assert!(db.rename_user(&john.id, "alice").await.is_err());
+
+    db.delete_user(&alice.id).await.unwrap();
+    assert!(db.user(&alice.id).await.unwrap().is_none());
+}
+
+async fn logins(db: &Db) {
+    let bob = user("bob");
+    db.create_user(&bob).await.unwrap();
+    let attempt = |outcome, create_date| LoginRecord {
+        user_id: Some(&bob.id),
+        username: "bob",
+        ip: Some("127.0.0.1"),
+        outcome,
+        create_date,
+    };
+
+    db.record_login(&attempt(LoginOutcome::BadCredentials, 10))
+        .await
+        .unwrap();
+    db.record_login(&attempt(LoginOutcome::Success, 20))
+        .await
+        .unwrap();
+    db.record_login(&attempt(LoginOutcome::BadCredentials, 30))
+        .await
+        .unwrap();
+    db.record_login(&attempt(LoginOutcome::BadCredentials, 40))
+        .await
+        .unwrap();
+
+    assert_eq!(db.failed_logins("bob", 0).await.unwrap(), (2, Some(40)));
+    assert_eq!(db.failed_logins("bob", 35).await.unwrap(), (1, Some(40)));
+    assert_eq!(db.failed_logins("nobody", 0).await.unwrap(), (0, None));
+
+    let logins = db.logins(&bob.id, 3).await.unwrap();
+    let dates = logins
+        .iter()
+        .map(|login| login.create_date)
+        .collect::<Vec<_>>();
+    assert_eq!(dates, vec![40, 30, 20]);
+    assert_eq!(logins[2].outcome, LoginOutcome::Success);
+}
+
+async fn rooms(db: &Db) {
+    let owner = user("owner");
+    let guest = user("guest");
+    db.create_user(&owner).await.unwrap();
+    db.create_user(&guest).await.unwrap();
+
+    let quiet = room("quiet", &owner, 100);
+    let busy = room("busy", &owner, 200);
+    db.create_room(&quiet).await.unwrap();
+    db.create_room(&busy).await.unwrap();
+    let ids = db
+        .rooms(&guest.id)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|room| room.id)
+        .collect::<Vec<_>>();
+    assert_eq!(ids, vec![busy.id.clone(), quiet.id.clone()]);
+
+    db.rename_room(&quiet.id, "calm").await.unwrap();
+    db.set_room_topic(&quiet.id, "nothing here").await.unwrap();
+    db.archive_room(&quiet.id).await.unwrap();
+    let updated = db.room(&quiet.id).await.unwrap().unwrap();
+    assert_eq!(updated.name, "calm");
+    assert_eq!(updated.topic, "nothing here");
+    assert!(updated.archived);
+
+    for date in [300, 400] {
+        db.insert_message(&message(&busy, &guest, date))
+            .await
+            .unwrap();
+    }
+    let summaries = db.room_summaries().await.unwrap();
+    assert_eq!(summaries[0].room.id, busy.id);
+    assert_eq!(summaries[0].messages, 2);
+    assert_eq!(summaries[0].last_activity, 400);
+    assert_eq!(summaries[1].messages, 0);
+    assert_eq!(summaries[1].last_activity, 100);
+
+    db.mark_seen(&guest.id, &busy.id, 500).await.unwrap();
+    db.mark_seen(&guest.id, &busy.id, 600).await.unwrap();
+    let members = db.room_members(&busy.id).await.unwrap();
+    assert_eq!(members.len(), 1);
+    assert_eq!(members[0].name, "guest");
+
+    db.delete_room(&busy.id).await.unwrap();
+    assert!(db.room(&busy.id).await.unwrap().is_none());
+    assert!(db.messages(&busy.id, 0, 10).await.unwrap().is_empty());
+    assert!(db.room_members(&busy.id).await.unwrap().is_empty());
+}
+
+async fn direct_rooms(db: &Db) {
+    let ann = user("ann");
+    let ben = user("ben");
+    let eli = user("eli");
+    for user in [&ann, &ben, &eli] {
+        db.create_user(user).await.unwrap();
+    }
+    let open = room("open", &eli, 700);
+    db.create_room(&open).await.unwrap();
+    let direct = room("ann & ben", &ben, 800);
+    db.create_direct_room(&direct, &ben.id, &ann.id)
+        .await
+        .unwrap();
+    // the room of a second direct room of the two isn't kept either
+    let again = room("again", &ann, 900);
+    assert!(db
+        .create_direct_room(&again, &ann.id, &ben.id)
+        .await
+        .is_err());
+    assert!(db.room(&again.id).await.unwrap().is_none());
+
+    let found = db.direct_room(&ann.id, &ben.id).await.unwrap().unwrap();
+    assert_eq!(found.id, direct.id);
+    assert!(db.direct_room(&ann.id, &eli.id).await.unwrap().is_none());
+    assert!(db.can_read_room(&direct.id, &ann.id).await.unwrap());
+    assert!(db.can_read_room(&direct.id, &ben.id).await.unwrap());
+    assert!(!db.can_read_room(&direct.id, &eli.id).await.unwrap());
+    assert!(db.can_read_room(&open.id, &eli.id).await.unwrap());
+
+    let listed = |rooms: Vec<Room>| rooms.iter().any(|room| room.id == direct.id);
+    assert!(listed(db.rooms(&ann.id).await.unwrap()));
+    assert!(!listed(db.rooms(&eli.id).await.unwrap()));
+    assert!(db
+        .rooms(&eli.id)
+        .await
+        .unwrap()
+        .iter()
+        .any(|room| room.id == open.id));
+
+    // the room stays private once one of the two is deleted
+    db.delete_user(&ben.id).await.unwrap();
+    assert!(!db.can_read_room(&direct.id, &eli.id).await.unwrap());
+    assert!(!listed(db.rooms(&eli.id).await.unwrap()));
+    assert!(db.can_read_room(&direct.id, &ann.id).await.unwrap());
+}
+
+async fn messages(db: &Db) {
+    let reader = user("reader");
+    let writer = user("writer");
+    db.create_user(&reader).await.unwrap();
+    db.create_user(&writer).await.unwrap();
+    let first = room("first", &writer, 0);
+    let second = room("second", &writer, 0);
+    db.create_room(&first).await.unwrap();
+    db.create_room(&second).await.unwrap();
+
+    for date in [3, 1, 2] {
+        db.insert_message(&message(&first, &writer, date))
+            .await
+            .unwrap();
+    }
+    let own = message(&second, &reader, 5);
+    db.insert_message(&own).await.unwrap();
+
+    let history = db.messages(&first.id, 0, 2).await.unwrap();
+    let dates = history
+        .iter()
+        .map(|message| message.create_date)
+        .collect::<Vec<_>>();
+    assert_eq!(dates, vec![1, 2]);
+    assert_eq!(history[0].sender_name, "writer");
+    let recent = db.messages(&first.id, 2, 10).await.unwrap();
+    assert_eq!(recent.len(), 2);
+    assert_eq!(recent[0].create_date, 2);
+    let latest = db.latest_messages(&first.id, 2).await.unwrap();
+    let dates = latest
+        .iter()
+        .map(|message| message.create_date)
+        .collect::<Vec<_>>();
+    assert_eq!(dates, vec![3, 2]);
+
+    let room_ids = [first.id.as_str(), second.id.as_str()];
+    let last = db.last_messages(&room_ids, &reader.id).await.unwrap();
+    assert_eq!(last.get(&first.id), Some(&3));
+    assert_eq!(last.get(&second.id), None);
+
+    db.mark_seen(&reader.id, &first.id, 2).await.unwrap();
+    let seen = db.last_seen(&reader.id, &room_ids).await.unwrap();
+    assert_eq!(seen.get(&first.id), Some(&Some(2)));
+    assert!(!seen.contains_key(&second.id));
+    assert!(db.last_seen(&reader.id, &[]).await.unwrap().is_empty());
+
+    db.leave_room(&reader.id, &first.id).await.unwrap();
+    assert!(db
+        .last_seen(&reader.id, &room_ids)
+        .await
+        .unwrap()
+        .is_empty());
+
+    let mut keyed = message(&second, &reader, 6);
+    keyed.client_id = Some("key".to_string());
+    db.insert_message(&keyed).await.unwrap();
+    let mut retried = message(&second, &reader, 7);
+    retried.client_id = keyed.client_id.clone();
+    assert!(db.insert_message(&retried).await.is_err());
+    let found = db.message_by_client_id(&reader.id, "key").await.unwrap();
+    assert_eq!(found.unwrap().id, keyed.id);
+    assert!(db
+        .message_by_client_id(&writer.id, "key")
+        .await
+        .unwrap()
+        .is_none());
+
+    let mut literal = message(&first, &writer, 4);
+    literal.content = "100% Done_".to_string();
+    db.insert_message(&literal).await.unwrap();
+    let found = db
+        .search_messages(&first.id, "MESSAGE AT", 10)
+        .await
+        .unwrap();
+    let dates = found
+        .iter()
+        .map(|message| message.create_date)
+        .collect::<Vec<_>>();
+    assert_eq!(dates, vec![3, 2, 1]);
+    assert_eq!(
+        db.search_messages(&first.id, "0% done_", 10)
+            .await
+            .unwrap()
+            .len(),
+        1
+    );
+    assert!(db
+        .search_messages(&first.id, "1_0", 10)
+        .await
+        .unwrap()
+        .is_empty());
+    assert!(db
+        .search_messages(&second.id, "100%", 10)
+        .await
+        .unwrap()
+        .is_empty());
+
+    assert!(db.remove_message(&second.id, &own.id).await.unwrap());
+    assert!(!db.remove_message(&second.id, &own.id).await.unwrap());
+
+    // the messages of deleted users stay
+    let leaving = user("leaving");
+    db.create_user(&leaving).await.unwrap();
+    db.insert_message(&message(&second, &leaving, 8))
+        .await
+        .unwrap();
+    db.delete_user(&leaving.id).await.unwrap();
+    let history = db.messages(&second.id, 8, 10).await.unwrap();
+    assert_eq!(history.len(), 1);
+    assert_eq!(history[0].sender_id, "");
+    assert_eq!(history[0].sender_name, "Deleted user");
+    let last = db.last_messages(&[&second.id], &reader.id).await.unwrap();
+    assert_eq!(last.get(&second.id), Some(&8));
+}
+
+async fn commands(db: &Db) {
+    let owner = user("commander");
+    let mut bot = user("deploy-bot");
+    bot.is_bot = true;
+    db.create_user(&owner).await.unwrap();
+    db.create_user(&bot).await.unwrap();
+    let room = room("releases", &owner, 0);
+    db.create_room(&room).await.unwrap();
+
+    let mut deploy = RoomCommand {
+        room_id: room.id.clone(),
+        name: "deploy".to_string(),
+        description: "Deploys a branch".to_string(),
+        bot_id: bot.id.clone(),
+        bot_name: String::new(),
+    };
+    db.register_command(&deploy).await.unwrap();
+    deploy.description = "Deploys a branch to staging".to_string();
+    db.register_command(&deploy).await.unwrap();
+
+    let commands = db.room_commands(&room.id).await.unwrap();
+    assert_eq!(commands.len(), 1);
+    assert_eq!(commands[0].bot_name, "deploy-bot");
+    assert_eq!(commands[0].description, "Deploys a branch to staging");
+
+    assert!(db.delete_command(&room.id, "deploy").await.unwrap());
+    assert!(!db.delete_command(&room.id, "deploy").await.unwrap());
+
+    db.register_command(&deploy).await.unwrap();
+    db.delete_user(&bot.id).await.unwrap();
+    assert!(db.room_commands(&room.id).await.unwrap().is_empty());
+}
+
+async fn tokens(db: &Db) {
+    let carol = user("carol");
+    db.create_user(&carol).await.unwrap();
+
+    assert!(!db.is_blacklisted("token").await.unwrap());
+    db.blacklist_token("token").await.unwrap();
+    db.blacklist_token("token").await.unwrap();
+    assert!(db.is_blacklisted("token").await.unwrap());
+
+    assert_eq!(db.tokens_revoked_at(&carol.id).await.unwrap(), None);
+    db.revoke_user_tokens(&carol.id, 10).await.unwrap();
+    db.revoke_user_tokens(&carol.id, 20).await.unwrap();
+    assert_eq!(db.tokens_revoked_at(&carol.id).await.unwrap(), Some(20));
+
+    let mut ci = user("ci");
+    ci.is_bot = true;
+    ci.owner_id = Some(carol.id.clone());
+    db.create_user(&ci).await.unwrap();
+    let stored = db.user(&ci.id).await.unwrap().unwrap();
+    assert!(stored.is_bot);
+    assert_eq!(stored.owner_id, Some(carol.id.clone()));
+
+    let token = ApiToken {
+        id: uuid::Uuid::new_v4().to_string(),
+        bot_id: ci.id.clone(),
+        name: "deploys".to_string(),
+        scopes: vec![ApiScope::Read, ApiScope::Send],
+        create_date: 30,
+    };
+    db.create_api_token(&token, "hash").await.unwrap();
+    let found = db.api_token("hash").await.unwrap().unwrap();
+    assert_eq!(found.bot_id, ci.id);
+    assert_eq!(found.scopes, vec![ApiScope::Read, ApiScope::Send]);
+    assert!(db.api_token("other").await.unwrap().is_none());
+    assert_eq!(db.api_tokens(&ci.id).await.unwrap().len(), 1);
+
+    let room = room("builds", &carol, 0);
+    db.create_room(&room).await.unwrap();
+    db.insert_message(&message(&room, &ci, 40)).await.unwrap();
+    db.insert_message(&message(&room, &carol, 41))
+        .await
+        .unwrap();
+    let bots = db
+        .messages(&room.id, 0, 10)
+        .await
+        .unwrap()
+        .iter()
+        .map(|message| message.is_bot)
+        .collect::<Vec<_>>();
+    assert_eq!(bots, vec![true, false]);
+
+    assert!(!db.delete_api_token(&carol.id, &token.id).await.unwrap());
+    assert!(db.delete_api_token(&ci.id, &token.id).await.unwrap());
+    assert!(db.api_token("hash").await.unwrap().is_none());
+}
+
+async fn moderation(db: &Db) {
+    let moderator = user("moderator");
+    let troll = user("troll");
+    db.create_user(&moderator).await.unwrap();
+    db.create_user(&troll).await.unwrap();
+    let lobby = room("lobby", &moderator, 0);
+    db.create_room(&lobby).await.unwrap();
+
+    db.add_sanction(&lobby.id, &troll.id, ModerationKind::Mute, Some(100))
+        .await
+        .unwrap();
+    db.add_sanction(&lobby.id, &troll.id, ModerationKind::Mute, Some(50))
+        .await
+        .unwrap();
+    assert_eq!(
+        db.active_sanctions(&lobby.id, &troll.id, 40).await.unwrap(),
+        vec![ModerationKind::Mute]
+    );
+    assert!(db
+        .active_sanctions(&lobby.id, &troll.id, 60)
+        .await
+        .unwrap()
+        .is_empty());
+
+    db.add_sanction(&lobby.id, &troll.id, ModerationKind::Ban, None)
+        .await
+        .unwrap();
+    assert_eq!(
+        db.active_sanctions(&lobby.id, &troll.id, 60).await.unwrap(),
+        vec![ModerationKind::Ban]
+    );
+    db.remove_sanction(&lobby.id, &troll.id, ModerationKind::Ban)
+        .await
+        .unwrap();
+    assert!(db
+        .active_sanctions(&lobby.id, &troll.id, 60)
+        .await
+        .unwrap()
+        .is_empty());
+
+    let action = |kind, create_date| ModerationAction {
+        id: uuid::Uuid::new_v4().to_string(),
+        room_id: lobby.id.clone(),
+        moderator_id: moderator.id.clone(),
+        kind,
+        target_user_id: Some(troll.id.clone()),
+        target_message_id: None,
+        reason: Some("spam".to_string()),
+        until: None,
+        create_date,
+    };
+    db.mark_seen(&troll.id, &lobby.id, 0).await.unwrap();
+    assert!(db.moderate(&action(ModerationKind::Kick, 1)).await.unwrap());
+    assert!(db
+        .last_seen(&troll.id, &[&lobby.id])
+        .await
+        .unwrap()
+        .is_empty());
+    assert!(db.moderate(&action(ModerationKind::Ban, 2)).await.unwrap());
+    assert_eq!(
+        db.active_sanctions(&lobby.id, &troll.id, 60).await.unwrap(),
+        vec![ModerationKind::Ban]
+    );
+    assert!(db
+        .moderate(&action(ModerationKind::Unban, 3))
+        .await
+        .unwrap());
+    assert!(db
+        .active_sanctions(&lobby.id, &troll.id, 60)
+        .await
+        .unwrap()
+        .is_empty());
+
+    let spam = message(&lobby, &troll, 4);
+    db.insert_message(&spam).await.unwrap();
+    let remove = ModerationAction {
+        target_user_id: None,
+        target_message_id: Some(spam.id.clone()),
+        ..action(ModerationKind::RemoveMessage, 5)
+    };
+    assert!(db.moderate(&remove).await.unwrap());
+    assert!(db.messages(&lobby.id, 0, 10).await.unwrap().is_empty());
+    // nothing is logged when the message is already gone
+    assert!(!db.moderate(&remove).await.unwrap());
+
+    let log = db.moderation_log(&lobby.id).await.unwrap();
+    let kinds = log.iter().map(|action| action.kind).collect::<Vec<_>>();
+    assert_eq!(
+        kinds,
+        vec![
+            ModerationKind::RemoveMessage,
+            ModerationKind::Unban,
+            ModerationKind::Ban,
+            ModerationKind::Kick
+        ]
+    );
+    assert_eq!(log[3].reason.as_deref(), Some("spam"));
+}
+
+async fn webhooks(db: &Db) {
+    let dave = user("dave");
+    db.create_user(&dave).await.unwrap();
+    let builds = room("builds", &dave, 0);
+    db.create_room(&builds).await.unwrap();
+
+    let webhook = Webhook {
+        id: uuid::Uuid::new_v4().to_string(),
+        room_id: builds.id.clone(),
+        url: "http://127.0.0.1:1/hook".to_string(),
+        events: vec!["message".to_string(), "member_joined".to_string()],
+        creator_id: dave.id.clone(),
+        create_date: 0,
+    };
+    db.create_webhook(&webhook, "secret").await.unwrap();
+    let webhooks = db.webhooks(&builds.id).await.unwrap();
+    assert_eq!(webhooks.len(), 1);
+    assert_eq!(webhooks[0].events, webhook.events);
+
+    for create_date in [1, 2] {
+        db.queue_delivery(&WebhookDelivery {
+            id: uuid::Uuid::new_v4().to_string(),
+            webhook_id: webhook.id.clone(),
+            event: "message".to_string(),
+            payload: "{}".to_string(),
+            status: DeliveryStatus::Pending,
+            attempts: 0,
+            response_status: None,
+            error: None,
+            next_attempt: create_date,
+            create_date,
+        })
+        .await
+        .unwrap();
+    }
+    let due = db.claim_deliveries(1, 100, 10).await.unwrap();
+    assert_eq!(due.len(), 1);
+    assert_eq!(due[0].secret, "secret");
+    // claimed, another instance doesn't get it
+    assert!(db.claim_deliveries(1, 100, 10).await.unwrap().is_empty());
+
+    let mut delivery = due[0].delivery.clone();
+    delivery.status = DeliveryStatus::Delivered;
+    delivery.attempts = 1;
+    delivery.response_status = Some(204);
+    db.update_delivery(&delivery).await.unwrap();
+    assert_eq!(db.claim_deliveries(2, 100, 10).await.unwrap().len(), 1);
+    // the attempt wasn't recorded before the lease ran out
+    assert_eq!(db.claim_deliveries(100, 200, 10).await.unwrap().len(), 1);
+    let log = db.deliveries(&webhook.id, 10).await.unwrap();
+    assert_eq!(log.len(), 2);
+    assert_eq!(log[1].status, DeliveryStatus::Delivered);
+    assert_eq!(log[1].response_status, Some(204));
+
+    assert!(!db.delete_webhook("elsewhere", &webhook.id).await.unwrap());
+    assert!(db.delete_webhook(&builds.id, &webhook.id).await.unwrap());
+    assert!(db.deliveries(&webhook.id, 10).await.unwrap().is_empty());
+
+    let mut deployer = user("deployer");
+    deployer.is_bot = true;
+    db.create_user(&deployer).await.unwrap();
+    let incoming = IncomingWebhook {
+        id: uuid::Uuid::new_v4().to_string(),
+        room_id: builds.id.clone(),
+        bot_id: deployer.id.clone(),
+        name: String::new(),
+        creator_id: dave.id.clone(),
+        create_date: 0,
+    };
+    db.create_incoming_webhook(&incoming, "hash").await.unwrap();
+    let found = db.incoming_webhook("hash").await.unwrap().unwrap();
+    assert_eq!(found.name, "deployer");
+    assert_eq!(found.room_id, builds.id);
+    assert!(db.incoming_webhook("other").await.unwrap().is_none());
+    assert_eq!(db.incoming_webhooks(&builds.id).await.unwrap().len(), 1);
+    assert!(db
+        .delete_incoming_webhook(&builds.id, &incoming.id)
+        .await
+        .unwrap());
+    assert!(db.incoming_webhook("hash").await.unwrap().is_none());
+}
+
+async fn run(url: &str) {
+    let db = connect(&DbConfig {
+        url: url.to_string(),
+        max_connections: 4,
+    })
+    .await
+    .expect("Unable to connect to the test database");
+    db.migrate()
+        .await
+        .expect("Unable to migrate the test database");
+
+    users(&db).await;
+    logins(&db).await;
+    rooms(&db).await;
+    direct_rooms(&db).await;
+    messages(&db).await;
+    tokens(&db).await;
+    moderation(&db).await;
+    webhooks(&db).await;
+    commands(&db).await;
+
+    db.close().await;
+}
+
+#[rocket::async_test]
+async fn test_sqlite_store() {
+    let database = TempDatabase::new();
+    run(database.url()).await;
+}
+
+#[cfg(feature = "postgres")]
+mod postgres {
+    use std::path::PathBuf;
+    use std::process::Command;
+
+    use super::run;
+
+    /// A throwaway PostgreSQL cluster, stopped and removed on drop.
+    struct LocalPostgres {
+        dir: PathBuf,
+        url: String,
+    }
+
+    impl LocalPostgres {
+        /// Needs `initdb` and `pg_ctl` on the path, and a user other than root.
+        fn start() -> Option<Self> {
+            let dir = std::env::temp_dir().join(format!("quchat-pg-{}", uuid::Uuid::new_v4()));
+            let port = std::net::TcpListener::bind("127.0.0.1:0")
+                .and_then(|listener| listener.local_addr())
+                .ok()?
+                .port();
+
+            let initdb = Command::new("initdb")
+                .args(["-U", "postgres", "--auth=trust", "-D"])
+                .arg(&dir)
+                .output()
+                .ok()?;
+            if !initdb.status.success() {
+                eprintln!("initdb failed: {}", String::from_utf8_lossy(&initdb.stderr));
+                let _ = std::fs::remove_dir_all(&dir);
+                return None;
+            }
+
+            let local = LocalPostgres {
+                url: format!("postgres://postgres@127.0.0.1:{}/postgres", port),
+                dir,
+            };
+            let options = format!(
+                "-p {} -c listen_addresses=127.0.0.1 -k {}",
+                port,
+                local.dir.display()
+            );
+            let started = Command::new("pg_ctl")
+                .args(["-w", "-l"])
+                .arg(local.dir.join("log"))
+                .args(["-o", &options, "-D"])
+                .arg(&local.dir)
+                .arg("start")
+                .status()
+                .ok()?;
+            started.success().then_some(local)
+        }
+    }
+
+    impl Drop for LocalPostgres {
+        fn drop(&mut self) {
+            let _ = Command::new("pg_ctl")
+                .args(["-m", "immediate", "-D"])
+                .arg(&self.dir)
+                .arg("stop")
+                .status();
+            let _ = std::fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    /// Runs against `QUCHAT_TEST_POSTGRES_URL` when set, in a database of its own that is dropped
+    /// afterwards. Otherwise starts a local cluster, and fails when that isn't possible rather
+    /// than passing without testing the backend the feature was turned on for.
+    #[rocket::async_test]
+    async fn test_postgres_store() {
+        use sqlx::{Connection, PgConnection};
+
+        let local;
+        let server_url = match std::env::var("QUCHAT_TEST_POSTGRES_URL") {
+            Ok(url) => url,
+            Err(_) => match LocalPostgres::start() {
+                Some(postgres) => {
+                    local = postgres;
+                    local.url.clone()
+                }
+                None => panic!(
+                    "No postgres server to test against, set QUCHAT_TEST_POSTGRES_URL \
+                    or run as a user with initdb and pg_ctl on the path"
+                ),
+            },
+        };
+
+        let name = format!("quchat_test_{}", uuid::Uuid::new_v4().simple());
+        let mut admin = PgConnection::connect(&server_url).await.unwrap();
+        sqlx::query(&format!("CREATE DATABASE {}", name))
+            .execute(&mut admin)
+            .await
+            .unwrap();
+
+        let (base, _) = server_url.rsplit_once('/').unwrap();
+        run(&format!("{}/{}", base, name)).await;
+
+        sqlx::query(&format!("DROP DATABASE {}", name))
+            .execute(&mut admin)
+            .await
+            .unwrap();
+    }
+}
@@ -0,0 +1,92 @@
+//! Fixtures shared by the tests: API responses and throwaway SQLite databases.
+use std::path::PathBuf;
+
+use rocket::figment::Figment;
+use rocket::http::{ContentType, Header};
+use rocket::local::asynchronous::{Client, LocalResponse};
+use rocket::serde::json::{serde_json, Value};
+use rocket::tokio::io::AsyncReadExt;
+
+/// The string `key` of the `data` an API response body carries, or of an object in it when the
+/// data has none, e.g. the `id` in the `info` of a new bot token.
+pub fn data(body: &str, key: &str) -> String {
+    let value = serde_json::from_str::<Value>(body)
+        .unwrap_or_else(|err| panic!("Response isn't JSON ({}): {}", err, body));
+    match find(&value["data"], key) {
+        Some(data) => data.to_string(),
+        None => panic!("No {} in the data of {}", key, body),
+    }
+}
+
+fn find<'a>(value: &'a Value, key: &str) -> Option<&'a str> {
+    let object = value.as_object()?;
+    object
+        .get(key)
+        .and_then(Value::as_str)
+        .or_else(|| object.values().find_map(|value| find(value, key)))
+}
+
+/// Registers a user, returning the header authorizing their requests and their id.
+pub async fn register(client: &Client, name: &str) -> (Header<'static>, String) {
+    let body = client
+        .post("/auth/register")
+        .header(ContentType::JSON)
+        .body(format!(r#"{{"username":"{}","password":"doe"}}"#, name))
+        .dispatch()
+        .await
+        .into_string()
+        .await
+        .unwrap();
+    let auth = Header::new("Authorization", format!("Bearer {}", data(&body, "token")));
+    let body = client
+        .get("/users/whoami")
+        .header(auth.clone())
+        .dispatch()
+        .await
+        .into_string()
+        .await
+        .unwrap();
+    (auth, data(&body, "id"))
+}
+
+/// Reads an event stream until it carried every needle, returning what it read.
+pub async fn read_until(response: &mut LocalResponse<'_>, needles: &[&str]) -> String {
+    let mut events = String::new();
+    let mut buf = [0; 1024];
+    while !needles.iter().all(|needle| events.contains(needle)) {
+        let read = response.read(&mut buf).await.unwrap();
+        assert!(read > 0, "Stream ended early");
+        events.push_str(&String::from_utf8_lossy(&buf[..read]));
+    }
+    events
+}
+
+/// A SQLite database in the temporary directory, removed along with its WAL files when dropped.
+/// Declare it before the clients using it so that they are closed first.
+pub struct TempDatabase {
+    path: PathBuf,
+}
+
+impl TempDatabase {
+    pub fn new() -> Self {
+        let path = std::env::temp_dir().join(format!("quchat-{}.sqlite", uuid::Uuid::new_v4()));
+        TempDatabase { path }
+    }
+
+    pub fn url(&self) -> &str {
+        self.path.to_str().unwrap()
+    }
+
+    /// The default configuration with this database.
+    pub fn figment(&self) -> Figment {
+        rocket::Config::figment().merge(("databases.main.url", self.url()))
+    }
+}
+
+impl Drop for TempDatabase {
+    fn drop(&mut self) {
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", self.path.display(), suffix));
+        }
+    }
+}
@@ -1,15 +1,21 @@
-use crate::base::{ApiResult, Db};
+use crate::base::{ApiResult, Error};
+use crate::store::Db;
 use crate::{authentication::UserId, base::ApiResultBuilder};
-use qu_chat_models::{Identifiable, UserProfile};
+use qu_chat_models::{Identifiable, LoginAttempt, RenameUserParams, UserProfile};
 use rocket::fairing::AdHoc;
-use rocket_db_pools::Connection;
+use rocket::serde::json::Json;
 use serde::Serialize;
 
-#[derive(Serialize)]
+#[derive(Serialize, sqlx::FromRow)]
 pub struct User {
     pub id: String,
     pub name: String,
     pub secret: String,
+    pub is_admin: bool,
+    /// Bots act with API tokens only, see `bots`.
+    pub is_bot: bool,
+    /// Who manages the bot, none for users and for bots of the server admin.
+    pub owner_id: Option<String>,
 }
 
 impl Identifiable for User {
@@ -19,50 +25,80 @@ impl Identifiable for User {
 }
 
 #[get("/")]
-async fn get_users(mut db: Connection<Db>) -> ApiResult<Vec<UserProfile>> {
-    let result = sqlx::query!("SELECT id, name FROM users")
-        .fetch_all(&mut **db)
-        .await
-        .map(|rows| {
-            rows.into_iter()
-                .map(|row| UserProfile {
-                    id: row.id,
-                    name: row.name,
-                })
-                .collect::<Vec<UserProfile>>()
-        });
+async fn get_users(db: Db) -> ApiResult<Vec<UserProfile>> {
+    let result = db.users().await.map(|users| {
+        users
+            .into_iter()
+            .map(|user| UserProfile {
+                id: user.id,
+                name: user.name,
+            })
+            .collect::<Vec<UserProfile>>()
+    });
 
     ApiResultBuilder::from(result, "Failed to fetch users")
 }
 
+async fn profile(db: &Db, id: &str) -> Result<UserProfile, sqlx::Error> {
+    db.user(id)
+        .await?
+        .map(|user| UserProfile {
+            id: user.id,
+            name: user.name,
+        })
+        .ok_or(sqlx::Error::RowNotFound)
+}
+
 #[get("/<id>")]
-async fn get_user(mut db: Connection<Db>, id: &str) -> ApiResult<UserProfile> {
-    let result = sqlx::query!("SELECT * FROM users WHERE id = ($1)", id)
-        .fetch_one(&mut **db)
-        .await
-        .map(|row| UserProfile {
-            id: row.id,
-            name: row.name,
-        });
+async fn get_user(db: Db, id: &str) -> ApiResult<UserProfile> {
+    let result = profile(&db, id).await;
 
     ApiResultBuilder::from(result, "Failed to fetch user")
 }
 
 #[get("/whoami")]
-async fn whoami(user_id: UserId, mut db: Connection<Db>) -> ApiResult<UserProfile> {
-    let result = sqlx::query_as!(User, "SELECT * FROM users WHERE id = ($1)", user_id.id)
-        .fetch_one(&mut **db)
-        .await
-        .map(|row| UserProfile {
-            id: row.id,
-            name: row.name,
-        });
+async fn whoami(user_id: UserId, db: Db) -> ApiResult<UserProfile> {
+    let result = profile(&db, &user_id.id).await;
 
     ApiResultBuilder::from(result, "Failed to fetch user profile")
 }
 
+#[get("/me/logins")]
+async fn my_logins(user_id: UserId, db: Db) -> ApiResult<Vec<LoginAttempt>> {
+    let result = db.logins(&user_id.id, 50).await;
+
+    ApiResultBuilder::from(result, "Failed to fetch sign-ins")
+}
+
+/// Renames the user, which is also the name they sign in with.
+#[post("/me/name", data = "<params>")]
+async fn rename(params: Json<RenameUserParams>, user_id: UserId, db: Db) -> ApiResult<UserProfile> {
+    let name = params.name.trim();
+    if name.is_empty() {
+        return ApiResultBuilder::err("Name can't be empty.");
+    }
+    match db.user_by_name(name).await {
+        Ok(None) => (),
+        Ok(Some(user)) if user.id == user_id.id => (),
+        Ok(Some(_)) => return ApiResultBuilder::err("This name is taken."),
+        Err(_) => return Err(Error::Internal(())),
+    }
+
+    let result = db.rename_user(&user_id.id, name).await;
+    ApiResultBuilder::from(
+        result.map(|_| UserProfile {
+            id: user_id.id,
+            name: name.to_string(),
+        }),
+        "Unable to rename user",
+    )
+}
+
 pub fn stage() -> AdHoc {
     AdHoc::on_ignite("Users Stage", |rocket| async {
-        rocket.mount("/users", routes![get_users, get_user, whoami])
+        rocket.mount(
+            "/users",
+            routes![get_users, get_user, whoami, my_logins, rename],
+        )
     })
 }
@@ -0,0 +1,687 @@
+//! Outgoing webhooks. Room admins register urls events of their room are POSTed to, signed with
+//! a secret shown once. Events published by this instance are queued per webhook in the
+//! database, then sent by a background task that retries failed deliveries with a growing delay
+//! and keeps how each attempt went as the delivery log of the webhook.
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+
+use hmac::{Hmac, Mac};
+use qu_chat_models::{
+    CreateWebhookParams, CreatedWebhook, DeliveryStatus, RoomEvent, Webhook, WebhookDelivery,
+    WebhookPayload,
+};
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use reqwest::redirect::Policy;
+use reqwest::Url;
+use rocket::fairing::AdHoc;
+use rocket::futures::future::join_all;
+use rocket::futures::{stream, StreamExt};
+use rocket::serde::json::{serde_json, Json};
+use rocket::tokio::sync::broadcast::error::RecvError;
+use rocket::tokio::sync::Notify;
+use rocket::{Shutdown, State};
+use serde::Deserialize;
+use sha2::Sha256;
+
+use crate::authentication::UserId;
+use crate::base::{ApiResult, ApiResultBuilder, Error, RoomChange};
+use crate::pubsub::Changes;
+use crate::rooms::ensure_room_admin;
+use crate::store::{Db, DueDelivery};
+
+/// Header carrying `sha256=<hex HMAC-SHA256 of the body keyed with the webhook secret>`.
+pub const SIGNATURE_HEADER: &str = "X-Quchat-Signature";
+pub const EVENT_HEADER: &str = "X-Quchat-Event";
+pub const DELIVERY_HEADER: &str = "X-Quchat-Delivery";
+
+/// How often due retries are looked for when nothing new was queued.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// Deliveries sent per round.
+const BATCH_SIZE: i64 = 50;
+/// Deliveries of a webhook sent at once. The webhooks of a batch are sent to side by side, so a
+/// slow receiver only holds up its own deliveries.
+const SENDS_PER_WEBHOOK: usize = 4;
+/// Seconds on top of the time a batch can take to send before its deliveries are due again,
+/// should the instance sending them stop on the way.
+const LEASE_MARGIN: i64 = 60;
+/// Deliveries listed in the log of a webhook.
+const LOG_SIZE: i64 = 50;
+
+/// `webhooks` in `Rocket.toml`.
+#[derive(Deserialize, Debug, Clone)]
+pub struct WebhookConfig {
+    /// Attempts before a delivery is given up on.
+    #[serde(default = "default_max_attempts")]
+    pub max_attempts: i64,
+    /// Seconds before the first retry, doubled after every failed attempt.
+    #[serde(default = "default_retry_delay")]
+    pub retry_delay: i64,
+    /// Seconds a receiver has to answer.
+    #[serde(default = "default_timeout")]
+    pub timeout: u64,
+    /// Hosts, as written in urls, that may be on this machine or a private network, which
+    /// webhooks are otherwise refused for.
+    #[serde(default)]
+    pub allowed_hosts: Vec<String>,
+    /// Hosts never sent to.
+    #[serde(default)]
+    pub denied_hosts: Vec<String>,
+}
+
+fn default_max_attempts() -> i64 {
+    6
+}
+
+fn default_retry_delay() -> i64 {
+    30
+}
+
+fn default_timeout() -> u64 {
+    10
+}
+
+impl Default for WebhookConfig {
+    fn default() -> Self {
+        WebhookConfig {
+            max_attempts: default_max_attempts(),
+            retry_delay: default_retry_delay(),
+            timeout: default_timeout(),
+            allowed_hosts: vec![],
+            denied_hosts: vec![],
+        }
+    }
+}
+
+impl WebhookConfig {
+    fn allowed(&self, host: &str) -> bool {
+        self.allowed_hosts.iter().any(|allowed| allowed == host)
+    }
+
+    /// Why deliveries can't be sent to the url, as far as can be told without resolving its
+    /// host. Names are resolved by `PublicResolver` when sending.
+    fn refuse(&self, url: &Url) -> Option<&'static str> {
+        if url.scheme() != "http" && url.scheme() != "https" {
+            return Some("The url has to start with http:// or https://.");
+        }
+        let Some(host) = url.host_str() else {
+            return Some("The url has no host.");
+        };
+        if self.denied_hosts.iter().any(|denied| denied == host) {
+            return Some("The host of the url is denied.");
+        }
+        if self.allowed(host) {
+            return None;
+        }
+        let private = match ip(host) {
+            Some(ip) => !is_public(ip),
+            None => host == "localhost" || host.ends_with(".localhost"),
+        };
+        private.then_some("The url points to this machine or a private network.")
+    }
+}
+
+/// The address of a url host that is one, IPv6 ones being in brackets.
+fn ip(host: &str) -> Option<IpAddr> {
+    let unbracketed = host
+        .strip_prefix('[')
+        .and_then(|host| host.strip_suffix(']'));
+    unbracketed.unwrap_or(host).parse().ok()
+}
+
+/// Whether the address is reachable from the internet, rather than being this machine, a
+/// private network or a cloud metadata service.
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            // 100.64.0.0/10 is shared by carrier-grade NATs
+            let shared = a == 100 && b & 0xc0 == 64;
+            !(ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || shared)
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public(IpAddr::V4(ip)),
+            None => {
+                !(ip.is_loopback()
+                    || ip.is_unspecified()
+                    || ip.is_unique_local()
+                    || ip.is_unicast_link_local())
+            }
+        },
+    }
+}
+
+/// Public addresses of the host, all of them when it's allowed.
+async fn resolve(host: &str, allowed: bool) -> std::io::Result<Vec<SocketAddr>> {
+    Ok(rocket::tokio::net::lookup_host((host, 0))
+        .await?
+        .filter(|addr| allowed || is_public(addr.ip()))
+        .collect())
+}
+
+/// Resolves the hosts of deliveries, so that a name can't point to a private address once the
+/// webhook is created.
+struct PublicResolver {
+    allowed_hosts: Vec<String>,
+}
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let allowed = self.allowed_hosts.iter().any(|host| host == name.as_str());
+        Box::pin(async move {
+            let addrs = resolve(name.as_str(), allowed).await?;
+            if addrs.is_empty() {
+                return Err("The host doesn't resolve to a public address".into());
+            }
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// Whether the webhook wants the event. Typing is only sent when asked for by name.
+fn wants(webhook: &Webhook, event: &RoomEvent) -> bool {
+    if webhook.events.is_empty() {
+        return !matches!(event, RoomEvent::Typing { .. });
+    }
+    webhook.events.iter().any(|kind| kind == event.kind())
+}
+
+pub fn sign(secret: &str, body: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any size");
+    mac.update(body.as_bytes());
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// Queues the change for every webhook of its room that wants it.
+async fn queue(db: &Db, change: &RoomChange) -> Result<usize, sqlx::Error> {
+    let room_id = change.event.room_id();
+    let now = chrono::Utc::now().timestamp();
+    let mut queued = 0;
+
+    for webhook in db.webhooks(room_id).await? {
+        if !wants(&webhook, &change.event) {
+            continue;
+        }
+        let id = uuid::Uuid::new_v4().to_string();
+        let payload = WebhookPayload {
+            delivery_id: id.clone(),
+            webhook_id: webhook.id.clone(),
+            room_id: room_id.to_string(),
+            create_date: now,
+            event: change.event.clone(),
+        };
+        let delivery = WebhookDelivery {
+            id,
+            webhook_id: webhook.id,
+            event: change.event.kind().to_string(),
+            payload: serde_json::to_string(&payload).expect("payloads serialize"),
+            status: DeliveryStatus::Pending,
+            attempts: 0,
+            response_status: None,
+            error: None,
+            next_attempt: now,
+            create_date: now,
+        };
+        db.queue_delivery(&delivery).await?;
+        queued += 1;
+    }
+    Ok(queued)
+}
+
+/// Sends the delivery once and returns it updated with the outcome.
+async fn attempt(
+    http: &reqwest::Client,
+    config: &WebhookConfig,
+    due: DueDelivery,
+    now: i64,
+) -> WebhookDelivery {
+    let mut delivery = due.delivery;
+    delivery.attempts += 1;
+
+    // the url may have been allowed when the webhook was created
+    let refused = match Url::parse(&due.url) {
+        Ok(url) => config.refuse(&url),
+        Err(_) => Some("The url is invalid."),
+    };
+    let response = match refused {
+        Some(reason) => Err(reason.to_string()),
+        None => http
+            .post(&due.url)
+            .timeout(Duration::from_secs(config.timeout))
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header(EVENT_HEADER, &delivery.event)
+            .header(DELIVERY_HEADER, &delivery.id)
+            .header(SIGNATURE_HEADER, sign(&due.secret, &delivery.payload))
+            .body(delivery.payload.clone())
+            .send()
+            .await
+            .map_err(|err| err.to_string()),
+    };
+    match response {
+        Ok(response) if response.status().is_success() => {
+            delivery.status = DeliveryStatus::Delivered;
+            delivery.response_status = Some(response.status().as_u16() as i64);
+            delivery.error = None;
+            return delivery;
+        }
+        Ok(response) => {
+            delivery.response_status = Some(response.status().as_u16() as i64);
+            delivery.error = Some(format!("The receiver answered {}", response.status()));
+        }
+        Err(err) => {
+            delivery.response_status = None;
+            delivery.error = Some(err);
+        }
+    }
+
+    if delivery.attempts >= config.max_attempts {
+        delivery.status = DeliveryStatus::Failed;
+    } else {
+        let backoff = 1 << (delivery.attempts - 1).min(16);
+        delivery.next_attempt = now + config.retry_delay * backoff;
+    }
+    delivery
+}
+
+/// Attempts the delivery and records how it went.
+async fn send(db: Db, http: reqwest::Client, config: WebhookConfig, due: DueDelivery, now: i64) {
+    let delivery = attempt(&http, &config, due, now).await;
+    if let Err(err) = db.update_delivery(&delivery).await {
+        error!("Unable to record webhook delivery {}: {}", delivery.id, err);
+    }
+}
+
+/// Sends every due delivery, returns how many there were.
+async fn deliver_due(db: &Db, http: &reqwest::Client, config: &WebhookConfig) -> usize {
+    let now = chrono::Utc::now().timestamp();
+    // the deliveries of one webhook may all be in the batch
+    let rounds = (BATCH_SIZE as usize).div_ceil(SENDS_PER_WEBHOOK) as i64;
+    let lease_until = now + rounds * config.timeout as i64 + LEASE_MARGIN;
+    let due = match db.claim_deliveries(now, lease_until, BATCH_SIZE).await {
+        Ok(due) => due,
+        Err(err) => {
+            error!("Unable to load webhook deliveries: {}", err);
+            return 0;
+        }
+    };
+
+    let count = due.len();
+    let mut by_webhook: HashMap<String, Vec<DueDelivery>> = HashMap::new();
+    for due in due {
+        by_webhook
+            .entry(due.delivery.webhook_id.clone())
+            .or_default()
+            .push(due);
+    }
+    let sends = by_webhook.into_values().map(|deliveries| {
+        stream::iter(deliveries).for_each_concurrent(SENDS_PER_WEBHOOK, |due| {
+            // owned, futures borrowing them don't satisfy the `Send` bound of the spawned task
+            send(db.clone(), http.clone(), config.clone(), due, now)
+        })
+    });
+    join_all(sends).await;
+    count
+}
+
+/// Queues what this instance publishes, so that with several instances each change is sent
+/// once.
+async fn queue_changes(db: Db, changes: Changes, wake: Arc<Notify>, mut shutdown: Shutdown) {
+    let mut rx = changes.published();
+    loop {
+        let change = rocket::tokio::select! {
+            change = rx.recv() => match change {
+                Ok(change) => change,
+                Err(RecvError::Closed) => break,
+                Err(RecvError::Lagged(missed)) => {
+                    error!("Webhooks missed {} room changes", missed);
+                    continue;
+                }
+            },
+            _ = &mut shutdown => break,
+        };
+        match queue(&db, &change).await {
+            Ok(0) => (),
+            Ok(_) => wake.notify_one(),
+            Err(err) => error!("Unable to queue webhook deliveries: {}", err),
+        }
+    }
+}
+
+async fn send_deliveries(db: Db, config: WebhookConfig, wake: Arc<Notify>, mut shutdown: Shutdown) {
+    // a redirect could lead anywhere
+    let http = reqwest::Client::builder()
+        .redirect(Policy::none())
+        .dns_resolver(Arc::new(PublicResolver {
+            allowed_hosts: config.allowed_hosts.clone(),
+        }))
+        .build()
+        .expect("the webhook client builds");
+    loop {
+        // a full batch likely means more are due
+        if deliver_due(&db, &http, &config).await as i64 == BATCH_SIZE {
+            continue;
+        }
+        rocket::tokio::select! {
+            _ = wake.notified() => (),
+            _ = rocket::tokio::time::sleep(POLL_INTERVAL) => (),
+            _ = &mut shutdown => break,
+        }
+    }
+}
+
+/// The webhook, if it belongs to the room.
+async fn room_webhook(db: &Db, room_id: &str, id: &str) -> Result<Webhook, Error<'static>> {
+    match db.webhooks(room_id).await {
+        Ok(webhooks) => webhooks
+            .into_iter()
+            .find(|webhook| webhook.id == id)
+            .ok_or(Error::logical("Webhook doesn't exists.")),
+        Err(_) => Err(Error::Internal(())),
+    }
+}
+
+#[post("/<room_id>/webhooks", data = "<params>", rank = 2)]
+async fn create(
+    room_id: &str,
+    params: Json<CreateWebhookParams>,
+    user_id: UserId,
+    db: Db,
+    config: &State<WebhookConfig>,
+) -> ApiResult<CreatedWebhook> {
+    ensure_room_admin(&db, &user_id, room_id).await?;
+    let Ok(url) = Url::parse(&params.url) else {
+        return ApiResultBuilder::err("The url has to start with http:// or https://.");
+    };
+    if let Some(reason) = config.refuse(&url) {
+        return ApiResultBuilder::err(reason);
+    }
+    let host = url.host_str().unwrap_or_default();
+    if ip(host).is_none() {
+        match resolve(host, config.allowed(host)).await {
+            Ok(addrs) if !addrs.is_empty() => (),
+            Ok(_) => {
+                return ApiResultBuilder::err(
+                    "The url points to this machine or a private network.",
+                )
+            }
+            Err(_) => return ApiResultBuilder::err("The host of the url can't be resolved."),
+        }
+    }
+    if params
+        .events
+        .iter()
+        .any(|kind| !RoomEvent::KINDS.contains(&kind.as_str()))
+    {
+        return ApiResultBuilder::err("Unknown event, events are named like `message`.");
+    }
+
+    let params = params.into_inner();
+    let webhook = Webhook {
+        id: uuid::Uuid::new_v4().to_string(),
+        room_id: room_id.to_string(),
+        url: params.url,
+        events: params.events,
+        creator_id: user_id.id,
+        create_date: chrono::Utc::now().timestamp(),
+    };
+    let secret = format!(
+        "{}{}",
+        uuid::Uuid::new_v4().simple(),
+        uuid::Uuid::new_v4().simple()
+    );
+    let result = db.create_webhook(&webhook, &secret).await;
+    ApiResultBuilder::from(
+        result.map(|_| CreatedWebhook {
+            secret,
+            info: webhook,
+        }),
+        "Unable to create webhook",
+    )
+}
+
+#[get("/<room_id>/webhooks")]
+async fn list(room_id: &str, user_id: UserId, db: Db) -> ApiResult<Vec<Webhook>> {
+    ensure_room_admin(&db, &user_id, room_id).await?;
+
+    let result = db.webhooks(room_id).await;
+    ApiResultBuilder::from(result, "Unable to fetch webhooks")
+}
+
+#[delete("/<room_id>/webhooks/<id>")]
+async fn delete(room_id: &str, id: &str, user_id: UserId, db: Db) -> ApiResult<String> {
+    ensure_room_admin(&db, &user_id, room_id).await?;
+
+    match db.delete_webhook(room_id, id).await {
+        Ok(true) => ApiResultBuilder::data("Deleted webhook".to_string()),
+        Ok(false) => ApiResultBuilder::err("Webhook doesn't exists."),
+        Err(_) => Err(Error::Internal(())),
+    }
+}
+
+/// Latest deliveries of the webhook, newest first.
+#[get("/<room_id>/webhooks/<id>/deliveries")]
+async fn deliveries(
+    room_id: &str,
+    id: &str,
+    user_id: UserId,
+    db: Db,
+) -> ApiResult<Vec<WebhookDelivery>> {
+    ensure_room_admin(&db, &user_id, room_id).await?;
+    room_webhook(&db, room_id, id).await?;
+
+    let result = db.deliveries(id, LOG_SIZE).await;
+    ApiResultBuilder::from(result, "Unable to fetch deliveries")
+}
+
+pub fn stage() -> AdHoc {
+    AdHoc::on_ignite("Webhooks Stage", |rocket| async {
+        let config = rocket
+            .figment()
+            .extract_inner::<WebhookConfig>("webhooks")
+            .unwrap_or_default();
+
+        rocket
+            .mount("/rooms", routes![create, list, delete, deliveries])
+            .manage(config.clone())
+            .attach(AdHoc::on_liftoff("Webhook Deliveries", |rocket| {
+                Box::pin(async move {
+                    let (Some(db), Some(changes)) =
+                        (rocket.state::<Db>(), rocket.state::<Changes>())
+                    else {
+                        error!("Webhooks need the store and the pubsub");
+                        return;
+                    };
+                    let wake = Arc::new(Notify::new());
+                    rocket::tokio::spawn(queue_changes(
+                        db.clone(),
+                        changes.clone(),
+                        wake.clone(),
+                        rocket.shutdown(),
+                    ));
+                    rocket::tokio::spawn(send_deliveries(
+                        db.clone(),
+                        config,
+                        wake,
+                        rocket.shutdown(),
+                    ));
+                })
+            }))
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rocket::http::{ContentType, Header};
+    use rocket::local::asynchronous::Client;
+    use rocket::tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+    use rocket::tokio::net::TcpListener;
+    use rocket::tokio::sync::mpsc;
+    use rocket::tokio::time::timeout;
+    use std::collections::HashMap;
+
+    use crate::test_util::{data, TempDatabase};
+
+    /// A receiver answering with `statuses` in turn, handing over the headers and body of
+    /// each request.
+    async fn receiver(
+        statuses: Vec<u16>,
+    ) -> (
+        String,
+        mpsc::UnboundedReceiver<(HashMap<String, String>, String)>,
+    ) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let (tx, rx) = mpsc::unbounded_channel();
+        rocket::tokio::spawn(async move {
+            for status in statuses {
+                let (stream, _) = listener.accept().await.unwrap();
+                let mut stream = BufReader::new(stream);
+                let mut headers = HashMap::new();
+                loop {
+                    let mut line = String::new();
+                    stream.read_line(&mut line).await.unwrap();
+                    let line = line.trim_end();
+                    if line.is_empty() {
+                        break;
+                    }
+                    if let Some((name, value)) = line.split_once(": ") {
+                        headers.insert(name.to_lowercase(), value.to_string());
+                    }
+                }
+                let length = headers["content-length"].parse().unwrap();
+                let mut body = vec![0; length];
+                stream.read_exact(&mut body).await.unwrap();
+                let response = format!(
+                    "HTTP/1.1 {} Status\r\ncontent-length: 0\r\nconnection: close\r\n\r\n",
+                    status
+                );
+                stream.write_all(response.as_bytes()).await.unwrap();
+                tx.send((headers, String::from_utf8(body).unwrap()))
+                    .unwrap();
+            }
+        });
+        (url, rx)
+    }
+
+    #[rocket::async_test]
+    async fn test_signed_delivery_is_retried() {
+        let (url, mut requests) = receiver(vec![500, 204]).await;
+        let database = TempDatabase::new();
+        let figment = database
+            .figment()
+            .merge(("webhooks.retry_delay", 0))
+            .merge(("webhooks.allowed_hosts", ["127.0.0.1"]));
+        let client = Client::untracked(crate::build_with(figment)).await.unwrap();
+
+        let body = client
+            .post("/auth/register")
+            .header(ContentType::JSON)
+            .body(r#"{"username":"john","password":"doe"}"#)
+            .dispatch()
+            .await
+            .into_string()
+            .await
+            .unwrap();
+        let auth = Header::new("Authorization", format!("Bearer {}", data(&body, "token")));
+        let body = client
+            .post("/rooms")
+            .header(ContentType::JSON)
+            .header(auth.clone())
+            .body(r#"{"name":"builds"}"#)
+            .dispatch()
+            .await
+            .into_string()
+            .await
+            .unwrap();
+        let room_id = data(&body, "id");
+        for url in [
+            "http://169.254.169.254/latest/meta-data",
+            "http://localhost:8000/hook",
+            "http://[::1]/hook",
+            "http://10.0.0.1/hook",
+        ] {
+            let body = client
+                .post(format!("/rooms/{}/webhooks", room_id))
+                .header(ContentType::JSON)
+                .header(auth.clone())
+                .body(format!(r#"{{"url":"{}"}}"#, url))
+                .dispatch()
+                .await
+                .into_string()
+                .await
+                .unwrap();
+            assert!(body.contains("private network"), "{} was allowed", url);
+        }
+        let body = client
+            .post(format!("/rooms/{}/webhooks", room_id))
+            .header(ContentType::JSON)
+            .header(auth.clone())
+            .body(format!(r#"{{"url":"{}","events":["message"]}}"#, url))
+            .dispatch()
+            .await
+            .into_string()
+            .await
+            .unwrap();
+        let secret = data(&body, "secret");
+        let webhook_id = data(&body, "id");
+
+        client
+            .post(format!("/rooms/{}/topic", room_id))
+            .header(ContentType::JSON)
+            .header(auth.clone())
+            .body(r#"{"topic":"not sent"}"#)
+            .dispatch()
+            .await;
+        client
+            .post("/messages/send")
+            .header(ContentType::JSON)
+            .header(auth.clone())
+            .body(format!(r#"{{"text":"green","room_id":"{}"}}"#, room_id))
+            .dispatch()
+            .await;
+
+        for _ in 0..2 {
+            let (headers, body) = timeout(Duration::from_secs(10), requests.recv())
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(headers["x-quchat-event"], "message");
+            assert_eq!(headers["x-quchat-signature"], sign(&secret, &body));
+            let payload = serde_json::from_str::<WebhookPayload>(&body).unwrap();
+            assert!(matches!(payload.event, RoomEvent::Message(m) if m.content == "green"));
+        }
+
+        let log = timeout(Duration::from_secs(10), async {
+            loop {
+                let body = client
+                    .get(format!(
+                        "/rooms/{}/webhooks/{}/deliveries",
+                        room_id, webhook_id
+                    ))
+                    .header(auth.clone())
+                    .dispatch()
+                    .await
+                    .into_string()
+                    .await
+                    .unwrap();
+                if body.contains(r#""status":"delivered""#) {
+                    break body;
+                }
+                rocket::tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+        })
+        .await
+        .unwrap();
+        assert!(log.contains(r#""attempts":2"#));
+        assert!(log.contains(r#""response_status":204"#));
+    }
+}
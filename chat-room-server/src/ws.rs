@@ -0,0 +1,349 @@
+//! A single WebSocket per client carrying the room events it subscribed to, and its requests
+//! acked by the id the client gave them. Events come from the same fan-out as the SSE streams.
+use std::collections::HashSet;
+use std::time::Instant;
+
+use qu_chat_models::{ApiScope, ClientFrame, ClientRequest, RoomEvent, ServerFrame};
+use rocket::fairing::AdHoc;
+use rocket::futures::{SinkExt, StreamExt};
+use rocket::serde::json::serde_json;
+use rocket::tokio::sync::broadcast::error::RecvError;
+use rocket::{Shutdown, State};
+use rocket_ws::{Channel, Message as WsMessage, WebSocket};
+
+use crate::authentication::UserId;
+use crate::base::RoomChange;
+use crate::message::send_message;
+use crate::moderation::ensure_not_sanctioned;
+use crate::pubsub::Changes;
+use crate::rate_limit::RateLimiter;
+use crate::store::Db;
+
+/// The session of one socket.
+struct Session<'r> {
+    user_id: String,
+    /// False for the API tokens of bots that can only read.
+    can_send: bool,
+    rooms: HashSet<String>,
+    db: Db,
+    changes: Changes,
+    limiter: Option<&'r RateLimiter>,
+}
+
+impl Session<'_> {
+    /// Handles a request of the client, the error is what the ack carries.
+    async fn handle(&mut self, request: ClientRequest) -> Result<(), String> {
+        match request {
+            ClientRequest::Subscribe { room_ids } => {
+                // the rooms the user can read are subscribed even when some are refused
+                let mut result = Ok(());
+                for room_id in room_ids {
+                    match ensure_not_sanctioned(&self.db, &room_id, &self.user_id, true).await {
+                        Ok(_) => {
+                            self.rooms.insert(room_id);
+                        }
+                        Err(err) => result = Err(err.msg().to_string()),
+                    }
+                }
+                result
+            }
+            ClientRequest::Unsubscribe { room_ids } => {
+                for room_id in room_ids {
+                    self.rooms.remove(&room_id);
+                }
+                Ok(())
+            }
+            ClientRequest::Send {
+                room_id,
+                text,
+                client_id,
+            } => {
+                if !self.can_send {
+                    return Err("The token can't send messages.".to_string());
+                }
+                // sends share the bucket of `POST /messages/send`
+                if let Some(limiter) = self.limiter {
+                    let keys = [format!("user:{}", self.user_id)];
+                    if let Err(secs) = limiter.check("messages", &keys, Instant::now()) {
+                        return Err(format!("Too many requests, retry after {}s.", secs));
+                    }
+                }
+                send_message(
+                    &self.db,
+                    &self.changes,
+                    &self.user_id,
+                    &room_id,
+                    &text,
+                    client_id.as_deref(),
+                )
+                .await
+                .map(|_| ())
+                .map_err(|err| err.msg().to_string())
+            }
+            ClientRequest::Typing { room_id } => {
+                if !self.can_send {
+                    return Err("The token can't send messages.".to_string());
+                }
+                if !self.rooms.contains(&room_id) {
+                    return Err("Not subscribed to this room.".to_string());
+                }
+                // muted or banned since subscribing
+                ensure_not_sanctioned(&self.db, &room_id, &self.user_id, false)
+                    .await
+                    .map_err(|err| err.msg().to_string())?;
+                let user_name = match self.db.user(&self.user_id).await {
+                    Ok(Some(user)) => user.name,
+                    _ => return Err("Can't find user name".to_string()),
+                };
+                let change = RoomChange {
+                    event: RoomEvent::Typing {
+                        room_id,
+                        user_id: self.user_id.clone(),
+                        user_name,
+                    },
+                };
+                self.changes
+                    .publish(change)
+                    .await
+                    .map_err(|err| err.to_string())
+            }
+        }
+    }
+}
+
+fn frame(frame: &ServerFrame) -> WsMessage {
+    WsMessage::Text(serde_json::to_string(frame).unwrap_or_default())
+}
+
+#[get("/")]
+fn socket<'r>(
+    ws: WebSocket,
+    user_id: UserId,
+    db: Db,
+    changes: &State<Changes>,
+    limiter: Option<&'r State<RateLimiter>>,
+    mut shutdown: Shutdown,
+) -> Channel<'r> {
+    let mut rx = changes.subscribe();
+    let mut session = Session {
+        can_send: user_id.can(ApiScope::Send),
+        user_id: user_id.id,
+        rooms: HashSet::new(),
+        db,
+        changes: changes.inner().clone(),
+        limiter: limiter.map(|limiter| limiter.inner()),
+    };
+
+    ws.channel(move |mut stream| {
+        Box::pin(async move {
+            loop {
+                rocket::tokio::select! {
+                    message = stream.next() => {
+                        let text = match message {
+                            Some(Ok(WsMessage::Text(text))) => text,
+                            Some(Ok(WsMessage::Close(_))) | None => break,
+                            Some(Ok(_)) => continue,
+                            Some(Err(err)) => return Err(err),
+                        };
+                        let ack = match serde_json::from_str::<ClientFrame>(&text) {
+                            Ok(client_frame) => ServerFrame::Ack {
+                                id: client_frame.id,
+                                error: session.handle(client_frame.request).await.err(),
+                            },
+                            Err(err) => ServerFrame::Ack {
+                                id: String::new(),
+                                error: Some(format!("Malformed frame: {}", err)),
+                            },
+                        };
+                        stream.send(frame(&ack)).await?;
+                    },
+                    change = rx.recv() => match change {
+                        // new rooms reach everyone who can read them, so clients can subscribe
+                        // to them
+                        Ok(change)
+                            if session.rooms.contains(change.event.room_id())
+                                || matches!(change.event, RoomEvent::Created { .. }) =>
+                        {
+                            if let RoomEvent::Created { room } = &change.event {
+                                let readable = ensure_not_sanctioned(
+                                    &session.db,
+                                    &room.id,
+                                    &session.user_id,
+                                    true,
+                                )
+                                .await;
+                                if readable.is_err() {
+                                    continue;
+                                }
+                            }
+                            // the ban is the last event of the room the user gets
+                            if let RoomEvent::UserBanned { room_id, user_id } = &change.event {
+                                if *user_id == session.user_id {
+                                    session.rooms.remove(room_id);
+                                }
+                            }
+                            stream.send(frame(&ServerFrame::Event { event: change.event })).await?;
+                        }
+                        Ok(_) | Err(RecvError::Lagged(_)) => continue,
+                        Err(RecvError::Closed) => break,
+                    },
+                    _ = &mut shutdown => break,
+                }
+            }
+            Ok(())
+        })
+    })
+}
+
+pub fn stage() -> AdHoc {
+    AdHoc::on_ignite("WebSocket Stage", |rocket| async {
+        rocket.mount("/ws", routes![socket])
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rocket::http::{ContentType, Header};
+    use rocket::local::asynchronous::Client;
+    use rocket::tokio::net::TcpStream;
+    use rocket::tokio::time::{sleep, timeout};
+    use std::time::Duration;
+    use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+    use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+
+    use crate::test_util::{data, TempDatabase};
+
+    type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+    async fn request(socket: &mut Socket, id: &str, request: ClientRequest) {
+        let frame = ClientFrame {
+            id: id.to_string(),
+            request,
+        };
+        let text = serde_json::to_string(&frame).unwrap();
+        socket.send(WsMessage::Text(text)).await.unwrap();
+    }
+
+    async fn next(socket: &mut Socket) -> ServerFrame {
+        loop {
+            let message = timeout(Duration::from_secs(5), socket.next())
+                .await
+                .expect("No frame from the server")
+                .unwrap()
+                .unwrap();
+            if let WsMessage::Text(text) = message {
+                return serde_json::from_str(&text).unwrap();
+            }
+        }
+    }
+
+    #[rocket::async_test]
+    async fn test_send_reaches_subscriber_and_is_acked() {
+        let database = TempDatabase::new();
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let figment = database
+            .figment()
+            .merge(("address", "127.0.0.1"))
+            .merge(("port", port))
+            .merge(("log_level", "off"));
+
+        let local = Client::untracked(crate::build_with(figment.clone()))
+            .await
+            .unwrap();
+        let body = local
+            .post("/auth/register")
+            .header(ContentType::JSON)
+            .body(r#"{"username":"john","password":"doe"}"#)
+            .dispatch()
+            .await
+            .into_string()
+            .await
+            .unwrap();
+        let bearer = format!("Bearer {}", data(&body, "token"));
+        let body = local
+            .post("/rooms")
+            .header(ContentType::JSON)
+            .header(Header::new("Authorization", bearer.clone()))
+            .body(r#"{"name":"lobby"}"#)
+            .dispatch()
+            .await
+            .into_string()
+            .await
+            .unwrap();
+        let room_id = data(&body, "id");
+
+        let server = rocket::tokio::spawn(crate::build_with(figment).launch());
+        let mut handshake = format!("ws://127.0.0.1:{}/ws", port)
+            .into_client_request()
+            .unwrap();
+        handshake
+            .headers_mut()
+            .insert("Authorization", bearer.parse().unwrap());
+        let mut socket = loop {
+            match tokio_tungstenite::connect_async(handshake.clone()).await {
+                Ok((socket, _)) => break socket,
+                Err(_) => sleep(Duration::from_millis(50)).await,
+            }
+        };
+
+        request(
+            &mut socket,
+            "1",
+            ClientRequest::Subscribe {
+                room_ids: vec![room_id.clone()],
+            },
+        )
+        .await;
+        assert!(
+            matches!(next(&mut socket).await, ServerFrame::Ack { id, error: None } if id == "1")
+        );
+
+        request(
+            &mut socket,
+            "2",
+            ClientRequest::Send {
+                room_id: room_id.clone(),
+                text: "hello over ws".to_string(),
+                client_id: None,
+            },
+        )
+        .await;
+        let (mut acked, mut delivered) = (false, false);
+        while !(acked && delivered) {
+            match next(&mut socket).await {
+                ServerFrame::Ack { id, error } => {
+                    assert_eq!((id.as_str(), error), ("2", None));
+                    acked = true;
+                }
+                ServerFrame::Event {
+                    event: RoomEvent::Message(message),
+                } => {
+                    assert_eq!(message.content, "hello over ws");
+                    delivered = true;
+                }
+                frame => panic!("Unexpected frame {:?}", frame),
+            }
+        }
+
+        request(
+            &mut socket,
+            "3",
+            ClientRequest::Send {
+                room_id: "nowhere".to_string(),
+                text: "lost".to_string(),
+                client_id: None,
+            },
+        )
+        .await;
+        assert!(
+            matches!(next(&mut socket).await, ServerFrame::Ack { id, error: Some(_) } if id == "3")
+        );
+
+        server.abort();
+    }
+}
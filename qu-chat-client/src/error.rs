@@ -0,0 +1,60 @@
+pub type Result<T> = std::result::Result<T, self::Error>;
+
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    /// The token is missing, expired or revoked.
+    Unauthorized,
+    TimedOut,
+    TooManyRequests {
+        retry_after: u64,
+    },
+    Decoding,
+    Other(String),
+    /// Refused by the server, with its reason.
+    Logical(String),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Unauthorized => write!(f, "Unauthorized"),
+            Error::TimedOut => write!(f, "Timed out"),
+            Error::TooManyRequests { retry_after } => {
+                write!(f, "Too many requests, try again in {} seconds", retry_after)
+            }
+            Error::Other(string) => write!(f, "Unknown Error {}", string),
+            Error::Logical(string) => write!(f, "{}", string),
+            Error::Decoding => write!(f, "Unable to decode"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl Error {
+    /// Whether the same request may succeed later, e.g. once the server is reachable again.
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            Error::TimedOut | Error::TooManyRequests { .. } | Error::Other(_)
+        )
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(error: reqwest::Error) -> Self {
+        if error.is_decode() {
+            return Self::Decoding;
+        }
+
+        if error.status() == Some(reqwest::StatusCode::UNAUTHORIZED) {
+            return Self::Unauthorized;
+        }
+
+        if error.is_timeout() {
+            return Self::TimedOut;
+        }
+
+        Self::Other(error.to_string())
+    }
+}
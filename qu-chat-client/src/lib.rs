@@ -0,0 +1,542 @@
+//! Async client of a quchat server, shared by the terminal client, bots and services.
+//!
+//! ```no_run
+//! # async fn run() -> qu_chat_client::Result<()> {
+//! use futures::StreamExt;
+//! use qu_chat_client::ChatClient;
+//!
+//! let mut client = ChatClient::new("http://127.0.0.1:8000", None);
+//! let token = client.sign_in("john", "secret").await?.token;
+//! client.set_token(Some(token));
+//! let room = &client.rooms().await?[0];
+//! client.send_message(&room.id, "hello", None).await?;
+//! let mut events = client.events().await?;
+//! while let Some(event) = events.next().await {
+//!     println!("{:?}", event);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+use std::pin::Pin;
+
+use futures::{Stream, StreamExt};
+use qu_chat_models::{
+    CreateRoomParam, DirectRoomParams, InvokeCommandParams, Message, ModerationParams, MuteParams,
+    RegisterCommandParams, RegisterParams, RegisterResponse, RemoveMessageParams, RenameUserParams,
+    Room, RoomCommand, RoomEvent, RoomState, RoomTopicParams, SendMessageParams, SignInParams,
+    SignInResponse, UserProfile,
+};
+use reqwest::RequestBuilder;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+mod error;
+mod socket;
+
+pub use error::{Error, Result};
+pub use socket::Socket;
+
+/// Events of the rooms the user can see, ending when the connection does.
+pub type EventStream = Pin<Box<dyn Stream<Item = RoomEvent> + Send>>;
+
+/// A server and, once signed in, the token requests are made with. Cloning it is cheap, clones
+/// share their connection pool.
+#[derive(Debug, Clone)]
+pub struct ChatClient {
+    http: reqwest::Client,
+    base_url: String,
+    token: Option<String>,
+}
+
+impl ChatClient {
+    /// `base_url` is the address of the server, e.g. `http://127.0.0.1:8000`.
+    pub fn new(base_url: impl Into<String>, token: Option<String>) -> Self {
+        Self::with_http(reqwest::Client::new(), base_url, token)
+    }
+
+    /// Same as `new`, making the requests with a configured `reqwest` client.
+    pub fn with_http(
+        http: reqwest::Client,
+        base_url: impl Into<String>,
+        token: Option<String>,
+    ) -> Self {
+        let base_url = base_url.into().trim_end_matches('/').to_string();
+        Self {
+            http,
+            base_url,
+            token,
+        }
+    }
+
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    pub fn token(&self) -> Option<&str> {
+        self.token.as_deref()
+    }
+
+    pub fn set_token(&mut self, token: Option<String>) {
+        self.token = token;
+    }
+
+    pub async fn register(&self, username: &str, password: &str) -> Result<RegisterResponse> {
+        let body = RegisterParams {
+            username: username.to_string(),
+            password: password.to_string(),
+        };
+        self.fetch(self.post("/auth/register").json(&body)).await
+    }
+
+    /// Returns a token for the user, see `set_token`.
+    pub async fn sign_in(&self, username: &str, password: &str) -> Result<SignInResponse> {
+        let body = SignInParams {
+            username: username.to_string(),
+            password: password.to_string(),
+        };
+        self.fetch(self.post("/auth/signin").json(&body)).await
+    }
+
+    /// Revokes the token.
+    pub async fn sign_out(&self) -> Result<()> {
+        self.fetch::<String>(self.post("/auth/signout"))
+            .await
+            .map(|_| ())
+    }
+
+    pub async fn whoami(&self) -> Result<UserProfile> {
+        self.fetch(self.get("/users/whoami")).await
+    }
+
+    /// Changes the name of the user, which is also the one they sign in with.
+    pub async fn rename(&self, name: &str) -> Result<UserProfile> {
+        let body = RenameUserParams {
+            name: name.to_string(),
+        };
+        self.fetch(self.post("/users/me/name").json(&body)).await
+    }
+
+    pub async fn rooms(&self) -> Result<Vec<Room>> {
+        self.fetch(self.get("/rooms")).await
+    }
+
+    pub async fn create_room(&self, params: &CreateRoomParam) -> Result<Room> {
+        self.fetch(self.post("/rooms").json(params)).await
+    }
+
+    /// The room only the user and `user_id` can read, created the first time.
+    pub async fn open_direct_room(&self, user_id: &str) -> Result<Room> {
+        let body = DirectRoomParams {
+            user_id: user_id.to_string(),
+        };
+        self.fetch(self.post("/rooms/direct").json(&body)).await
+    }
+
+    /// Needs the user to be an admin or the creator of the room.
+    pub async fn set_room_topic(&self, room_id: &str, topic: &str) -> Result<()> {
+        let body = RoomTopicParams {
+            topic: topic.to_string(),
+        };
+        let request = self.post(&format!("/rooms/{}/topic", room_id)).json(&body);
+        self.fetch::<String>(request).await.map(|_| ())
+    }
+
+    pub async fn room_members(&self, room_id: &str) -> Result<Vec<UserProfile>> {
+        self.fetch(self.get(&format!("/rooms/{}/members", room_id)))
+            .await
+    }
+
+    pub async fn room_states(&self, room_ids: &[&str]) -> Result<Vec<RoomState>> {
+        let request = self
+            .get("/rooms/states/")
+            .query(&[("room_ids", room_ids.join(","))]);
+        self.fetch(request).await
+    }
+
+    /// Marks everything sent to the room so far as seen by the user.
+    pub async fn mark_room_seen(&self, room_id: &str) -> Result<()> {
+        self.fetch::<String>(self.post(&format!("/rooms/states/{}", room_id)))
+            .await
+            .map(|_| ())
+    }
+
+    /// Leaves the members of the room, until it's marked as seen again.
+    pub async fn leave_room(&self, room_id: &str) -> Result<()> {
+        self.fetch::<String>(self.delete(&format!("/rooms/states/{}", room_id)))
+            .await
+            .map(|_| ())
+    }
+
+    /// Commands bots answer in the room, see `invoke_command`.
+    pub async fn room_commands(&self, room_id: &str) -> Result<Vec<RoomCommand>> {
+        self.fetch(self.get(&format!("/rooms/{}/commands", room_id)))
+            .await
+    }
+
+    /// Registers a command of the room for `bot_id`, or the bot the token belongs to, which then
+    /// receives a `RoomEvent::CommandInvoked` each time it's run. Only room admins can.
+    pub async fn register_command(
+        &self,
+        room_id: &str,
+        name: &str,
+        description: &str,
+        bot_id: Option<&str>,
+    ) -> Result<RoomCommand> {
+        let body = RegisterCommandParams {
+            name: name.to_string(),
+            description: description.to_string(),
+            bot_id: bot_id.map(str::to_string),
+        };
+        let request = self
+            .post(&format!("/rooms/{}/commands", room_id))
+            .json(&body);
+        self.fetch(request).await
+    }
+
+    pub async fn unregister_command(&self, room_id: &str, name: &str) -> Result<()> {
+        let request = self.delete(&format!("/rooms/{}/commands/{}", room_id, name));
+        self.fetch::<String>(request).await.map(|_| ())
+    }
+
+    /// Runs a command of the room, `args` is whatever follows its name.
+    pub async fn invoke_command(&self, room_id: &str, name: &str, args: &str) -> Result<()> {
+        let body = InvokeCommandParams {
+            args: args.to_string(),
+        };
+        let request = self
+            .post(&format!("/rooms/{}/commands/{}", room_id, name))
+            .json(&body);
+        self.fetch::<String>(request).await.map(|_| ())
+    }
+
+    /// The first messages of the room, oldest first.
+    pub async fn messages(&self, room_id: &str) -> Result<Vec<Message>> {
+        self.fetch(self.get(&format!("/messages/{}", room_id)))
+            .await
+    }
+
+    /// Up to `size` messages of the room sent at or after `since`, oldest first.
+    pub async fn messages_since(
+        &self,
+        room_id: &str,
+        since: i64,
+        size: u32,
+    ) -> Result<Vec<Message>> {
+        let request = self
+            .get(&format!("/messages/{}", room_id))
+            .query(&[("since", since.to_string()), ("size", size.to_string())]);
+        self.fetch(request).await
+    }
+
+    /// The last `size` messages of the room, newest first.
+    pub async fn latest_messages(&self, room_id: &str, size: u32) -> Result<Vec<Message>> {
+        let request = self
+            .get(&format!("/messages/{}/latest", room_id))
+            .query(&[("size", size)]);
+        self.fetch(request).await
+    }
+
+    /// Latest messages of the room containing `text`, newest first.
+    pub async fn search_messages(&self, room_id: &str, text: &str) -> Result<Vec<Message>> {
+        let request = self
+            .get(&format!("/messages/{}/search", room_id))
+            .query(&[("q", text)]);
+        self.fetch(request).await
+    }
+
+    /// Sends a text message. A message sent again with the same `client_id` isn't duplicated.
+    pub async fn send_message(
+        &self,
+        room_id: &str,
+        text: &str,
+        client_id: Option<&str>,
+    ) -> Result<()> {
+        let body = SendMessageParams {
+            text: text.to_string(),
+            room_id: room_id.to_string(),
+            client_id: client_id.map(str::to_string),
+        };
+        let response = self.send(self.post("/messages/send").json(&body)).await?;
+        if response.status().is_success() {
+            Ok(())
+        } else {
+            match response.json::<BaseRes<String>>().await {
+                Ok(result) => handle_result(result).map(|_| ()),
+                Err(_) => Err(Error::Logical(format!(
+                    "Unable to send message for {}",
+                    room_id
+                ))),
+            }
+        }
+    }
+
+    pub async fn kick_user(
+        &self,
+        room_id: &str,
+        user_id: &str,
+        reason: Option<String>,
+    ) -> Result<()> {
+        let body = ModerationParams {
+            user_id: user_id.to_string(),
+            reason,
+        };
+        self.moderate(room_id, "kick", &body).await
+    }
+
+    pub async fn ban_user(
+        &self,
+        room_id: &str,
+        user_id: &str,
+        reason: Option<String>,
+    ) -> Result<()> {
+        let body = ModerationParams {
+            user_id: user_id.to_string(),
+            reason,
+        };
+        self.moderate(room_id, "ban", &body).await
+    }
+
+    pub async fn mute_user(
+        &self,
+        room_id: &str,
+        user_id: &str,
+        minutes: u32,
+        reason: Option<String>,
+    ) -> Result<()> {
+        let body = MuteParams {
+            user_id: user_id.to_string(),
+            minutes,
+            reason,
+        };
+        self.moderate(room_id, "mute", &body).await
+    }
+
+    pub async fn remove_message(
+        &self,
+        room_id: &str,
+        message_id: &str,
+        reason: String,
+    ) -> Result<()> {
+        let body = RemoveMessageParams { reason };
+        let action = format!("messages/{}/remove", message_id);
+        self.moderate(room_id, &action, &body).await
+    }
+
+    async fn moderate<B: Serialize>(&self, room_id: &str, action: &str, body: &B) -> Result<()> {
+        let request = self
+            .post(&format!("/moderation/{}/{}", room_id, action))
+            .json(body);
+        self.fetch::<String>(request).await.map(|_| ())
+    }
+
+    /// Follows the events of every room on a single SSE stream.
+    pub async fn events(&self) -> Result<EventStream> {
+        let response = self.send(self.get("/events")).await?;
+        if !response.status().is_success() {
+            return Err(Error::Other(response.status().to_string()));
+        }
+        let mut buffer = SseBuffer::default();
+        let events = response
+            .bytes_stream()
+            .take_while(|chunk| futures::future::ready(chunk.is_ok()))
+            .flat_map(move |chunk| {
+                let events = chunk.map(|chunk| buffer.push(&chunk)).unwrap_or_default();
+                futures::stream::iter(events)
+            });
+        Ok(Box::pin(events))
+    }
+
+    /// Opens a WebSocket, which receives the events of the rooms it subscribes to with
+    /// `ClientRequest::Subscribe`. Fails when the server can't be reached over WebSocket,
+    /// `events` can be followed then.
+    pub async fn connect(&self) -> Result<(Socket, EventStream)> {
+        let token = self.token.as_deref().ok_or(Error::Unauthorized)?;
+        socket::connect(self.socket_url(), token).await
+    }
+
+    fn socket_url(&self) -> String {
+        let base = match self.base_url.strip_prefix("http") {
+            // http -> ws, https -> wss
+            Some(rest) => format!("ws{}", rest),
+            None => self.base_url.clone(),
+        };
+        format!("{}/ws", base)
+    }
+
+    fn get(&self, path: &str) -> RequestBuilder {
+        self.authorize(self.http.get(format!("{}{}", self.base_url, path)))
+    }
+
+    fn post(&self, path: &str) -> RequestBuilder {
+        self.authorize(self.http.post(format!("{}{}", self.base_url, path)))
+    }
+
+    fn delete(&self, path: &str) -> RequestBuilder {
+        self.authorize(self.http.delete(format!("{}{}", self.base_url, path)))
+    }
+
+    fn authorize(&self, request: RequestBuilder) -> RequestBuilder {
+        match &self.token {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        }
+    }
+
+    async fn send(&self, request: RequestBuilder) -> Result<reqwest::Response> {
+        let response = request.send().await?;
+        match response.status() {
+            reqwest::StatusCode::UNAUTHORIZED => Err(Error::Unauthorized),
+            reqwest::StatusCode::TOO_MANY_REQUESTS => Err(throttled(&response)),
+            _ => Ok(response),
+        }
+    }
+
+    async fn fetch<T: DeserializeOwned>(&self, request: RequestBuilder) -> Result<T> {
+        let result = self.send(request).await?.json::<BaseRes<T>>().await?;
+        handle_result(result)
+    }
+}
+
+/// Splits an SSE body in events. A chunk of the response can end anywhere in an event or hold
+/// several of them, events end with a blank line.
+#[derive(Default)]
+struct SseBuffer {
+    bytes: Vec<u8>,
+}
+
+impl SseBuffer {
+    /// Adds the chunk and returns the events it completes.
+    fn push(&mut self, chunk: &[u8]) -> Vec<RoomEvent> {
+        self.bytes.extend_from_slice(chunk);
+        let mut events = vec![];
+        while let Some((end, separator)) = event_end(&self.bytes) {
+            let event = self.bytes.drain(..end + separator).collect::<Vec<u8>>();
+            events.extend(parse_event(&event[..end]));
+        }
+        events
+    }
+}
+
+/// Where the first event ends, and the length of the blank line after it.
+fn event_end(bytes: &[u8]) -> Option<(usize, usize)> {
+    ["\n\n", "\r\n\r\n", "\r\r"]
+        .iter()
+        .filter_map(|separator| {
+            let separator = separator.as_bytes();
+            let end = bytes
+                .windows(separator.len())
+                .position(|window| window == separator)?;
+            Some((end, separator.len()))
+        })
+        .min()
+}
+
+/// The room event an SSE event carries, e.g. `data:{"type":"message",...}`. Its `data:` lines are joined,
+/// other fields and comments are ignored.
+fn parse_event(event: &[u8]) -> Option<RoomEvent> {
+    let event = std::str::from_utf8(event).ok()?;
+    let data = event
+        .lines()
+        .filter_map(|line| line.strip_prefix("data:"))
+        .map(|data| data.strip_prefix(' ').unwrap_or(data))
+        .collect::<Vec<&str>>();
+    if data.is_empty() {
+        return None;
+    }
+    serde_json::from_str(&data.join("\n")).ok()
+}
+
+/// Reads how long to wait from the `Retry-After` of a throttled response.
+fn throttled(response: &reqwest::Response) -> Error {
+    let retry_after = response
+        .headers()
+        .get(reqwest::header::RETRY_AFTER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(1);
+    Error::TooManyRequests { retry_after }
+}
+
+fn handle_result<T>(res: BaseRes<T>) -> Result<T> {
+    match res {
+        BaseRes::Data { data } => Ok(data),
+        BaseRes::Error { msg } => Err(Error::Logical(msg)),
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum BaseRes<T> {
+    Data { data: T },
+    Error { msg: String },
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[test]
+    fn test_urls() {
+        let client = ChatClient::new("https://chat.example.com/", None);
+        assert_eq!(client.base_url(), "https://chat.example.com");
+        assert_eq!(client.socket_url(), "wss://chat.example.com/ws");
+
+        let event = parse_event(br#"data:{"type":"archived","room_id":"r"}"#);
+        assert!(matches!(event, Some(RoomEvent::Archived { room_id }) if room_id == "r"));
+        assert!(parse_event(b":keepalive").is_none());
+    }
+
+    #[test]
+    fn test_sse_events_across_chunks() {
+        let archived = |events: Vec<RoomEvent>| {
+            events
+                .into_iter()
+                .map(|event| match event {
+                    RoomEvent::Archived { room_id } => room_id,
+                    event => panic!("unexpected event {:?}", event),
+                })
+                .collect::<Vec<String>>()
+        };
+        let mut buffer = SseBuffer::default();
+        assert!(buffer.push(br#"data:{"type":"archived","#).is_empty());
+        assert_eq!(
+            archived(buffer.push(b"\"room_id\":\"a\"}\n\n:\n\ndata: {\"type\":\"archived\",")),
+            vec!["a"]
+        );
+        // an event split over several data lines
+        assert_eq!(
+            archived(buffer.push(b"\ndata:\"room_id\":\"b\"}\r\n\r\n")),
+            vec!["b"]
+        );
+        assert!(buffer.bytes.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_throttled_keeps_retry_after() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = [0; 1024];
+            let _ = stream.read(&mut request).await;
+            let response = "HTTP/1.1 429 Too Many Requests\r\nretry-after: 7\r\n\
+                content-length: 0\r\nconnection: close\r\n\r\n";
+            stream.write_all(response.as_bytes()).await.unwrap();
+        });
+
+        let client = ChatClient::new(&base, Some("token".to_string()));
+        assert_eq!(
+            client.whoami().await.err(),
+            Some(Error::TooManyRequests { retry_after: 7 })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_connect_needs_a_token() {
+        let client = ChatClient::new("http://127.0.0.1:1", None);
+        assert_eq!(client.connect().await.err(), Some(Error::Unauthorized));
+        let client = ChatClient::new("http://127.0.0.1:1", Some("token".to_string()));
+        assert!(client.rooms().await.unwrap_err().is_transient());
+    }
+}
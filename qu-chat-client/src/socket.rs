@@ -0,0 +1,101 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use futures::{SinkExt, StreamExt};
+use qu_chat_models::{ClientFrame, ClientRequest, RoomEvent, ServerFrame};
+use tokio::sync::{mpsc, oneshot};
+use tokio_tungstenite::tungstenite::{self, client::IntoClientRequest};
+
+use crate::{Error, EventStream, Result};
+
+type PendingRequest = (ClientFrame, oneshot::Sender<Result<()>>);
+
+/// An open WebSocket to the server, closed once dropped along with its events.
+#[derive(Debug)]
+pub struct Socket {
+    requests: mpsc::UnboundedSender<PendingRequest>,
+    next_id: AtomicU64,
+}
+
+impl Socket {
+    /// Sends the request and waits for the server to ack it.
+    pub async fn request(&self, request: ClientRequest) -> Result<()> {
+        let frame = ClientFrame {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed).to_string(),
+            request,
+        };
+        let (tx, rx) = oneshot::channel();
+        let closed = || Error::Other(String::from("WebSocket is closed"));
+        self.requests.send((frame, tx)).map_err(|_| closed())?;
+        rx.await.map_err(|_| closed())?
+    }
+
+    /// Resolves once the connection is gone, whoever closed it.
+    pub async fn closed(&self) {
+        self.requests.closed().await
+    }
+}
+
+pub(crate) async fn connect(url: String, token: &str) -> Result<(Socket, EventStream)> {
+    let mut request = url
+        .into_client_request()
+        .map_err(|e| Error::Other(e.to_string()))?;
+    let authorization = format!("Bearer {}", token)
+        .parse()
+        .map_err(|_| Error::Unauthorized)?;
+    request.headers_mut().insert("Authorization", authorization);
+    let (mut stream, _) = tokio_tungstenite::connect_async(request)
+        .await
+        .map_err(|e| match e {
+            tungstenite::Error::Http(response) if response.status() == 401 => Error::Unauthorized,
+            e => Error::Other(e.to_string()),
+        })?;
+
+    let (requests, mut rx) = mpsc::unbounded_channel::<PendingRequest>();
+    let (sender, events) = mpsc::channel::<RoomEvent>(32);
+    tokio::spawn(async move {
+        let mut pending = HashMap::new();
+        loop {
+            tokio::select! {
+                request = rx.recv() => {
+                    let Some((frame, ack)) = request else { break };
+                    let text = serde_json::to_string(&frame).unwrap();
+                    if stream.send(tungstenite::Message::Text(text)).await.is_err() {
+                        break;
+                    }
+                    pending.insert(frame.id, ack);
+                }
+                message = stream.next() => {
+                    let text = match message {
+                        Some(Ok(tungstenite::Message::Text(text))) => text,
+                        Some(Ok(_)) => continue,
+                        _ => break,
+                    };
+                    match serde_json::from_str::<ServerFrame>(&text) {
+                        Ok(ServerFrame::Event { event }) => {
+                            if sender.send(event).await.is_err() {
+                                break;
+                            }
+                        }
+                        Ok(ServerFrame::Ack { id, error }) => {
+                            if let Some(ack) = pending.remove(&id) {
+                                let _ = ack.send(error.map_or(Ok(()), |e| Err(Error::Logical(e))));
+                            }
+                        }
+                        Err(_) => (),
+                    }
+                }
+            }
+        }
+        let _ = stream.close(None).await;
+    });
+
+    let socket = Socket {
+        requests,
+        next_id: AtomicU64::new(0),
+    };
+    let events = futures::stream::unfold(events, |mut events| async move {
+        events.recv().await.map(|event| (event, events))
+    });
+    Ok((socket, Box::pin(events)))
+}
@@ -1,3 +1,5 @@
+pub mod markup;
+
 use std::str::FromStr;
 
 use serde::{Deserialize, Serialize};
@@ -13,18 +15,27 @@ pub trait Identifiable {
 pub struct Message {
     pub id: String,
     pub content: String,
+    /// Empty once the sender's account is deleted, their messages stay.
     pub sender_id: String,
     pub room_id: String,
     pub create_date: i64,
     pub sender_name: String,
+    /// The idempotency key the sender gave the message, lets it match the echo to what it sent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<String>,
+    /// Sent by a bot account.
+    #[serde(default)]
+    pub is_bot: bool,
 }
 
-#[derive(Deserialize, Serialize, Debug)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct Room {
     pub id: String,
     pub name: String,
     pub creator_id: String,
     pub create_date: i64,
+    pub topic: String,
+    pub archived: bool,
 }
 impl Room {
     pub fn uuid(&self) -> uuid::Uuid {
@@ -38,6 +49,173 @@ impl Identifiable for Room {
     }
 }
 
+/// Everything that can happen inside a room, as delivered on the room streams.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RoomEvent {
+    Message(Message),
+    Created {
+        room: Room,
+    },
+    Renamed {
+        room_id: String,
+        name: String,
+    },
+    TopicChanged {
+        room_id: String,
+        topic: String,
+    },
+    Archived {
+        room_id: String,
+    },
+    Deleted {
+        room_id: String,
+    },
+    MessageRemoved {
+        room_id: String,
+        message_id: String,
+    },
+    UserKicked {
+        room_id: String,
+        user_id: String,
+    },
+    UserBanned {
+        room_id: String,
+        user_id: String,
+    },
+    UserUnbanned {
+        room_id: String,
+        user_id: String,
+    },
+    UserMuted {
+        room_id: String,
+        user_id: String,
+        until: i64,
+    },
+    UserUnmuted {
+        room_id: String,
+        user_id: String,
+    },
+    Typing {
+        room_id: String,
+        user_id: String,
+        user_name: String,
+    },
+    /// The user opened the room for the first time, see `UserProfile` for members.
+    MemberJoined {
+        room_id: String,
+        user_id: String,
+        user_name: String,
+    },
+    /// A user ran a command the bot registered for the room, see `RoomCommand`.
+    CommandInvoked {
+        room_id: String,
+        bot_id: String,
+        command: String,
+        args: String,
+        user_id: String,
+        user_name: String,
+    },
+}
+
+impl RoomEvent {
+    /// Every `kind`, e.g. to check the events a webhook subscribes to.
+    pub const KINDS: [&'static str; 15] = [
+        "message",
+        "created",
+        "renamed",
+        "topic_changed",
+        "archived",
+        "deleted",
+        "message_removed",
+        "user_kicked",
+        "user_banned",
+        "user_unbanned",
+        "user_muted",
+        "user_unmuted",
+        "typing",
+        "member_joined",
+        "command_invoked",
+    ];
+
+    /// The `type` the event is serialized with.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            RoomEvent::Message(_) => "message",
+            RoomEvent::Created { .. } => "created",
+            RoomEvent::Renamed { .. } => "renamed",
+            RoomEvent::TopicChanged { .. } => "topic_changed",
+            RoomEvent::Archived { .. } => "archived",
+            RoomEvent::Deleted { .. } => "deleted",
+            RoomEvent::MessageRemoved { .. } => "message_removed",
+            RoomEvent::UserKicked { .. } => "user_kicked",
+            RoomEvent::UserBanned { .. } => "user_banned",
+            RoomEvent::UserUnbanned { .. } => "user_unbanned",
+            RoomEvent::UserMuted { .. } => "user_muted",
+            RoomEvent::UserUnmuted { .. } => "user_unmuted",
+            RoomEvent::Typing { .. } => "typing",
+            RoomEvent::MemberJoined { .. } => "member_joined",
+            RoomEvent::CommandInvoked { .. } => "command_invoked",
+        }
+    }
+
+    pub fn room_id(&self) -> &str {
+        match self {
+            RoomEvent::Message(message) => &message.room_id,
+            RoomEvent::Created { room } => &room.id,
+            RoomEvent::Renamed { room_id, .. } => room_id,
+            RoomEvent::TopicChanged { room_id, .. } => room_id,
+            RoomEvent::Archived { room_id } => room_id,
+            RoomEvent::Deleted { room_id } => room_id,
+            RoomEvent::MessageRemoved { room_id, .. } => room_id,
+            RoomEvent::UserKicked { room_id, .. } => room_id,
+            RoomEvent::UserBanned { room_id, .. } => room_id,
+            RoomEvent::UserUnbanned { room_id, .. } => room_id,
+            RoomEvent::UserMuted { room_id, .. } => room_id,
+            RoomEvent::UserUnmuted { room_id, .. } => room_id,
+            RoomEvent::Typing { room_id, .. } => room_id,
+            RoomEvent::MemberJoined { room_id, .. } => room_id,
+            RoomEvent::CommandInvoked { room_id, .. } => room_id,
+        }
+    }
+}
+
+/// A frame sent by the client over the WebSocket, answered with an `Ack` carrying the same id.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ClientFrame {
+    pub id: String,
+    #[serde(flatten)]
+    pub request: ClientRequest,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClientRequest {
+    Subscribe {
+        room_ids: Vec<String>,
+    },
+    Unsubscribe {
+        room_ids: Vec<String>,
+    },
+    Send {
+        room_id: String,
+        text: String,
+        #[serde(default)]
+        client_id: Option<String>,
+    },
+    Typing {
+        room_id: String,
+    },
+}
+
+/// A frame sent by the server over the WebSocket.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerFrame {
+    Event { event: RoomEvent },
+    Ack { id: String, error: Option<String> },
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct RoomState {
     pub room_id: String,
@@ -71,13 +249,381 @@ pub struct SignInResponse {
     pub token: String,
 }
 
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum LoginOutcome {
+    Success,
+    BadCredentials,
+    Locked,
+}
+
+impl LoginOutcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LoginOutcome::Success => "success",
+            LoginOutcome::BadCredentials => "bad_credentials",
+            LoginOutcome::Locked => "locked",
+        }
+    }
+}
+
+impl FromStr for LoginOutcome {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "success" => Ok(LoginOutcome::Success),
+            "bad_credentials" => Ok(LoginOutcome::BadCredentials),
+            "locked" => Ok(LoginOutcome::Locked),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct LoginAttempt {
+    pub ip: Option<String>,
+    pub outcome: LoginOutcome,
+    pub create_date: i64,
+}
+
 #[derive(Deserialize, Serialize)]
 pub struct CreateRoomParam {
     pub name: String,
 }
 
+/// Opens the direct room of the user and another one, see `POST /rooms/direct`.
+#[derive(Deserialize, Serialize)]
+pub struct DirectRoomParams {
+    pub user_id: String,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct RenameRoomParams {
+    pub name: String,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct RoomTopicParams {
+    pub topic: String,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct RenameUserParams {
+    pub name: String,
+}
+
 #[derive(Debug, Deserialize, Clone, Serialize)]
 pub struct SendMessageParams {
     pub text: String,
     pub room_id: String,
+    /// Sending again with the same key returns the message already sent instead of a new one.
+    #[serde(default)]
+    pub client_id: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ModerationKind {
+    Kick,
+    Ban,
+    Unban,
+    Mute,
+    Unmute,
+    RemoveMessage,
+}
+
+impl ModerationKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ModerationKind::Kick => "kick",
+            ModerationKind::Ban => "ban",
+            ModerationKind::Unban => "unban",
+            ModerationKind::Mute => "mute",
+            ModerationKind::Unmute => "unmute",
+            ModerationKind::RemoveMessage => "remove_message",
+        }
+    }
+
+    /// The sanction the action puts on its target or lifts.
+    pub fn sanction(&self) -> Option<ModerationKind> {
+        match self {
+            ModerationKind::Ban | ModerationKind::Unban => Some(ModerationKind::Ban),
+            ModerationKind::Mute | ModerationKind::Unmute => Some(ModerationKind::Mute),
+            ModerationKind::Kick | ModerationKind::RemoveMessage => None,
+        }
+    }
+}
+
+impl FromStr for ModerationKind {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "kick" => Ok(ModerationKind::Kick),
+            "ban" => Ok(ModerationKind::Ban),
+            "unban" => Ok(ModerationKind::Unban),
+            "mute" => Ok(ModerationKind::Mute),
+            "unmute" => Ok(ModerationKind::Unmute),
+            "remove_message" => Ok(ModerationKind::RemoveMessage),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ModerationParams {
+    pub user_id: String,
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct MuteParams {
+    pub user_id: String,
+    pub minutes: u32,
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct RemoveMessageParams {
+    pub reason: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ModerationAction {
+    pub id: String,
+    pub room_id: String,
+    pub moderator_id: String,
+    pub kind: ModerationKind,
+    pub target_user_id: Option<String>,
+    pub target_message_id: Option<String>,
+    pub reason: Option<String>,
+    pub until: Option<i64>,
+    pub create_date: i64,
+}
+
+/// What an API token lets its bot do, the tokens users sign in with can do everything.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ApiScope {
+    /// Read rooms, messages and users, and follow room events.
+    Read,
+    /// Send messages.
+    Send,
+}
+
+impl ApiScope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ApiScope::Read => "read",
+            ApiScope::Send => "send",
+        }
+    }
+}
+
+impl FromStr for ApiScope {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "read" => Ok(ApiScope::Read),
+            "send" => Ok(ApiScope::Send),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct CreateBotParams {
+    pub name: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Bot {
+    pub id: String,
+    pub name: String,
+    /// The user who created it, none for bots created by the server admin.
+    pub owner_id: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct CreateApiTokenParams {
+    /// What the token is for, e.g. `ci`.
+    pub name: String,
+    pub scopes: Vec<ApiScope>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct ApiToken {
+    pub id: String,
+    pub bot_id: String,
+    pub name: String,
+    pub scopes: Vec<ApiScope>,
+    pub create_date: i64,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct CreatedApiToken {
+    /// Only shown once, the server keeps a hash of it.
+    pub token: String,
+    pub info: ApiToken,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct CreateWebhookParams {
+    /// Where events are POSTed to.
+    pub url: String,
+    /// Kinds of events to send, see `RoomEvent::kind`. Every event but typing when empty.
+    #[serde(default)]
+    pub events: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Webhook {
+    pub id: String,
+    pub room_id: String,
+    pub url: String,
+    pub events: Vec<String>,
+    pub creator_id: String,
+    pub create_date: i64,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct CreatedWebhook {
+    /// Key of the `X-Quchat-Signature` HMAC, only shown once.
+    pub secret: String,
+    pub info: Webhook,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum DeliveryStatus {
+    /// Not sent yet, or to be retried at `next_attempt`.
+    Pending,
+    Delivered,
+    /// Given up on after too many attempts.
+    Failed,
+}
+
+impl DeliveryStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeliveryStatus::Pending => "pending",
+            DeliveryStatus::Delivered => "delivered",
+            DeliveryStatus::Failed => "failed",
+        }
+    }
+}
+
+impl FromStr for DeliveryStatus {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pending" => Ok(DeliveryStatus::Pending),
+            "delivered" => Ok(DeliveryStatus::Delivered),
+            "failed" => Ok(DeliveryStatus::Failed),
+            _ => Err(()),
+        }
+    }
+}
+
+/// An event queued for a webhook, and how sending it went.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct WebhookDelivery {
+    pub id: String,
+    pub webhook_id: String,
+    /// Kind of the event, see `RoomEvent::kind`.
+    pub event: String,
+    /// The body POSTed, a `WebhookPayload`.
+    pub payload: String,
+    pub status: DeliveryStatus,
+    pub attempts: i64,
+    /// Status code of the last attempt, none when the receiver couldn't be reached.
+    pub response_status: Option<i64>,
+    pub error: Option<String>,
+    pub next_attempt: i64,
+    pub create_date: i64,
+}
+
+/// Body of the requests made to webhooks.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct WebhookPayload {
+    pub delivery_id: String,
+    pub webhook_id: String,
+    pub room_id: String,
+    pub create_date: i64,
+    pub event: RoomEvent,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct CreateIncomingWebhookParams {
+    /// Name the messages are sent under, a bot of that name the user manages is reused.
+    pub name: String,
+}
+
+/// A url posting to a room, see `IncomingMessage`.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct IncomingWebhook {
+    pub id: String,
+    pub room_id: String,
+    /// The bot the messages are sent as.
+    pub bot_id: String,
+    pub name: String,
+    pub creator_id: String,
+    pub create_date: i64,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct CreatedIncomingWebhook {
+    /// Messages are POSTed to `/hooks/<token>`. Only shown once, the server keeps a hash of it.
+    pub token: String,
+    pub info: IncomingWebhook,
+}
+
+/// Body of a JSON request to an incoming webhook, a plain text body is sent as is.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct IncomingMessage {
+    pub text: String,
+    /// Shown in bold above the text.
+    #[serde(default)]
+    pub title: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct RegisterCommandParams {
+    /// Run as `/<name>`, made of letters, digits, `-` and `_`.
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    /// The bot answering it, a bot the room admin manages. None when the admin is the bot.
+    #[serde(default)]
+    pub bot_id: Option<String>,
+}
+
+/// A slash command a bot answers in a room. Running it sends the bot a
+/// `RoomEvent::CommandInvoked` instead of a message.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct RoomCommand {
+    pub room_id: String,
+    pub name: String,
+    pub description: String,
+    pub bot_id: String,
+    pub bot_name: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct InvokeCommandParams {
+    /// Whatever follows the command name.
+    #[serde(default)]
+    pub args: String,
+}
+
+/// Machine readable codes sent next to the error message, for errors clients may want to act on.
+pub mod error_codes {
+    pub const BANNED: &str = "banned";
+    pub const MUTED: &str = "muted";
+    pub const BAD_CREDENTIALS: &str = "bad_credentials";
+    pub const ACCOUNT_LOCKED: &str = "account_locked";
+    pub const MISSING_SCOPE: &str = "missing_scope";
 }
@@ -0,0 +1,359 @@
+//! The markup of message contents, a small subset of Markdown: `**bold**`, `*italics*` or
+//! `_italics_`, `` `inline code` ``, `[links](https://example.com)` and code blocks fenced by
+//! ```` ``` ```` lines, optionally followed by a language. A `\` before a markup character
+//! keeps it as is. Anything else, an unclosed `**` included, is plain text.
+
+/// Characters a `\` escapes.
+const ESCAPED: [char; 7] = ['\\', '*', '_', '`', '[', ']', '('];
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Style {
+    pub bold: bool,
+    pub italic: bool,
+    pub code: bool,
+}
+
+/// A run of text in the same style.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Span {
+    pub text: String,
+    pub style: Style,
+    /// Where the text links to.
+    pub link: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Block {
+    /// A line of text, empty for blank lines.
+    Line(Vec<Span>),
+    /// The lines between two fences, whitespace included. An unclosed fence runs to the end.
+    Code {
+        language: Option<String>,
+        code: String,
+    },
+}
+
+/// Splits the content in lines and code blocks.
+pub fn parse(content: &str) -> Vec<Block> {
+    let mut blocks = vec![];
+    let mut code: Option<(Option<String>, Vec<&str>)> = None;
+    for line in content.lines() {
+        let fence = line.trim_start().strip_prefix("```");
+        code = match (code, fence) {
+            (None, Some(language)) => {
+                let language = language.trim();
+                let language = (!language.is_empty()).then(|| language.to_string());
+                Some((language, vec![]))
+            }
+            (None, None) => {
+                blocks.push(Block::Line(inlines(line)));
+                None
+            }
+            (Some((language, lines)), Some(_)) => {
+                blocks.push(Block::Code {
+                    language,
+                    code: lines.join("\n"),
+                });
+                None
+            }
+            (Some((language, mut lines)), None) => {
+                lines.push(line);
+                Some((language, lines))
+            }
+        };
+    }
+    if let Some((language, lines)) = code {
+        blocks.push(Block::Code {
+            language,
+            code: lines.join("\n"),
+        });
+    }
+    blocks
+}
+
+/// The content without markup, e.g. for notifications.
+pub fn plain_text(content: &str) -> String {
+    parse(content)
+        .iter()
+        .map(|block| match block {
+            Block::Line(spans) => spans.iter().map(|span| span.text.as_str()).collect(),
+            Block::Code { code, .. } => code.clone(),
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+struct Inlines {
+    spans: Vec<Span>,
+    text: String,
+    style: Style,
+}
+
+impl Inlines {
+    fn flush(&mut self) {
+        if !self.text.is_empty() {
+            let text = std::mem::take(&mut self.text);
+            self.push(text, self.style, None);
+        }
+    }
+
+    fn push(&mut self, text: String, style: Style, link: Option<String>) {
+        self.spans.push(Span { text, style, link });
+    }
+}
+
+/// Where the delimiters of a line are closed, found in one pass from its end, so that each
+/// delimiter is a lookup rather than a scan of the rest of the line.
+struct Closers {
+    /// Next `` ` ``, `]`, `)` and whitespace at or after each index, the length of the line when
+    /// there is none.
+    backtick: Vec<usize>,
+    bracket: Vec<usize>,
+    paren: Vec<usize>,
+    space: Vec<usize>,
+    /// Start of the last `**`.
+    bold: Option<usize>,
+    /// Last `*` and `_` able to close italics.
+    star: Option<usize>,
+    underscore: Option<usize>,
+}
+
+impl Closers {
+    fn new(chars: &[char]) -> Self {
+        let next = |is: fn(&char) -> bool| {
+            let mut next = vec![chars.len(); chars.len() + 1];
+            for i in (0..chars.len()).rev() {
+                next[i] = if is(&chars[i]) { i } else { next[i + 1] };
+            }
+            next
+        };
+        let last = |ch: char| (0..chars.len()).rfind(|&j| chars[j] == ch && can_close(chars, j));
+        Closers {
+            backtick: next(|ch| *ch == '`'),
+            bracket: next(|ch| *ch == ']'),
+            paren: next(|ch| *ch == ')'),
+            space: next(|ch| ch.is_whitespace()),
+            bold: (1..chars.len())
+                .rfind(|&j| chars[j - 1] == '*' && chars[j] == '*')
+                .map(|j| j - 1),
+            star: last('*'),
+            underscore: last('_'),
+        }
+    }
+
+    /// Whether italics opened at `i` with `ch` are closed further on.
+    fn italic(&self, ch: char, i: usize) -> bool {
+        let last = if ch == '*' {
+            self.star
+        } else {
+            self.underscore
+        };
+        last.is_some_and(|j| j >= i + 2)
+    }
+}
+
+fn inlines(line: &str) -> Vec<Span> {
+    let chars = line.chars().collect::<Vec<char>>();
+    let closers = Closers::new(&chars);
+    let mut out = Inlines {
+        spans: vec![],
+        text: String::new(),
+        style: Style::default(),
+    };
+    // `*` or `_`, whichever opened the italics
+    let mut italic: Option<char> = None;
+    let mut i = 0;
+    while i < chars.len() {
+        let ch = chars[i];
+        match ch {
+            '\\' if chars.get(i + 1).is_some_and(|next| ESCAPED.contains(next)) => {
+                out.text.push(chars[i + 1]);
+                i += 2;
+                continue;
+            }
+            '`' if closers.backtick[i + 1] < chars.len() => {
+                let end = closers.backtick[i + 1];
+                out.flush();
+                let style = Style {
+                    code: true,
+                    ..out.style
+                };
+                out.push(chars[i + 1..end].iter().collect(), style, None);
+                i = end + 1;
+                continue;
+            }
+            '*' if chars.get(i + 1) == Some(&'*') => {
+                let closed = closers.bold.is_some_and(|j| j >= i + 2);
+                if out.style.bold || (closed && can_open(&chars, i + 1)) {
+                    out.flush();
+                    out.style.bold = !out.style.bold;
+                } else {
+                    out.text.push_str("**");
+                }
+                i += 2;
+                continue;
+            }
+            '*' | '_' if italic == Some(ch) && can_close(&chars, i) => {
+                out.flush();
+                out.style.italic = false;
+                italic = None;
+                i += 1;
+                continue;
+            }
+            '*' | '_' if italic.is_none() && can_open(&chars, i) && closers.italic(ch, i) => {
+                out.flush();
+                out.style.italic = true;
+                italic = Some(ch);
+                i += 1;
+                continue;
+            }
+            '[' => {
+                if let Some((text, url, end)) = link(&chars, &closers, i) {
+                    out.flush();
+                    out.push(text, out.style, Some(url));
+                    i = end;
+                    continue;
+                }
+            }
+            _ => (),
+        }
+        out.text.push(ch);
+        i += 1;
+    }
+    out.flush();
+    out.spans
+}
+
+/// Whether the delimiter at `i` can start a style, it has to be followed by text and `_` can't be
+/// in a word, as in snake_case.
+fn can_open(chars: &[char], i: usize) -> bool {
+    let next = chars.get(i + 1).is_some_and(|ch| !ch.is_whitespace());
+    let outside_word = chars[i] != '_' || i == 0 || !chars[i - 1].is_alphanumeric();
+    next && outside_word
+}
+
+fn can_close(chars: &[char], i: usize) -> bool {
+    let previous = i > 0 && !chars[i - 1].is_whitespace();
+    let outside_word = chars[i] != '_' || !chars.get(i + 1).is_some_and(|ch| ch.is_alphanumeric());
+    previous && outside_word
+}
+
+/// `[text](url)` starting at `start`, with the index after it. Nothing is copied before it's
+/// known to be one, or every `[` of a line could copy the rest of it.
+fn link(chars: &[char], closers: &Closers, start: usize) -> Option<(String, String, usize)> {
+    let close = closers.bracket[start + 1];
+    if chars.get(close + 1) != Some(&'(') {
+        return None;
+    }
+    let end = closers.paren[close + 2];
+    let url_start = close + 2;
+    if end == chars.len() || close == start + 1 || end == url_start {
+        return None;
+    }
+    if closers.space[url_start] < end {
+        return None;
+    }
+    let text = chars[start + 1..close].iter().collect::<String>();
+    let url = chars[url_start..end].iter().collect::<String>();
+    Some((text, url, end + 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::markup::*;
+
+    fn line(content: &str) -> Vec<(String, Style)> {
+        match parse(content).remove(0) {
+            Block::Line(spans) => spans.into_iter().map(|s| (s.text, s.style)).collect(),
+            block => panic!("unexpected block {:?}", block),
+        }
+    }
+
+    const BOLD: Style = Style {
+        bold: true,
+        italic: false,
+        code: false,
+    };
+    const ITALIC: Style = Style {
+        bold: false,
+        italic: true,
+        code: false,
+    };
+    const CODE: Style = Style {
+        bold: false,
+        italic: false,
+        code: true,
+    };
+    const PLAIN: Style = Style {
+        bold: false,
+        italic: false,
+        code: false,
+    };
+
+    #[test]
+    fn test_inlines() {
+        assert_eq!(
+            line("a **b** *c* `d*e*`"),
+            vec![
+                ("a ".into(), PLAIN),
+                ("b".into(), BOLD),
+                (" ".into(), PLAIN),
+                ("c".into(), ITALIC),
+                (" ".into(), PLAIN),
+                ("d*e*".into(), CODE),
+            ]
+        );
+        assert_eq!(line("_john waves_"), vec![("john waves".into(), ITALIC)]);
+        assert_eq!(
+            line("snake_case_name"),
+            vec![("snake_case_name".into(), PLAIN)]
+        );
+        assert_eq!(line("2 * 3 ** 4"), vec![("2 * 3 ** 4".into(), PLAIN)]);
+        assert_eq!(line("\\*not\\* `open"), vec![("*not* `open".into(), PLAIN)]);
+
+        let spans = match parse("see [docs](https://quchat.dev) [x] (y)").remove(0) {
+            Block::Line(spans) => spans,
+            block => panic!("unexpected block {:?}", block),
+        };
+        assert_eq!(spans[1].text, "docs");
+        assert_eq!(spans[1].link.as_deref(), Some("https://quchat.dev"));
+        assert_eq!(spans[2].text, " [x] (y)");
+    }
+
+    #[test]
+    fn test_unclosed_delimiters_take_linear_time() {
+        // each delimiter used to scan the rest of the line for its closer
+        for content in [
+            "*a ".repeat(40_000),
+            "[a](b ".repeat(40_000),
+            format!("`{}", "** _".repeat(40_000)),
+        ] {
+            let start = std::time::Instant::now();
+            let text = plain_text(&content);
+            assert_eq!(text.len(), content.len());
+            assert!(start.elapsed() < std::time::Duration::from_secs(1));
+        }
+    }
+
+    #[test]
+    fn test_code_blocks() {
+        let blocks = parse("run:\n```rust\nfn main() {\n    **x**\n}\n```\n\n```\nopen");
+        assert_eq!(blocks.len(), 4);
+        assert_eq!(
+            blocks[1],
+            Block::Code {
+                language: Some("rust".into()),
+                code: "fn main() {\n    **x**\n}".into()
+            }
+        );
+        assert_eq!(blocks[2], Block::Line(vec![]));
+        assert_eq!(
+            blocks[3],
+            Block::Code {
+                language: None,
+                code: "open".into()
+            }
+        );
+        assert_eq!(plain_text("**Build 42**\npassed"), "Build 42\npassed");
+    }
+}